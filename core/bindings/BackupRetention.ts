@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface BackupRetention { keep_last: number, keep_daily: number, keep_weekly: number, }
\ No newline at end of file
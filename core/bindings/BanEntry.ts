@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Player } from "./Player";
+
+export interface BanEntry { player: Player, reason: string | null, source: string | null, expires: bigint | null, }
\ No newline at end of file
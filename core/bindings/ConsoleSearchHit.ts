@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ConsoleLine } from "./ConsoleLine";
+
+export interface ConsoleSearchHit { line: ConsoleLine, before: Array<ConsoleLine>, after: Array<ConsoleLine>, }
\ No newline at end of file
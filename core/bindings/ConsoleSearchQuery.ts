@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ConsoleSearchQuery { query: string, regex: boolean, case_sensitive: boolean, start: bigint | null, end: bigint | null, context: number | null, limit: number | null, }
\ No newline at end of file
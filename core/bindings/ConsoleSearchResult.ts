@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ConsoleSearchHit } from "./ConsoleSearchHit";
+
+export interface ConsoleSearchResult { hits: Array<ConsoleSearchHit>, truncated: boolean, }
\ No newline at end of file
@@ -1,5 +1,6 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { GameType } from "./GameType";
 import type { InstanceUuid } from "./InstanceUuid";
+import type { ScheduledJob } from "./ScheduledJob";
 
-export interface DotLodestoneConfig { game_type: GameType, uuid: InstanceUuid, creation_time: bigint, }
\ No newline at end of file
+export interface DotLodestoneConfig { game_type: GameType, uuid: InstanceUuid, creation_time: bigint, schedule: Array<ScheduledJob>, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ClientEvent } from "./ClientEvent";
+import type { Snowflake } from "./Snowflake";
+
+export interface EventSearchPage { events: Array<ClientEvent>, next_cursor: Snowflake | null, }
\ No newline at end of file
@@ -1,3 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { MacroResource } from "./MacroResource";
 
-export type ExitStatus = { "type": "Success", time: bigint, } | { "type": "Killed", time: bigint, } | { "type": "Error", time: bigint, error_msg: string, };
\ No newline at end of file
+export type ExitStatus = { "type": "Success", time: bigint, } | { "type": "Killed", time: bigint, } | { "type": "Error", time: bigint, error_msg: string, } | { "type": "ResourceExceeded", time: bigint, resource: MacroResource, };
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ExtensionUpdateStatus { current_commit: string, latest_commit: string, update_available: boolean, }
\ No newline at end of file
@@ -1,3 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { MacroLimits } from "./MacroLimits";
+import type { RetentionRule } from "./RetentionRule";
 
-export interface GlobalSettingsData { core_name: string, safe_mode: boolean, domain: string | null, playit_enabled: boolean, }
\ No newline at end of file
+export interface GlobalSettingsData { core_name: string, safe_mode: boolean, domain: string | null, playit_enabled: boolean, mod_index: string | null, event_retention: Array<RetentionRule>, require_two_factor_for_unsafe: boolean, macro_limits: MacroLimits, }
\ No newline at end of file
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type HandlerGameType = "MinecraftJavaVanilla" | "MinecraftFabric" | "MinecraftForge" | "MinecraftPaper" | "MinecraftBedrock";
\ No newline at end of file
+export type HandlerGameType = "MinecraftJavaVanilla" | "MinecraftFabric" | "MinecraftForge" | "MinecraftPaper" | "MinecraftSpigot" | "MinecraftBedrock";
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ExtensionType } from "./ExtensionType";
+import type { Manifest } from "./Manifest";
+
+export interface InstalledExtension { type: ExtensionType, id: string, manifest: Manifest, url: string, commit: string, tag: string | null, previous_commit: string | null, permissions_approved: boolean, }
\ No newline at end of file
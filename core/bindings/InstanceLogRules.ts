@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LogRule } from "./LogRule";
+
+export interface InstanceLogRules { rules: Array<LogRule>, defaults: Array<LogRule>, is_default: boolean, }
\ No newline at end of file
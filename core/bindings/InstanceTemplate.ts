@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Game } from "./Game";
+import type { Snowflake } from "./Snowflake";
+
+export interface InstanceTemplate { id: Snowflake, name: string, description: string, game_type: Game, version: string, creation_time: bigint, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type LogMatch = { "type": "player_joined", player: string, id: string | null, } | { "type": "player_left", player: string, } | { "type": "player_message", player: string, message: string, } | { "type": "server_started" } | { "type": "warning", message: string, } | { "type": "error", message: string, };
\ No newline at end of file
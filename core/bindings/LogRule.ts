@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LogRuleAction } from "./LogRuleAction";
+
+export interface LogRule { name: string, pattern: string, action: LogRuleAction, }
\ No newline at end of file
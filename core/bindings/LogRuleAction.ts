@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type LogRuleAction = "player_joined" | "player_left" | "player_message" | "server_started" | "warning" | "error";
\ No newline at end of file
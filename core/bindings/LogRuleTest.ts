@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LogRule } from "./LogRule";
+
+export interface LogRuleTest { rules: Array<LogRule> | null, lines: Array<string>, }
\ No newline at end of file
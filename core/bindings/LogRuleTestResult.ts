@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LogMatch } from "./LogMatch";
+
+export interface LogRuleTestResult { line: string, rule: string | null, match: LogMatch | null, }
\ No newline at end of file
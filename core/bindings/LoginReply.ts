@@ -2,4 +2,4 @@
 import type { JwtToken } from "./JwtToken";
 import type { PublicUser } from "./PublicUser";
 
-export interface LoginReply { token: JwtToken, user: PublicUser, }
\ No newline at end of file
+export interface LoginReply { token: JwtToken, refresh_token: JwtToken, user: PublicUser, }
\ No newline at end of file
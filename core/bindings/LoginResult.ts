@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { JwtToken } from "./JwtToken";
+import type { LoginReply } from "./LoginReply";
+
+export type LoginResult = { "type": "LoggedIn" } & LoginReply | { "type": "TwoFactorRequired", challenge: JwtToken, };
\ No newline at end of file
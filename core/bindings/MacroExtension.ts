@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { InstanceUuid } from "./InstanceUuid";
+import type { Manifest } from "./Manifest";
+
+export interface MacroExtension { id: string, manifest: Manifest, url: string, instances: Array<InstanceUuid> | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface MacroLimits { heap_mb: number | null, wall_time_secs: number | null, cpu_time_secs: number | null, concurrent_per_instance: number | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { MacroStream } from "./MacroStream";
+
+export interface MacroLogLine { stream: MacroStream, line: string, time: bigint, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { MacroLogLine } from "./MacroLogLine";
+
+export interface MacroLogSnapshot { stdout: Array<MacroLogLine>, stderr: Array<MacroLogLine>, truncated: boolean, running: boolean, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ModMetadata } from "./ModMetadata";
+import type { ModSource } from "./ModSource";
+
+export interface ModEntry { file_name: string, enabled: boolean, size: bigint, metadata: ModMetadata | null, source: ModSource | null, }
\ No newline at end of file
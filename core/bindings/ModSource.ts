@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface ModSource { project_id: string, version_id: string, version_number: string, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ModerationAction = { "type": "Kick", reason: string | null, } | { "type": "Ban", reason: string | null, expires: bigint | null, } | { "type": "Pardon" } | { "type": "Op" } | { "type": "Deop" } | { "type": "WhitelistAdd" } | { "type": "WhitelistRemove" };
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UserPermission } from "./UserPermission";
+
+export interface NewApiToken { name: string, permissions: UserPermission, expiry: bigint | null, }
\ No newline at end of file
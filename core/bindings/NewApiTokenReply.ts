@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { JwtToken } from "./JwtToken";
+import type { PublicApiToken } from "./PublicApiToken";
+
+export interface NewApiTokenReply { token: JwtToken, api_token: PublicApiToken, }
\ No newline at end of file
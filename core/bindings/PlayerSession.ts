@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface PlayerSession { player_id: string, player_name: string, join_time: bigint, leave_time: bigint | null, }
\ No newline at end of file
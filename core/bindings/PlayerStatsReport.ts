@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PlayerStats } from "./PlayerStats";
+
+export interface PlayerStatsReport { players: Array<PlayerStats>, peak_concurrent: number, peak_time: bigint | null, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Snowflake } from "./Snowflake";
+import type { UserPermission } from "./UserPermission";
+
+export interface PublicApiToken { id: Snowflake, name: string, permissions: UserPermission, expiry: bigint | null, creation_time: bigint, last_used: bigint | null, }
\ No newline at end of file
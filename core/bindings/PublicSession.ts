@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Snowflake } from "./Snowflake";
+
+export interface PublicSession { id: Snowflake, creation_time: bigint, last_used: bigint | null, ip: string | null, expiry: bigint, is_current: boolean, }
\ No newline at end of file
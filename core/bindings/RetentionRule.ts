@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { EventType } from "./EventType";
+import type { InstanceEventKind } from "./InstanceEventKind";
+import type { InstanceUuid } from "./InstanceUuid";
+import type { UserEventKind } from "./UserEventKind";
+
+export interface RetentionRule { event_type: EventType | null, instance_event_kind: InstanceEventKind | null, user_event_kind: UserEventKind | null, instance_id: InstanceUuid | null, keep_days: number | null, }
\ No newline at end of file
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Snowflake } from "./Snowflake";
+import type { UserPermission } from "./UserPermission";
+
+export interface Role { id: Snowflake, name: string, permissions: UserPermission, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ScheduledAction = { "type": "run_macro", name: string, args: Array<string>, } | { "type": "command", command: string, } | { "type": "start" } | { "type": "stop" } | { "type": "restart" } | { "type": "backup" };
\ No newline at end of file
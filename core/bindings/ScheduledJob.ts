@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ScheduledAction } from "./ScheduledAction";
+import type { Snowflake } from "./Snowflake";
+import type { UserId } from "./UserId";
+
+export interface ScheduledJob { id: Snowflake, name: string, cron: string, action: ScheduledAction, enabled: boolean, creator: UserId | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ScheduledAction } from "./ScheduledAction";
+
+export interface ScheduledJobConfig { name: string, cron: string, action: ScheduledAction, enabled: boolean | null, }
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { HandlerGameType } from "./HandlerGameType";
+
+export interface UpgradeRequest { version: string, flavour: HandlerGameType | null, }
\ No newline at end of file
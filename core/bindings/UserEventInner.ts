@@ -1,4 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Snowflake } from "./Snowflake";
 import type { UserPermission } from "./UserPermission";
 
-export type UserEventInner = { "type": "UserCreated" } | { "type": "UserDeleted" } | { "type": "UserLoggedIn" } | { "type": "UserLoggedOut" } | { "type": "UsernameChanged", new_username: string, } | { "type": "PermissionChanged", new_permissions: UserPermission, };
\ No newline at end of file
+export type UserEventInner = { "type": "UserCreated" } | { "type": "UserDeleted" } | { "type": "UserLoggedIn" } | { "type": "UserLoggedOut" } | { "type": "UsernameChanged", new_username: string, } | { "type": "PermissionChanged", new_permissions: UserPermission, } | { "type": "ApiTokenCreated", token_id: Snowflake, name: string, } | { "type": "ApiTokenRevoked", token_id: Snowflake, } | { "type": "SessionRevoked", session_id: Snowflake, } | { "type": "TwoFactorEnabled" } | { "type": "TwoFactorDisabled" } | { "type": "LoginFailed", ip: string | null, } | { "type": "AccountLocked", locked_until: bigint, ip: string | null, } | { "type": "AccountUnlocked" };
\ No newline at end of file
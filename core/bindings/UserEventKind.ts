@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type UserEventKind = "UserCreated" | "UserDeleted" | "UserLoggedIn" | "UserLoggedOut" | "UsernameChanged" | "PermissionChanged";
\ No newline at end of file
+export type UserEventKind = "UserCreated" | "UserDeleted" | "UserLoggedIn" | "UserLoggedOut" | "UsernameChanged" | "PermissionChanged" | "ApiTokenCreated" | "ApiTokenRevoked" | "SessionRevoked" | "TwoFactorEnabled" | "TwoFactorDisabled" | "LoginFailed" | "AccountLocked" | "AccountUnlocked";
\ No newline at end of file
@@ -0,0 +1,18 @@
+-- Player sessions, one row per join, leave_time is NULL while the player is online
+CREATE TABLE IF NOT EXISTS PlayerSessions (
+    id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
+    instance_id         TEXT        NOT NULL,
+    player_id           TEXT        NOT NULL,
+    player_name         TEXT        NOT NULL,
+    join_time           BIGINT      NOT NULL,
+    leave_time          BIGINT
+);
+CREATE INDEX IF NOT EXISTS PlayerSessionsInstanceJoinTime ON PlayerSessions (instance_id, join_time);
+CREATE INDEX IF NOT EXISTS PlayerSessionsInstancePlayer ON PlayerSessions (instance_id, player_id);
+-- Columns and indexes to search events by, filled in from event_value for existing rows
+ALTER TABLE ClientEvents ADD COLUMN event_type VARCHAR(32);
+ALTER TABLE ClientEvents ADD COLUMN event_kind VARCHAR(32);
+ALTER TABLE ClientEvents ADD COLUMN user_id TEXT;
+CREATE INDEX IF NOT EXISTS ClientEventsSnowflake ON ClientEvents (snowflake);
+CREATE INDEX IF NOT EXISTS ClientEventsTypeSnowflake ON ClientEvents (event_type, snowflake);
+CREATE INDEX IF NOT EXISTS ClientEventsInstanceSnowflake ON ClientEvents (instance_id, snowflake);
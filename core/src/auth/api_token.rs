@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+
+use jsonwebtoken::{Algorithm, Validation};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{error::Error, types::Snowflake};
+
+use super::{
+    jwt_token::JwtToken, permission::UserPermission, user_id::UserId, user_secrets::UserSecret,
+};
+
+/// Unlike a login claim an API token claim has no `exp`,
+/// the expiry is stored with the token so it can't be forged or outlive a revoke
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ApiTokenClaim {
+    pub uid: UserId,
+    pub token_id: Snowflake,
+}
+
+impl ApiTokenClaim {
+    fn validation() -> Validation {
+        let mut validation = Validation::new(Algorithm::HS512);
+        validation.validate_exp = false;
+        validation.required_spec_claims = HashSet::new();
+        validation
+    }
+
+    pub fn decode_no_verify(token: &str) -> Option<ApiTokenClaim> {
+        let mut no_verify = Self::validation();
+        no_verify.insecure_disable_signature_validation();
+        jsonwebtoken::decode::<ApiTokenClaim>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret("noverify".as_bytes()),
+            &no_verify,
+        )
+        .ok()
+        .map(|t| t.claims)
+    }
+}
+
+/// A named credential acting on behalf of a user with a subset of their permissions
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiToken {
+    pub id: Snowflake,
+    pub name: String,
+    /// Capped by the permissions of the user when the token is used
+    pub permissions: UserPermission,
+    /// Unix timestamp in seconds, `None` never expires
+    pub expiry: Option<i64>,
+    pub creation_time: i64,
+    pub last_used: Option<i64>,
+    secret: UserSecret,
+}
+
+impl ApiToken {
+    pub fn new(name: String, permissions: UserPermission, expiry: Option<i64>) -> Self {
+        ApiToken {
+            id: Snowflake::default(),
+            name,
+            permissions,
+            expiry,
+            creation_time: chrono::Utc::now().timestamp(),
+            last_used: None,
+            secret: UserSecret::default(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expiry
+            .map_or(false, |expiry| expiry <= chrono::Utc::now().timestamp())
+    }
+
+    pub fn create_jwt(&self, uid: &UserId) -> Result<JwtToken, Error> {
+        JwtToken::new(
+            ApiTokenClaim {
+                uid: uid.clone(),
+                token_id: self.id,
+            },
+            self.secret.clone(),
+        )
+    }
+
+    /// Whether `token` was signed for this token
+    pub fn verify(&self, token: &str, claim: &ApiTokenClaim) -> bool {
+        jsonwebtoken::decode::<ApiTokenClaim>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret(self.secret.as_ref().as_bytes()),
+            &ApiTokenClaim::validation(),
+        )
+        .map_or(false, |t| &t.claims == claim && claim.token_id == self.id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, TS)]
+#[ts(export)]
+pub struct PublicApiToken {
+    pub id: Snowflake,
+    pub name: String,
+    pub permissions: UserPermission,
+    pub expiry: Option<i64>,
+    pub creation_time: i64,
+    pub last_used: Option<i64>,
+}
+
+impl From<&ApiToken> for PublicApiToken {
+    fn from(api_token: &ApiToken) -> Self {
+        PublicApiToken {
+            id: api_token.id,
+            name: api_token.name.clone(),
+            permissions: api_token.permissions.clone(),
+            expiry: api_token.expiry,
+            creation_time: api_token.creation_time,
+            last_used: api_token.last_used,
+        }
+    }
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct NewApiToken {
+    pub name: String,
+    pub permissions: UserPermission,
+    /// Unix timestamp in seconds, `None` never expires
+    pub expiry: Option<i64>,
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct NewApiTokenReply {
+    /// Only returned on creation
+    pub token: JwtToken,
+    pub api_token: PublicApiToken,
+}
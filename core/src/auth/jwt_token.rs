@@ -4,7 +4,7 @@ use ts_rs::TS;
 
 use crate::error::Error;
 
-use super::{user::Claim, user_secrets::UserSecret};
+use super::user_secrets::UserSecret;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
 #[serde(transparent)]
@@ -30,7 +30,7 @@ impl AsRef<str> for JwtToken {
 }
 
 impl JwtToken {
-    pub fn new(claim: Claim, secret: UserSecret) -> Result<JwtToken, Error> {
+    pub fn new(claim: impl Serialize, secret: UserSecret) -> Result<JwtToken, Error> {
         Ok(JwtToken(
             jsonwebtoken::encode(
                 &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS512),
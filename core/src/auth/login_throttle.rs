@@ -0,0 +1,239 @@
+//! Throttling of failed logins, tracked both by client address and by username
+//! so neither spraying one account from many addresses nor many accounts from
+//! one address goes unchecked.
+//!
+//! Failures against a username only ever delay further attempts, lockouts are kept
+//! per address and per username from an address, so failing logins from elsewhere
+//! can't lock the user out of their account.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Failures allowed without any delay, so a mistyped password isn't punished
+const FREE_ATTEMPTS: u32 = 3;
+const MAX_DELAY_SECONDS: i64 = 5 * 60;
+/// Consecutive failures after which an address, or a username from an address, is locked out
+pub const LOCKOUT_THRESHOLD: u32 = 10;
+pub const LOCKOUT_MINUTES: i64 = 30;
+/// Failures are forgotten after this long without another one
+const FORGET_AFTER_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct FailedLogins {
+    failures: u32,
+    last_failure: i64,
+    locked_until: Option<i64>,
+}
+
+impl FailedLogins {
+    /// Seconds until another attempt is allowed, 0 if one is allowed now
+    fn retry_after(&self, now: i64) -> i64 {
+        let delay = if self.failures < FREE_ATTEMPTS {
+            0
+        } else {
+            2i64.saturating_pow(self.failures - FREE_ATTEMPTS)
+                .min(MAX_DELAY_SECONDS)
+        };
+        let locked_for = self
+            .locked_until
+            .map_or(0, |locked_until| locked_until - now);
+        (self.last_failure + delay - now).max(locked_for).max(0)
+    }
+
+    /// Returns when the lockout ends if this failure started one
+    fn record_failure(&mut self, now: i64, lock_out: bool) -> Option<i64> {
+        self.failures += 1;
+        self.last_failure = now;
+        if lock_out && self.failures >= LOCKOUT_THRESHOLD {
+            // the delays start over once the lockout is served
+            self.failures = 0;
+            let locked_until = now + LOCKOUT_MINUTES * 60;
+            self.locked_until = Some(locked_until);
+            Some(locked_until)
+        } else {
+            None
+        }
+    }
+
+    fn is_stale(&self, now: i64) -> bool {
+        self.last_failure + FORGET_AFTER_SECONDS < now
+            && self
+                .locked_until
+                .map_or(true, |locked_until| locked_until < now)
+    }
+}
+
+/// Persisted so restarting the core doesn't reset an attacker's progress
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LoginThrottle {
+    by_ip: HashMap<String, FailedLogins>,
+    by_username: HashMap<String, FailedLogins>,
+    /// Keyed by `username_from`
+    #[serde(default)]
+    by_username_from_ip: HashMap<String, FailedLogins>,
+}
+
+/// Addresses never contain a space, unknown addresses share one key
+fn username_from(username: &str, ip: Option<&str>) -> String {
+    format!("{} {}", ip.unwrap_or_default(), username)
+}
+
+impl LoginThrottle {
+    /// `Err` with the seconds to wait if the username or the address may not attempt a login now
+    pub fn check(&self, username: &str, ip: Option<&str>, now: i64) -> Result<(), i64> {
+        Self::retry_after(
+            self.by_username
+                .get(username)
+                .into_iter()
+                .chain(self.address_records(username, ip)),
+            now,
+        )
+    }
+
+    /// Like `check`, without the delay of the username.
+    ///
+    /// For someone who already proved knowing the password, failures from other
+    /// addresses shouldn't keep them from passing their second factor.
+    pub fn check_address(&self, username: &str, ip: Option<&str>, now: i64) -> Result<(), i64> {
+        Self::retry_after(self.address_records(username, ip), now)
+    }
+
+    fn address_records<'a>(
+        &'a self,
+        username: &str,
+        ip: Option<&str>,
+    ) -> impl Iterator<Item = &'a FailedLogins> {
+        ip.and_then(|ip| self.by_ip.get(ip))
+            .into_iter()
+            .chain(self.by_username_from_ip.get(&username_from(username, ip)))
+    }
+
+    fn retry_after<'a>(
+        records: impl Iterator<Item = &'a FailedLogins>,
+        now: i64,
+    ) -> Result<(), i64> {
+        let retry_after = records
+            .map(|failed| failed.retry_after(now))
+            .max()
+            .unwrap_or(0);
+        if retry_after > 0 {
+            Err(retry_after)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns when the lockout of the username from this address ends if this failure locked it
+    pub fn record_failure(&mut self, username: &str, ip: Option<&str>, now: i64) -> Option<i64> {
+        self.prune(now);
+        if let Some(ip) = ip {
+            self.by_ip
+                .entry(ip.to_string())
+                .or_default()
+                .record_failure(now, true);
+        }
+        self.by_username
+            .entry(username.to_string())
+            .or_default()
+            .record_failure(now, false);
+        self.by_username_from_ip
+            .entry(username_from(username, ip))
+            .or_default()
+            .record_failure(now, true)
+    }
+
+    /// Forget the failures of a username after it logged in, returns whether there were any.
+    ///
+    /// The address keeps its record, logging into one account shouldn't
+    /// clear failures against others.
+    pub fn record_success(&mut self, username: &str, ip: Option<&str>) -> bool {
+        let from_ip = self
+            .by_username_from_ip
+            .remove(&username_from(username, ip))
+            .is_some();
+        self.by_username.remove(username).is_some() || from_ip
+    }
+
+    /// Lift every lockout of a username, returns whether it was locked from any address
+    pub fn unlock(&mut self, username: &str, now: i64) -> bool {
+        let locked = self.locked_until(username, now).is_some();
+        self.by_username.remove(username);
+        self.by_username_from_ip
+            .retain(|key, _| key.split_once(' ').map(|(_, name)| name) != Some(username));
+        locked
+    }
+
+    /// When the last lockout of the username from any address ends
+    pub fn locked_until(&self, username: &str, now: i64) -> Option<i64> {
+        self.by_username_from_ip
+            .iter()
+            .filter(|(key, _)| key.split_once(' ').map(|(_, name)| name) == Some(username))
+            .filter_map(|(_, failed)| failed.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .max()
+    }
+
+    fn prune(&mut self, now: i64) {
+        self.by_ip.retain(|_, failed| !failed.is_stale(now));
+        self.by_username.retain(|_, failed| !failed.is_stale(now));
+        self.by_username_from_ip
+            .retain(|_, failed| !failed.is_stale(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_throttle() {
+        let mut throttle = LoginThrottle::default();
+        let now = 1_000_000;
+        for _ in 0..FREE_ATTEMPTS {
+            assert!(throttle.check("alice", Some("10.0.0.1"), now).is_ok());
+            assert_eq!(
+                throttle.record_failure("alice", Some("10.0.0.1"), now),
+                None
+            );
+        }
+        // the delay doubles with every further failure
+        assert_eq!(throttle.check("alice", None, now), Err(1));
+        throttle.record_failure("alice", None, now);
+        assert_eq!(throttle.check("alice", None, now), Err(2));
+        // the address is throttled for other usernames too
+        assert_eq!(throttle.check("bob", Some("10.0.0.1"), now), Err(1));
+        assert!(throttle.check("bob", Some("10.0.0.2"), now).is_ok());
+
+        let mut locked_until = None;
+        for _ in 1..LOCKOUT_THRESHOLD {
+            locked_until = throttle.record_failure("alice", None, now);
+        }
+        assert_eq!(locked_until, Some(now + LOCKOUT_MINUTES * 60));
+        assert_eq!(
+            throttle.check("alice", None, now + 60),
+            Err(LOCKOUT_MINUTES * 60 - 60)
+        );
+        assert!(throttle
+            .check("alice", None, now + LOCKOUT_MINUTES * 60)
+            .is_ok());
+        // the lockout is kept to the address the failures came from, elsewhere it's only
+        // the delay of the username, which doesn't hold back a second factor
+        assert_eq!(
+            throttle.check("alice", Some("10.0.0.2"), now + MAX_DELAY_SECONDS),
+            Ok(())
+        );
+        assert!(throttle
+            .check_address("alice", Some("10.0.0.2"), now)
+            .is_ok());
+        assert!(throttle.locked_until("alice", now).is_some());
+
+        assert!(throttle.unlock("alice", now));
+        assert!(throttle.check("alice", None, now).is_ok());
+        assert!(!throttle.unlock("alice", now));
+
+        throttle.record_failure("carol", None, now);
+        assert!(throttle.record_success("carol", None));
+        assert!(!throttle.record_success("carol", None));
+    }
+}
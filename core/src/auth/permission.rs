@@ -20,6 +20,8 @@ pub struct UserPermission {
     pub can_read_instance_file: HashSet<InstanceUuid>,
     // unsafe permission, owner exclusive unless explicitly granted
     pub can_write_instance_file: HashSet<InstanceUuid>,
+    #[serde(default)]
+    pub can_manage_instance_players: HashSet<InstanceUuid>,
 
     pub can_create_instance: bool,
     pub can_delete_instance: bool,
@@ -45,6 +47,7 @@ impl UserPermission {
             can_access_instance_macro: HashSet::new(),
             can_read_instance_file: HashSet::new(),
             can_write_instance_file: HashSet::new(),
+            can_manage_instance_players: HashSet::new(),
             can_create_instance: false,
             can_delete_instance: false,
             can_read_global_file: false,
@@ -53,6 +56,110 @@ impl UserPermission {
             can_install_extension: false,
         }
     }
+
+    /// Whether any permission the owner has to grant explicitly is held
+    pub fn has_unsafe_permission(&self) -> bool {
+        !self.can_write_instance_resource.is_empty()
+            || !self.can_access_instance_macro.is_empty()
+            || self.can_write_global_file
+            || self.can_manage_permission
+            || !self.can_write_instance_file.is_empty()
+    }
+
+    /// Add every grant of `other` to these permissions
+    pub fn merge(&mut self, other: &UserPermission) {
+        self.can_view_instance
+            .extend(other.can_view_instance.iter().cloned());
+        self.can_start_instance
+            .extend(other.can_start_instance.iter().cloned());
+        self.can_stop_instance
+            .extend(other.can_stop_instance.iter().cloned());
+        self.can_access_instance_console
+            .extend(other.can_access_instance_console.iter().cloned());
+        self.can_access_instance_setting
+            .extend(other.can_access_instance_setting.iter().cloned());
+        self.can_read_instance_resource
+            .extend(other.can_read_instance_resource.iter().cloned());
+        self.can_write_instance_resource
+            .extend(other.can_write_instance_resource.iter().cloned());
+        self.can_access_instance_macro
+            .extend(other.can_access_instance_macro.iter().cloned());
+        self.can_read_instance_file
+            .extend(other.can_read_instance_file.iter().cloned());
+        self.can_write_instance_file
+            .extend(other.can_write_instance_file.iter().cloned());
+        self.can_manage_instance_players
+            .extend(other.can_manage_instance_players.iter().cloned());
+        self.can_create_instance |= other.can_create_instance;
+        self.can_delete_instance |= other.can_delete_instance;
+        self.can_read_global_file |= other.can_read_global_file;
+        self.can_write_global_file |= other.can_write_global_file;
+        self.can_manage_permission |= other.can_manage_permission;
+        self.can_install_extension |= other.can_install_extension;
+    }
+
+    /// Whether every grant of `other` is also one of these permissions
+    pub fn covers(&self, other: &UserPermission) -> bool {
+        let covers_instances = |held: &HashSet<InstanceUuid>, wanted: &HashSet<InstanceUuid>| {
+            wanted
+                .iter()
+                .all(|instance| grants_instance(held, instance))
+        };
+        covers_instances(&self.can_view_instance, &other.can_view_instance)
+            && covers_instances(&self.can_start_instance, &other.can_start_instance)
+            && covers_instances(&self.can_stop_instance, &other.can_stop_instance)
+            && covers_instances(
+                &self.can_access_instance_console,
+                &other.can_access_instance_console,
+            )
+            && covers_instances(
+                &self.can_access_instance_setting,
+                &other.can_access_instance_setting,
+            )
+            && covers_instances(
+                &self.can_read_instance_resource,
+                &other.can_read_instance_resource,
+            )
+            && covers_instances(
+                &self.can_write_instance_resource,
+                &other.can_write_instance_resource,
+            )
+            && covers_instances(
+                &self.can_access_instance_macro,
+                &other.can_access_instance_macro,
+            )
+            && covers_instances(&self.can_read_instance_file, &other.can_read_instance_file)
+            && covers_instances(
+                &self.can_write_instance_file,
+                &other.can_write_instance_file,
+            )
+            && covers_instances(
+                &self.can_manage_instance_players,
+                &other.can_manage_instance_players,
+            )
+            && (self.can_create_instance || !other.can_create_instance)
+            && (self.can_delete_instance || !other.can_delete_instance)
+            && (self.can_read_global_file || !other.can_read_global_file)
+            && (self.can_write_global_file || !other.can_write_global_file)
+            && (self.can_manage_permission || !other.can_manage_permission)
+            && (self.can_install_extension || !other.can_install_extension)
+    }
+
+    pub fn without_unsafe_permissions(&self) -> Self {
+        UserPermission {
+            can_write_instance_resource: HashSet::new(),
+            can_access_instance_macro: HashSet::new(),
+            can_write_instance_file: HashSet::new(),
+            can_write_global_file: false,
+            can_manage_permission: false,
+            ..self.clone()
+        }
+    }
+}
+
+/// Whether `grants` covers `instance`, either directly or through the wildcard
+pub fn grants_instance(grants: &HashSet<InstanceUuid>, instance: &InstanceUuid) -> bool {
+    grants.contains(instance) || grants.iter().any(InstanceUuid::is_wildcard)
 }
 
 impl Default for UserPermission {
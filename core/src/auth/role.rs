@@ -0,0 +1,40 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::types::Snowflake;
+
+use super::permission::UserPermission;
+
+/// A named set of permissions that can be assigned to any number of users
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct Role {
+    pub id: Snowflake,
+    pub name: String,
+    pub permissions: UserPermission,
+}
+
+impl Role {
+    pub fn new(name: String, permissions: UserPermission) -> Self {
+        Role {
+            id: Snowflake::default(),
+            name,
+            permissions,
+        }
+    }
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct RoleConfig {
+    pub name: String,
+    pub permissions: UserPermission,
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct RoleAssignment {
+    pub roles: HashSet<Snowflake>,
+}
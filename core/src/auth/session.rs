@@ -0,0 +1,128 @@
+use jsonwebtoken::{Algorithm, Validation};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{error::Error, types::Snowflake};
+
+use super::{jwt_token::JwtToken, user_id::UserId, user_secrets::UserSecret};
+
+/// How long an access token is valid, clients renew it with their refresh token
+pub const ACCESS_TOKEN_LIFETIME_MINUTES: i64 = 15;
+/// How long a session lasts without being refreshed
+pub const SESSION_LIFETIME_DAYS: i64 = 30;
+
+#[derive(Serialize, Deserialize)]
+pub struct RefreshClaim {
+    pub uid: UserId,
+    pub sid: Snowflake,
+    pub exp: usize,
+}
+
+impl RefreshClaim {
+    pub fn decode_no_verify(token: &str) -> Option<RefreshClaim> {
+        let mut no_verify = Validation::new(Algorithm::HS512);
+        no_verify.insecure_disable_signature_validation();
+        jsonwebtoken::decode::<RefreshClaim>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret("noverify".as_bytes()),
+            &no_verify,
+        )
+        .ok()
+        .map(|t| t.claims)
+    }
+}
+
+/// A single login of a user, identified by the `jti` of its access tokens
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    pub id: Snowflake,
+    pub creation_time: i64,
+    pub last_used: Option<i64>,
+    /// Address of the client when it logged in or last refreshed
+    pub ip: Option<String>,
+    /// Unix timestamp in seconds, pushed back on every refresh
+    pub expiry: i64,
+    /// Rotated on every refresh so a refresh token can only be used once
+    refresh_secret: UserSecret,
+}
+
+impl Session {
+    pub fn new(ip: Option<String>) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        Session {
+            id: Snowflake::default(),
+            creation_time: now,
+            last_used: Some(now),
+            ip,
+            expiry: now + SESSION_LIFETIME_DAYS * 24 * 60 * 60,
+            refresh_secret: UserSecret::default(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expiry <= chrono::Utc::now().timestamp()
+    }
+
+    /// Invalidate the current refresh token and extend the session
+    pub fn renew(&mut self, ip: Option<String>) {
+        let now = chrono::Utc::now().timestamp();
+        self.refresh_secret = UserSecret::default();
+        self.expiry = now + SESSION_LIFETIME_DAYS * 24 * 60 * 60;
+        self.last_used = Some(now);
+        if ip.is_some() {
+            self.ip = ip;
+        }
+    }
+
+    pub fn create_refresh_token(&self, uid: &UserId) -> Result<JwtToken, Error> {
+        JwtToken::new(
+            RefreshClaim {
+                uid: uid.clone(),
+                sid: self.id,
+                exp: self.expiry as usize,
+            },
+            self.refresh_secret.clone(),
+        )
+    }
+
+    /// Whether `token` is the current refresh token of this session
+    pub fn verify_refresh_token(&self, token: &str) -> bool {
+        jsonwebtoken::decode::<RefreshClaim>(
+            token,
+            &jsonwebtoken::DecodingKey::from_secret(self.refresh_secret.as_ref().as_bytes()),
+            &Validation::new(Algorithm::HS512),
+        )
+        .map_or(false, |t| t.claims.sid == self.id)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, TS)]
+#[ts(export)]
+pub struct PublicSession {
+    pub id: Snowflake,
+    pub creation_time: i64,
+    pub last_used: Option<i64>,
+    pub ip: Option<String>,
+    pub expiry: i64,
+    /// Whether this is the session making the request
+    pub is_current: bool,
+}
+
+impl PublicSession {
+    pub fn new(session: &Session, current_session: Option<Snowflake>) -> Self {
+        PublicSession {
+            id: session.id,
+            creation_time: session.creation_time,
+            last_used: session.last_used,
+            ip: session.ip.clone(),
+            expiry: session.expiry,
+            is_current: current_session == Some(session.id),
+        }
+    }
+}
+
+/// The credentials handed out when a session is created or refreshed
+pub struct SessionTokens {
+    pub token: JwtToken,
+    pub refresh_token: JwtToken,
+}
@@ -0,0 +1,255 @@
+//! Time-based one-time passwords (RFC 6238) as used by authenticator apps,
+//! with the SHA-1, 6 digit, 30 second defaults every app supports
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+use crate::util::rand_alphanumeric;
+
+use super::hashed_password::{hash_password, HashedPassword};
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+/// Codes from one step before or after the current one are accepted to allow for clock drift
+const ALLOWED_DRIFT_STEPS: u64 = 1;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Alphanumeric characters in a recovery code, not counting the dash in the middle
+const RECOVERY_CODE_LEN: usize = 10;
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(5) {
+        let mut buffer = [0u8; 5];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        let bits = buffer
+            .iter()
+            .fold(0u64, |bits, byte| (bits << 8) | *byte as u64);
+        // unpadded, as authenticator apps expect
+        let chars = (chunk.len() * 8 + 4) / 5;
+        for i in 0..chars {
+            encoded.push(BASE32_ALPHABET[((bits >> (35 - i * 5)) & 0x1f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut bits = 0u64;
+    let mut bit_count = 0;
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())?;
+        bits = (bits << 5) | value as u64;
+        bit_count += 5;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+    Some(decoded)
+}
+
+/// A new random base32 encoded secret
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill(&mut secret);
+    base32_encode(&secret)
+}
+
+/// The code for the `step`th 30 second window since the unix epoch
+fn code_at(secret: &[u8], step: u64) -> u32 {
+    let hash = hmac_sha1(secret, &step.to_be_bytes());
+    let offset = (hash[19] & 0xf) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    truncated % 10u32.pow(DIGITS)
+}
+
+/// The code an authenticator app shows for `step`
+pub fn code_at_step(secret: &str, step: u64) -> Option<String> {
+    let secret = base32_decode(secret)?;
+    Some(format!(
+        "{:0width$}",
+        code_at(&secret, step),
+        width = DIGITS as usize
+    ))
+}
+
+pub fn current_step() -> u64 {
+    chrono::Utc::now().timestamp() as u64 / STEP_SECONDS
+}
+
+/// The step `code` is valid for around `step`, if any
+pub fn verify(secret: &str, code: &str, step: u64) -> Option<u64> {
+    let secret = base32_decode(secret)?;
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    (step.saturating_sub(ALLOWED_DRIFT_STEPS)..=step + ALLOWED_DRIFT_STEPS)
+        .find(|step| bool::from(code_at(&secret, *step).ct_eq(&code)))
+}
+
+/// The `otpauth://` URI authenticator apps enroll with, usually shown as a QR code
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let encode = |s: &str| url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode(issuer).replace('+', "%20"),
+        encode(account).replace('+', "%20"),
+        secret,
+        encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// A user's second factor, an authenticator app plus single use recovery codes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TwoFactor {
+    /// Base32 encoded shared secret
+    secret: String,
+    /// Stays false until the user confirms enrollment with a code from their app
+    pub enabled: bool,
+    /// Step of the last accepted code, so a code can't be replayed
+    last_step: Option<u64>,
+    recovery_codes: Vec<HashedPassword>,
+}
+
+impl TwoFactor {
+    pub fn new() -> Self {
+        TwoFactor {
+            secret: generate_secret(),
+            enabled: false,
+            last_step: None,
+            recovery_codes: Vec::new(),
+        }
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    /// Check a code from the authenticator app or, once enabled, a recovery code.
+    ///
+    /// Accepted codes are used up, the caller must persist the change.
+    pub fn verify(&mut self, code: &str) -> bool {
+        let code = code.trim();
+        if let Some(step) = verify(&self.secret, code, current_step()) {
+            if self.last_step.map_or(false, |last_step| step <= last_step) {
+                return false;
+            }
+            self.last_step = Some(step);
+            return true;
+        }
+        // checking a recovery code hashes it against every stored one, only do that
+        // for something shaped like a recovery code rather than a wrong app code
+        let normalized = normalize_recovery_code(code);
+        if normalized.len() != RECOVERY_CODE_LEN || normalized.chars().all(|c| c.is_ascii_digit()) {
+            return false;
+        }
+        match self
+            .recovery_codes
+            .iter()
+            .position(|hashed| hashed.eq(normalized.as_str()))
+        {
+            Some(index) => {
+                self.recovery_codes.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replace the recovery codes, returns the new codes which are only stored hashed
+    pub fn regenerate_recovery_codes(&mut self) -> Vec<String> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| loop {
+                let code = rand_alphanumeric(RECOVERY_CODE_LEN).to_lowercase();
+                if !code.chars().all(|c| c.is_ascii_digit()) {
+                    break code;
+                }
+            })
+            .collect();
+        self.recovery_codes = codes.iter().map(hash_password).collect();
+        codes
+            .into_iter()
+            .map(|code| format!("{}-{}", &code[..5], &code[5..]))
+            .collect()
+    }
+}
+
+impl Default for TwoFactor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totp() {
+        // test vectors from RFC 6238, which truncates them to 8 digits
+        let secret = base32_encode(b"12345678901234567890");
+        assert_eq!(base32_decode(&secret).unwrap(), b"12345678901234567890");
+        for (time, code) in [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+        ] {
+            let code = format!("{:06}", code % 1_000_000);
+            assert_eq!(
+                verify(&secret, &code, time / STEP_SECONDS),
+                Some(time / STEP_SECONDS)
+            );
+        }
+        assert_eq!(verify(&secret, "000000", 1), None);
+        assert_eq!(verify(&secret, "94287", 1), None);
+    }
+
+    #[test]
+    fn test_two_factor() {
+        let mut two_factor = TwoFactor::new();
+        let code = code_at_step(two_factor.secret(), current_step()).unwrap();
+        assert!(two_factor.verify(&code));
+        // codes can't be replayed
+        assert!(!two_factor.verify(&code));
+
+        let recovery_codes = two_factor.regenerate_recovery_codes();
+        assert_eq!(recovery_codes.len(), RECOVERY_CODE_COUNT);
+        assert!(two_factor.verify(&recovery_codes[0].to_uppercase()));
+        assert!(!two_factor.verify(&recovery_codes[0]));
+        assert!(two_factor.verify(&recovery_codes[1]));
+        // only well formed recovery codes are checked
+        assert!(!two_factor.verify(&recovery_codes[2][..5]));
+        assert!(two_factor.verify(&recovery_codes[2]));
+    }
+}
@@ -1,14 +1,20 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+};
 
 use argon2::{Argon2, PasswordVerifier};
 use color_eyre::eyre::{eyre, Context};
 use jsonwebtoken::{Algorithm, Validation};
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 use tracing::warn;
 use ts_rs::TS;
 
 use crate::{
+    db::types::EventVisibility,
     error::{Error, ErrorKind},
     event_broadcaster::EventBroadcaster,
     events::{CausedBy, Event, EventInner, UserEvent, UserEventInner},
@@ -16,9 +22,14 @@ use crate::{
 };
 
 use super::{
+    api_token::{ApiToken, ApiTokenClaim, PublicApiToken},
     hashed_password::{hash_password, HashedPassword},
     jwt_token::JwtToken,
-    permission::UserPermission,
+    login_throttle::LoginThrottle,
+    permission::{grants_instance, UserPermission},
+    role::Role,
+    session::{PublicSession, RefreshClaim, Session, SessionTokens, ACCESS_TOKEN_LIFETIME_MINUTES},
+    totp::{provisioning_uri, TwoFactor},
     user_id::UserId,
     user_secrets::UserSecret,
 };
@@ -27,6 +38,50 @@ use super::{
 pub struct Claim {
     pub uid: UserId,
     pub exp: usize,
+    /// The session the token was issued for
+    pub jti: Snowflake,
+}
+
+/// Proof that the password was checked, traded for a session along a second factor
+#[derive(Deserialize, Serialize)]
+pub struct TwoFactorClaim {
+    pub uid: UserId,
+    pub exp: usize,
+    pub challenge: Snowflake,
+}
+
+const TWO_FACTOR_CHALLENGE_LIFETIME_MINUTES: i64 = 5;
+/// Wrong codes accepted for one two-factor challenge before logging in has to start over
+const TWO_FACTOR_CHALLENGE_MAX_FAILURES: u32 = 5;
+
+pub enum LoginOutcome {
+    LoggedIn(User, SessionTokens),
+    /// The password was correct, the user still has to pass their second factor
+    TwoFactorRequired(JwtToken),
+}
+
+/// A login whose password is yet to be verified, see [`UsersManager::start_login`]
+pub struct PendingLogin {
+    username: String,
+    hashed_psw: Option<HashedPassword>,
+}
+
+impl PendingLogin {
+    /// Slow on purpose, so run it off the async runtime and without holding the users manager.
+    ///
+    /// Unknown usernames are checked against a dummy hash to take as long as known ones.
+    pub fn verify(&self, password: &str) -> bool {
+        lazy_static! {
+            static ref DUMMY_HASH: HashedPassword = hash_password("");
+        }
+        match &self.hashed_psw {
+            Some(hashed_psw) => hashed_psw == password,
+            None => {
+                let _ = &*DUMMY_HASH == password;
+                false
+            }
+        }
+    }
 }
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct User {
@@ -37,6 +92,24 @@ pub struct User {
     pub is_admin: bool,
     pub permissions: UserPermission,
     pub secret: UserSecret,
+    #[serde(default)]
+    pub api_tokens: Vec<ApiToken>,
+    /// Set when the user was authenticated with one of their API tokens,
+    /// in which case `permissions` is already narrowed to the token's
+    #[serde(skip)]
+    pub api_token_id: Option<Snowflake>,
+    #[serde(default)]
+    pub sessions: Vec<Session>,
+    /// Set when the user was authenticated with an access token of one of their sessions
+    #[serde(skip)]
+    pub session_id: Option<Snowflake>,
+    #[serde(default)]
+    pub two_factor: Option<TwoFactor>,
+    #[serde(default)]
+    pub roles: HashSet<Snowflake>,
+    /// Grants of `roles`, resolved by the users manager whenever roles change
+    #[serde(skip)]
+    pub role_permissions: UserPermission,
 }
 
 impl User {
@@ -55,8 +128,20 @@ impl User {
             is_admin,
             permissions,
             secret: UserSecret::default(),
+            api_tokens: Vec::new(),
+            api_token_id: None,
+            sessions: Vec::new(),
+            session_id: None,
+            two_factor: None,
+            roles: HashSet::new(),
+            role_permissions: UserPermission::default(),
         }
     }
+    pub fn two_factor_enabled(&self) -> bool {
+        self.two_factor
+            .as_ref()
+            .map_or(false, |two_factor| two_factor.enabled)
+    }
     fn get_permission_level(&self) -> u8 {
         if self.is_owner {
             u8::MAX
@@ -66,28 +151,71 @@ impl User {
             1
         }
     }
-    pub fn update_permission(
-        &self,
-        other: &mut User,
-        permissions: UserPermission,
-    ) -> Result<(), Error> {
+    /// Only users of a strictly higher level can manage someone's permissions,
+    /// which also rules out managing one's own
+    pub fn check_outranks(&self, other: &User) -> Result<(), Error> {
         if self.get_permission_level() <= other.get_permission_level() {
             return Err(Error {
                 kind: ErrorKind::PermissionDenied,
                 source: eyre!("You don't have permission to manage other users' permission"),
             });
         }
+        Ok(())
+    }
+
+    /// Handing out or taking away `permissions`, directly or through a role, requires holding
+    /// all of them, and only the owner can hand out unsafe permissions
+    pub fn check_role_grant(&self, permissions: &UserPermission) -> Result<(), Error> {
+        if self.is_owner {
+            return Ok(());
+        }
+        if permissions.has_unsafe_permission() {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!(
+                    "Unsafe and owner exclusive permissions can only be granted by the owner"
+                ),
+            });
+        }
+        let mut held = self.effective_permissions();
+        if self.is_admin {
+            // what admins can do on every instance without being granted it
+            for grants in [
+                &mut held.can_view_instance,
+                &mut held.can_start_instance,
+                &mut held.can_stop_instance,
+                &mut held.can_access_instance_console,
+                &mut held.can_access_instance_setting,
+                &mut held.can_read_instance_resource,
+                &mut held.can_read_instance_file,
+                &mut held.can_manage_instance_players,
+            ] {
+                grants.insert(InstanceUuid::wildcard());
+            }
+            held.can_create_instance = true;
+            held.can_delete_instance = true;
+        }
+        if !held.covers(permissions) {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("You can't hand out permissions you don't hold yourself"),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn update_permission(
+        &self,
+        other: &mut User,
+        permissions: UserPermission,
+    ) -> Result<(), Error> {
+        self.check_outranks(other)?;
         if self.is_owner {
             other.permissions = permissions;
             Ok(())
         } else {
             // reject granting any unsafe permission
-            if !permissions.can_write_instance_resource.is_empty()
-                || !permissions.can_access_instance_macro.is_empty()
-                || permissions.can_write_global_file
-                || permissions.can_manage_permission
-                || !permissions.can_write_instance_file.is_empty()
-            {
+            if permissions.has_unsafe_permission() {
                 Err(Error {
                     kind: ErrorKind::PermissionDenied,
                     source: eyre!(
@@ -106,73 +234,85 @@ impl User {
         }
     }
 
+    /// Whether the user's own grants or one of their roles cover `instance` in the set `grants` picks
+    fn has_instance_grant(
+        &self,
+        grants: fn(&UserPermission) -> &HashSet<InstanceUuid>,
+        instance: &InstanceUuid,
+    ) -> bool {
+        grants_instance(grants(&self.permissions), instance)
+            || grants_instance(grants(&self.role_permissions), instance)
+    }
+
+    fn has_grant(&self, grant: fn(&UserPermission) -> bool) -> bool {
+        grant(&self.permissions) || grant(&self.role_permissions)
+    }
+
+    /// The user's own grants combined with those of their roles
+    pub fn effective_permissions(&self) -> UserPermission {
+        let mut permissions = self.permissions.clone();
+        permissions.merge(&self.role_permissions);
+        permissions
+    }
+
     pub fn can_perform_action(&self, action: &UserAction) -> bool {
         if self.is_owner {
             return true;
         }
         match action {
             UserAction::ViewInstance(instance_id) => {
-                self.is_admin || self.permissions.can_view_instance.contains(instance_id)
+                self.is_admin || self.has_instance_grant(|p| &p.can_view_instance, instance_id)
             }
             UserAction::StartInstance(instance_id) => {
-                self.is_admin || self.permissions.can_start_instance.contains(instance_id)
+                self.is_admin || self.has_instance_grant(|p| &p.can_start_instance, instance_id)
             }
             UserAction::StopInstance(instance_id) => {
-                self.is_admin || self.permissions.can_stop_instance.contains(instance_id)
+                self.is_admin || self.has_instance_grant(|p| &p.can_stop_instance, instance_id)
             }
             UserAction::AccessConsole(instance_id) => {
                 self.is_admin
-                    || self
-                        .permissions
-                        .can_access_instance_console
-                        .contains(instance_id)
+                    || self.has_instance_grant(|p| &p.can_access_instance_console, instance_id)
             }
             UserAction::AccessSetting(instance_id) => {
                 self.is_admin
-                    || self
-                        .permissions
-                        .can_access_instance_setting
-                        .contains(instance_id)
+                    || self.has_instance_grant(|p| &p.can_access_instance_setting, instance_id)
             }
             UserAction::ReadResource(instance_id) => {
                 self.is_admin
-                    || self
-                        .permissions
-                        .can_read_instance_resource
-                        .contains(instance_id)
-            }
-            UserAction::WriteResource(instance_id) => self
-                .permissions
-                .can_write_instance_resource
-                .contains(instance_id),
+                    || self.has_instance_grant(|p| &p.can_read_instance_resource, instance_id)
+            }
+            UserAction::WriteResource(instance_id) => {
+                self.has_instance_grant(|p| &p.can_write_instance_resource, instance_id)
+            }
             UserAction::ReadInstanceFile(instance_id) => {
                 self.is_admin
-                    || self.permissions.can_read_global_file
-                    || self
-                        .permissions
-                        .can_read_instance_file
-                        .contains(instance_id)
+                    || self.has_grant(|p| p.can_read_global_file)
+                    || self.has_instance_grant(|p| &p.can_read_instance_file, instance_id)
             }
             UserAction::WriteInstanceFile(instance_id) => {
-                self.permissions.can_write_global_file
-                    || self
-                        .permissions
-                        .can_write_instance_file
-                        .contains(instance_id)
-            }
-            UserAction::AccessMacro(Some(instance_id)) => self
-                .permissions
-                .can_access_instance_macro
-                .contains(instance_id),
+                self.has_grant(|p| p.can_write_global_file)
+                    || self.has_instance_grant(|p| &p.can_write_instance_file, instance_id)
+            }
+            UserAction::ManagePlayers(instance_id) => {
+                self.is_admin
+                    || self.has_instance_grant(|p| &p.can_manage_instance_players, instance_id)
+            }
+            UserAction::AccessMacro(Some(instance_id)) => {
+                self.has_instance_grant(|p| &p.can_access_instance_macro, instance_id)
+            }
             // TODO(CheatCod3): check if the macro is global
             UserAction::AccessMacro(None) => false,
-            UserAction::CreateInstance => self.is_admin || self.permissions.can_create_instance,
-            UserAction::DeleteInstance => self.is_admin || self.permissions.can_delete_instance,
-            UserAction::ReadGlobalFile => self.permissions.can_read_global_file,
-            UserAction::WriteGlobalFile => self.permissions.can_write_global_file,
+            UserAction::CreateInstance => {
+                self.is_admin || self.has_grant(|p| p.can_create_instance)
+            }
+            UserAction::DeleteInstance => {
+                self.is_admin || self.has_grant(|p| p.can_delete_instance)
+            }
+            UserAction::ReadGlobalFile => self.has_grant(|p| p.can_read_global_file),
+            UserAction::WriteGlobalFile => self.has_grant(|p| p.can_write_global_file),
             UserAction::ManageUser => self.is_owner,
-            UserAction::ManagePermission => self.permissions.can_manage_permission,
-            UserAction::InstallExtension => self.permissions.can_install_extension,
+            UserAction::ManagePermission => self.has_grant(|p| p.can_manage_permission),
+            UserAction::InstallExtension => self.has_grant(|p| p.can_install_extension),
         }
     }
 
@@ -215,6 +355,9 @@ impl User {
                     UserAction::WriteInstanceFile(_) => {
                         eyre!("You don't have permission to write this instance's file")
                     }
+                    UserAction::ManagePlayers(_) => {
+                        eyre!("You don't have permission to manage this instance's players")
+                    }
                     UserAction::CreateInstance => {
                         eyre!("You don't have permission to create instance")
                     }
@@ -255,14 +398,134 @@ impl User {
         }
     }
 
-    pub fn create_jwt(&self) -> Result<JwtToken, Error> {
+    /// `can_view_event` in a form that can filter stored events, keep the two in sync
+    pub fn event_visibility(&self) -> EventVisibility {
+        let permissions = self.effective_permissions();
+        let all_macro_instances = permissions
+            .can_access_instance_macro
+            .iter()
+            .any(InstanceUuid::is_wildcard);
+        EventVisibility {
+            all: self.is_owner,
+            all_instances: self.is_admin
+                || permissions
+                    .can_view_instance
+                    .iter()
+                    .any(InstanceUuid::is_wildcard),
+            instances: permissions.can_view_instance.into_iter().collect(),
+            all_macro_instances,
+            macro_instances: permissions.can_access_instance_macro.into_iter().collect(),
+            users_and_files: self.can_perform_action(&UserAction::ManageUser),
+        }
+    }
+
+    /// The part of `permissions` this user is currently allowed to use
+    pub fn restrict_permissions(&self, permissions: &UserPermission) -> UserPermission {
+        let mut permissions = permissions.clone();
+        let retain = |set: &mut HashSet<InstanceUuid>, action: fn(InstanceUuid) -> UserAction| {
+            set.retain(|uuid| self.can_perform_action(&action(uuid.clone())))
+        };
+        retain(&mut permissions.can_view_instance, UserAction::ViewInstance);
+        retain(
+            &mut permissions.can_start_instance,
+            UserAction::StartInstance,
+        );
+        retain(&mut permissions.can_stop_instance, UserAction::StopInstance);
+        retain(
+            &mut permissions.can_access_instance_console,
+            UserAction::AccessConsole,
+        );
+        retain(
+            &mut permissions.can_access_instance_setting,
+            UserAction::AccessSetting,
+        );
+        retain(
+            &mut permissions.can_read_instance_resource,
+            UserAction::ReadResource,
+        );
+        retain(
+            &mut permissions.can_write_instance_resource,
+            UserAction::WriteResource,
+        );
+        retain(&mut permissions.can_access_instance_macro, |uuid| {
+            UserAction::AccessMacro(Some(uuid))
+        });
+        retain(
+            &mut permissions.can_read_instance_file,
+            UserAction::ReadInstanceFile,
+        );
+        retain(
+            &mut permissions.can_write_instance_file,
+            UserAction::WriteInstanceFile,
+        );
+        retain(
+            &mut permissions.can_manage_instance_players,
+            UserAction::ManagePlayers,
+        );
+        permissions.can_create_instance &= self.can_perform_action(&UserAction::CreateInstance);
+        permissions.can_delete_instance &= self.can_perform_action(&UserAction::DeleteInstance);
+        permissions.can_read_global_file &= self.can_perform_action(&UserAction::ReadGlobalFile);
+        permissions.can_write_global_file &= self.can_perform_action(&UserAction::WriteGlobalFile);
+        permissions.can_manage_permission &= self.can_perform_action(&UserAction::ManagePermission);
+        permissions.can_install_extension &= self.can_perform_action(&UserAction::InstallExtension);
+        permissions
+    }
+
+    /// This user as seen through one of their API tokens.
+    ///
+    /// Tokens never carry the owner or admin flag, so they can't manage users
+    /// or anything else gated on those flags.
+    pub fn with_api_token(&self, api_token: &ApiToken) -> User {
+        User {
+            is_owner: false,
+            is_admin: false,
+            permissions: self.restrict_permissions(&api_token.permissions),
+            role_permissions: UserPermission::default(),
+            api_tokens: Vec::new(),
+            api_token_id: Some(api_token.id),
+            sessions: Vec::new(),
+            session_id: None,
+            ..self.clone()
+        }
+    }
+
+    /// Account management (passwords, sessions, tokens) requires logging in as the user
+    pub fn try_manage_account(&self) -> Result<(), Error> {
+        if self.api_token_id.is_some() {
+            return Err(Error {
+                kind: ErrorKind::PermissionDenied,
+                source: eyre!("API tokens cannot manage accounts"),
+            });
+        }
+        Ok(())
+    }
+
+    fn create_two_factor_challenge(&self) -> Result<JwtToken, Error> {
         let exp = chrono::Utc::now()
-            .checked_add_signed(chrono::Duration::days(60))
+            .checked_add_signed(chrono::Duration::minutes(
+                TWO_FACTOR_CHALLENGE_LIFETIME_MINUTES,
+            ))
+            .ok_or_else(|| eyre!("Failed to create JWT token"))?
+            .timestamp();
+        JwtToken::new(
+            TwoFactorClaim {
+                uid: self.uid.clone(),
+                exp: exp as usize,
+                challenge: Snowflake::default(),
+            },
+            self.secret.clone(),
+        )
+    }
+
+    pub fn create_access_token(&self, session: &Session) -> Result<JwtToken, Error> {
+        let exp = chrono::Utc::now()
+            .checked_add_signed(chrono::Duration::minutes(ACCESS_TOKEN_LIFETIME_MINUTES))
             .ok_or_else(|| eyre!("Failed to create JWT token"))?
             .timestamp();
         let claim = Claim {
             uid: self.uid.clone(),
             exp: exp as usize,
+            jti: session.id,
         };
 
         JwtToken::new(claim, self.secret.clone())
@@ -281,6 +544,7 @@ pub enum UserAction {
     AccessMacro(Option<InstanceUuid>),
     ReadInstanceFile(InstanceUuid),
     WriteInstanceFile(InstanceUuid),
+    ManagePlayers(InstanceUuid),
 
     // global actions:
     CreateInstance,
@@ -305,6 +569,7 @@ impl UserAction {
             UserAction::AccessMacro(_) => true,
             UserAction::ReadInstanceFile(_) => true,
             UserAction::WriteInstanceFile(_) => true,
+            UserAction::ManagePlayers(_) => true,
             UserAction::CreateInstance => true,
             UserAction::DeleteInstance => true,
             UserAction::ReadGlobalFile => false,
@@ -324,6 +589,8 @@ pub struct PublicUser {
     pub is_owner: bool,
     pub is_admin: bool,
     pub permissions: UserPermission,
+    pub roles: HashSet<Snowflake>,
+    pub two_factor_enabled: bool,
 }
 
 impl From<&User> for PublicUser {
@@ -334,6 +601,8 @@ impl From<&User> for PublicUser {
             is_owner: user.is_owner,
             is_admin: user.is_admin,
             permissions: user.permissions.clone(),
+            roles: user.roles.clone(),
+            two_factor_enabled: user.two_factor_enabled(),
         }
     }
 }
@@ -341,11 +610,13 @@ impl From<&User> for PublicUser {
 impl From<User> for PublicUser {
     fn from(user: User) -> Self {
         PublicUser {
+            two_factor_enabled: user.two_factor_enabled(),
             uid: user.uid,
             username: user.username,
             is_owner: user.is_owner,
             is_admin: user.is_admin,
             permissions: user.permissions,
+            roles: user.roles,
         }
     }
 }
@@ -355,6 +626,20 @@ pub struct UsersManager {
     event_broadcaster: EventBroadcaster,
     users: HashMap<UserId, User>,
     path_to_users: PathBuf,
+    roles: HashMap<Snowflake, Role>,
+    /// Stored beside the users file
+    path_to_roles: PathBuf,
+    login_throttle: LoginThrottle,
+    path_to_login_throttle: PathBuf,
+    /// Last use of each session and API token since the users were last written,
+    /// kept aside since authenticating only takes a read lock
+    last_used: Arc<std::sync::Mutex<HashMap<Snowflake, i64>>>,
+    /// Mirrors the owner's global setting, users holding unsafe permissions
+    /// without two-factor authentication can't use those permissions
+    require_two_factor_for_unsafe: bool,
+    /// Expiry and wrong codes of two-factor challenges, a challenge is spent
+    /// once it was traded for a session or after too many wrong codes
+    two_factor_attempts: HashMap<Snowflake, (usize, u32)>,
 }
 
 impl UsersManager {
@@ -366,7 +651,14 @@ impl UsersManager {
         Self {
             event_broadcaster,
             users,
+            path_to_roles: path_to_users.with_file_name("roles.json"),
+            path_to_login_throttle: path_to_users.with_file_name("login_attempts.json"),
             path_to_users,
+            roles: HashMap::new(),
+            login_throttle: LoginThrottle::default(),
+            last_used: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            require_two_factor_for_unsafe: false,
+            two_factor_attempts: HashMap::new(),
         }
     }
     pub async fn load_users(&mut self) -> Result<(), Error> {
@@ -405,10 +697,270 @@ impl UsersManager {
             .context("Failed to deserialize user json")?;
             self.users = users;
         }
+        self.roles = match tokio::fs::read(&self.path_to_roles).await {
+            Ok(roles) => {
+                serde_json::from_slice(&roles).context("Failed to deserialize role json")?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => Err(e).context(format!(
+                "Failed to read role file : {}",
+                &self.path_to_roles.display()
+            ))?,
+        };
+        self.resolve_roles();
+        self.login_throttle = match tokio::fs::read(&self.path_to_login_throttle).await {
+            Ok(login_throttle) => serde_json::from_slice(&login_throttle)
+                .context("Failed to deserialize login attempts json")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => LoginThrottle::default(),
+            Err(e) => Err(e).context(format!(
+                "Failed to read login attempts file : {}",
+                &self.path_to_login_throttle.display()
+            ))?,
+        };
+        Ok(())
+    }
+
+    async fn write_roles_to_file(&self) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(&self.path_to_roles)
+            .await
+            .context(format!(
+                "Failed to open/create json file {}",
+                &self.path_to_roles.display()
+            ))?;
+
+        file.write_all(
+            serde_json::to_string(&self.roles)
+                .context("Failed to serialize role json")?
+                .as_bytes(),
+        )
+        .await
+        .context("Failed to write to role json".to_string())?;
+        Ok(())
+    }
+
+    async fn write_login_throttle_to_file(&self) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(&self.path_to_login_throttle)
+            .await
+            .context(format!(
+                "Failed to open/create json file {}",
+                &self.path_to_login_throttle.display()
+            ))?;
+
+        file.write_all(
+            serde_json::to_string(&self.login_throttle)
+                .context("Failed to serialize login attempts json")?
+                .as_bytes(),
+        )
+        .await
+        .context("Failed to write to login attempts json".to_string())?;
+        Ok(())
+    }
+
+    /// Recompute the role grants of every user, unknown role ids are ignored
+    fn resolve_roles(&mut self) {
+        for user in self.users.values_mut() {
+            let mut role_permissions = UserPermission::default();
+            for role in user.roles.iter().filter_map(|id| self.roles.get(id)) {
+                role_permissions.merge(&role.permissions);
+            }
+            user.role_permissions = role_permissions;
+        }
+    }
+
+    /// Tell clients the effective permissions of `uids` changed
+    fn broadcast_permission_changed(&self, uids: Vec<UserId>, caused_by: CausedBy) {
+        for uid in uids {
+            let Some(user) = self.users.get(&uid) else {
+                continue;
+            };
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::UserEvent(UserEvent {
+                    user_id: uid,
+                    user_event_inner: UserEventInner::PermissionChanged {
+                        new_permissions: Box::new(user.effective_permissions()),
+                    },
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: caused_by.clone(),
+            });
+        }
+    }
+
+    fn holders_of(&self, role_id: Snowflake) -> Vec<UserId> {
+        self.users
+            .values()
+            .filter(|user| user.roles.contains(&role_id))
+            .map(|user| user.uid.clone())
+            .collect()
+    }
+
+    fn check_role_name(&self, name: &str, except: Option<Snowflake>) -> Result<(), Error> {
+        if name.trim().is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Role name cannot be empty"),
+            });
+        }
+        if self
+            .roles
+            .values()
+            .any(|role| role.name == name && Some(role.id) != except)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Role name already exist"),
+            });
+        }
         Ok(())
     }
 
-    async fn write_to_file(&self) -> Result<(), Error> {
+    pub fn list_roles(&self) -> Vec<Role> {
+        self.roles.values().cloned().collect()
+    }
+
+    pub fn get_role(&self, role_id: Snowflake) -> Option<Role> {
+        self.roles.get(&role_id).cloned()
+    }
+
+    /// The requester must outrank every user holding the role to change it
+    fn check_role_holders(&self, requester: &User, role_id: Snowflake) -> Result<(), Error> {
+        self.users
+            .values()
+            .filter(|user| user.roles.contains(&role_id))
+            .try_for_each(|user| requester.check_outranks(user))
+    }
+
+    pub async fn create_role(
+        &mut self,
+        requester: &User,
+        name: String,
+        permissions: UserPermission,
+    ) -> Result<Role, Error> {
+        requester.check_role_grant(&permissions)?;
+        self.check_role_name(&name, None)?;
+        let role = Role::new(name, permissions);
+        self.roles.insert(role.id, role.clone());
+        if let Err(e) = self.write_roles_to_file().await {
+            self.roles.remove(&role.id);
+            return Err(e);
+        }
+        Ok(role)
+    }
+
+    /// Replace a role's name and permissions, the change applies to every holder right away
+    pub async fn update_role(
+        &mut self,
+        requester: &User,
+        role_id: Snowflake,
+        name: String,
+        permissions: UserPermission,
+        caused_by: CausedBy,
+    ) -> Result<Role, Error> {
+        self.check_role_name(&name, Some(role_id))?;
+        let old_role = self.roles.get(&role_id).cloned().ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Role not found"),
+        })?;
+        requester.check_role_grant(&old_role.permissions)?;
+        requester.check_role_grant(&permissions)?;
+        self.check_role_holders(requester, role_id)?;
+        let role = Role {
+            name,
+            permissions,
+            ..old_role.clone()
+        };
+        self.roles.insert(role_id, role.clone());
+        if let Err(e) = self.write_roles_to_file().await {
+            self.roles.insert(role_id, old_role);
+            return Err(e);
+        }
+        self.resolve_roles();
+        if old_role.permissions != role.permissions {
+            self.broadcast_permission_changed(self.holders_of(role_id), caused_by);
+        }
+        Ok(role)
+    }
+
+    pub async fn delete_role(
+        &mut self,
+        requester: &User,
+        role_id: Snowflake,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let role = self.roles.get(&role_id).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Role not found"),
+        })?;
+        requester.check_role_grant(&role.permissions)?;
+        self.check_role_holders(requester, role_id)?;
+        let role = self
+            .roles
+            .remove(&role_id)
+            .ok_or_else(|| eyre!("Role not found"))?;
+        if let Err(e) = self.write_roles_to_file().await {
+            self.roles.insert(role_id, role);
+            return Err(e);
+        }
+        let holders = self.holders_of(role_id);
+        for uid in holders.iter() {
+            if let Some(user) = self.users.get_mut(uid) {
+                user.roles.remove(&role_id);
+            }
+        }
+        self.resolve_roles();
+        self.broadcast_permission_changed(holders, caused_by);
+        // the role is already gone, a stale id left in the users file is ignored
+        self.write_to_file().await
+    }
+
+    /// Replace the roles of a user
+    pub async fn assign_roles(
+        &mut self,
+        requester: &User,
+        uid: impl AsRef<UserId>,
+        roles: HashSet<Snowflake>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        if let Some(unknown) = roles.iter().find(|id| !self.roles.contains_key(id)) {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Role {} not found", unknown),
+            });
+        }
+        let user = self.users.get(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        requester.check_outranks(user)?;
+        // both granting and taking away a role is limited to what the requester holds
+        for role_id in user.roles.symmetric_difference(&roles) {
+            if let Some(role) = self.roles.get(role_id) {
+                requester.check_role_grant(&role.permissions)?;
+            }
+        }
+        let user = self
+            .users
+            .get_mut(uid.as_ref())
+            .ok_or_else(|| eyre!("User id not found"))?;
+        let old_roles = std::mem::replace(&mut user.roles, roles);
+        match self.write_to_file().await {
+            Ok(()) => {
+                self.resolve_roles();
+                self.broadcast_permission_changed(vec![uid.as_ref().to_owned()], caused_by);
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.roles = old_roles;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn write_to_file(&mut self) -> Result<(), Error> {
+        self.apply_last_used();
         let mut file = tokio::fs::File::create(&self.path_to_users)
             .await
             .context(format!(
@@ -500,8 +1052,10 @@ impl UsersManager {
             })?
             .secret
             .clone();
+        let mut old_sessions = Vec::new();
         if let Some(user) = self.users.get_mut(uid.as_ref()) {
             user.secret = UserSecret::default();
+            old_sessions = std::mem::take(&mut user.sessions);
         }
 
         match self.write_to_file().await {
@@ -519,73 +1073,239 @@ impl UsersManager {
             }
             Err(e) => {
                 if let Some(user) = self.users.get_mut(uid.as_ref()) {
-                    user.secret = old_secret
+                    user.secret = old_secret;
+                    user.sessions = old_sessions;
                 }
                 Err(e)
             }
         }
     }
 
-    pub async fn rename_user(
+    /// Log every user out of every session, for when credentials may have leaked
+    pub async fn sign_out_everywhere(
         &mut self,
-        uid: impl AsRef<UserId>,
-        new_username: String,
+        revoke_api_tokens: bool,
         caused_by: CausedBy,
     ) -> Result<(), Error> {
-        let old_username = self
-            .users
-            .get_mut(uid.as_ref())
-            .ok_or_else(|| Error {
-                kind: ErrorKind::NotFound,
-                source: eyre!("User id not found"),
-            })?
-            .username
-            .clone();
-        if let Some(user) = self.users.get_mut(uid.as_ref()) {
-            user.username = new_username.clone();
-            match self.write_to_file().await {
-                Ok(_) => {
+        let old_users = self.users.clone();
+        for user in self.users.values_mut() {
+            user.secret = UserSecret::default();
+            user.sessions.clear();
+            if revoke_api_tokens {
+                user.api_tokens.clear();
+            }
+        }
+        match self.write_to_file().await {
+            Ok(_) => {
+                for uid in self.users.keys() {
                     self.event_broadcaster.send(Event {
                         event_inner: EventInner::UserEvent(UserEvent {
-                            user_id: uid.as_ref().to_owned(),
-                            user_event_inner: UserEventInner::UsernameChanged {
-                                new_username: new_username.clone(),
-                            },
+                            user_id: uid.clone(),
+                            user_event_inner: UserEventInner::UserLoggedOut,
                         }),
                         details: "".to_string(),
                         snowflake: Snowflake::default(),
-                        caused_by,
+                        caused_by: caused_by.clone(),
                     });
-                    Ok(())
-                }
-                Err(e) => {
-                    if let Some(user) = self.users.get_mut(uid.as_ref()) {
-                        user.username = old_username
-                    }
-                    Err(e)
                 }
+                Ok(())
+            }
+            Err(e) => {
+                self.users = old_users;
+                Err(e)
             }
-        } else {
-            Err(Error {
-                kind: ErrorKind::NotFound,
-                source: eyre!("User id not found"),
-            })
         }
     }
 
-    pub async fn change_password(
+    pub async fn create_session(
         &mut self,
         uid: impl AsRef<UserId>,
-        old_password: Option<impl AsRef<str>>,
-        password: String,
-        caused_by: CausedBy,
-    ) -> Result<(), Error> {
-        let old_data = self
-            .users
-            .get_mut(uid.as_ref())
-            .ok_or_else(|| Error {
-                kind: ErrorKind::NotFound,
-                source: eyre!("User id not found"),
+        ip: Option<String>,
+    ) -> Result<SessionTokens, Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let session = Session::new(ip);
+        let tokens = SessionTokens {
+            token: user.create_access_token(&session)?,
+            refresh_token: session.create_refresh_token(&user.uid)?,
+        };
+        let session_id = session.id;
+        user.sessions.retain(|session| !session.is_expired());
+        user.sessions.push(session);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                user.sessions.retain(|session| session.id != session_id);
+            }
+            return Err(e);
+        }
+        Ok(tokens)
+    }
+
+    /// Trade a refresh token for a new access token and refresh token,
+    /// the old refresh token can't be used again
+    pub async fn refresh_session(
+        &mut self,
+        refresh_token: &str,
+        ip: Option<String>,
+    ) -> Result<(User, SessionTokens), Error> {
+        let unauthorized = || Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Invalid or expired refresh token"),
+        };
+        let claim = RefreshClaim::decode_no_verify(refresh_token).ok_or_else(unauthorized)?;
+        let user = self.users.get_mut(&claim.uid).ok_or_else(unauthorized)?;
+        let session = user
+            .sessions
+            .iter_mut()
+            .find(|session| session.id == claim.sid)
+            .ok_or_else(unauthorized)?;
+        if !session.verify_refresh_token(refresh_token) || session.is_expired() {
+            return Err(unauthorized());
+        }
+        let old_session = session.clone();
+        session.renew(ip);
+        let session = session.clone();
+        let tokens = SessionTokens {
+            token: user.create_access_token(&session)?,
+            refresh_token: session.create_refresh_token(&user.uid)?,
+        };
+        let user = user.clone();
+        if let Err(e) = self.write_to_file().await {
+            if let Some(session) = self
+                .users
+                .get_mut(&claim.uid)
+                .and_then(|user| user.sessions.iter_mut().find(|s| s.id == claim.sid))
+            {
+                *session = old_session;
+            }
+            return Err(e);
+        }
+        Ok((user, tokens))
+    }
+
+    pub fn list_sessions(&self, user: &User) -> Result<Vec<PublicSession>, Error> {
+        let current = self.users.get(&user.uid).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let last_used = self.last_used.lock().unwrap();
+        Ok(current
+            .sessions
+            .iter()
+            .filter(|session| !session.is_expired())
+            .map(|session| {
+                let mut public = PublicSession::new(session, user.session_id);
+                if let Some(time) = last_used.get(&session.id) {
+                    public.last_used = Some(*time);
+                }
+                public
+            })
+            .collect())
+    }
+
+    pub async fn revoke_session(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        session_id: Snowflake,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let index = user
+            .sessions
+            .iter()
+            .position(|session| session.id == session_id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Session not found"),
+            })?;
+        let session = user.sessions.remove(index);
+        match self.write_to_file().await {
+            Ok(_) => {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::UserEvent(UserEvent {
+                        user_id: uid.as_ref().to_owned(),
+                        user_event_inner: UserEventInner::SessionRevoked { session_id },
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.sessions.insert(index, session);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn rename_user(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        new_username: String,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let old_username = self
+            .users
+            .get_mut(uid.as_ref())
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("User id not found"),
+            })?
+            .username
+            .clone();
+        if let Some(user) = self.users.get_mut(uid.as_ref()) {
+            user.username = new_username.clone();
+            match self.write_to_file().await {
+                Ok(_) => {
+                    self.event_broadcaster.send(Event {
+                        event_inner: EventInner::UserEvent(UserEvent {
+                            user_id: uid.as_ref().to_owned(),
+                            user_event_inner: UserEventInner::UsernameChanged {
+                                new_username: new_username.clone(),
+                            },
+                        }),
+                        details: "".to_string(),
+                        snowflake: Snowflake::default(),
+                        caused_by,
+                    });
+                    Ok(())
+                }
+                Err(e) => {
+                    if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                        user.username = old_username
+                    }
+                    Err(e)
+                }
+            }
+        } else {
+            Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("User id not found"),
+            })
+        }
+    }
+
+    pub async fn change_password(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        old_password: Option<impl AsRef<str>>,
+        password: String,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let old_data = self
+            .users
+            .get_mut(uid.as_ref())
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("User id not found"),
             })?
             .hashed_psw
             .clone();
@@ -675,13 +1395,211 @@ impl UsersManager {
     }
 
     pub fn try_auth(&self, token: &str) -> Option<User> {
-        let claimed_uid = decode_no_verify(token)?;
-        let claimed_requester = self.users.get(&claimed_uid)?;
-        let requester_uid = decode_token(token, &claimed_requester.secret)?;
-        if claimed_uid != requester_uid {
+        let Some(claimed) = decode_no_verify(token) else {
+            return self.try_auth_api_token(token);
+        };
+        let claimed_requester = self.users.get(&claimed.uid)?;
+        let requester = decode_token(token, &claimed_requester.secret)?;
+        if claimed.uid != requester.uid {
+            return None;
+        }
+        // revoking the session revokes its access tokens right away
+        claimed_requester
+            .sessions
+            .iter()
+            .find(|session| session.id == requester.jti && !session.is_expired())?;
+        self.last_used
+            .lock()
+            .unwrap()
+            .insert(requester.jti, chrono::Utc::now().timestamp());
+        Some(self.apply_two_factor_policy(User {
+            session_id: Some(requester.jti),
+            ..claimed_requester.to_owned()
+        }))
+    }
+
+    pub fn set_two_factor_policy(&mut self, require_two_factor_for_unsafe: bool) {
+        self.require_two_factor_for_unsafe = require_two_factor_for_unsafe;
+    }
+
+    fn apply_two_factor_policy(&self, user: User) -> User {
+        if self.require_two_factor_for_unsafe && !user.is_owner && !user.two_factor_enabled() {
+            User {
+                permissions: user.permissions.without_unsafe_permissions(),
+                role_permissions: user.role_permissions.without_unsafe_permissions(),
+                ..user
+            }
+        } else {
+            user
+        }
+    }
+
+    fn try_auth_api_token(&self, token: &str) -> Option<User> {
+        let claim = ApiTokenClaim::decode_no_verify(token)?;
+        let owner = self.users.get(&claim.uid)?;
+        let api_token = owner
+            .api_tokens
+            .iter()
+            .find(|api_token| api_token.id == claim.token_id)?;
+        if !api_token.verify(token, &claim) || api_token.is_expired() {
             return None;
         }
-        Some(claimed_requester.to_owned())
+        self.last_used
+            .lock()
+            .unwrap()
+            .insert(api_token.id, chrono::Utc::now().timestamp());
+        Some(
+            self.apply_two_factor_policy(owner.clone())
+                .with_api_token(api_token),
+        )
+    }
+
+    /// The current state of an authenticated user,
+    /// `None` if the user was deleted or their session or API token revoked or expired
+    pub fn refresh_user(&self, user: &User) -> Option<User> {
+        let current = self.users.get(&user.uid)?;
+        if let Some(token_id) = user.api_token_id {
+            return current
+                .api_tokens
+                .iter()
+                .find(|api_token| api_token.id == token_id && !api_token.is_expired())
+                .map(|api_token| {
+                    self.apply_two_factor_policy(current.clone())
+                        .with_api_token(api_token)
+                });
+        }
+        if let Some(session_id) = user.session_id {
+            current
+                .sessions
+                .iter()
+                .find(|session| session.id == session_id && !session.is_expired())?;
+        }
+        Some(self.apply_two_factor_policy(User {
+            session_id: user.session_id,
+            ..current.clone()
+        }))
+    }
+
+    fn apply_last_used(&mut self) {
+        let last_used = std::mem::take(&mut *self.last_used.lock().unwrap());
+        if last_used.is_empty() {
+            return;
+        }
+        for user in self.users.values_mut() {
+            for api_token in user.api_tokens.iter_mut() {
+                if let Some(time) = last_used.get(&api_token.id) {
+                    api_token.last_used = Some(*time);
+                }
+            }
+            for session in user.sessions.iter_mut() {
+                if let Some(time) = last_used.get(&session.id) {
+                    session.last_used = Some(*time);
+                }
+            }
+        }
+    }
+
+    /// Persist the last use of sessions and API tokens, which is otherwise only written along other changes
+    pub async fn save_last_used(&mut self) -> Result<(), Error> {
+        if self.last_used.lock().unwrap().is_empty() {
+            return Ok(());
+        }
+        self.write_to_file().await
+    }
+
+    pub fn list_api_tokens(&self, uid: impl AsRef<UserId>) -> Result<Vec<PublicApiToken>, Error> {
+        let user = self.users.get(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let last_used = self.last_used.lock().unwrap();
+        Ok(user
+            .api_tokens
+            .iter()
+            .map(|api_token| {
+                let mut public = PublicApiToken::from(api_token);
+                if let Some(time) = last_used.get(&api_token.id) {
+                    public.last_used = Some(*time);
+                }
+                public
+            })
+            .collect())
+    }
+
+    pub async fn add_api_token(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        api_token: ApiToken,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let token_id = api_token.id;
+        let name = api_token.name.clone();
+        user.api_tokens.push(api_token);
+        match self.write_to_file().await {
+            Ok(_) => {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::UserEvent(UserEvent {
+                        user_id: uid.as_ref().to_owned(),
+                        user_event_inner: UserEventInner::ApiTokenCreated { token_id, name },
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.api_tokens.retain(|api_token| api_token.id != token_id);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    pub async fn revoke_api_token(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        token_id: Snowflake,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let index = user
+            .api_tokens
+            .iter()
+            .position(|api_token| api_token.id == token_id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("API token not found"),
+            })?;
+        let api_token = user.api_tokens.remove(index);
+        match self.write_to_file().await {
+            Ok(_) => {
+                self.event_broadcaster.send(Event {
+                    event_inner: EventInner::UserEvent(UserEvent {
+                        user_id: uid.as_ref().to_owned(),
+                        user_event_inner: UserEventInner::ApiTokenRevoked { token_id },
+                    }),
+                    details: "".to_string(),
+                    snowflake: Snowflake::default(),
+                    caused_by,
+                });
+                Ok(())
+            }
+            Err(e) => {
+                if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                    user.api_tokens.insert(index, api_token);
+                }
+                Err(e)
+            }
+        }
     }
 
     pub fn try_auth_or_err(&self, token: &str) -> Result<User, Error> {
@@ -691,71 +1609,432 @@ impl UsersManager {
         })
     }
 
-    pub fn login(
-        &self,
+    /// Log in at once, blocking on the password hash
+    #[cfg(test)]
+    pub async fn login(
+        &mut self,
         username: impl AsRef<str>,
         password: impl AsRef<str>,
-    ) -> Result<JwtToken, Error> {
-        let user = self.get_user_by_username(username).ok_or_else(|| Error {
-            kind: ErrorKind::Unauthorized,
-            source: eyre!("Credential mismatch"),
-        })?;
-        Argon2::default()
-            .verify_password(
-                password.as_ref().as_bytes(),
-                &argon2::PasswordHash::new(user.hashed_psw.as_ref()).unwrap(),
-            )
-            .map_err(|_| Error {
+        ip: Option<String>,
+    ) -> Result<LoginOutcome, Error> {
+        let pending = self.start_login(username.as_ref(), ip.as_deref())?;
+        let verified = pending.verify(password.as_ref());
+        self.finish_login(pending, verified, ip).await
+    }
+
+    /// First step of logging in, only needs to read the users manager
+    pub fn start_login(&self, username: &str, ip: Option<&str>) -> Result<PendingLogin, Error> {
+        self.check_login_throttle(username, ip)?;
+        Ok(PendingLogin {
+            username: username.to_string(),
+            hashed_psw: self
+                .get_user_by_username(username)
+                .map(|user| user.hashed_psw),
+        })
+    }
+
+    /// Last step of logging in, with whether [`PendingLogin::verify`] accepted the password
+    pub async fn finish_login(
+        &mut self,
+        pending: PendingLogin,
+        verified: bool,
+        ip: Option<String>,
+    ) -> Result<LoginOutcome, Error> {
+        let username = pending.username.as_str();
+        // the password may have changed while it was being verified
+        let user = self.get_user_by_username(username).filter(|user| {
+            verified
+                && pending.hashed_psw.as_ref().map_or(false, |hashed_psw| {
+                    hashed_psw.as_ref() == user.hashed_psw.as_ref()
+                })
+        });
+        let Some(user) = user else {
+            self.record_failed_login(username, ip).await;
+            return Err(Error {
                 kind: ErrorKind::Unauthorized,
                 source: eyre!("Credential mismatch"),
-            })?;
-        user.create_jwt()
+            });
+        };
+        // failures are only forgiven once the second factor is passed too
+        if user.two_factor_enabled() {
+            return Ok(LoginOutcome::TwoFactorRequired(
+                user.create_two_factor_challenge()?,
+            ));
+        }
+        self.record_successful_login(username, ip.as_deref()).await;
+        let tokens = self.create_session(&user.uid, ip).await?;
+        Ok(LoginOutcome::LoggedIn(user, tokens))
     }
-}
 
-fn decode_token(token: &str, jwt_secret: &UserSecret) -> Option<UserId> {
-    match jsonwebtoken::decode::<Claim>(
-        token,
-        &jsonwebtoken::DecodingKey::from_secret(jwt_secret.as_ref().as_bytes()),
-        &Validation::new(Algorithm::HS512),
-    ) {
-        Ok(t) => Some(t.claims.uid),
-        Err(_) => None,
+    fn check_login_throttle(&self, username: &str, ip: Option<&str>) -> Result<(), Error> {
+        self.login_throttle
+            .check(username, ip, chrono::Utc::now().timestamp())
+            .map_err(|retry_after| Error {
+                kind: ErrorKind::TooManyRequests,
+                source: eyre!(
+                    "Too many failed login attempts, try again in {} seconds",
+                    retry_after
+                ),
+            })
     }
-}
 
-fn decode_no_verify(token: &str) -> Option<UserId> {
-    let mut no_verify = Validation::new(Algorithm::HS512);
-    no_verify.insecure_disable_signature_validation();
-    match jsonwebtoken::decode::<Claim>(
-        token,
-        &jsonwebtoken::DecodingKey::from_secret("noverify".as_bytes()),
-        &no_verify,
-    ) {
-        Ok(t) => Some(t.claims.uid),
-        Err(_) => None,
+    /// Count a failed login against the username and address, locking the account out
+    /// from that address if there were too many
+    async fn record_failed_login(&mut self, username: &str, ip: Option<String>) {
+        let locked_until = self.login_throttle.record_failure(
+            username,
+            ip.as_deref(),
+            chrono::Utc::now().timestamp(),
+        );
+        if let Err(e) = self.write_login_throttle_to_file().await {
+            warn!("Failed to save failed login attempts: {}", e);
+        }
+        let Some(uid) = self.get_user_by_username(username).map(|user| user.uid) else {
+            return;
+        };
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::UserEvent(UserEvent {
+                user_id: uid.clone(),
+                user_event_inner: UserEventInner::LoginFailed { ip: ip.clone() },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::Unknown,
+        });
+        if let Some(locked_until) = locked_until {
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::UserEvent(UserEvent {
+                    user_id: uid,
+                    user_event_inner: UserEventInner::AccountLocked { locked_until, ip },
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: CausedBy::System,
+            });
+        }
     }
-}
 
-impl AsRef<HashMap<UserId, User>> for UsersManager {
-    fn as_ref(&self) -> &HashMap<UserId, User> {
-        &self.users
+    async fn record_successful_login(&mut self, username: &str, ip: Option<&str>) {
+        if self.login_throttle.record_success(username, ip) {
+            if let Err(e) = self.write_login_throttle_to_file().await {
+                warn!("Failed to save failed login attempts: {}", e);
+            }
+        }
     }
-}
-
-mod tests {
 
-    #[tokio::test]
-    async fn test_login() {
-        use super::*;
-        // create a temporary folder
-        let temp_dir = tempdir::TempDir::new("test_login").unwrap().into_path();
-        let (tx, _rx) = EventBroadcaster::new(10);
-        let mut users_manager =
-            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
-        let test_user1 = User::new(
-            "test_user1".to_string(),
-            "12345",
+    /// Lift a lockout and forget the failed logins of a user
+    pub async fn unlock_user(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let username = self
+            .users
+            .get(uid.as_ref())
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("User id not found"),
+            })?
+            .username
+            .clone();
+        let was_locked = self
+            .login_throttle
+            .unlock(&username, chrono::Utc::now().timestamp());
+        self.write_login_throttle_to_file().await?;
+        if was_locked {
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::UserEvent(UserEvent {
+                    user_id: uid.as_ref().to_owned(),
+                    user_event_inner: UserEventInner::AccountUnlocked,
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by,
+            });
+        }
+        Ok(())
+    }
+
+    /// Second step of logging in, `code` is from the user's authenticator app or a recovery code
+    pub async fn login_two_factor(
+        &mut self,
+        challenge: &str,
+        code: &str,
+        ip: Option<String>,
+    ) -> Result<(User, SessionTokens), Error> {
+        let unauthorized = || Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Invalid or expired two-factor challenge"),
+        };
+        let mut no_verify = Validation::new(Algorithm::HS512);
+        no_verify.insecure_disable_signature_validation();
+        let claimed_uid = jsonwebtoken::decode::<TwoFactorClaim>(
+            challenge,
+            &jsonwebtoken::DecodingKey::from_secret("noverify".as_bytes()),
+            &no_verify,
+        )
+        .map_err(|_| unauthorized())?
+        .claims
+        .uid;
+        let username = self
+            .users
+            .get(&claimed_uid)
+            .ok_or_else(unauthorized)?
+            .username
+            .clone();
+        // the password was already checked, failures from other addresses don't hold the user back
+        self.login_throttle
+            .check_address(&username, ip.as_deref(), chrono::Utc::now().timestamp())
+            .map_err(|retry_after| Error {
+                kind: ErrorKind::TooManyRequests,
+                source: eyre!(
+                    "Too many failed login attempts, try again in {} seconds",
+                    retry_after
+                ),
+            })?;
+        let user = self.users.get_mut(&claimed_uid).ok_or_else(unauthorized)?;
+        let claim = jsonwebtoken::decode::<TwoFactorClaim>(
+            challenge,
+            &jsonwebtoken::DecodingKey::from_secret(user.secret.as_ref().as_bytes()),
+            &Validation::new(Algorithm::HS512),
+        )
+        .map_err(|_| unauthorized())?
+        .claims;
+        let now = chrono::Utc::now().timestamp() as usize;
+        self.two_factor_attempts.retain(|_, (exp, _)| *exp > now);
+        let (_, failures) = self
+            .two_factor_attempts
+            .entry(claim.challenge)
+            .or_insert((claim.exp, 0));
+        if *failures >= TWO_FACTOR_CHALLENGE_MAX_FAILURES {
+            return Err(unauthorized());
+        }
+        let two_factor = user
+            .two_factor
+            .as_mut()
+            .filter(|two_factor| two_factor.enabled)
+            .ok_or_else(unauthorized)?;
+        if !two_factor.verify(code) {
+            *failures += 1;
+            self.record_failed_login(&username, ip).await;
+            return Err(Error {
+                kind: ErrorKind::Unauthorized,
+                source: eyre!("Invalid two-factor code"),
+            });
+        }
+        *failures = TWO_FACTOR_CHALLENGE_MAX_FAILURES;
+        let user = user.clone();
+        self.record_successful_login(&username, ip.as_deref()).await;
+        // also persists the used up code
+        let tokens = self.create_session(&user.uid, ip).await?;
+        Ok((user, tokens))
+    }
+
+    /// Start enrolling an authenticator app, returns the shared secret and its provisioning URI
+    pub async fn enroll_two_factor(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        issuer: &str,
+    ) -> Result<(String, String), Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        if user.two_factor_enabled() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Two-factor authentication is already enabled"),
+            });
+        }
+        let two_factor = TwoFactor::new();
+        let secret = two_factor.secret().to_string();
+        let uri = provisioning_uri(issuer, &user.username, &secret);
+        let old_two_factor = user.two_factor.replace(two_factor);
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                user.two_factor = old_two_factor;
+            }
+            return Err(e);
+        }
+        Ok((secret, uri))
+    }
+
+    /// Finish enrolling with a code from the app, returns the recovery codes
+    pub async fn confirm_two_factor(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: &str,
+        caused_by: CausedBy,
+    ) -> Result<Vec<String>, Error> {
+        self.update_two_factor(uid, caused_by, |two_factor| {
+            let two_factor = two_factor
+                .as_mut()
+                .filter(|two_factor| !two_factor.enabled)
+                .ok_or_else(|| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("No two-factor enrollment in progress"),
+                })?;
+            if !two_factor.verify(code) {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid two-factor code"),
+                });
+            }
+            two_factor.enabled = true;
+            Ok(two_factor.regenerate_recovery_codes())
+        })
+        .await
+    }
+
+    pub async fn regenerate_recovery_codes(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: &str,
+        caused_by: CausedBy,
+    ) -> Result<Vec<String>, Error> {
+        self.update_two_factor(uid, caused_by, |two_factor| {
+            let two_factor = two_factor
+                .as_mut()
+                .filter(|two_factor| two_factor.enabled)
+                .ok_or_else(|| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Two-factor authentication is not enabled"),
+                })?;
+            if !two_factor.verify(code) {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid two-factor code"),
+                });
+            }
+            Ok(two_factor.regenerate_recovery_codes())
+        })
+        .await
+    }
+
+    /// Turn off two-factor authentication, `code` is required unless another user resets it
+    pub async fn disable_two_factor(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        code: Option<&str>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let required = self.require_two_factor_for_unsafe
+            && self.users.get(uid.as_ref()).map_or(false, |user| {
+                user.is_owner || user.effective_permissions().has_unsafe_permission()
+            });
+        self.update_two_factor(uid, caused_by, |two_factor| {
+            if let Some(code) = code {
+                if required {
+                    return Err(Error {
+                        kind: ErrorKind::PermissionDenied,
+                        source: eyre!("Two-factor authentication is required for your permissions"),
+                    });
+                }
+                if !two_factor
+                    .as_mut()
+                    .map_or(false, |two_factor| two_factor.verify(code))
+                {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Invalid two-factor code"),
+                    });
+                }
+            }
+            *two_factor = None;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Apply `f` to a user's two-factor state and persist it, rolling back on error
+    async fn update_two_factor<T>(
+        &mut self,
+        uid: impl AsRef<UserId>,
+        caused_by: CausedBy,
+        f: impl FnOnce(&mut Option<TwoFactor>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let user = self.users.get_mut(uid.as_ref()).ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("User id not found"),
+        })?;
+        let was_enabled = user.two_factor_enabled();
+        let old_two_factor = user.two_factor.clone();
+        let ret = match f(&mut user.two_factor) {
+            Ok(ret) => ret,
+            Err(e) => {
+                user.two_factor = old_two_factor;
+                return Err(e);
+            }
+        };
+        let is_enabled = user.two_factor_enabled();
+        if let Err(e) = self.write_to_file().await {
+            if let Some(user) = self.users.get_mut(uid.as_ref()) {
+                user.two_factor = old_two_factor;
+            }
+            return Err(e);
+        }
+        if was_enabled != is_enabled {
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::UserEvent(UserEvent {
+                    user_id: uid.as_ref().to_owned(),
+                    user_event_inner: if is_enabled {
+                        UserEventInner::TwoFactorEnabled
+                    } else {
+                        UserEventInner::TwoFactorDisabled
+                    },
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by,
+            });
+        }
+        Ok(ret)
+    }
+}
+
+fn decode_token(token: &str, jwt_secret: &UserSecret) -> Option<Claim> {
+    match jsonwebtoken::decode::<Claim>(
+        token,
+        &jsonwebtoken::DecodingKey::from_secret(jwt_secret.as_ref().as_bytes()),
+        &Validation::new(Algorithm::HS512),
+    ) {
+        Ok(t) => Some(t.claims),
+        Err(_) => None,
+    }
+}
+
+fn decode_no_verify(token: &str) -> Option<Claim> {
+    let mut no_verify = Validation::new(Algorithm::HS512);
+    no_verify.insecure_disable_signature_validation();
+    match jsonwebtoken::decode::<Claim>(
+        token,
+        &jsonwebtoken::DecodingKey::from_secret("noverify".as_bytes()),
+        &no_verify,
+    ) {
+        Ok(t) => Some(t.claims),
+        Err(_) => None,
+    }
+}
+
+impl AsRef<HashMap<UserId, User>> for UsersManager {
+    fn as_ref(&self) -> &HashMap<UserId, User> {
+        &self.users
+    }
+}
+
+mod tests {
+
+    #[tokio::test]
+    async fn test_login() {
+        use super::*;
+        // create a temporary folder
+        let temp_dir = tempdir::TempDir::new("test_login").unwrap().into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let test_user1 = User::new(
+            "test_user1".to_string(),
+            "12345",
             true,
             false,
             UserPermission::default(),
@@ -766,7 +2045,57 @@ mod tests {
             .await
             .unwrap();
 
-        users_manager.login("test_user1", "12345").unwrap();
+        users_manager
+            .login("test_user1", "12345", None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_login_throttle() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_login_throttle")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let test_user1 = User::new(
+            "test_user1".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(test_user1.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        let ip = Some("10.0.0.1".to_string());
+        for _ in 0..3 {
+            let Err(e) = users_manager.login("test_user1", "wrong", ip.clone()).await else {
+                panic!("wrong password accepted");
+            };
+            assert!(matches!(e.kind, ErrorKind::Unauthorized));
+        }
+        // even the right password has to wait, also after a restart
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        users_manager.load_users().await.unwrap();
+        let Err(e) = users_manager.login("test_user1", "12345", None).await else {
+            panic!("throttled login accepted");
+        };
+        assert!(matches!(e.kind, ErrorKind::TooManyRequests));
+
+        users_manager
+            .unlock_user(&test_user1.uid, CausedBy::System)
+            .await
+            .unwrap();
+        assert!(users_manager
+            .login("test_user1", "12345", None)
+            .await
+            .is_ok());
     }
 
     #[tokio::test]
@@ -790,7 +2119,10 @@ mod tests {
             .await
             .unwrap();
 
-        users_manager.login("test_user1", "12345").unwrap();
+        users_manager
+            .login("test_user1", "12345", None)
+            .await
+            .unwrap();
 
         users_manager
             .change_password(
@@ -802,7 +2134,502 @@ mod tests {
             .await
             .unwrap();
 
-        users_manager.login("test_user1", "54321").unwrap();
+        users_manager
+            .login("test_user1", "54321", None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_sessions").unwrap().into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let test_user1 = User::new(
+            "test_user1".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        users_manager
+            .add_user(test_user1.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        let Ok(LoginOutcome::LoggedIn(_, phone)) = users_manager
+            .login("test_user1", "12345", Some("10.0.0.2".to_string()))
+            .await
+        else {
+            panic!("Login failed");
+        };
+        let Ok(LoginOutcome::LoggedIn(_, laptop)) =
+            users_manager.login("test_user1", "12345", None).await
+        else {
+            panic!("Login failed");
+        };
+        let requester = users_manager.try_auth(laptop.token.as_ref()).unwrap();
+        let sessions = users_manager.list_sessions(&requester).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions.iter().filter(|s| s.is_current).count(), 1);
+
+        // a refresh token can only be used once
+        let (_, refreshed) = users_manager
+            .refresh_session(phone.refresh_token.as_ref(), None)
+            .await
+            .unwrap();
+        assert!(users_manager
+            .refresh_session(phone.refresh_token.as_ref(), None)
+            .await
+            .is_err());
+        let phone_user = users_manager.try_auth(refreshed.token.as_ref()).unwrap();
+        assert!(users_manager
+            .try_auth(refreshed.refresh_token.as_ref())
+            .is_none());
+
+        // revoking a session only signs out that session
+        users_manager
+            .revoke_session(
+                &test_user1.uid,
+                phone_user.session_id.unwrap(),
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+        assert!(users_manager.try_auth(refreshed.token.as_ref()).is_none());
+        assert!(users_manager.refresh_user(&phone_user).is_none());
+        assert!(users_manager.try_auth(laptop.token.as_ref()).is_some());
+
+        users_manager
+            .sign_out_everywhere(false, CausedBy::System)
+            .await
+            .unwrap();
+        assert!(users_manager.try_auth(laptop.token.as_ref()).is_none());
+        assert!(users_manager
+            .refresh_session(laptop.refresh_token.as_ref(), None)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_two_factor_login() {
+        use super::*;
+        use crate::auth::totp::{code_at_step, current_step};
+        let temp_dir = tempdir::TempDir::new("test_two_factor_login")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let mut permissions = UserPermission::default();
+        permissions
+            .can_write_instance_file
+            .insert(InstanceUuid::default());
+        let test_user1 = User::new("test_user1".to_string(), "12345", false, false, permissions);
+        users_manager
+            .add_user(test_user1.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        // unsafe permissions are withheld until two-factor is set up
+        users_manager.set_two_factor_policy(true);
+        let Ok(LoginOutcome::LoggedIn(_, tokens)) =
+            users_manager.login("test_user1", "12345", None).await
+        else {
+            panic!("Login failed");
+        };
+        let requester = users_manager.try_auth(tokens.token.as_ref()).unwrap();
+        assert!(!requester.permissions.has_unsafe_permission());
+
+        let (secret, uri) = users_manager
+            .enroll_two_factor(&test_user1.uid, "Lodestone")
+            .await
+            .unwrap();
+        assert!(uri.starts_with("otpauth://totp/Lodestone:test_user1?secret="));
+        assert!(users_manager
+            .confirm_two_factor(&test_user1.uid, "000000", CausedBy::System)
+            .await
+            .is_err());
+        let recovery_codes = users_manager
+            .confirm_two_factor(
+                &test_user1.uid,
+                &code_at_step(&secret, current_step()).unwrap(),
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+        let requester = users_manager.try_auth(tokens.token.as_ref()).unwrap();
+        assert!(requester.permissions.has_unsafe_permission());
+
+        let Ok(LoginOutcome::TwoFactorRequired(challenge)) =
+            users_manager.login("test_user1", "12345", None).await
+        else {
+            panic!("Two-factor authentication was not required");
+        };
+        assert!(users_manager.try_auth(challenge.as_ref()).is_none());
+        assert!(users_manager
+            .login_two_factor(challenge.as_ref(), "000000", None)
+            .await
+            .is_err());
+        users_manager
+            .login_two_factor(challenge.as_ref(), &recovery_codes[0], None)
+            .await
+            .unwrap();
+        // challenges are single use
+        assert!(users_manager
+            .login_two_factor(challenge.as_ref(), &recovery_codes[2], None)
+            .await
+            .is_err());
+        // and so are recovery codes
+        let Ok(LoginOutcome::TwoFactorRequired(challenge)) =
+            users_manager.login("test_user1", "12345", None).await
+        else {
+            panic!("Two-factor authentication was not required");
+        };
+        assert!(users_manager
+            .login_two_factor(challenge.as_ref(), &recovery_codes[0], None)
+            .await
+            .is_err());
+        users_manager
+            .login_two_factor(challenge.as_ref(), &recovery_codes[2], None)
+            .await
+            .unwrap();
+
+        // users can't opt out while they hold unsafe permissions
+        assert!(users_manager
+            .disable_two_factor(&test_user1.uid, Some(&recovery_codes[1]), CausedBy::System)
+            .await
+            .is_err());
+        users_manager
+            .disable_two_factor(&test_user1.uid, None, CausedBy::System)
+            .await
+            .unwrap();
+        assert!(!users_manager
+            .get_user(&test_user1.uid)
+            .unwrap()
+            .two_factor_enabled());
+    }
+
+    #[tokio::test]
+    async fn test_api_token() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_api_token").unwrap().into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let instance = InstanceUuid::default();
+        let other_instance = InstanceUuid::default();
+        let mut permissions = UserPermission::default();
+        permissions.can_start_instance.insert(instance.clone());
+        permissions.can_stop_instance.insert(instance.clone());
+        let test_user1 = User::new("test_user1".to_string(), "12345", false, false, permissions);
+        users_manager
+            .add_user(test_user1.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        let mut token_permissions = UserPermission::default();
+        token_permissions
+            .can_start_instance
+            .insert(instance.clone());
+        token_permissions
+            .can_start_instance
+            .insert(other_instance.clone());
+        let api_token = ApiToken::new("ci".to_string(), token_permissions, None);
+        let token = api_token.create_jwt(&test_user1.uid).unwrap();
+        users_manager
+            .add_api_token(&test_user1.uid, api_token.clone(), CausedBy::System)
+            .await
+            .unwrap();
+
+        // the token is capped by both its own and the user's permissions
+        let requester = users_manager.try_auth(token.as_ref()).unwrap();
+        assert_eq!(requester.api_token_id, Some(api_token.id));
+        assert!(requester.can_perform_action(&UserAction::StartInstance(instance.clone())));
+        assert!(!requester.can_perform_action(&UserAction::StopInstance(instance.clone())));
+        assert!(!requester.can_perform_action(&UserAction::StartInstance(other_instance)));
+        assert!(requester.try_manage_account().is_err());
+        assert!(users_manager.list_api_tokens(&test_user1.uid).unwrap()[0]
+            .last_used
+            .is_some());
+
+        // a token signed for another token id is rejected
+        let forged = ApiToken::new("forged".to_string(), UserPermission::default(), None)
+            .create_jwt(&test_user1.uid)
+            .unwrap();
+        assert!(users_manager.try_auth(forged.as_ref()).is_none());
+
+        users_manager
+            .revoke_api_token(&test_user1.uid, api_token.id, CausedBy::System)
+            .await
+            .unwrap();
+        assert!(users_manager.try_auth(token.as_ref()).is_none());
+        assert!(users_manager.refresh_user(&requester).is_none());
+
+        let expired = ApiToken::new(
+            "expired".to_string(),
+            UserPermission::default(),
+            Some(chrono::Utc::now().timestamp() - 1),
+        );
+        let token = expired.create_jwt(&test_user1.uid).unwrap();
+        users_manager
+            .add_api_token(&test_user1.uid, expired, CausedBy::System)
+            .await
+            .unwrap();
+        assert!(users_manager.try_auth(token.as_ref()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_roles() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_roles").unwrap().into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let instance = InstanceUuid::default();
+        let mut permissions = UserPermission::default();
+        permissions.can_stop_instance.insert(instance.clone());
+        let test_user1 = User::new("test_user1".to_string(), "12345", false, false, permissions);
+        users_manager
+            .add_user(test_user1.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+
+        // the wildcard also covers instances created later
+        let mut role_permissions = UserPermission::default();
+        role_permissions
+            .can_view_instance
+            .insert(InstanceUuid::wildcard());
+        role_permissions.can_start_instance.insert(instance.clone());
+        let role = users_manager
+            .create_role(&owner, "operator".to_string(), role_permissions.clone())
+            .await
+            .unwrap();
+        assert!(users_manager
+            .create_role(&owner, "operator".to_string(), UserPermission::default())
+            .await
+            .is_err());
+        users_manager
+            .assign_roles(
+                &owner,
+                &test_user1.uid,
+                HashSet::from([role.id]),
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+
+        let user = users_manager.get_user(&test_user1.uid).unwrap();
+        assert!(user.can_perform_action(&UserAction::ViewInstance(InstanceUuid::default())));
+        assert!(user.can_perform_action(&UserAction::StartInstance(instance.clone())));
+        assert!(user.can_perform_action(&UserAction::StopInstance(instance.clone())));
+        assert!(!user.can_perform_action(&UserAction::StartInstance(InstanceUuid::default())));
+        assert!(user.event_visibility().all_instances);
+
+        // roles are resolved again after a restart
+        let mut reloaded =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        reloaded.load_users().await.unwrap();
+        let user = reloaded.get_user(&test_user1.uid).unwrap();
+        assert!(user.can_perform_action(&UserAction::StartInstance(instance.clone())));
+
+        // changing a role changes the permissions of its holders
+        role_permissions.can_start_instance.clear();
+        users_manager
+            .update_role(
+                &owner,
+                role.id,
+                "operator".to_string(),
+                role_permissions,
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+        let user = users_manager.get_user(&test_user1.uid).unwrap();
+        assert!(!user.can_perform_action(&UserAction::StartInstance(instance.clone())));
+        assert!(user.can_perform_action(&UserAction::ViewInstance(instance.clone())));
+
+        users_manager
+            .delete_role(&owner, role.id, CausedBy::System)
+            .await
+            .unwrap();
+        let user = users_manager.get_user(&test_user1.uid).unwrap();
+        assert!(user.roles.is_empty());
+        assert!(!user.can_perform_action(&UserAction::ViewInstance(instance.clone())));
+        // the user's own grants are untouched
+        assert!(user.can_perform_action(&UserAction::StopInstance(instance)));
+    }
+
+    #[tokio::test]
+    async fn test_role_escalation() {
+        use super::*;
+        let temp_dir = tempdir::TempDir::new("test_role_escalation")
+            .unwrap()
+            .into_path();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let mut users_manager =
+            UsersManager::new(tx.clone(), HashMap::new(), temp_dir.join("users.json"));
+        let owner = User::new(
+            "owner".to_string(),
+            "12345",
+            true,
+            false,
+            UserPermission::default(),
+        );
+        let admin = User::new(
+            "admin".to_string(),
+            "12345",
+            false,
+            true,
+            UserPermission::default(),
+        );
+        let other_admin = User::new(
+            "other_admin".to_string(),
+            "12345",
+            false,
+            true,
+            UserPermission::default(),
+        );
+        let mut permissions = UserPermission::default();
+        permissions.can_manage_permission = true;
+        let manager = User::new("manager".to_string(), "12345", false, false, permissions);
+        let test_user1 = User::new(
+            "test_user1".to_string(),
+            "12345",
+            false,
+            false,
+            UserPermission::default(),
+        );
+        for user in [&admin, &other_admin, &manager, &test_user1] {
+            users_manager
+                .add_user(user.clone(), CausedBy::System)
+                .await
+                .unwrap();
+        }
+
+        let mut viewer_permissions = UserPermission::default();
+        viewer_permissions
+            .can_view_instance
+            .insert(InstanceUuid::wildcard());
+        let viewer = users_manager
+            .create_role(&owner, "viewer".to_string(), viewer_permissions.clone())
+            .await
+            .unwrap();
+        let mut installer_permissions = UserPermission::default();
+        installer_permissions.can_install_extension = true;
+        let installer = users_manager
+            .create_role(
+                &owner,
+                "installer".to_string(),
+                installer_permissions.clone(),
+            )
+            .await
+            .unwrap();
+        // roles can't hand out what their creator doesn't hold
+        assert!(users_manager
+            .create_role(&admin, "installer2".to_string(), installer_permissions)
+            .await
+            .is_err());
+
+        // admins can view every instance, so they can hand that out to users below them
+        users_manager
+            .assign_roles(
+                &admin,
+                &test_user1.uid,
+                HashSet::from([viewer.id]),
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+        // but not to themselves
+        assert!(users_manager
+            .assign_roles(
+                &admin,
+                &admin.uid,
+                HashSet::from([viewer.id]),
+                CausedBy::System,
+            )
+            .await
+            .is_err());
+        assert!(users_manager
+            .assign_roles(
+                &manager,
+                &manager.uid,
+                HashSet::from([installer.id]),
+                CausedBy::System,
+            )
+            .await
+            .is_err());
+        // nor to users of the same or a higher level
+        assert!(users_manager
+            .assign_roles(
+                &admin,
+                &other_admin.uid,
+                HashSet::from([viewer.id]),
+                CausedBy::System,
+            )
+            .await
+            .is_err());
+        assert!(users_manager
+            .assign_roles(
+                &manager,
+                &admin.uid,
+                HashSet::from([viewer.id]),
+                CausedBy::System,
+            )
+            .await
+            .is_err());
+        // and only roles whose permissions they hold
+        assert!(users_manager
+            .assign_roles(
+                &admin,
+                &test_user1.uid,
+                HashSet::from([viewer.id, installer.id]),
+                CausedBy::System,
+            )
+            .await
+            .is_err());
+
+        // changing a role changes its holders, the requester has to outrank all of them
+        assert!(users_manager
+            .update_role(
+                &manager,
+                viewer.id,
+                "viewer".to_string(),
+                UserPermission::default(),
+                CausedBy::System,
+            )
+            .await
+            .is_err());
+        assert!(users_manager
+            .delete_role(&manager, viewer.id, CausedBy::System)
+            .await
+            .is_err());
+        users_manager
+            .update_role(
+                &admin,
+                viewer.id,
+                "viewer".to_string(),
+                UserPermission::default(),
+                CausedBy::System,
+            )
+            .await
+            .unwrap();
+        users_manager
+            .delete_role(&admin, viewer.id, CausedBy::System)
+            .await
+            .unwrap();
+        let user = users_manager.get_user(&test_user1.uid).unwrap();
+        assert!(user.roles.is_empty());
     }
 
     #[tokio::test]
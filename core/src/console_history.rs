@@ -0,0 +1,516 @@
+//! Console history of an instance, made of its stored `InstanceOutput` events and,
+//! for the time before the oldest of those, the gzipped log files the server keeps in `logs/`
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike};
+use color_eyre::eyre::eyre;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::{
+    db::{
+        read::{get_console_output, get_console_output_start},
+        types::ConsoleLine,
+    },
+    error::{Error, ErrorKind},
+    types::{InstanceUuid, Snowflake},
+};
+
+pub const DEFAULT_CONTEXT_LINES: usize = 2;
+pub const MAX_CONTEXT_LINES: usize = 20;
+pub const DEFAULT_SEARCH_LIMIT: usize = 100;
+pub const MAX_SEARCH_LIMIT: usize = 1000;
+
+#[derive(Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ConsoleSearchQuery {
+    pub query: String,
+    /// treat `query` as a regular expression instead of a substring
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    /// unix millis, inclusive
+    pub start: Option<i64>,
+    /// unix millis, inclusive
+    pub end: Option<i64>,
+    /// lines shown before and after each hit, defaults to 2
+    pub context: Option<usize>,
+    /// defaults to 100
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ConsoleSearchHit {
+    pub line: ConsoleLine,
+    pub before: Vec<ConsoleLine>,
+    pub after: Vec<ConsoleLine>,
+}
+
+#[derive(Serialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ConsoleSearchResult {
+    /// the most recent hits, oldest first
+    pub hits: Vec<ConsoleSearchHit>,
+    /// whether the search stopped at the limit before reaching the start of the history
+    pub truncated: bool,
+}
+
+enum Matcher {
+    Substring {
+        needle: String,
+        case_sensitive: bool,
+    },
+    Regex(fancy_regex::Regex),
+}
+
+impl Matcher {
+    fn new(query: &ConsoleSearchQuery) -> Result<Self, Error> {
+        if query.regex {
+            let pattern = if query.case_sensitive {
+                query.query.clone()
+            } else {
+                format!("(?i){}", query.query)
+            };
+            fancy_regex::Regex::new(&pattern)
+                .map(Matcher::Regex)
+                .map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid regex: {}", e),
+                })
+        } else {
+            Ok(Matcher::Substring {
+                needle: if query.case_sensitive {
+                    query.query.clone()
+                } else {
+                    query.query.to_lowercase()
+                },
+                case_sensitive: query.case_sensitive,
+            })
+        }
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        match self {
+            Matcher::Substring {
+                needle,
+                case_sensitive: true,
+            } => line.contains(needle.as_str()),
+            Matcher::Substring { needle, .. } => line.to_lowercase().contains(needle.as_str()),
+            // a line too costly to match is not a hit
+            Matcher::Regex(regex) => regex.is_match(line).unwrap_or(false),
+        }
+    }
+}
+
+/// The most recent hits of `query` in `lines`, which are newest first, with the lines around them.
+///
+/// Reading `lines` stops once the limit is reached.
+pub fn search(
+    lines: impl Iterator<Item = Result<ConsoleLine, Error>>,
+    query: &ConsoleSearchQuery,
+) -> Result<ConsoleSearchResult, Error> {
+    let matcher = Matcher::new(query)?;
+    let context = query
+        .context
+        .unwrap_or(DEFAULT_CONTEXT_LINES)
+        .min(MAX_CONTEXT_LINES);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .min(MAX_SEARCH_LIMIT);
+    let mut lines = lines.peekable();
+    // newest first, `before` is nearest first until the end
+    let mut hits: Vec<ConsoleSearchHit> = Vec::new();
+    // the lines read last, which follow the next hit
+    let mut newer: VecDeque<ConsoleLine> = VecDeque::with_capacity(context + 1);
+    let mut truncated = false;
+    while let Some(line) = lines.next() {
+        let line = line?;
+        // the latest hits may still miss lines before them
+        for hit in hits
+            .iter_mut()
+            .rev()
+            .take_while(|hit| hit.before.len() < context)
+        {
+            hit.before.push(line.clone());
+        }
+        if matcher.is_match(&line.line) {
+            if hits.len() == limit {
+                truncated = true;
+            } else {
+                hits.push(ConsoleSearchHit {
+                    line: line.clone(),
+                    before: Vec::new(),
+                    after: newer.iter().rev().cloned().collect(),
+                });
+            }
+        }
+        // stop once the last hit has all lines before it
+        if hits.len() == limit && hits.last().map_or(true, |hit| hit.before.len() == context) {
+            truncated |= lines.peek().is_some();
+            break;
+        }
+        newer.push_back(line);
+        if newer.len() > context {
+            newer.pop_front();
+        }
+    }
+    hits.reverse();
+    for hit in hits.iter_mut() {
+        hit.before.reverse();
+    }
+    Ok(ConsoleSearchResult { hits, truncated })
+}
+
+/// The console history of an instance between `start` and `end` (unix millis, inclusive),
+/// read lazily so a search or an export only reads as far as it needs.
+///
+/// Stored output is fetched by blocking on the database, iterate it in `spawn_blocking`.
+pub struct ConsoleHistory {
+    runtime: tokio::runtime::Handle,
+    pool: SqlitePool,
+    instance_id: InstanceUuid,
+    start: Option<i64>,
+    end: Option<i64>,
+    newest_first: bool,
+    logs_dir: PathBuf,
+    /// log files only fill in what is no longer, or never was, in the database
+    log_end: Option<i64>,
+    /// log files left to read, the next one last, `None` until they are listed
+    log_files: Option<Vec<(NaiveDate, PathBuf)>>,
+    /// cursor of the next page of stored output, `None` once all of it was read
+    cursor: Option<Option<Snowflake>>,
+    /// lines read but not yielded yet
+    buffer: VecDeque<ConsoleLine>,
+}
+
+impl ConsoleHistory {
+    pub async fn new(
+        pool: SqlitePool,
+        instance_id: InstanceUuid,
+        instance_path: &Path,
+        start: Option<i64>,
+        end: Option<i64>,
+        newest_first: bool,
+    ) -> Result<Self, Error> {
+        let stored_since = get_console_output_start(&pool, &instance_id).await?;
+        let log_end = [end, stored_since.map(|stored_since| stored_since - 1)]
+            .into_iter()
+            .flatten()
+            .min();
+        Ok(Self {
+            runtime: tokio::runtime::Handle::current(),
+            pool,
+            instance_id,
+            start,
+            end,
+            newest_first,
+            logs_dir: instance_path.join("logs"),
+            log_end,
+            log_files: None,
+            cursor: Some(None),
+            buffer: VecDeque::new(),
+        })
+    }
+
+    /// Returns whether there was a page left
+    fn read_stored_page(&mut self) -> Result<bool, Error> {
+        let Some(cursor) = self.cursor.take() else {
+            return Ok(false);
+        };
+        let (lines, next_cursor) = self.runtime.block_on(get_console_output(
+            &self.pool,
+            &self.instance_id,
+            self.start,
+            self.end,
+            cursor,
+            self.newest_first,
+        ))?;
+        self.cursor = next_cursor.map(Some);
+        self.buffer.extend(lines);
+        Ok(true)
+    }
+
+    /// Returns whether there was a log file left
+    fn read_next_log_file(&mut self) -> bool {
+        let newest_first = self.newest_first;
+        let log_files = self.log_files.get_or_insert_with(|| {
+            let mut log_files = log_files_between(&self.logs_dir, self.start, self.log_end);
+            if !newest_first {
+                log_files.reverse();
+            }
+            log_files
+        });
+        let Some((date, path)) = log_files.pop() else {
+            return false;
+        };
+        let mut lines = read_log_lines(&path, date, self.start, self.log_end);
+        if newest_first {
+            lines.reverse();
+        }
+        self.buffer.extend(lines);
+        true
+    }
+}
+
+impl Iterator for ConsoleHistory {
+    type Item = Result<ConsoleLine, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.buffer.pop_front() {
+                return Some(Ok(line));
+            }
+            // in time, the log files come before the stored output
+            let read = if self.newest_first {
+                self.read_stored_page()
+                    .map(|read| read || self.read_next_log_file())
+            } else if self.read_next_log_file() {
+                Ok(true)
+            } else {
+                self.read_stored_page()
+            };
+            match read {
+                Ok(true) => continue,
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// `2023-04-01-2.log.gz` is the second log started on the 1st of April 2023
+fn parse_log_file_name(name: &str) -> Option<(NaiveDate, u32)> {
+    let stem = name.strip_suffix(".log.gz")?;
+    let date = NaiveDate::parse_from_str(stem.get(..10)?, "%Y-%m-%d").ok()?;
+    let index = stem.get(10..)?.strip_prefix('-')?.parse().ok()?;
+    Some((date, index))
+}
+
+/// The time of day a line was logged at, lines start with `[12:34:56]` or `[12:34:56 INFO]`
+fn parse_line_time(line: &str) -> Option<NaiveTime> {
+    if !line.starts_with('[') {
+        return None;
+    }
+    NaiveTime::parse_from_str(line.get(1..9)?, "%H:%M:%S").ok()
+}
+
+/// Log files record the server's local time
+fn local_millis(time: NaiveDateTime) -> i64 {
+    chrono::Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map(|time| time.timestamp_millis())
+        .unwrap_or_else(|| time.timestamp_millis())
+}
+
+/// The log files that may hold lines between `start` and `end`, oldest first
+fn log_files_between(
+    dir: &Path,
+    start: Option<i64>,
+    end: Option<i64>,
+) -> Vec<(NaiveDate, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut log_files: Vec<(NaiveDate, u32, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let (date, index) = parse_log_file_name(&entry.file_name().to_string_lossy())?;
+            Some((date, index, entry.path()))
+        })
+        .collect();
+    log_files.sort();
+    let midnight = |date: NaiveDate| local_millis(date.and_hms_opt(0, 0, 0).unwrap());
+    let mut between = Vec::new();
+    for (i, (date, _, path)) in log_files.iter().enumerate() {
+        if end.map_or(false, |end| midnight(*date) > end) {
+            break;
+        }
+        // a log ends before the next one starts, at the latest by the end of that day
+        let ends_before = log_files
+            .get(i + 1)
+            .and_then(|(next_date, _, _)| next_date.succ_opt())
+            .map(midnight);
+        if let (Some(start), Some(ends_before)) = (start, ends_before) {
+            if ends_before < start {
+                continue;
+            }
+        }
+        between.push((*date, path.clone()));
+    }
+    between
+}
+
+/// The lines of a log file between `start` and `end`, oldest first
+fn read_log_lines(
+    path: &Path,
+    date: NaiveDate,
+    start: Option<i64>,
+    end: Option<i64>,
+) -> Vec<ConsoleLine> {
+    match read_log_file(path, date) {
+        Ok(lines) => lines
+            .into_iter()
+            .filter(|line| {
+                start.map_or(true, |start| line.time >= start)
+                    && end.map_or(true, |end| line.time <= end)
+            })
+            .collect(),
+        Err(e) => {
+            warn!("Failed to read log file {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+fn read_log_file(path: &Path, date: NaiveDate) -> std::io::Result<Vec<ConsoleLine>> {
+    let log_file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string());
+    let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    let mut day = date;
+    let mut last_time = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+    let mut lines = Vec::new();
+    for line in reader.split(b'\n') {
+        let line = String::from_utf8_lossy(&line?)
+            .trim_end_matches('\r')
+            .to_string();
+        // lines without a time, like stack traces, belong to the line before
+        if let Some(time) = parse_line_time(&line) {
+            // a server running past midnight keeps writing to the same log
+            if time.num_seconds_from_midnight() + 12 * 60 * 60
+                < last_time.num_seconds_from_midnight()
+            {
+                day = day.succ_opt().unwrap_or(day);
+            }
+            last_time = time;
+        }
+        lines.push(ConsoleLine {
+            time: local_millis(day.and_time(last_time)),
+            line,
+            log_file: log_file.clone(),
+        });
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn console_line(line: &str) -> ConsoleLine {
+        ConsoleLine {
+            time: 0,
+            line: line.to_string(),
+            log_file: None,
+        }
+    }
+
+    fn search_query(query: &str) -> ConsoleSearchQuery {
+        ConsoleSearchQuery {
+            query: query.to_string(),
+            regex: false,
+            case_sensitive: false,
+            start: None,
+            end: None,
+            context: Some(1),
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_log_file_name() {
+        assert_eq!(
+            parse_log_file_name("2023-04-01-2.log.gz"),
+            Some((NaiveDate::from_ymd_opt(2023, 4, 1).unwrap(), 2))
+        );
+        assert_eq!(parse_log_file_name("latest.log"), None);
+        assert_eq!(parse_log_file_name("debug-1.log.gz"), None);
+    }
+
+    fn read_log_files(dir: &Path, start: Option<i64>, end: Option<i64>) -> Vec<ConsoleLine> {
+        log_files_between(dir, start, end)
+            .into_iter()
+            .flat_map(|(date, path)| read_log_lines(&path, date, start, end))
+            .collect()
+    }
+
+    #[test]
+    fn test_read_log_files() {
+        let temp_dir = tempdir::TempDir::new("test_read_log_files").unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(temp_dir.path().join("2023-04-01-1.log.gz")).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder
+            .write_all(
+                b"[23:59:58] [Server thread/INFO]: Done\n\tat stack\n[00:00:01 WARN]: Late\n",
+            )
+            .unwrap();
+        encoder.finish().unwrap();
+
+        let lines = read_log_files(temp_dir.path(), None, None);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].line, "\tat stack");
+        assert_eq!(lines[0].time, lines[1].time);
+        assert_eq!(lines[2].time - lines[0].time, 3000);
+        assert_eq!(lines[2].log_file.as_deref(), Some("2023-04-01-1.log.gz"));
+
+        let lines = read_log_files(temp_dir.path(), Some(lines[2].time), None);
+        assert_eq!(lines.len(), 1);
+        assert!(read_log_files(temp_dir.path(), None, Some(0)).is_empty());
+    }
+
+    #[test]
+    fn test_search() {
+        let lines: Vec<ConsoleLine> = ["a", "Player joined", "b", "c", "player left"]
+            .into_iter()
+            .map(console_line)
+            .collect();
+        // searched newest first
+        let newest_first = || lines.iter().rev().cloned().map(Ok);
+        let result = search(newest_first(), &search_query("PLAYER")).unwrap();
+        assert_eq!(result.hits.len(), 2);
+        assert_eq!(result.hits[0].before[0].line, "a");
+        assert_eq!(result.hits[0].after[0].line, "b");
+        assert!(result.hits[1].after.is_empty());
+        assert!(!result.truncated);
+
+        let query = ConsoleSearchQuery {
+            case_sensitive: true,
+            ..search_query("Player")
+        };
+        assert_eq!(search(newest_first(), &query).unwrap().hits.len(), 1);
+
+        let query = ConsoleSearchQuery {
+            regex: true,
+            limit: Some(1),
+            ..search_query("^(b|c)$")
+        };
+        let result = search(newest_first(), &query).unwrap();
+        assert_eq!(result.hits.len(), 1);
+        assert_eq!(result.hits[0].line.line, "c");
+        assert_eq!(result.hits[0].before[0].line, "b");
+        assert!(result.truncated);
+
+        let query = ConsoleSearchQuery {
+            regex: true,
+            ..search_query("(")
+        };
+        assert!(search(newest_first(), &query).is_err());
+    }
+}
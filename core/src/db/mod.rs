@@ -1,3 +1,4 @@
 pub mod read;
+pub mod retention;
 pub mod types;
 pub mod write;
@@ -1,156 +1,601 @@
 use crate::{
-    error::Error, events::EventQuery, output_types::ClientEvent, prelude::LODESTONE_EPOCH_MIL,
+    error::Error,
+    events::{EventInner, EventQuery, EventType, InstanceEventInner, InstanceEventKind},
+    output_types::ClientEvent,
+    types::{InstanceUuid, Snowflake},
 };
 
 use color_eyre::eyre::Context;
-use sqlx::sqlite::SqlitePool;
+use sqlx::{sqlite::SqlitePool, Encode, QueryBuilder, Row, Sqlite, Type};
 use tracing::error;
 
+use super::types::{
+    variant_name, ConsoleLine, EventSearchPage, EventVisibility, PlayerSession, PlayerStats,
+    PlayerStatsReport,
+};
+
 // TODO clean up all unwraps
 
+/// Upper bound on the page size of `search_events`
+pub const MAX_SEARCH_LIMIT: u32 = 1000;
+
+/// `column IN (values...)`, never true if `values` is empty
+fn push_in<'a, T>(
+    builder: &mut QueryBuilder<'a, Sqlite>,
+    column: &str,
+    values: impl IntoIterator<Item = T>,
+) where
+    T: 'a + Encode<'a, Sqlite> + Send + Type<Sqlite>,
+{
+    builder.push(column).push(" IN (");
+    let mut separated = builder.separated(", ");
+    let mut empty = true;
+    for value in values {
+        separated.push_bind(value);
+        empty = false;
+    }
+    if empty {
+        separated.push("NULL");
+    }
+    builder.push(")");
+}
+
+fn push_event_type(builder: &mut QueryBuilder<'_, Sqlite>, event_type: EventType) {
+    builder
+        .push(" AND event_type = ")
+        .push_bind(variant_name(&event_type));
+}
+
+/// Events matching `event_query` that `visibility` allows, most recent first
+///
+/// Pass the `next_cursor` of a page as `cursor` to get the page after it.
 pub async fn search_events(
     pool: &SqlitePool,
-    event_query: EventQuery,
-) -> Result<Vec<ClientEvent>, Error> {
-    // TODO do not return sqlx::Error
+    event_query: &EventQuery,
+    visibility: &EventVisibility,
+    cursor: Option<Snowflake>,
+    limit: u32,
+) -> Result<EventSearchPage, Error> {
     let mut connection = pool
         .acquire()
         .await
         .context("Failed to aquire connection to db")?;
-    let parsed_client_events = if let Some(time_range) = &event_query.time_range {
-        let start = (time_range.start - LODESTONE_EPOCH_MIL.with(|p| *p)) << 22;
-        let end = (time_range.end + 1 - LODESTONE_EPOCH_MIL.with(|p| *p)) << 22;
-        let rows = sqlx::query!(
-            r#"
-SELECT
-event_value, details, snowflake, level, caused_by_user_id, instance_id
-FROM ClientEvents
-WHERE snowflake >= ($1) AND snowflake <= ($2)"#,
-            start,
-            end
-        ) // TODO bit shift
+    let mut builder: QueryBuilder<Sqlite> =
+        QueryBuilder::new("SELECT event_value, snowflake FROM ClientEvents WHERE 1 = 1");
+
+    if !visibility.all {
+        builder.push(" AND (");
+        push_in(
+            &mut builder,
+            "event_type",
+            [EventType::ProgressionEvent, EventType::PlayitggRunnerEvent]
+                .iter()
+                .map(variant_name),
+        );
+        if visibility.users_and_files {
+            builder.push(" OR ");
+            push_in(
+                &mut builder,
+                "event_type",
+                [EventType::UserEvent, EventType::FSEvent]
+                    .iter()
+                    .map(variant_name),
+            );
+        }
+        builder
+            .push(" OR (event_type = ")
+            .push_bind(variant_name(&EventType::InstanceEvent));
+        if !visibility.all_instances {
+            builder.push(" AND ");
+            push_in(&mut builder, "instance_id", visibility.instances.clone());
+        }
+        builder
+            .push(") OR (event_type = ")
+            .push_bind(variant_name(&EventType::MacroEvent))
+            .push(" AND ");
+        if visibility.all_macro_instances {
+            builder.push("instance_id IS NOT NULL");
+        } else {
+            push_in(
+                &mut builder,
+                "instance_id",
+                visibility.macro_instances.clone(),
+            );
+        }
+        builder.push("))");
+    }
+
+    if let Some(event_levels) = &event_query.event_levels {
+        builder.push(" AND ");
+        push_in(&mut builder, "level", event_levels.iter().map(variant_name));
+    }
+    if let Some(event_types) = &event_query.event_types {
+        builder.push(" AND ");
+        push_in(
+            &mut builder,
+            "event_type",
+            event_types.iter().map(variant_name),
+        );
+    }
+    if let Some(instance_event_types) = &event_query.instance_event_types {
+        push_event_type(&mut builder, EventType::InstanceEvent);
+        builder.push(" AND ");
+        push_in(
+            &mut builder,
+            "event_kind",
+            instance_event_types.iter().map(variant_name),
+        );
+    }
+    if let Some(user_event_types) = &event_query.user_event_types {
+        push_event_type(&mut builder, EventType::UserEvent);
+        builder.push(" AND ");
+        push_in(
+            &mut builder,
+            "event_kind",
+            user_event_types.iter().map(variant_name),
+        );
+    }
+    if let Some(event_user_ids) = &event_query.event_user_ids {
+        push_event_type(&mut builder, EventType::UserEvent);
+        builder.push(" AND ");
+        push_in(&mut builder, "user_id", event_user_ids.clone());
+    }
+    if let Some(event_instance_ids) = &event_query.event_instance_ids {
+        push_event_type(&mut builder, EventType::InstanceEvent);
+        builder.push(" AND ");
+        push_in(&mut builder, "instance_id", event_instance_ids.clone());
+    }
+    if let Some(time_range) = &event_query.time_range {
+        let start = Snowflake::from_timestamp_millis(time_range.start);
+        let end = Snowflake::from_timestamp_millis(time_range.end + 1);
+        builder
+            .push(" AND snowflake >= ")
+            .push_bind(start)
+            .push(" AND snowflake < ")
+            .push_bind(end);
+    }
+    if let Some(cursor) = cursor {
+        builder.push(" AND snowflake < ").push_bind(cursor);
+    }
+    let limit = limit.min(MAX_SEARCH_LIMIT);
+    // one extra row tells whether there is a next page
+    builder
+        .push(" ORDER BY snowflake DESC LIMIT ")
+        .push_bind(limit as i64 + 1);
+
+    let mut rows = builder
+        .build()
         .fetch_all(&mut connection)
         .await
         .context("Failed to fetch events")?;
-        let mut parsed_client_events: Vec<ClientEvent> = Vec::new();
-        for row in rows {
-            if let Ok(client_event) = serde_json::from_str(&row.event_value) {
-                parsed_client_events.push(client_event);
-            } else {
-                error!("Failed to parse client event: {}", row.event_value);
-            }
+    let next_cursor = if rows.len() > limit as usize {
+        rows.truncate(limit as usize);
+        match rows.last() {
+            Some(row) => Some(
+                row.try_get::<Snowflake, _>("snowflake")
+                    .context("Failed to read event snowflake")?,
+            ),
+            None => None,
         }
-        parsed_client_events
     } else {
-        let rows = sqlx::query!(
-            r#"
-SELECT
-*
-FROM ClientEvents"#
-        )
-        .fetch_all(&mut connection)
+        None
+    };
+    let mut events: Vec<ClientEvent> = Vec::with_capacity(rows.len());
+    for row in rows {
+        let event_value: String = row
+            .try_get("event_value")
+            .context("Failed to read event value")?;
+        if let Ok(client_event) = serde_json::from_str(&event_value) {
+            events.push(client_event);
+        } else {
+            error!("Failed to parse client event: {}", event_value);
+        }
+    }
+    Ok(EventSearchPage {
+        events,
+        next_cursor,
+    })
+}
+
+fn console_output_query<'a>(select: &str, instance_id: &InstanceUuid) -> QueryBuilder<'a, Sqlite> {
+    let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(select);
+    builder
+        .push(" FROM ClientEvents WHERE event_type = ")
+        .push_bind(variant_name(&EventType::InstanceEvent))
+        .push(" AND event_kind = ")
+        .push_bind(variant_name(&InstanceEventKind::InstanceOutput))
+        .push(" AND instance_id = ")
+        .push_bind(instance_id.clone());
+    builder
+}
+
+/// Rows of stored console output read at once by `get_console_output`
+pub const CONSOLE_OUTPUT_PAGE_SIZE: u32 = 1000;
+
+/// A page of the stored console output of an instance between `start` and `end` (unix millis, inclusive),
+/// past `cursor` in the given order.
+///
+/// Also returns the cursor of the next page, `None` if this is the last page.
+pub async fn get_console_output(
+    pool: &SqlitePool,
+    instance_id: &InstanceUuid,
+    start: Option<i64>,
+    end: Option<i64>,
+    cursor: Option<Snowflake>,
+    newest_first: bool,
+) -> Result<(Vec<ConsoleLine>, Option<Snowflake>), Error> {
+    let mut connection = pool
+        .acquire()
         .await
-        .context("Failed to fetch events")?;
-        let mut parsed_client_events: Vec<ClientEvent> = Vec::new();
-        for row in rows {
-            if let Ok(client_event) = serde_json::from_str(&row.event_value) {
-                parsed_client_events.push(client_event);
+        .context("Failed to aquire connection to db")?;
+    let mut builder = console_output_query("SELECT event_value, snowflake", instance_id);
+    if let Some(start) = start {
+        builder
+            .push(" AND snowflake >= ")
+            .push_bind(Snowflake::from_timestamp_millis(start));
+    }
+    if let Some(end) = end {
+        builder
+            .push(" AND snowflake < ")
+            .push_bind(Snowflake::from_timestamp_millis(end + 1));
+    }
+    if let Some(cursor) = cursor {
+        builder
+            .push(if newest_first {
+                " AND snowflake < "
             } else {
-                error!("Failed to parse client event: {}", row.event_value);
-            }
+                " AND snowflake > "
+            })
+            .push_bind(cursor);
+    }
+    // one extra row tells whether there is a next page
+    builder
+        .push(if newest_first {
+            " ORDER BY snowflake DESC LIMIT "
+        } else {
+            " ORDER BY snowflake ASC LIMIT "
+        })
+        .push_bind(CONSOLE_OUTPUT_PAGE_SIZE as i64 + 1);
+    let mut rows = builder
+        .build()
+        .fetch_all(&mut connection)
+        .await
+        .context("Failed to fetch console output")?;
+    let next_cursor = if rows.len() > CONSOLE_OUTPUT_PAGE_SIZE as usize {
+        rows.truncate(CONSOLE_OUTPUT_PAGE_SIZE as usize);
+        match rows.last() {
+            Some(row) => Some(
+                row.try_get::<Snowflake, _>("snowflake")
+                    .context("Failed to read event snowflake")?,
+            ),
+            None => None,
         }
-        parsed_client_events
+    } else {
+        None
     };
-    let filtered = parsed_client_events
+    let mut lines = Vec::with_capacity(rows.len());
+    for row in rows {
+        let event_value: String = row
+            .try_get("event_value")
+            .context("Failed to read event value")?;
+        let Ok(client_event) = serde_json::from_str::<ClientEvent>(&event_value) else {
+            error!("Failed to parse client event: {}", event_value);
+            continue;
+        };
+        if let EventInner::InstanceEvent(instance_event) = client_event.event_inner {
+            if let InstanceEventInner::InstanceOutput { message } =
+                instance_event.instance_event_inner
+            {
+                lines.push(ConsoleLine {
+                    time: client_event.snowflake.timestamp_millis(),
+                    line: message,
+                    log_file: None,
+                });
+            }
+        }
+    }
+    Ok((lines, next_cursor))
+}
+
+/// Time of the oldest stored console output of an instance in unix millis, if there is any
+pub async fn get_console_output_start(
+    pool: &SqlitePool,
+    instance_id: &InstanceUuid,
+) -> Result<Option<i64>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let row = console_output_query("SELECT MIN(snowflake) AS snowflake", instance_id)
+        .build()
+        .fetch_one(&mut connection)
+        .await
+        .context("Failed to fetch console output")?;
+    Ok(row
+        .try_get::<Option<Snowflake>, _>("snowflake")
+        .context("Failed to read event snowflake")?
+        .map(|snowflake| snowflake.timestamp_millis()))
+}
+
+/// Sessions of an instance overlapping the given time range, most recent first
+pub async fn get_player_sessions(
+    pool: &SqlitePool,
+    instance_id: &InstanceUuid,
+    player_name: Option<&str>,
+    start: Option<i64>,
+    end: Option<i64>,
+) -> Result<Vec<PlayerSession>, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire connection to db")?;
+    let rows = sqlx::query!(
+        r#"
+SELECT
+player_id, player_name, join_time, leave_time
+FROM PlayerSessions
+WHERE instance_id = ?1
+AND (?2 IS NULL OR player_name = ?2 COLLATE NOCASE)
+AND (?3 IS NULL OR leave_time IS NULL OR leave_time >= ?3)
+AND (?4 IS NULL OR join_time <= ?4)
+ORDER BY join_time DESC"#,
+        instance_id,
+        player_name,
+        start,
+        end
+    )
+    .fetch_all(&mut connection)
+    .await
+    .context("Failed to fetch player sessions")?;
+    Ok(rows
         .into_iter()
-        .filter(|client_event| event_query.filter(client_event))
+        .map(|row| PlayerSession {
+            player_id: row.player_id,
+            player_name: row.player_name,
+            join_time: row.join_time,
+            leave_time: row.leave_time,
+        })
+        .collect())
+}
+
+pub async fn get_player_stats(
+    pool: &SqlitePool,
+    instance_id: &InstanceUuid,
+) -> Result<PlayerStatsReport, Error> {
+    let sessions = get_player_sessions(pool, instance_id, None, None, None).await?;
+    let now = chrono::Utc::now().timestamp();
+    let mut players: Vec<PlayerStats> = Vec::new();
+    // sessions are sorted most recent first, so the first session seen has the latest name
+    for session in sessions.iter() {
+        let leave_time = session.leave_time.unwrap_or(now);
+        match players
+            .iter_mut()
+            .find(|p| p.player_id == session.player_id)
+        {
+            Some(stats) => {
+                stats.total_playtime += leave_time - session.join_time;
+                stats.last_seen = stats.last_seen.max(leave_time);
+                stats.session_count += 1;
+            }
+            None => players.push(PlayerStats {
+                player_id: session.player_id.clone(),
+                player_name: session.player_name.clone(),
+                total_playtime: leave_time - session.join_time,
+                last_seen: leave_time,
+                session_count: 1,
+            }),
+        }
+    }
+    let (peak_concurrent, peak_time) = peak_concurrent(&sessions, now);
+    Ok(PlayerStatsReport {
+        players,
+        peak_concurrent,
+        peak_time,
+    })
+}
+
+/// Highest number of overlapping sessions and the first time it was reached
+fn peak_concurrent(sessions: &[PlayerSession], now: i64) -> (u32, Option<i64>) {
+    // leaves sort before joins at the same instant so back to back sessions don't overlap
+    let mut changes: Vec<(i64, i32)> = sessions
+        .iter()
+        .flat_map(|s| [(s.join_time, 1), (s.leave_time.unwrap_or(now), -1)])
         .collect();
-    Ok(filtered)
+    changes.sort();
+    let mut current = 0;
+    let mut peak = 0;
+    let mut peak_time = None;
+    for (time, change) in changes {
+        current += change;
+        if current > peak {
+            peak = current;
+            peak_time = Some(time);
+        }
+    }
+    (peak as u32, peak_time)
 }
 
 #[cfg(test)]
 #[allow(unused_imports)]
 mod tests {
-    use std::{path::PathBuf, str::FromStr};
+    use std::path::PathBuf;
 
-    use sqlx::{sqlite::SqliteConnectOptions, Pool, Sqlite};
+    use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
 
     use crate::{
-        db::write::init_client_events_table,
-        events::{CausedBy, EventInner, EventLevel, FSEvent, FSOperation, FSTarget},
+        auth::user_id::UserId,
+        db::write::{init_client_events_table, write_client_event},
+        events::{
+            CausedBy, EventInner, EventLevel, FSEvent, FSOperation, FSTarget, InstanceEvent,
+            InstanceEventInner, InstanceEventKind, UserEvent, UserEventInner,
+        },
         types::Snowflake,
     };
 
     use super::*;
 
+    fn session(join_time: i64, leave_time: Option<i64>) -> PlayerSession {
+        PlayerSession {
+            player_id: "id".to_string(),
+            player_name: "name".to_string(),
+            join_time,
+            leave_time,
+        }
+    }
+
+    #[test]
+    fn test_peak_concurrent() {
+        assert_eq!(peak_concurrent(&[], 100), (0, None));
+        let sessions = vec![
+            session(0, Some(10)),
+            session(5, Some(20)),
+            // starts exactly when the first one ends
+            session(10, None),
+            session(30, Some(40)),
+        ];
+        assert_eq!(peak_concurrent(&sessions, 100), (2, Some(5)));
+        let sessions = vec![session(0, None), session(1, None), session(2, Some(3))];
+        assert_eq!(peak_concurrent(&sessions, 100), (3, Some(2)));
+    }
+
+    fn event(event_inner: EventInner, level: EventLevel) -> ClientEvent {
+        ClientEvent {
+            event_inner,
+            details: "Dummy detail".to_string(),
+            snowflake: Snowflake::new(),
+            level,
+            caused_by: CausedBy::System,
+        }
+    }
+
+    fn instance_event(instance_uuid: &InstanceUuid, inner: InstanceEventInner) -> EventInner {
+        EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid: instance_uuid.clone(),
+            instance_name: "test".to_string(),
+            instance_event_inner: inner,
+        })
+    }
+
+    fn empty_query() -> EventQuery {
+        EventQuery {
+            event_levels: None,
+            event_types: None,
+            instance_event_types: None,
+            user_event_types: None,
+            event_user_ids: None,
+            event_instance_ids: None,
+            bearer_token: None,
+            time_range: None,
+        }
+    }
+
+    fn snowflakes(page: &EventSearchPage) -> Vec<Snowflake> {
+        page.events.iter().map(|e| e.snowflake).collect()
+    }
+
     #[tokio::test]
     async fn test_search() {
-        let pool: Pool<Sqlite> = Pool::connect_with(
-            SqliteConnectOptions::from_str("sqlite://test.db")
-                .unwrap()
-                .create_if_missing(true),
-        )
-        .await
-        .unwrap();
-        let drop_result = sqlx::query!(r#"DROP TABLE IF EXISTS ClientEvents"#)
-            .execute(&pool)
-            .await;
-        assert!(drop_result.is_ok());
-        let init_result = init_client_events_table(&pool).await;
-        assert!(init_result.is_ok());
-
-        let snowflake = Snowflake::new();
-        let _dummy_event_1 = ClientEvent {
-            event_inner: EventInner::FSEvent(FSEvent {
+        // a single connection keeps every query on the same in memory database
+        let pool: Pool<Sqlite> = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_client_events_table(&pool).await.unwrap();
+
+        let instance_a = InstanceUuid::default();
+        let instance_b = InstanceUuid::default();
+        let user_id = UserId::default();
+        let output = event(
+            instance_event(
+                &instance_a,
+                InstanceEventInner::InstanceOutput {
+                    message: "hello".to_string(),
+                },
+            ),
+            EventLevel::Info,
+        );
+        let error = event(
+            instance_event(
+                &instance_b,
+                InstanceEventInner::InstanceError {
+                    message: "oops".to_string(),
+                },
+            ),
+            EventLevel::Error,
+        );
+        let login = event(
+            EventInner::UserEvent(UserEvent {
+                user_id: user_id.clone(),
+                user_event_inner: UserEventInner::UserLoggedIn,
+            }),
+            EventLevel::Info,
+        );
+        let fs = event(
+            EventInner::FSEvent(FSEvent {
                 operation: FSOperation::Read,
                 target: FSTarget::File(PathBuf::from("/test")),
             }),
-            details: "Dummy detail 1".to_string(),
-            snowflake,
-            level: EventLevel::Info,
-            caused_by: CausedBy::System,
+            EventLevel::Info,
+        );
+        for e in [&output, &error, &login, &fs] {
+            write_client_event(&pool, e.clone()).await.unwrap();
+        }
+        let everything = EventVisibility {
+            all: true,
+            ..Default::default()
+        };
+
+        // pages are most recent first and chained by their cursor
+        let first = search_events(&pool, &empty_query(), &everything, None, 3)
+            .await
+            .unwrap();
+        assert_eq!(
+            snowflakes(&first),
+            vec![fs.snowflake, login.snowflake, error.snowflake]
+        );
+        assert_eq!(first.next_cursor, Some(error.snowflake));
+        let second = search_events(&pool, &empty_query(), &everything, first.next_cursor, 3)
+            .await
+            .unwrap();
+        assert_eq!(snowflakes(&second), vec![output.snowflake]);
+        assert_eq!(second.next_cursor, None);
+
+        let query = EventQuery {
+            event_levels: Some(vec![EventLevel::Error]),
+            ..empty_query()
         };
+        let page = search_events(&pool, &query, &everything, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(snowflakes(&page), vec![error.snowflake]);
 
-        // let row_1_result = sqlx::query!(
-        //     r#"
-        //     INSERT INTO ClientEvents (event_value, details, snowflake, level)
-        //     VALUES ($1, $2, $3, $4);
-        //     "#,
-        //     serde_json::to_string(&dummy_event_1).unwrap(),
-        //     "Dummy detail 1".to_string(),
-        //     snowflake.clone().to_string(),
-        //     "Info"
-        // )
-        // .execute(&pool)
-        // .await;
-
-        // let row_1 = row_1_result.unwrap();
-    }
-
-    // TODO should properly implement tests, with dummy values
-    // #[tokio::test]
-    // async fn test_read() {
-    //     let pool = SqlitePool::connect("sqlite://dev.db")
-    //         .await
-    //         .unwrap();
-    //     let results = search_events(
-    //         &pool,
-    //         EventQuery {
-    //             event_levels: Some(vec![EventLevel::Error]),
-    //             event_types: None,
-    //             instance_event_types: None,
-    //             user_event_types: None,
-    //             event_user_ids: None,
-    //             event_instance_ids: None,
-    //             bearer_token: None,
-    //             time_range: None,
-    //         },
-    //     )
-    //     .await
-    //     .unwrap();
-    //     assert!(results.is_empty());
-    //     for result in results {
-    //         println!("{:?}", result);
-    //     }
-    // }
+        let query = EventQuery {
+            instance_event_types: Some(vec![InstanceEventKind::InstanceOutput]),
+            ..empty_query()
+        };
+        let page = search_events(&pool, &query, &everything, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(snowflakes(&page), vec![output.snowflake]);
+
+        let query = EventQuery {
+            event_user_ids: Some(vec![user_id.clone()]),
+            ..empty_query()
+        };
+        let page = search_events(&pool, &query, &everything, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(snowflakes(&page), vec![login.snowflake]);
+
+        // a user who can only view instance a sees none of the other events
+        let restricted = EventVisibility {
+            instances: vec![instance_a.clone()],
+            ..Default::default()
+        };
+        let page = search_events(&pool, &empty_query(), &restricted, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(snowflakes(&page), vec![output.snowflake]);
+    }
 }
@@ -0,0 +1,270 @@
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqlitePool, QueryBuilder, Sqlite};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    events::{EventType, InstanceEventKind, UserEventKind},
+    global_settings::GlobalSettings,
+    types::{InstanceUuid, Snowflake},
+};
+
+use super::types::variant_name;
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Gives the event writer time to create and migrate the table before the first prune
+const FIRST_PRUNE_DELAY: Duration = Duration::from_secs(5 * 60);
+/// VACUUM rewrites the whole database, so it runs at most this often
+const VACUUM_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long stored events are kept
+///
+/// The first rule matching an event decides how long it is kept,
+/// events no rule matches are kept forever.
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq)]
+#[ts(export)]
+pub struct RetentionRule {
+    /// `None` matches events of any type
+    pub event_type: Option<EventType>,
+    /// only match instance events of this kind
+    pub instance_event_kind: Option<InstanceEventKind>,
+    /// only match user events of this kind
+    pub user_event_kind: Option<UserEventKind>,
+    /// only match events of this instance
+    pub instance_id: Option<InstanceUuid>,
+    /// `None` keeps matching events forever
+    pub keep_days: Option<u32>,
+}
+
+impl RetentionRule {
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: &str| {
+            Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Invalid retention rule: {}", reason),
+            })
+        };
+        if self.instance_event_kind.is_some() && self.user_event_kind.is_some() {
+            return invalid("an event cannot be both an instance and a user event");
+        }
+        if self.instance_event_kind.is_some()
+            && !matches!(self.event_type, None | Some(EventType::InstanceEvent))
+        {
+            return invalid("instance event kinds only apply to instance events");
+        }
+        if self.user_event_kind.is_some()
+            && !matches!(self.event_type, None | Some(EventType::UserEvent))
+        {
+            return invalid("user event kinds only apply to user events");
+        }
+        Ok(())
+    }
+
+    /// A condition matching the events this rule applies to, never NULL
+    fn push_condition(&self, builder: &mut QueryBuilder<'_, Sqlite>) {
+        builder.push("(1 = 1");
+        if let Some(event_type) = &self.event_type {
+            builder
+                .push(" AND event_type IS ")
+                .push_bind(variant_name(event_type));
+        }
+        if let Some(kind) = &self.instance_event_kind {
+            builder
+                .push(" AND event_type IS ")
+                .push_bind(variant_name(&EventType::InstanceEvent))
+                .push(" AND event_kind IS ")
+                .push_bind(variant_name(kind));
+        }
+        if let Some(kind) = &self.user_event_kind {
+            builder
+                .push(" AND event_type IS ")
+                .push_bind(variant_name(&EventType::UserEvent))
+                .push(" AND event_kind IS ")
+                .push_bind(variant_name(kind));
+        }
+        if let Some(instance_id) = &self.instance_id {
+            builder
+                .push(" AND instance_id IS ")
+                .push_bind(instance_id.clone());
+        }
+        builder.push(")");
+    }
+}
+
+/// Delete the stored events that are past their retention, returns how many were deleted
+pub async fn prune_events(pool: &SqlitePool, rules: &[RetentionRule]) -> Result<u64, Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut deleted = 0;
+    for (index, rule) in rules.iter().enumerate() {
+        let Some(keep_days) = rule.keep_days else {
+            continue;
+        };
+        let cutoff = Snowflake::from_timestamp_millis(now - keep_days as i64 * 24 * 60 * 60 * 1000);
+        let mut builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("DELETE FROM ClientEvents WHERE snowflake < ");
+        builder.push_bind(cutoff).push(" AND ");
+        rule.push_condition(&mut builder);
+        // events an earlier rule matches are up to that rule
+        for earlier_rule in &rules[..index] {
+            builder.push(" AND NOT ");
+            earlier_rule.push_condition(&mut builder);
+        }
+        deleted += builder
+            .build()
+            .execute(&mut connection)
+            .await
+            .context("Failed to delete events")?
+            .rows_affected();
+    }
+    Ok(deleted)
+}
+
+pub async fn vacuum(pool: &SqlitePool) -> Result<(), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    sqlx::query("VACUUM")
+        .execute(&mut connection)
+        .await
+        .context("Failed to vacuum database")?;
+    Ok(())
+}
+
+/// Periodically enforce the retention rules in the global settings
+pub async fn prune_events_task(
+    sqlite_pool: SqlitePool,
+    global_settings: Arc<Mutex<GlobalSettings>>,
+) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + FIRST_PRUNE_DELAY,
+        PRUNE_INTERVAL,
+    );
+    let mut last_vacuum: Option<tokio::time::Instant> = None;
+    let mut deleted_since_vacuum = 0;
+    loop {
+        interval.tick().await;
+        let rules = global_settings.lock().await.event_retention();
+        match prune_events(&sqlite_pool, &rules).await {
+            Ok(0) => {}
+            Ok(deleted) => {
+                info!("Deleted {} events past their retention", deleted);
+                deleted_since_vacuum += deleted;
+            }
+            Err(e) => {
+                error!("Failed to prune events: {}", e);
+                continue;
+            }
+        }
+        if deleted_since_vacuum > 0
+            && last_vacuum.map_or(true, |last_vacuum| last_vacuum.elapsed() >= VACUUM_INTERVAL)
+        {
+            match vacuum(&sqlite_pool).await {
+                Ok(()) => {
+                    deleted_since_vacuum = 0;
+                    last_vacuum = Some(tokio::time::Instant::now());
+                }
+                Err(e) => error!("Failed to vacuum database: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use crate::{
+        db::write::{init_client_events_table, write_client_event},
+        events::{CausedBy, EventInner, EventLevel, InstanceEvent, InstanceEventInner},
+        output_types::ClientEvent,
+    };
+
+    use super::*;
+
+    /// Console output is by far the bulk of stored events
+    fn output_rule() -> RetentionRule {
+        RetentionRule {
+            event_type: Some(EventType::InstanceEvent),
+            instance_event_kind: Some(InstanceEventKind::InstanceOutput),
+            user_event_kind: None,
+            instance_id: None,
+            keep_days: Some(7),
+        }
+    }
+
+    fn output_event(instance_uuid: &InstanceUuid, snowflake: Snowflake) -> ClientEvent {
+        ClientEvent {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: instance_uuid.clone(),
+                instance_name: "test".to_string(),
+                instance_event_inner: InstanceEventInner::InstanceOutput {
+                    message: "hello".to_string(),
+                },
+            }),
+            details: "".to_string(),
+            snowflake,
+            level: EventLevel::Info,
+            caused_by: CausedBy::System,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prune_events() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_client_events_table(&pool).await.unwrap();
+        let kept_instance = InstanceUuid::default();
+        let other_instance = InstanceUuid::default();
+        let ten_days_ago = Snowflake::from_timestamp_millis(
+            chrono::Utc::now().timestamp_millis() - 10 * 24 * 60 * 60 * 1000,
+        );
+        for instance in [&kept_instance, &other_instance] {
+            write_client_event(&pool, output_event(instance, ten_days_ago))
+                .await
+                .unwrap();
+            write_client_event(&pool, output_event(instance, Snowflake::new()))
+                .await
+                .unwrap();
+        }
+
+        let rules = vec![
+            RetentionRule {
+                event_type: None,
+                instance_event_kind: None,
+                user_event_kind: None,
+                instance_id: Some(kept_instance.clone()),
+                keep_days: None,
+            },
+            output_rule(),
+        ];
+        // only the old output of the other instance is past its retention
+        assert_eq!(prune_events(&pool, &rules).await.unwrap(), 1);
+        assert_eq!(prune_events(&pool, &rules).await.unwrap(), 0);
+        vacuum(&pool).await.unwrap();
+    }
+
+    #[test]
+    fn test_validate_retention_rule() {
+        let mut rule = output_rule();
+        assert!(rule.validate().is_ok());
+        rule.event_type = Some(EventType::UserEvent);
+        assert!(rule.validate().is_err());
+        rule.event_type = None;
+        assert!(rule.validate().is_ok());
+        rule.user_event_kind = Some(UserEventKind::UserLoggedIn);
+        assert!(rule.validate().is_err());
+    }
+}
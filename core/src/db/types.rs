@@ -1,9 +1,10 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use ts_rs::TS;
 
 use crate::{
     auth::user_id::UserId,
-    events::{CausedBy, EventInner, EventLevel},
+    events::{CausedBy, EventInner, EventLevel, EventType, InstanceEventKind, UserEventKind},
     output_types::ClientEvent,
     types::{InstanceUuid, Snowflake},
 };
@@ -15,7 +16,21 @@ pub struct ClientEventRow {
     pub snowflake: Snowflake,
     pub level: EventLevel,
     pub caused_by_user_id: Option<UserId>,
+    /// the instance an instance or macro event belongs to
     pub instance_id: Option<InstanceUuid>,
+    pub event_type: String,
+    /// the kind of an instance or user event
+    pub event_kind: Option<String>,
+    /// the user a user event is about
+    pub user_id: Option<UserId>,
+}
+
+/// The name of a unit enum variant, such as an event kind, as it is serialized
+pub fn variant_name(value: &impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => name,
+        _ => panic!("Expected a unit enum variant"),
+    }
 }
 
 impl From<&ClientEvent> for ClientEventRow {
@@ -26,10 +41,21 @@ impl From<&ClientEvent> for ClientEventRow {
             None
         };
 
-        let instance_id = if let EventInner::InstanceEvent(i) = &client_event.event_inner {
-            Some(i.instance_uuid.to_owned())
-        } else {
-            None
+        let (instance_id, event_kind, user_id) = match &client_event.event_inner {
+            EventInner::InstanceEvent(i) => (
+                Some(i.instance_uuid.to_owned()),
+                Some(variant_name(&InstanceEventKind::from(
+                    &i.instance_event_inner,
+                ))),
+                None,
+            ),
+            EventInner::UserEvent(u) => (
+                None,
+                Some(variant_name(&UserEventKind::from(&u.user_event_inner))),
+                Some(u.user_id.to_owned()),
+            ),
+            EventInner::MacroEvent(m) => (m.instance_uuid.to_owned(), None, None),
+            _ => (None, None, None),
         };
 
         ClientEventRow {
@@ -39,6 +65,9 @@ impl From<&ClientEvent> for ClientEventRow {
             level: client_event.level.clone(),
             caused_by_user_id,
             instance_id,
+            event_type: variant_name(&EventType::from(&client_event.event_inner)),
+            event_kind,
+            user_id,
         }
     }
 }
@@ -48,3 +77,67 @@ impl From<&ClientEventRow> for ClientEvent {
         serde_json::from_value(client_event_row.event_value.to_owned()).unwrap()
     }
 }
+
+/// A continuous period of time a player spent on an instance
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct PlayerSession {
+    pub player_id: String,
+    pub player_name: String,
+    pub join_time: i64,
+    /// `None` if the player is still online
+    pub leave_time: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct PlayerStats {
+    pub player_id: String,
+    pub player_name: String,
+    /// in seconds
+    pub total_playtime: i64,
+    pub last_seen: i64,
+    pub session_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct PlayerStatsReport {
+    pub players: Vec<PlayerStats>,
+    pub peak_concurrent: u32,
+    /// when the peak was first reached
+    pub peak_time: Option<i64>,
+}
+
+/// Which stored events a user may see, see `User::can_view_event`
+#[derive(Clone, Debug, Default)]
+pub struct EventVisibility {
+    pub all: bool,
+    pub all_instances: bool,
+    /// instances whose events are visible, if not `all_instances`
+    pub instances: Vec<InstanceUuid>,
+    pub all_macro_instances: bool,
+    /// instances whose macro events are visible, if not `all_macro_instances`
+    pub macro_instances: Vec<InstanceUuid>,
+    pub users_and_files: bool,
+}
+
+/// A line of an instance's console history
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct ConsoleLine {
+    /// unix millis, for lines from a log file it is read from the line itself
+    pub time: i64,
+    pub line: String,
+    /// the log file the line was read from, `None` if it is stored output
+    pub log_file: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[ts(export)]
+pub struct EventSearchPage {
+    /// most recent first
+    pub events: Vec<ClientEvent>,
+    /// pass as `cursor` to get the next, older page, `None` if this is the last page
+    pub next_cursor: Option<Snowflake>,
+}
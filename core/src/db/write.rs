@@ -1,7 +1,10 @@
 use crate::{
     error::Error,
-    events::{Event, EventInner, ProgressionEventInner},
+    events::{Event, EventInner, InstanceEvent, InstanceEventInner, ProgressionEventInner},
     output_types::ClientEvent,
+    prelude::LODESTONE_EPOCH_MIL,
+    traits::t_player::TPlayer,
+    types::{InstanceUuid, Snowflake},
 };
 
 use color_eyre::eyre::Context;
@@ -49,7 +52,10 @@ pub async fn write_event_to_db_task(mut event_receiver: Receiver<Event>, sqlite_
     }
 }
 
-async fn write_client_event(pool: &SqlitePool, client_event: ClientEvent) -> Result<i64, Error> {
+pub(super) async fn write_client_event(
+    pool: &SqlitePool,
+    client_event: ClientEvent,
+) -> Result<i64, Error> {
     let mut connection = pool
         .acquire()
         .await
@@ -59,9 +65,9 @@ async fn write_client_event(pool: &SqlitePool, client_event: ClientEvent) -> Res
     let id = sqlx::query!(
         r#"
 INSERT INTO ClientEvents
-(event_value, details, snowflake, level, caused_by_user_id, instance_id)
+(event_value, details, snowflake, level, caused_by_user_id, instance_id, event_type, event_kind, user_id)
 VALUES
-(?1, ?2, ?3, ?4, ?5, ?6)
+(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
         "#,
         row.event_value,
         row.details,
@@ -69,6 +75,9 @@ VALUES
         row.level,
         row.caused_by_user_id,
         row.instance_id,
+        row.event_type,
+        row.event_kind,
+        row.user_id,
     )
     .execute(&mut connection)
     .await
@@ -92,7 +101,10 @@ pub async fn init_client_events_table(pool: &SqlitePool) -> Result<(), Error> {
             snowflake           BIGINT      NOT NULL,
             level               VARCHAR(20) NOT NULL,
             caused_by_user_id   TEXT,
-            instance_id         TEXT
+            instance_id         TEXT,
+            event_type          VARCHAR(32),
+            event_kind          VARCHAR(32),
+            user_id             TEXT
         );
         "#
     )
@@ -100,6 +112,279 @@ pub async fn init_client_events_table(pool: &SqlitePool) -> Result<(), Error> {
     .await
     .context("Failed to create table")?;
 
+    // tables created before events could be searched lack the columns to search by,
+    // add them and fill them in from the stored events.
+    // Not checked at compile time, the columns may or may not exist yet
+    let columns: Vec<String> =
+        sqlx::query_scalar("SELECT name FROM pragma_table_info('ClientEvents')")
+            .fetch_all(&mut connection)
+            .await
+            .context("Failed to read table info")?;
+    if !columns.iter().any(|column| column == "event_type") {
+        for statement in [
+            "ALTER TABLE ClientEvents ADD COLUMN event_type VARCHAR(32)",
+            "ALTER TABLE ClientEvents ADD COLUMN event_kind VARCHAR(32)",
+            "ALTER TABLE ClientEvents ADD COLUMN user_id TEXT",
+        ] {
+            sqlx::query(statement)
+                .execute(&mut connection)
+                .await
+                .context("Failed to add column")?;
+        }
+    }
+    sqlx::query!(
+        r#"
+        UPDATE ClientEvents SET
+            event_type = json_extract(event_value, '$.event_inner.type'),
+            event_kind = coalesce(
+                json_extract(event_value, '$.event_inner.instance_event_inner.type'),
+                json_extract(event_value, '$.event_inner.user_event_inner.type')
+            ),
+            user_id = CASE json_extract(event_value, '$.event_inner.type')
+                WHEN 'UserEvent' THEN json_extract(event_value, '$.event_inner.user_id')
+            END,
+            instance_id = coalesce(instance_id, json_extract(event_value, '$.event_inner.instance_uuid'))
+        WHERE event_type IS NULL;
+        "#
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to fill in search columns")?;
+
+    sqlx::query!(
+        r#"
+        CREATE INDEX IF NOT EXISTS ClientEventsSnowflake
+        ON ClientEvents (snowflake);
+        "#
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create index")?;
+
+    sqlx::query!(
+        r#"
+        CREATE INDEX IF NOT EXISTS ClientEventsTypeSnowflake
+        ON ClientEvents (event_type, snowflake);
+        "#
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create index")?;
+
+    sqlx::query!(
+        r#"
+        CREATE INDEX IF NOT EXISTS ClientEventsInstanceSnowflake
+        ON ClientEvents (instance_id, snowflake);
+        "#
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create index")?;
+
+    Ok(())
+}
+
+/// Record player sessions from the join and leave events of every instance
+pub async fn write_player_sessions_task(
+    mut event_receiver: Receiver<Event>,
+    sqlite_pool: SqlitePool,
+    up_since: i64,
+) {
+    let init_result = init_player_sessions_table(&sqlite_pool).await;
+    if let Err(error) = init_result.as_ref() {
+        warn!("Failed to initialize player sessions table: {}", error);
+        return;
+    }
+    if let Err(e) = close_all_player_sessions(&sqlite_pool, up_since).await {
+        error!("Failed to close dangling player sessions: {}", e);
+    }
+
+    loop {
+        let event = match event_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                warn!("Event buffer lagged");
+                continue;
+            }
+            Err(RecvError::Closed) => {
+                warn!("Event buffer closed");
+                break;
+            }
+        };
+        if let EventInner::InstanceEvent(InstanceEvent {
+            instance_uuid,
+            instance_event_inner:
+                InstanceEventInner::PlayerChange {
+                    players_joined,
+                    players_left,
+                    ..
+                },
+            ..
+        }) = event.event_inner
+        {
+            let now = chrono::Utc::now().timestamp();
+            for player in players_joined {
+                if let Err(e) = start_player_session(
+                    &sqlite_pool,
+                    &instance_uuid,
+                    &player.get_id(),
+                    &player.get_name(),
+                    now,
+                )
+                .await
+                {
+                    error!("Failed to record player session: {}", e);
+                }
+            }
+            for player in players_left {
+                if let Err(e) =
+                    end_player_session(&sqlite_pool, &instance_uuid, &player.get_id(), now).await
+                {
+                    error!("Failed to record player session: {}", e);
+                }
+            }
+        }
+    }
+}
+
+async fn start_player_session(
+    pool: &SqlitePool,
+    instance_id: &InstanceUuid,
+    player_id: &str,
+    player_name: &str,
+    join_time: i64,
+) -> Result<(), Error> {
+    // a player can't be online twice on the same instance
+    end_player_session(pool, instance_id, player_id, join_time).await?;
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    sqlx::query!(
+        r#"
+INSERT INTO PlayerSessions
+(instance_id, player_id, player_name, join_time)
+VALUES
+(?1, ?2, ?3, ?4)
+        "#,
+        instance_id,
+        player_id,
+        player_name,
+        join_time,
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to write to DB")?;
+    Ok(())
+}
+
+async fn end_player_session(
+    pool: &SqlitePool,
+    instance_id: &InstanceUuid,
+    player_id: &str,
+    leave_time: i64,
+) -> Result<(), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    sqlx::query!(
+        r#"
+UPDATE PlayerSessions
+SET leave_time = ?3
+WHERE instance_id = ?1 AND player_id = ?2 AND leave_time IS NULL
+        "#,
+        instance_id,
+        player_id,
+        leave_time,
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to write to DB")?;
+    Ok(())
+}
+
+/// Sessions left open by a previous run end at the last event its instance emitted in that run,
+/// the server was still up then but we can't tell for how much longer
+async fn close_all_player_sessions(pool: &SqlitePool, up_since: i64) -> Result<(), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+    // no event was ever written, so no session was ever opened either.
+    // Not checked at compile time, the table may not exist yet
+    let has_events: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'ClientEvents')",
+    )
+    .fetch_one(&mut connection)
+    .await
+    .context("Failed to read table info")?;
+    if !has_events {
+        return Ok(());
+    }
+    let this_run = Snowflake::from_timestamp_millis(up_since * 1000);
+    let epoch = LODESTONE_EPOCH_MIL.with(|p| *p);
+    sqlx::query!(
+        r#"
+UPDATE PlayerSessions
+SET leave_time = MAX(join_time, COALESCE((
+    SELECT ((MAX(snowflake) >> 22) + ?2) / 1000
+    FROM ClientEvents
+    WHERE ClientEvents.instance_id = PlayerSessions.instance_id AND snowflake < ?1
+), join_time))
+WHERE leave_time IS NULL
+        "#,
+        this_run,
+        epoch,
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to write to DB")?;
+    Ok(())
+}
+
+pub async fn init_player_sessions_table(pool: &SqlitePool) -> Result<(), Error> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to aquire db connection")?;
+
+    sqlx::query!(
+        r#"
+        CREATE TABLE IF NOT EXISTS PlayerSessions (
+            id                  INTEGER     PRIMARY KEY     AUTOINCREMENT,
+            instance_id         TEXT        NOT NULL,
+            player_id           TEXT        NOT NULL,
+            player_name         TEXT        NOT NULL,
+            join_time           BIGINT      NOT NULL,
+            leave_time          BIGINT
+        );
+        "#
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create table")?;
+
+    sqlx::query!(
+        r#"
+        CREATE INDEX IF NOT EXISTS PlayerSessionsInstanceJoinTime
+        ON PlayerSessions (instance_id, join_time);
+        "#
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create index")?;
+
+    sqlx::query!(
+        r#"
+        CREATE INDEX IF NOT EXISTS PlayerSessionsInstancePlayer
+        ON PlayerSessions (instance_id, player_id);
+        "#
+    )
+    .execute(&mut connection)
+    .await
+    .context("Failed to create index")?;
+
     Ok(())
 }
 
@@ -111,6 +396,8 @@ mod tests {
 
     use sqlx::{sqlite::SqliteConnectOptions, Pool};
 
+    use sqlx::sqlite::SqlitePoolOptions;
+
     use crate::{
         events::{CausedBy, EventLevel, FSEvent, FSOperation, FSTarget},
         types::Snowflake,
@@ -165,5 +452,59 @@ mod tests {
         assert_eq!(row.level, "Info".to_string()); // consider using sqlx::Encode trait to compare
         assert_eq!(row.caused_by_user_id, None);
         assert_eq!(row.instance_id, None);
+        assert_eq!(row.event_type, Some("FSEvent".to_string()));
+        assert_eq!(row.event_kind, None);
+    }
+
+    #[tokio::test]
+    async fn test_dangling_sessions_end_at_last_event() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_client_events_table(&pool).await.unwrap();
+        init_player_sessions_table(&pool).await.unwrap();
+        let active_instance = InstanceUuid::default();
+        let silent_instance = InstanceUuid::default();
+        let join_time = 1_700_000_000;
+        let up_since = join_time + 3600;
+        for instance in [&active_instance, &silent_instance] {
+            start_player_session(&pool, instance, "player", "player", join_time)
+                .await
+                .unwrap();
+        }
+        // one event from the previous run, and one from this run that must be ignored
+        for time in [join_time + 600, up_since + 60] {
+            let event = ClientEvent {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: active_instance.clone(),
+                    instance_name: "test".to_string(),
+                    instance_event_inner: InstanceEventInner::InstanceOutput {
+                        message: "hello".to_string(),
+                    },
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::from_timestamp_millis(time * 1000),
+                level: EventLevel::Info,
+                caused_by: CausedBy::System,
+            };
+            write_client_event(&pool, event).await.unwrap();
+        }
+
+        close_all_player_sessions(&pool, up_since).await.unwrap();
+
+        let leave_times: Vec<(String, Option<i64>)> =
+            sqlx::query_as("SELECT instance_id, leave_time FROM PlayerSessions")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        for (instance_id, leave_time) in leave_times {
+            if InstanceUuid::from(instance_id) == active_instance {
+                assert_eq!(leave_time, Some(join_time + 600));
+            } else {
+                assert_eq!(leave_time, Some(join_time));
+            }
+        }
     }
 }
@@ -305,6 +305,9 @@ async fn is_rcon_available(instance_uuid: InstanceUuid) -> Result<bool, anyhow::
         crate::prelude::GameInstance::GenericInstance(_) => {
             bail!("RCON not available for atom instances")
         }
+        crate::prelude::GameInstance::BedrockInstance(_) => {
+            bail!("RCON not available for bedrock instances")
+        }
     }
 }
 
@@ -322,6 +325,9 @@ async fn try_send_rcon_command(
         crate::prelude::GameInstance::GenericInstance(_) => {
             bail!("RCON not available for atom instances")
         }
+        crate::prelude::GameInstance::BedrockInstance(_) => {
+            bail!("RCON not available for bedrock instances")
+        }
     }
 }
 
@@ -347,6 +353,9 @@ async fn send_rcon_command(
         crate::prelude::GameInstance::GenericInstance(_) => {
             bail!("RCON not available for atom instances")
         }
+        crate::prelude::GameInstance::BedrockInstance(_) => {
+            bail!("RCON not available for bedrock instances")
+        }
     }
 }
 
@@ -369,6 +378,9 @@ async fn wait_till_rcon_available(instance_uuid: InstanceUuid) -> Result<(), any
         crate::prelude::GameInstance::GenericInstance(_) => {
             bail!("RCON not available for atom instances")
         }
+        crate::prelude::GameInstance::BedrockInstance(_) => {
+            bail!("RCON not available for bedrock instances")
+        }
     }
 }
 
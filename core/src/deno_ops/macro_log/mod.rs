@@ -0,0 +1,59 @@
+use std::{cell::RefCell, rc::Rc};
+
+use deno_core::{op, OpState};
+
+use crate::{
+    macro_executor::MacroPID,
+    macro_log::{MacroLogs, MacroStream},
+};
+
+/// Copies what `console` prints into the log of the macro, it is still printed to the core's stdout
+pub const CAPTURE_CONSOLE_CODE: &str = r#"
+(() => {
+    const core = Deno[Deno.internal].core;
+    const print = core.print;
+    core.print = (msg, isErr) => {
+        core.ops.emit_macro_log(msg, !!isErr);
+        print(msg, isErr);
+    };
+})();
+"#;
+
+/// The log a worker writes to, set by the core so a macro can't write to another task's log
+#[derive(Clone)]
+struct MacroLog {
+    macro_logs: MacroLogs,
+    macro_pid: MacroPID,
+}
+
+#[op]
+fn emit_macro_log(state: Rc<RefCell<OpState>>, msg: String, is_err: bool) {
+    let MacroLog {
+        macro_logs,
+        macro_pid,
+    } = state.borrow().borrow::<MacroLog>().clone();
+    let stream = if is_err {
+        MacroStream::Stderr
+    } else {
+        MacroStream::Stdout
+    };
+    macro_logs.push(macro_pid, stream, &msg);
+}
+
+pub fn register_macro_log_ops(
+    worker_options: &mut deno_runtime::worker::WorkerOptions,
+    macro_logs: MacroLogs,
+    macro_pid: MacroPID,
+) {
+    worker_options.extensions.push(
+        deno_core::Extension::builder("macro_log_ops")
+            .ops(vec![emit_macro_log::decl()])
+            .state(|state| {
+                state.put(MacroLog {
+                    macro_logs,
+                    macro_pid,
+                });
+            })
+            .build(),
+    );
+}
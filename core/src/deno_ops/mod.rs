@@ -1,3 +1,4 @@
 pub mod events;
 pub mod instance_control;
+pub mod macro_log;
 pub mod prelude;
@@ -9,7 +9,12 @@ use crate::{
     auth::{permission::UserPermission, user_id::UserId},
     macro_executor::MacroPID,
     output_types::ClientEvent,
-    traits::{t_macro::ExitStatus, t_player::Player, t_server::State, InstanceInfo},
+    traits::{
+        t_macro::ExitStatus,
+        t_player::{ModerationAction, Player},
+        t_server::State,
+        InstanceInfo,
+    },
     types::{InstanceUuid, Snowflake, TimeRange},
 };
 
@@ -120,6 +125,10 @@ pub enum InstanceEventInner {
         player: String,
         player_message: String,
     },
+    PlayerModeration {
+        player: String,
+        action: ModerationAction,
+    },
 }
 
 impl AsRef<InstanceEventInner> for InstanceEventInner {
@@ -151,6 +160,28 @@ pub enum UserEventInner {
     PermissionChanged {
         new_permissions: Box<UserPermission>,
     },
+    ApiTokenCreated {
+        token_id: Snowflake,
+        name: String,
+    },
+    ApiTokenRevoked {
+        token_id: Snowflake,
+    },
+    SessionRevoked {
+        session_id: Snowflake,
+    },
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    /// A wrong password or two-factor code was given for this user
+    LoginFailed {
+        ip: Option<String>,
+    },
+    /// Too many failed logins from `ip`, no login from there is accepted until `locked_until` (unix seconds)
+    AccountLocked {
+        locked_until: i64,
+        ip: Option<String>,
+    },
+    AccountUnlocked,
 }
 
 impl AsRef<UserEventInner> for UserEventInner {
@@ -502,6 +533,23 @@ impl Event {
         }
     }
 
+    pub fn new_instance_warning(
+        instance_uuid: InstanceUuid,
+        instance_name: String,
+        message: String,
+    ) -> Event {
+        Event {
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid,
+                instance_name,
+                instance_event_inner: InstanceEventInner::InstanceWarning { message },
+            }),
+            caused_by: CausedBy::System,
+        }
+    }
+
     pub fn new_instance_state_transition(
         instance_uuid: InstanceUuid,
         instance_name: String,
@@ -0,0 +1,138 @@
+//! Atoms, the extensions generic instances are made of, installed under `extensions/atom`
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::Context;
+use deno_runtime::permissions::PermissionsOptions;
+use tracing::{info, warn};
+
+use crate::{error::Error, prelude::lodestone_path};
+
+use super::{read_local_manifest, requested_permission, Permission};
+
+pub fn path_to_atoms() -> PathBuf {
+    lodestone_path().join("extensions").join("atom")
+}
+
+/// The permissions the owner approved for each atom, by directory name
+fn path_to_approved_permissions(path_to_atoms: &Path) -> PathBuf {
+    path_to_atoms.join("permissions.json")
+}
+
+pub fn read_approved_permissions(
+    path_to_atoms: &Path,
+) -> Result<HashMap<String, Permission>, Error> {
+    let path = path_to_approved_permissions(path_to_atoms);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(serde_json::from_str(&content).context(format!(
+            "Failed to parse atom permissions at {}, was the file modified manually?",
+            path.display()
+        ))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e)
+            .context(format!(
+                "Failed to read atom permissions at {}",
+                path.display()
+            ))
+            .map_err(Into::into),
+    }
+}
+
+/// Record what an atom may access, `None` for nothing beyond the defaults
+pub fn set_approved_permission(
+    path_to_atoms: &Path,
+    id: &str,
+    permission: Option<Permission>,
+) -> Result<(), Error> {
+    let mut approved = read_approved_permissions(path_to_atoms)?;
+    match permission {
+        Some(permission) => approved.insert(id.to_string(), permission),
+        None => approved.remove(id),
+    };
+    write_approved_permissions(path_to_atoms, &approved)
+}
+
+fn write_approved_permissions(
+    path_to_atoms: &Path,
+    approved: &HashMap<String, Permission>,
+) -> Result<(), Error> {
+    let path = path_to_approved_permissions(path_to_atoms);
+    std::fs::write(
+        &path,
+        serde_json::to_string_pretty(approved).context(
+            "Failed to serialize atom permissions to string, this is a bug, please report it",
+        )?,
+    )
+    .context(format!(
+        "Failed to write atom permissions to {}",
+        path.display()
+    ))?;
+    Ok(())
+}
+
+/// Atoms installed before approvals were recorded ran with whatever their manifest asked for,
+/// keep it that way for the atoms at `paths_to_sources` that instances already run.
+///
+/// Only runs once, before the first approval is recorded.
+pub async fn migrate_approved_permissions(
+    path_to_atoms: &Path,
+    paths_to_sources: impl IntoIterator<Item = PathBuf>,
+) -> Result<(), Error> {
+    if !path_to_atoms.is_dir() || path_to_approved_permissions(path_to_atoms).exists() {
+        return Ok(());
+    }
+    let mut approved = HashMap::new();
+    for path_to_source in paths_to_sources {
+        let Some(id) = path_to_source
+            .strip_prefix(path_to_atoms)
+            .ok()
+            .and_then(|id| id.to_str())
+        else {
+            continue;
+        };
+        let manifest = match read_local_manifest(&path_to_source).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!("Not approving the permissions of atom {}: {}", id, e.source);
+                continue;
+            }
+        };
+        if let Some(permission) = requested_permission(&manifest) {
+            info!("Approving the permissions atom {} already ran with", id);
+            approved.insert(id.to_string(), permission);
+        }
+    }
+    write_approved_permissions(path_to_atoms, &approved)
+}
+
+/// What the atom at `path_to_source` may access when it runs, on top of the instance's directory.
+///
+/// Only installed atoms get more than reading their own code, and only what the owner approved.
+pub fn atom_permissions_options(path_to_source: &Path) -> PermissionsOptions {
+    let path_to_atoms = path_to_atoms();
+    let approved = path_to_source
+        .strip_prefix(&path_to_atoms)
+        .ok()
+        .and_then(|id| id.to_str())
+        .and_then(|id| {
+            read_approved_permissions(&path_to_atoms)
+                .unwrap_or_default()
+                .remove(id)
+        });
+    let mut options = approved
+        .map(|permission| permission.to_permissions_options(path_to_source))
+        .unwrap_or_default();
+    if path_to_source.is_dir() {
+        match &mut options.allow_read {
+            // an empty list already grants everything
+            Some(paths) if paths.is_empty() => {}
+            allow_read => allow_read
+                .get_or_insert_with(Vec::new)
+                .push(path_to_source.to_path_buf()),
+        }
+    }
+    options
+}
@@ -1,15 +1,22 @@
-use std::{
-    path::{Path, PathBuf},
-    process::Stdio,
-};
+use std::path::{Path, PathBuf};
 
 use color_eyre::eyre::{eyre, Error};
 
+/// Where the commit a rollback returns to is kept, the reflog moves on every checkout
+const PREVIOUS_COMMIT_REF: &str = "refs/lodestone/previous";
+
 pub struct GitClient {
     cwd: PathBuf,
 }
 
 impl GitClient {
+    /// A client for a repository that is already cloned
+    pub fn open(path: impl AsRef<Path>) -> Self {
+        Self {
+            cwd: path.as_ref().to_path_buf(),
+        }
+    }
+
     pub fn cwd(&self) -> PathBuf {
         self.cwd.clone()
     }
@@ -38,56 +45,103 @@ impl GitClient {
         // dbg!(&output);
         // extract the path from the output
         // ex. Cloning into 'PATH'...;
-        let path = output
+        let cloned_into = output
             .split('\'')
             .nth(1)
             .ok_or_else(|| eyre!("Failed to parse git clone output"))?;
 
+        // git reports the path relative to where it was run
         Ok(Self {
-            cwd: PathBuf::from(path),
+            cwd: path.as_ref().join(cloned_into),
         })
     }
 
-    pub async fn get_current_commit(&self) -> Result<String, Error> {
+    /// Run git in the repository and return its trimmed stdout
+    async fn run(&self, args: &[&str]) -> Result<String, Error> {
         let output = tokio::process::Command::new("git")
-            .arg("rev-parse")
-            .arg("HEAD")
+            .args(args)
             .current_dir(&self.cwd)
             .output()
             .await
             .map_err(|e| eyre!("Failed to get output {}", e))?;
         if !output.status.success() {
-            return Err(eyre!("git rev-parse HEAD failed"));
+            return Err(eyre!(
+                "git {} failed : {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
         }
         let output = String::from_utf8(output.stdout)?;
         Ok(output.trim().to_string())
     }
 
+    pub async fn get_current_commit(&self) -> Result<String, Error> {
+        self.run(&["rev-parse", "HEAD"]).await
+    }
+
+    /// Fetch new commits and tags from origin
     pub async fn fetch(&self) -> Result<(), Error> {
-        let status = tokio::process::Command::new("git")
-            .arg("fetch")
-            .current_dir(&self.cwd)
-            .status()
-            .await
-            .map_err(|e| eyre!("Failed to get status {}", e))?;
-        if !status.success() {
-            return Err(eyre!("git fetch failed"));
-        }
+        self.run(&["fetch", "--tags", "--force", "origin"]).await?;
         Ok(())
     }
 
+    /// The latest commit of the default branch of origin, as of the last fetch
     pub async fn get_latest_commit(&self) -> Result<String, Error> {
-        let output = tokio::process::Command::new("git")
-            .arg("rev-parse")
-            .arg("origin/main")
-            .current_dir(&self.cwd)
-            .output()
+        self.run(&["rev-parse", "origin/HEAD"]).await
+    }
+
+    /// The commit a tag points to, as of the last fetch
+    pub async fn get_tag_commit(&self, tag: &str) -> Result<String, Error> {
+        self.run(&[
+            "rev-parse",
+            "--verify",
+            &format!("refs/tags/{tag}^{{commit}}"),
+        ])
+        .await
+        .map_err(|_| eyre!("Tag {} not found", tag))
+    }
+
+    /// The tag the checked out commit is at, if any
+    pub async fn get_current_tag(&self) -> Option<String> {
+        self.run(&["describe", "--tags", "--exact-match", "HEAD"])
             .await
-            .map_err(|e| eyre!("Failed to get output {}", e))?;
-        if !output.status.success() {
-            return Err(eyre!("git rev-parse origin/main failed"));
-        }
-        let output = String::from_utf8(output.stdout)?;
-        Ok(output.trim().to_string())
+            .ok()
+    }
+
+    /// The commit recorded with `set_previous_commit`
+    pub async fn get_previous_commit(&self) -> Option<String> {
+        self.run(&["rev-parse", "--verify", "--quiet", PREVIOUS_COMMIT_REF])
+            .await
+            .ok()
+            .filter(|commit| !commit.is_empty())
+    }
+
+    /// Record the commit a rollback returns to, or forget it with `None`
+    pub async fn set_previous_commit(&self, commit: Option<&str>) -> Result<(), Error> {
+        match commit {
+            Some(commit) => {
+                self.run(&["update-ref", PREVIOUS_COMMIT_REF, commit])
+                    .await?
+            }
+            None => self.run(&["update-ref", "-d", PREVIOUS_COMMIT_REF]).await?,
+        };
+        Ok(())
+    }
+
+    pub async fn get_remote_url(&self) -> Result<String, Error> {
+        self.run(&["remote", "get-url", "origin"]).await
+    }
+
+    pub async fn checkout(&self, commit: &str) -> Result<(), Error> {
+        self.run(&[
+            "-c",
+            "advice.detachedHead=false",
+            "checkout",
+            "--quiet",
+            "--detach",
+            commit,
+        ])
+        .await?;
+        Ok(())
     }
 }
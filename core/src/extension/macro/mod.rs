@@ -0,0 +1,162 @@
+//! Macro extensions, installed once under `extensions/macro` and shared by instances
+//! instead of being copied into the `macros` folder of each of them
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context};
+use deno_runtime::permissions::PermissionsOptions;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    error::{Error, ErrorKind},
+    implementations::minecraft::r#macro::resolve_macro_invocation,
+    prelude::lodestone_path,
+    types::InstanceUuid,
+};
+
+use super::Manifest;
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MacroExtension {
+    /// name of the directory the macro is cloned into, `<name>.<username>`
+    pub id: String,
+    pub manifest: Manifest,
+    pub url: String,
+    /// the instances the macro is available to, every instance if `None`
+    pub instances: Option<HashSet<InstanceUuid>>,
+}
+
+impl MacroExtension {
+    pub fn is_available_to(&self, instance_uuid: &InstanceUuid) -> bool {
+        self.instances
+            .as_ref()
+            .map_or(true, |instances| instances.contains(instance_uuid))
+    }
+
+    /// What the macro may access when it runs, on top of its own directory.
+    /// A macro declaring no permission gets nothing more.
+    pub fn permissions_options(&self, path_to_macro_extensions: &Path) -> PermissionsOptions {
+        self.manifest
+            .permission
+            .as_ref()
+            .map(|permission| {
+                permission.to_permissions_options(&path_to_macro_extensions.join(&self.id))
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
+pub struct MacroAvailability {
+    /// every instance if `None`
+    pub instances: Option<HashSet<InstanceUuid>>,
+}
+
+pub fn path_to_macro_extensions() -> PathBuf {
+    lodestone_path().join("extensions").join("macro")
+}
+
+fn path_to_index(path_to_macro_extensions: &Path) -> PathBuf {
+    path_to_macro_extensions.join("macros.json")
+}
+
+pub fn read_macro_extensions(
+    path_to_macro_extensions: &Path,
+) -> Result<Vec<MacroExtension>, Error> {
+    let path = path_to_index(path_to_macro_extensions);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(serde_json::from_str(&content).context(format!(
+            "Failed to parse macro extensions at {}, was the file modified manually?",
+            path.display()
+        ))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e)
+            .context(format!(
+                "Failed to read macro extensions at {}",
+                path.display()
+            ))
+            .map_err(Into::into),
+    }
+}
+
+pub fn write_macro_extensions(
+    path_to_macro_extensions: &Path,
+    macro_extensions: &[MacroExtension],
+) -> Result<(), Error> {
+    let path = path_to_index(path_to_macro_extensions);
+    std::fs::write(
+        &path,
+        serde_json::to_string_pretty(macro_extensions).context(
+            "Failed to serialize macro extensions to string, this is a bug, please report it",
+        )?,
+    )
+    .context(format!(
+        "Failed to write macro extensions to {}",
+        path.display()
+    ))?;
+    Ok(())
+}
+
+/// Macro names end up in paths and urls
+pub fn check_macro_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "Macro name \"{}\" may only contain letters, digits, '-' and '_'",
+                name
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// The macro extensions an instance can run
+pub fn available_macro_extensions(instance_uuid: &InstanceUuid) -> Vec<MacroExtension> {
+    read_macro_extensions(&path_to_macro_extensions())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|macro_extension| macro_extension.is_available_to(instance_uuid))
+        .collect()
+}
+
+/// The entry point of the macro extension named `name` if it is available to the instance,
+/// along with the permissions it runs with
+pub fn resolve_macro_extension(
+    instance_uuid: &InstanceUuid,
+    name: &str,
+) -> Option<(PathBuf, PermissionsOptions)> {
+    let path_to_macro_extensions = path_to_macro_extensions();
+    let macro_extension = available_macro_extensions(instance_uuid)
+        .into_iter()
+        .find(|macro_extension| macro_extension.manifest.name == name)?;
+    let path = resolve_macro_invocation(&path_to_macro_extensions, &macro_extension.id)?;
+    Some((
+        path,
+        macro_extension.permissions_options(&path_to_macro_extensions),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_macro_name() {
+        assert!(check_macro_name("auto-backup").is_ok());
+        assert!(check_macro_name("auto_backup2").is_ok());
+        assert!(check_macro_name("").is_err());
+        assert!(check_macro_name("../auto-backup").is_err());
+        assert!(check_macro_name("auto backup").is_err());
+    }
+}
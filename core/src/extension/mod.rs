@@ -1,18 +1,27 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
 use axum::Json;
 use color_eyre::eyre::{self, Context};
+use deno_runtime::permissions::PermissionsOptions;
 use serde_json::Value;
-use tracing::error;
+use tracing::{error, warn};
 use ts_rs::TS;
 
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
+use crate::implementations::minecraft::r#macro::resolve_macro_invocation;
+use crate::types::InstanceUuid;
+
+use self::atom::{read_approved_permissions, set_approved_permission};
+use self::r#macro::{
+    check_macro_name, read_macro_extensions, write_macro_extensions, MacroExtension,
+};
 
 pub mod atom;
 pub mod git;
 pub mod r#macro;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, TS)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, TS)]
 #[ts(export)]
 /// https://docs.deno.com/runtime/manual/basics/permissions
 pub struct Permission {
@@ -39,7 +48,66 @@ pub struct Permission {
     pub allow_run: Option<Vec<String>>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, TS)]
+/// In deno an empty list grants access to everything of its kind,
+/// so an empty list in a manifest grants nothing instead
+fn all_or<T>(all: bool, list: Option<Vec<T>>) -> Option<Vec<T>> {
+    if all {
+        Some(Vec::new())
+    } else {
+        list.filter(|list| !list.is_empty())
+    }
+}
+
+fn is_none_or_empty<T>(list: &Option<Vec<T>>) -> bool {
+    list.as_ref().map_or(true, |list| list.is_empty())
+}
+
+impl Permission {
+    /// Whether the extension asks for nothing beyond the defaults every extension gets
+    pub fn is_empty(&self) -> bool {
+        !(self.full_disk_access
+            || self.full_network_access
+            || self.full_env_access
+            || self.disk_read
+            || self.disk_write
+            || self.sys_info
+            || self.subprocess)
+            && is_none_or_empty(&self.allow_env)
+            && is_none_or_empty(&self.allow_read)
+            && is_none_or_empty(&self.allow_write)
+            && is_none_or_empty(&self.allow_net)
+            && is_none_or_empty(&self.allow_run)
+    }
+
+    /// Relative paths are resolved against the extension's directory
+    pub fn to_permissions_options(&self, path_to_extension: &Path) -> PermissionsOptions {
+        let resolve = |paths: &Option<Vec<PathBuf>>| {
+            paths.as_ref().map(|paths| {
+                paths
+                    .iter()
+                    .map(|path| path_to_extension.join(path))
+                    .collect::<Vec<_>>()
+            })
+        };
+        PermissionsOptions {
+            allow_read: all_or(
+                self.full_disk_access || self.disk_read,
+                resolve(&self.allow_read),
+            ),
+            allow_write: all_or(
+                self.full_disk_access || self.disk_write,
+                resolve(&self.allow_write),
+            ),
+            allow_net: all_or(self.full_network_access, self.allow_net.clone()),
+            allow_env: all_or(self.full_env_access, self.allow_env.clone()),
+            allow_run: all_or(self.subprocess, self.allow_run.clone()),
+            allow_sys: all_or(self.sys_info, None),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, TS)]
 #[ts(export)]
 pub enum ExtensionType {
     Atom,
@@ -71,6 +139,76 @@ pub struct ManifestWithMetadata {
     pub domain: String,
 }
 
+/// An installed extension and the version it is at
+#[derive(Debug, Clone, serde::Serialize, TS)]
+#[ts(export)]
+pub struct InstalledExtension {
+    pub r#type: ExtensionType,
+    /// name of the directory the extension is cloned into, `<name>.<username>`
+    pub id: String,
+    pub manifest: Manifest,
+    pub url: String,
+    pub commit: String,
+    /// the tag the extension is pinned to, if the commit is tagged
+    pub tag: Option<String>,
+    /// the commit checked out before the last update, which a rollback returns to
+    pub previous_commit: Option<String>,
+    /// whether the owner approved what the checked out version asks for,
+    /// atoms without an approval on record run with the defaults until it is given
+    pub permissions_approved: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize, TS)]
+#[ts(export)]
+pub struct ExtensionUpdateStatus {
+    pub current_commit: String,
+    /// the latest commit of the default branch
+    pub latest_commit: String,
+    pub update_available: bool,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, TS)]
+#[ts(export)]
+pub struct ExtensionUpdateRequest {
+    /// tag to pin the extension to, the latest commit if absent
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// approve the permissions the new version asks for if they changed
+    #[serde(default)]
+    pub approve_permissions: bool,
+}
+
+/// The manifest committed with an installed extension
+async fn read_local_manifest(path_to_extension: &Path) -> Result<Manifest, Error> {
+    let path = path_to_extension.join("lodestone.json");
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .context(format!("Failed to read manifest at {}", path.display()))?;
+    Ok(serde_json::from_str(&content)
+        .context(format!("Failed to parse manifest at {}", path.display()))?)
+}
+
+/// Extensions asking for more than the defaults can only be used once the owner approves
+fn check_approval(manifest: &Manifest, permissions_approved: bool) -> Result<(), Error> {
+    match &manifest.permission {
+        Some(permission) if !permission.is_empty() && !permissions_approved => Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre::eyre!(
+                "{} asks for permissions beyond the defaults, the owner has to approve them",
+                manifest.name
+            ),
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn requested_permission(manifest: &Manifest) -> Option<Permission> {
+    manifest
+        .permission
+        .clone()
+        .filter(|permission| !permission.is_empty())
+}
+
 #[derive(serde::Serialize)]
 pub enum FetchExtensionManifestError {
     NotFound,
@@ -211,31 +349,445 @@ impl ExtensionManager {
         }
     }
 
-    pub async fn install_extension(&self, url: impl AsRef<str>) -> Result<PathBuf, Error> {
+    /// `macro_instances` are the instances an installed macro is available to, every instance if `None`.
+    ///
+    /// `permissions_approved` is whether the owner approved the permissions the manifest asks for.
+    pub async fn install_extension(
+        &self,
+        url: impl AsRef<str>,
+        macro_instances: Option<HashSet<InstanceUuid>>,
+        permissions_approved: bool,
+    ) -> Result<PathBuf, Error> {
         // a possible race condition, but it's fine
         let manifest = get_manifest(url).await?;
+        check_approval(&manifest.manifest, permissions_approved)?;
         let extension_path = match manifest.manifest.r#type {
-            ExtensionType::Atom => self.install_atom(&manifest.username, &manifest.url, &manifest.manifest.name).await?,
-            ExtensionType::Macro => todo!(),
+            ExtensionType::Atom => self.install_atom(&manifest, permissions_approved).await?,
+            ExtensionType::Macro => {
+                self.install_macro(manifest, macro_instances, permissions_approved)
+                    .await?
+            }
         };
         Ok(extension_path)
     }
 
     async fn install_atom(
         &self,
-        username: &str,
-        url: &str,
-        atom_name: &str,
+        manifest: &ManifestWithMetadata,
+        permissions_approved: bool,
     ) -> Result<PathBuf, Error> {
         tokio::fs::create_dir_all(&self.atom_path)
             .await
             .context("Failed to create atom directory")?;
+        // the name ends up in the path the atom is cloned into
+        check_macro_name(&manifest.manifest.name)?;
+        let id = format!("{}.{}", manifest.manifest.name, manifest.username);
         // check if the extension already exists
-        let path = self.atom_path.join(atom_name);
+        let path = self.atom_path.join(&id);
+        let cloned = !path.exists();
+        if cloned {
+            git::GitClient::clone(&manifest.url, &self.atom_path, &id).await?;
+        }
+        // the checkout can be newer than the manifest fetched above, or cloned long before
+        let local_manifest = read_local_manifest(&path).await.and_then(|local_manifest| {
+            check_approval(&local_manifest, permissions_approved)?;
+            Ok(local_manifest)
+        });
+        let local_manifest = match local_manifest {
+            Ok(local_manifest) => local_manifest,
+            Err(e) => {
+                if cloned {
+                    let _ = tokio::fs::remove_dir_all(&path).await;
+                }
+                return Err(e);
+            }
+        };
+        // atoms run with what the owner approved, not with what their manifest says at the time
+        set_approved_permission(&self.atom_path, &id, requested_permission(&local_manifest))?;
+        Ok(path)
+    }
+
+    async fn install_macro(
+        &self,
+        manifest: ManifestWithMetadata,
+        instances: Option<HashSet<InstanceUuid>>,
+        permissions_approved: bool,
+    ) -> Result<PathBuf, Error> {
+        let name = &manifest.manifest.name;
+        check_macro_name(name)?;
+        tokio::fs::create_dir_all(&self.macro_path)
+            .await
+            .context("Failed to create macro directory")?;
+        let mut macro_extensions = read_macro_extensions(&self.macro_path)?;
+        let id = format!("{name}.{}", manifest.username);
+        if macro_extensions
+            .iter()
+            .any(|macro_extension| macro_extension.id == id)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre::eyre!("Macro {} is already installed", id),
+            });
+        }
+        let path = self.macro_path.join(&id);
+        // no installed macro uses the directory, it is left over from an earlier install
         if path.exists() {
-            return Ok(path);
+            crate::util::fs::remove_dir_all(&path).await?;
+        }
+        git::GitClient::clone(&manifest.url, &self.macro_path, &id).await?;
+        // the recorded manifest is what the macro runs with, so it has to be the cloned one
+        let local_manifest = match self
+            .check_cloned_macro(&id, &macro_extensions, permissions_approved)
+            .await
+        {
+            Ok(local_manifest) => local_manifest,
+            Err(e) => {
+                let _ = tokio::fs::remove_dir_all(&path).await;
+                return Err(e);
+            }
+        };
+        macro_extensions.push(MacroExtension {
+            id,
+            manifest: local_manifest,
+            url: manifest.url,
+            instances,
+        });
+        write_macro_extensions(&self.macro_path, &macro_extensions)?;
+        Ok(path)
+    }
+
+    /// The manifest of a freshly cloned macro, if it can be installed next to `macro_extensions`
+    async fn check_cloned_macro(
+        &self,
+        id: &str,
+        macro_extensions: &[MacroExtension],
+        permissions_approved: bool,
+    ) -> Result<Manifest, Error> {
+        if resolve_macro_invocation(&self.macro_path, id).is_none() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre::eyre!("The repository has no index.ts or index.js to run"),
+            });
+        }
+        let manifest = read_local_manifest(&self.macro_path.join(id)).await?;
+        check_macro_name(&manifest.name)?;
+        // instances run macro extensions by name
+        if macro_extensions
+            .iter()
+            .any(|macro_extension| macro_extension.manifest.name == manifest.name)
+        {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre::eyre!("A macro named {} is already installed", manifest.name),
+            });
+        }
+        check_approval(&manifest, permissions_approved)?;
+        Ok(manifest)
+    }
+
+    pub fn list_macros(&self) -> Result<Vec<MacroExtension>, Error> {
+        read_macro_extensions(&self.macro_path)
+    }
+
+    /// Make a macro available to the given instances, or every instance with `None`
+    pub fn set_macro_instances(
+        &self,
+        name: &str,
+        instances: Option<HashSet<InstanceUuid>>,
+    ) -> Result<MacroExtension, Error> {
+        let mut macro_extensions = read_macro_extensions(&self.macro_path)?;
+        let macro_extension = macro_extensions
+            .iter_mut()
+            .find(|macro_extension| macro_extension.manifest.name == name)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre::eyre!("Macro extension {} not found", name),
+            })?;
+        macro_extension.instances = instances;
+        let macro_extension = macro_extension.clone();
+        write_macro_extensions(&self.macro_path, &macro_extensions)?;
+        Ok(macro_extension)
+    }
+
+    fn path_to_extensions(&self, r#type: &ExtensionType) -> &Path {
+        match r#type {
+            ExtensionType::Atom => &self.atom_path,
+            ExtensionType::Macro => &self.macro_path,
+        }
+    }
+
+    async fn installed_extension(
+        &self,
+        r#type: ExtensionType,
+        id: String,
+        manifest: Manifest,
+        url: String,
+    ) -> Result<InstalledExtension, Error> {
+        let git = git::GitClient::open(self.path_to_extensions(&r#type).join(&id));
+        let permissions_approved = match r#type {
+            ExtensionType::Atom => {
+                requested_permission(&manifest)
+                    == read_approved_permissions(&self.atom_path)?.remove(&id)
+            }
+            // macros run with the recorded manifest, which is only updated once approved
+            ExtensionType::Macro => true,
+        };
+        Ok(InstalledExtension {
+            commit: git.get_current_commit().await?,
+            tag: git.get_current_tag().await,
+            previous_commit: git.get_previous_commit().await,
+            permissions_approved,
+            r#type,
+            id,
+            manifest,
+            url,
+        })
+    }
+
+    /// Every installed atom and macro, extensions git can't make sense of are left out
+    pub async fn list_extensions(&self) -> Result<Vec<InstalledExtension>, Error> {
+        let mut extensions = Vec::new();
+        if self.atom_path.exists() {
+            let mut entries = tokio::fs::read_dir(&self.atom_path)
+                .await
+                .context("Failed to read atom directory")?;
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .context("Failed to read atom directory")?
+            {
+                if !entry.path().is_dir() {
+                    continue;
+                }
+                let id = entry.file_name().to_string_lossy().to_string();
+                let manifest = match read_local_manifest(&entry.path()).await {
+                    Ok(manifest) => manifest,
+                    Err(e) => {
+                        warn!("Skipping atom {}: {}", id, e.source);
+                        continue;
+                    }
+                };
+                let url = git::GitClient::open(entry.path())
+                    .get_remote_url()
+                    .await
+                    .unwrap_or_default();
+                match self
+                    .installed_extension(ExtensionType::Atom, id.clone(), manifest, url)
+                    .await
+                {
+                    Ok(extension) => extensions.push(extension),
+                    Err(e) => warn!("Skipping atom {}: {}", id, e.source),
+                }
+            }
+        }
+        for macro_extension in read_macro_extensions(&self.macro_path)? {
+            let id = macro_extension.id.clone();
+            match self
+                .installed_extension(
+                    ExtensionType::Macro,
+                    macro_extension.id,
+                    macro_extension.manifest,
+                    macro_extension.url,
+                )
+                .await
+            {
+                Ok(extension) => extensions.push(extension),
+                Err(e) => warn!("Skipping macro {}: {}", id, e.source),
+            }
         }
-        let git = git::GitClient::clone(url, &self.atom_path, format!("{atom_name}.{username}")).await?;
-        Ok(git.cwd())
+        Ok(extensions)
+    }
+
+    pub async fn get_extension(
+        &self,
+        r#type: &ExtensionType,
+        id: &str,
+    ) -> Result<InstalledExtension, Error> {
+        self.list_extensions()
+            .await?
+            .into_iter()
+            .find(|extension| &extension.r#type == r#type && extension.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre::eyre!("Extension {} not found", id),
+            })
+    }
+
+    /// Fetch the extension's repository and compare the checked out commit to the latest one
+    pub async fn check_update(
+        &self,
+        r#type: &ExtensionType,
+        id: &str,
+    ) -> Result<ExtensionUpdateStatus, Error> {
+        let extension = self.get_extension(r#type, id).await?;
+        let git = git::GitClient::open(self.path_to_extensions(r#type).join(&extension.id));
+        git.fetch().await.map_err(|e| Error {
+            kind: ErrorKind::External,
+            source: e,
+        })?;
+        let latest_commit = git.get_latest_commit().await?;
+        Ok(ExtensionUpdateStatus {
+            update_available: latest_commit != extension.commit,
+            current_commit: extension.commit,
+            latest_commit,
+        })
+    }
+
+    /// Check out the latest commit, or the commit of `tag` to pin the extension to it
+    pub async fn update_extension(
+        &self,
+        r#type: &ExtensionType,
+        id: &str,
+        tag: Option<&str>,
+        permissions_approved: bool,
+    ) -> Result<InstalledExtension, Error> {
+        let extension = self.get_extension(r#type, id).await?;
+        let git = git::GitClient::open(self.path_to_extensions(r#type).join(&extension.id));
+        git.fetch().await.map_err(|e| Error {
+            kind: ErrorKind::External,
+            source: e,
+        })?;
+        let target = match tag {
+            Some(tag) => git.get_tag_commit(tag).await.map_err(|e| Error {
+                kind: ErrorKind::NotFound,
+                source: e,
+            })?,
+            None => git.get_latest_commit().await?,
+        };
+        if target != extension.commit {
+            self.switch_commit(&git, &extension, &target, permissions_approved)
+                .await?;
+            git.set_previous_commit(Some(&extension.commit)).await?;
+        }
+        self.get_extension(r#type, id).await
+    }
+
+    /// Go back to the commit checked out before the last update, only once per update
+    pub async fn rollback_extension(
+        &self,
+        r#type: &ExtensionType,
+        id: &str,
+        permissions_approved: bool,
+    ) -> Result<InstalledExtension, Error> {
+        let extension = self.get_extension(r#type, id).await?;
+        let previous_commit = extension.previous_commit.clone().ok_or_else(|| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre::eyre!("Extension {} has no previous version to roll back to", id),
+        })?;
+        let git = git::GitClient::open(self.path_to_extensions(r#type).join(&extension.id));
+        self.switch_commit(&git, &extension, &previous_commit, permissions_approved)
+            .await?;
+        // rolling back again would return to the version just rolled back from
+        git.set_previous_commit(None).await?;
+        self.get_extension(r#type, id).await
+    }
+
+    /// Check out `commit`, going back to the current one if the extension can't be used at it
+    /// or asks for permissions the owner didn't approve
+    async fn switch_commit(
+        &self,
+        git: &git::GitClient,
+        extension: &InstalledExtension,
+        commit: &str,
+        permissions_approved: bool,
+    ) -> Result<(), Error> {
+        git.checkout(commit).await?;
+        let result = match extension.r#type {
+            ExtensionType::Atom => self.refresh_atom(&extension.id, permissions_approved).await,
+            ExtensionType::Macro => {
+                self.refresh_macro(&extension.id, permissions_approved)
+                    .await
+            }
+        };
+        if result.is_err() {
+            git.checkout(&extension.commit).await?;
+        }
+        result
+    }
+
+    /// Approve the permissions the checked out version of an atom asks for
+    async fn refresh_atom(&self, id: &str, permissions_approved: bool) -> Result<(), Error> {
+        let manifest = match read_local_manifest(&self.atom_path.join(id)).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!("Keeping the permissions of atom {}: {}", id, e.source);
+                return Ok(());
+            }
+        };
+        let permission = requested_permission(&manifest);
+        if permission != read_approved_permissions(&self.atom_path)?.remove(id) {
+            check_approval(&manifest, permissions_approved)?;
+            set_approved_permission(&self.atom_path, id, permission)?;
+        }
+        Ok(())
+    }
+
+    /// Let an atom have what its checked out version asks for, for atoms installed
+    /// before approvals were recorded or whose approval is out of date
+    pub async fn approve_atom_permissions(&self, id: &str) -> Result<InstalledExtension, Error> {
+        let extension = self.get_extension(&ExtensionType::Atom, id).await?;
+        set_approved_permission(
+            &self.atom_path,
+            &extension.id,
+            requested_permission(&extension.manifest),
+        )?;
+        self.get_extension(&ExtensionType::Atom, id).await
+    }
+
+    /// Bring the recorded manifest of a macro in line with the checked out version
+    async fn refresh_macro(&self, id: &str, permissions_approved: bool) -> Result<(), Error> {
+        if resolve_macro_invocation(&self.macro_path, id).is_none() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre::eyre!("This version has no index.ts or index.js to run"),
+            });
+        }
+        let manifest = match read_local_manifest(&self.macro_path.join(id)).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                warn!("Keeping the manifest of macro {}: {}", id, e.source);
+                return Ok(());
+            }
+        };
+        check_macro_name(&manifest.name)?;
+        let mut macro_extensions = read_macro_extensions(&self.macro_path)?;
+        if macro_extensions.iter().any(|macro_extension| {
+            macro_extension.id != id && macro_extension.manifest.name == manifest.name
+        }) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre::eyre!("A macro named {} is already installed", manifest.name),
+            });
+        }
+        if let Some(macro_extension) = macro_extensions
+            .iter_mut()
+            .find(|macro_extension| macro_extension.id == id)
+        {
+            // the recorded manifest holds the permissions the macro runs with
+            if requested_permission(&manifest) != requested_permission(&macro_extension.manifest) {
+                check_approval(&manifest, permissions_approved)?;
+            }
+            macro_extension.manifest = manifest;
+        }
+        write_macro_extensions(&self.macro_path, &macro_extensions)
+    }
+
+    /// Remove an extension, the caller makes sure no instance still needs it
+    pub async fn uninstall_extension(&self, r#type: &ExtensionType, id: &str) -> Result<(), Error> {
+        let extension = self.get_extension(r#type, id).await?;
+        match r#type {
+            ExtensionType::Atom => set_approved_permission(&self.atom_path, &extension.id, None)?,
+            ExtensionType::Macro => {
+                let mut macro_extensions = read_macro_extensions(&self.macro_path)?;
+                macro_extensions.retain(|macro_extension| macro_extension.id != extension.id);
+                write_macro_extensions(&self.macro_path, &macro_extensions)?;
+            }
+        }
+        crate::util::fs::remove_dir_all(self.path_to_extensions(r#type).join(&extension.id)).await
+    }
+
+    pub fn path_to_extension(&self, extension: &InstalledExtension) -> PathBuf {
+        self.path_to_extensions(&extension.r#type)
+            .join(&extension.id)
     }
 }
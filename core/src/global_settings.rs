@@ -5,7 +5,10 @@ use serde::{Deserialize, Serialize};
 use tokio::io::AsyncWriteExt;
 use ts_rs::TS;
 
-use crate::{error::Error, event_broadcaster::EventBroadcaster};
+use crate::{
+    db::retention::RetentionRule, error::Error, event_broadcaster::EventBroadcaster,
+    implementations::minecraft::mods::DEFAULT_MOD_INDEX, macro_limits::MacroLimits,
+};
 
 #[derive(Serialize, Deserialize, Clone, TS)]
 #[ts(export)]
@@ -15,6 +18,19 @@ pub struct GlobalSettingsData {
     pub domain: Option<String>,
     #[serde(default)]
     pub playit_enabled: bool,
+    /// Modrinth style index mods and plugins are installed from, `None` for Modrinth itself
+    #[serde(default)]
+    pub mod_index: Option<String>,
+    /// How long stored events are kept, the first matching rule applies.
+    /// Without rules every event is kept
+    #[serde(default)]
+    pub event_retention: Vec<RetentionRule>,
+    /// Users holding unsafe permissions can only use them with two-factor authentication enabled
+    #[serde(default)]
+    pub require_two_factor_for_unsafe: bool,
+    /// Resources any macro a user can write runs within
+    #[serde(default)]
+    pub macro_limits: MacroLimits,
 }
 
 impl Default for GlobalSettingsData {
@@ -24,6 +40,10 @@ impl Default for GlobalSettingsData {
             safe_mode: true,
             domain: None,
             playit_enabled: true,
+            mod_index: None,
+            event_retention: Vec::new(),
+            require_two_factor_for_unsafe: false,
+            macro_limits: MacroLimits::default(),
         }
     }
 }
@@ -165,6 +185,84 @@ impl GlobalSettings {
     pub fn playit_enabled(&self) -> bool {
         self.global_settings_data.playit_enabled
     }
+
+    pub async fn set_mod_index(&mut self, mod_index: Option<String>) -> Result<(), Error> {
+        let old_mod_index = self.global_settings_data.mod_index.clone();
+        self.global_settings_data.mod_index = mod_index;
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.mod_index = old_mod_index;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn mod_index(&self) -> String {
+        self.global_settings_data
+            .mod_index
+            .clone()
+            .unwrap_or_else(|| DEFAULT_MOD_INDEX.to_string())
+    }
+
+    pub async fn set_event_retention(
+        &mut self,
+        event_retention: Vec<RetentionRule>,
+    ) -> Result<(), Error> {
+        let old_event_retention = std::mem::replace(
+            &mut self.global_settings_data.event_retention,
+            event_retention,
+        );
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.event_retention = old_event_retention;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn event_retention(&self) -> Vec<RetentionRule> {
+        self.global_settings_data.event_retention.clone()
+    }
+
+    pub async fn set_require_two_factor_for_unsafe(
+        &mut self,
+        require_two_factor_for_unsafe: bool,
+    ) -> Result<(), Error> {
+        let old_require_two_factor_for_unsafe = std::mem::replace(
+            &mut self.global_settings_data.require_two_factor_for_unsafe,
+            require_two_factor_for_unsafe,
+        );
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.require_two_factor_for_unsafe =
+                    old_require_two_factor_for_unsafe;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn require_two_factor_for_unsafe(&self) -> bool {
+        self.global_settings_data.require_two_factor_for_unsafe
+    }
+
+    pub async fn set_macro_limits(&mut self, macro_limits: MacroLimits) -> Result<(), Error> {
+        let old_macro_limits =
+            std::mem::replace(&mut self.global_settings_data.macro_limits, macro_limits);
+        match self.write_to_file().await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.global_settings_data.macro_limits = old_macro_limits;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn macro_limits(&self) -> MacroLimits {
+        self.global_settings_data.macro_limits
+    }
 }
 
 impl AsRef<GlobalSettingsData> for GlobalSettings {
@@ -1,25 +1,33 @@
 use std::sync::Arc;
 
 use axum::{
+    body::{Bytes, StreamBody},
     extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
-    response::Response,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{IntoResponse, Response},
     routing::get,
     Json, Router,
 };
 use axum_auth::AuthBearer;
 
-use color_eyre::eyre::eyre;
+use chrono::TimeZone;
+use color_eyre::eyre::{eyre, Context};
 use futures::{SinkExt, StreamExt};
 use ringbuffer::{AllocRingBuffer, RingBufferExt};
 use tracing::{debug, error};
 
 use crate::output_types::ClientEvent;
-use crate::types::InstanceUuid;
+use crate::traits::t_configurable::TConfigurable;
+use crate::types::{InstanceUuid, Snowflake};
 use crate::{
-    auth::{user::UsersManager, user_id::UserId},
-    db::read::search_events,
+    auth::user::{User, UserAction, UsersManager},
+    console_history::{search, ConsoleHistory, ConsoleSearchQuery, ConsoleSearchResult},
+    db::{
+        read::{search_events, MAX_SEARCH_LIMIT},
+        types::{variant_name, EventSearchPage},
+    },
     error::{Error, ErrorKind},
-    events::EventQuery,
+    events::{EventQuery, EventType},
 };
 
 use crate::{
@@ -73,21 +81,28 @@ pub async fn get_event_buffer(
     ))
 }
 
-// TODO implement me
+#[derive(Deserialize, Clone, Debug, TS)]
+pub struct EventSearchQuery {
+    filter: String,
+    /// `next_cursor` of the previous page
+    cursor: Option<Snowflake>,
+    limit: Option<u32>,
+}
+
 pub async fn get_event_search(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
-    query: Query<EventQueryWrapper>,
-) -> Result<Json<Vec<ClientEvent>>, Error> {
+    query: Query<EventSearchQuery>,
+) -> Result<Json<EventSearchPage>, Error> {
     // deserialize query
-    let query: EventQuery = serde_json::from_str(&query.filter).map_err(|e| {
+    let event_query: EventQuery = serde_json::from_str(&query.filter).map_err(|e| {
         error!("Error deserializing event query: {}", e);
         Error {
             kind: ErrorKind::BadRequest,
             source: e.into(),
         }
     })?;
-    let _requester = state
+    let requester = state
         .users_manager
         .read()
         .await
@@ -96,7 +111,150 @@ pub async fn get_event_search(
             kind: ErrorKind::Unauthorized,
             source: eyre!("Token error"),
         })?;
-    search_events(&state.sqlite_pool, query).await.map(Json)
+    search_events(
+        &state.sqlite_pool,
+        &event_query,
+        &requester.event_visibility(),
+        query.cursor,
+        query.limit.unwrap_or(100),
+    )
+    .await
+    .map(Json)
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum EventExportFormat {
+    Ndjson,
+    Csv,
+}
+
+const CSV_HEADER: &str = "snowflake,time,level,event_type,details,event\n";
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c: char| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl EventExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            EventExportFormat::Ndjson => "application/x-ndjson",
+            EventExportFormat::Csv => "text/csv",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            EventExportFormat::Ndjson => "ndjson",
+            EventExportFormat::Csv => "csv",
+        }
+    }
+
+    fn format_event(&self, event: &ClientEvent) -> String {
+        match self {
+            EventExportFormat::Ndjson => format!("{}\n", serde_json::to_string(event).unwrap()),
+            EventExportFormat::Csv => {
+                let time = chrono::Utc
+                    .timestamp_millis_opt(event.snowflake.timestamp_millis())
+                    .single()
+                    .map(|time| time.to_rfc3339())
+                    .unwrap_or_default();
+                format!(
+                    "{},{},{},{},{},{}\n",
+                    event.snowflake.to_string(),
+                    time,
+                    variant_name(&event.level),
+                    variant_name(&EventType::from(&event.event_inner)),
+                    csv_field(&event.details),
+                    csv_field(&serde_json::to_string(&event.event_inner).unwrap()),
+                )
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, TS)]
+pub struct EventExportQuery {
+    filter: String,
+    /// defaults to ndjson
+    format: Option<EventExportFormat>,
+}
+
+/// Stream every stored event matching the query that the requester can see, most recent first
+pub async fn export_events(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    query: Query<EventExportQuery>,
+) -> Result<Response, Error> {
+    let event_query: EventQuery = serde_json::from_str(&query.filter).map_err(|e| {
+        error!("Error deserializing event query: {}", e);
+        Error {
+            kind: ErrorKind::BadRequest,
+            source: e.into(),
+        }
+    })?;
+    let requester = state
+        .users_manager
+        .read()
+        .await
+        .try_auth(&token)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    let visibility = requester.event_visibility();
+    let format = query.format.unwrap_or(EventExportFormat::Ndjson);
+    let pool = state.sqlite_pool.clone();
+    // `None` once the last page has been sent
+    let pages = futures::stream::unfold(Some(None), move |cursor: Option<Option<Snowflake>>| {
+        let pool = pool.clone();
+        let event_query = event_query.clone();
+        let visibility = visibility.clone();
+        async move {
+            let cursor = cursor?;
+            match search_events(&pool, &event_query, &visibility, cursor, MAX_SEARCH_LIMIT).await {
+                Ok(page) => {
+                    let chunk: String = page
+                        .events
+                        .iter()
+                        .map(|event| format.format_event(event))
+                        .collect();
+                    Some((Ok(Bytes::from(chunk)), page.next_cursor.map(Some)))
+                }
+                Err(e) => {
+                    error!("Failed to export events: {}", e);
+                    Some((
+                        Err(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            e.to_string(),
+                        )),
+                        None,
+                    ))
+                }
+            }
+        }
+    });
+    let header = match format {
+        EventExportFormat::Csv => Some(Ok(Bytes::from(CSV_HEADER))),
+        EventExportFormat::Ndjson => None,
+    };
+    let headers = [
+        (CONTENT_TYPE, format.content_type().to_string()),
+        (
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"events.{}\"", format.extension()),
+        ),
+    ];
+    Ok((
+        headers,
+        StreamBody::new(futures::stream::iter(header).chain(pages)),
+    )
+        .into_response())
 }
 
 pub async fn get_console_buffer(
@@ -133,6 +291,124 @@ pub async fn get_console_buffer(
     ))
 }
 
+/// Search the console history of an instance, including the server's archived log files
+pub async fn search_console(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<ConsoleSearchQuery>,
+) -> Result<Json<ConsoleSearchResult>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let path = instance.path().await;
+    drop(instance);
+    let history = ConsoleHistory::new(
+        state.sqlite_pool.clone(),
+        uuid,
+        &path,
+        query.start,
+        query.end,
+        true,
+    )
+    .await?;
+    let result = tokio::task::spawn_blocking(move || search(history, &query))
+        .await
+        .context("Failed to search console history")??;
+    Ok(Json(result))
+}
+
+/// Console history is sent in chunks of about this many bytes
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize, Clone, Debug, TS)]
+pub struct ConsoleExportQuery {
+    /// unix millis, inclusive
+    start: Option<i64>,
+    /// unix millis, inclusive
+    end: Option<i64>,
+}
+
+/// Download the console history of an instance as a plain text log
+pub async fn export_console(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<ConsoleExportQuery>,
+) -> Result<Response, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    let path = instance.path().await;
+    drop(instance);
+    let history = ConsoleHistory::new(
+        state.sqlite_pool.clone(),
+        uuid.clone(),
+        &path,
+        query.start,
+        query.end,
+        false,
+    )
+    .await?;
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let mut chunk = String::new();
+        for line in history {
+            match line {
+                Ok(line) => {
+                    chunk.push_str(&line.line);
+                    chunk.push('\n');
+                    if chunk.len() < EXPORT_CHUNK_SIZE {
+                        continue;
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to export console history: {}", e);
+                    let _ = tx.blocking_send(Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        e.to_string(),
+                    )));
+                    return;
+                }
+            }
+            // the download was cancelled
+            if tx
+                .blocking_send(Ok(Bytes::from(std::mem::take(&mut chunk))))
+                .is_err()
+            {
+                return;
+            }
+        }
+        if !chunk.is_empty() {
+            let _ = tx.blocking_send(Ok(Bytes::from(chunk)));
+        }
+    });
+    let headers = [
+        (CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+        (
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}-console.log\"", uuid.no_prefix()),
+        ),
+    ];
+    Ok((
+        headers,
+        StreamBody::new(tokio_stream::wrappers::ReceiverStream::new(rx)),
+    )
+        .into_response())
+}
+
 #[derive(Deserialize)]
 pub struct WebsocketQuery {
     token: String,
@@ -167,7 +443,7 @@ pub async fn event_stream(
     let event_receiver = state.event_broadcaster.subscribe();
 
     Ok(ws.on_upgrade(move |socket| {
-        event_stream_ws(socket, event_receiver, query, user.uid, state.users_manager)
+        event_stream_ws(socket, event_receiver, query, user, state.users_manager)
     }))
 }
 
@@ -175,7 +451,7 @@ async fn event_stream_ws(
     stream: WebSocket,
     mut event_receiver: Receiver<Event>,
     query: EventQuery,
-    uid: UserId,
+    user: User,
     users_manager: Arc<RwLock<UsersManager>>,
 ) {
     let (mut sender, mut receiver) = stream.split();
@@ -185,7 +461,7 @@ async fn event_stream_ws(
                 if event.is_event_console_message() {
                     continue;
                 }
-                let user = match users_manager.read().await.get_user(&uid) {
+                let user = match users_manager.read().await.refresh_user(&user) {
                     Some(user) => user,
                     None => {
                         break;
@@ -226,14 +502,14 @@ pub async fn console_stream(
     let event_receiver = state.event_broadcaster.subscribe();
 
     Ok(ws.on_upgrade(move |socket| {
-        console_stream_ws(socket, event_receiver, user.uid, uuid, state.users_manager)
+        console_stream_ws(socket, event_receiver, user, uuid, state.users_manager)
     }))
 }
 
 async fn console_stream_ws(
     stream: WebSocket,
     mut event_receiver: Receiver<Event>,
-    uid: UserId,
+    user: User,
     uuid: InstanceUuid,
     users_manager: Arc<RwLock<UsersManager>>,
 ) {
@@ -243,7 +519,7 @@ async fn console_stream_ws(
             Ok(event) = event_receiver.recv() => {
                 match &event.event_inner {
                     EventInner::InstanceEvent(instance_event) => {
-                        let user = match users_manager.read().await.get_user(&uid) {
+                        let user = match users_manager.read().await.refresh_user(&user) {
                             Some(user) => user,
                             None => break,
                         };
@@ -264,7 +540,7 @@ async fn console_stream_ws(
                     EventInner::UserEvent(user_event) => {
                         match user_event.user_event_inner {
                             UserEventInner::UserLoggedOut | UserEventInner::UserDeleted => {
-                                if user_event.user_id == uid {
+                                if user_event.user_id == user.uid {
                                     break;
                                 }
                             },
@@ -292,7 +568,10 @@ pub fn get_events_routes(state: AppState) -> Router {
         .route("/events/:uuid/stream", get(event_stream))
         .route("/events/:uuid/buffer", get(get_event_buffer))
         .route("/events/search", get(get_event_search))
+        .route("/events/export", get(export_events))
         .route("/instance/:uuid/console/stream", get(console_stream))
         .route("/instance/:uuid/console/buffer", get(get_console_buffer))
+        .route("/instance/:uuid/console/search", get(search_console))
+        .route("/instance/:uuid/console/export", get(export_console))
         .with_state(state)
 }
@@ -1,8 +1,9 @@
+use std::collections::HashSet;
 use std::num::NonZeroU16;
 
 use axum::{
-    extract::Path,
-    routing::{get, put},
+    extract::{Path, Query},
+    routing::{delete, get, put},
     Json, Router,
 };
 use axum_auth::AuthBearer;
@@ -12,10 +13,17 @@ use serde_json::Value;
 use tracing::error;
 
 use crate::{
-    auth::user::UserAction,
+    auth::user::{User, UserAction},
     error::{Error, ErrorKind},
-    extension::{self, FetchExtensionManifestError},
-    prelude::lodestone_path,
+    extension::{
+        self,
+        r#macro::{MacroAvailability, MacroExtension},
+        ExtensionType, ExtensionUpdateRequest, ExtensionUpdateStatus, FetchExtensionManifestError,
+        InstalledExtension,
+    },
+    prelude::{lodestone_path, GameInstance},
+    traits::t_configurable::TConfigurable,
+    types::InstanceUuid,
     AppState,
 };
 
@@ -28,6 +36,34 @@ struct ExtensionRequestBody {
     url: String,
 }
 
+#[derive(serde::Deserialize)]
+struct InstallExtensionBody {
+    url: String,
+    /// instances a macro is made available to, every instance if absent
+    #[serde(default)]
+    instances: Option<HashSet<InstanceUuid>>,
+    /// approve the permissions the manifest asks for beyond the defaults
+    #[serde(default)]
+    approve_permissions: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct ApprovePermissionsQuery {
+    #[serde(default)]
+    approve_permissions: bool,
+}
+
+/// Only the owner can let an extension have more than the defaults
+fn permissions_approved(requester: &User, approve_permissions: bool) -> Result<bool, Error> {
+    if approve_permissions && !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner can approve the permissions of an extension"),
+        });
+    }
+    Ok(approve_permissions)
+}
+
 impl axum::response::IntoResponse for FetchExtensionManifestError {
     fn into_response(self) -> axum::response::Response {
         match self {
@@ -73,26 +109,201 @@ async fn fetch_extension_manifest(
 
 async fn install_extension(
     axum::extract::State(state): axum::extract::State<AppState>,
-    Json(body): Json<ExtensionRequestBody>,
-    // AuthBearer(token): AuthBearer,
+    AuthBearer(token): AuthBearer,
+    Json(body): Json<InstallExtensionBody>,
 ) -> Result<(), Error> {
-    // let requester = state
-    //     .users_manager
-    //     .read()
-    //     .await
-    //     .try_auth(&token)
-    //     .ok_or_else(|| Error {
-    //         kind: ErrorKind::Unauthorized,
-    //         source: eyre!("Token error"),
-    //     })?;
-    // requester.try_action(&UserAction::InstallExtension)?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::InstallExtension,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let permissions_approved = permissions_approved(&requester, body.approve_permissions)?;
+    let manager = extension_manager().await?;
+    manager
+        .install_extension(&body.url, body.instances, permissions_approved)
+        .await?;
+    Ok(())
+}
+
+async fn extension_manager() -> Result<extension::ExtensionManager, Error> {
     let path = lodestone_path().join("extensions");
     tokio::fs::create_dir_all(&path)
         .await
         .context("Failed to create extensions directory")?;
-    let manager = extension::ExtensionManager::new(path);
-    manager.install_extension(&body.url).await?;
-    Ok(())
+    Ok(extension::ExtensionManager::new(path))
+}
+
+async fn list_macro_extensions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<MacroExtension>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::InstallExtension,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(extension_manager().await?.list_macros()?))
+}
+
+async fn set_macro_extension_instances(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(name): Path<String>,
+    AuthBearer(token): AuthBearer,
+    Json(availability): Json<MacroAvailability>,
+) -> Result<Json<MacroExtension>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::InstallExtension,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(
+        extension_manager()
+            .await?
+            .set_macro_instances(&name, availability.instances)?,
+    ))
+}
+
+async fn list_extensions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<InstalledExtension>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::InstallExtension,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(extension_manager().await?.list_extensions().await?))
+}
+
+async fn check_extension_update(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((r#type, id)): Path<(ExtensionType, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<ExtensionUpdateStatus>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::InstallExtension,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(
+        extension_manager()
+            .await?
+            .check_update(&r#type, &id)
+            .await?,
+    ))
+}
+
+async fn update_extension(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((r#type, id)): Path<(ExtensionType, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<ExtensionUpdateRequest>,
+) -> Result<Json<InstalledExtension>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::InstallExtension,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let permissions_approved = permissions_approved(&requester, request.approve_permissions)?;
+    Ok(Json(
+        extension_manager()
+            .await?
+            .update_extension(&r#type, &id, request.tag.as_deref(), permissions_approved)
+            .await?,
+    ))
+}
+
+async fn rollback_extension(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((r#type, id)): Path<(ExtensionType, String)>,
+    Query(query): Query<ApprovePermissionsQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstalledExtension>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::InstallExtension,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let permissions_approved = permissions_approved(&requester, query.approve_permissions)?;
+    Ok(Json(
+        extension_manager()
+            .await?
+            .rollback_extension(&r#type, &id, permissions_approved)
+            .await?,
+    ))
+}
+
+async fn approve_atom_permissions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(id): Path<String>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstalledExtension>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::InstallExtension,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    permissions_approved(&requester, true)?;
+    Ok(Json(
+        extension_manager()
+            .await?
+            .approve_atom_permissions(&id)
+            .await?,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+struct UninstallExtensionQuery {
+    /// uninstall an atom even if instances still run it
+    #[serde(default)]
+    force: bool,
+}
+
+/// Generic instances whose bootstrap imports the atom at `path_to_atom`
+async fn instances_using_atom(state: &AppState, path_to_atom: &std::path::Path) -> Vec<String> {
+    let path_to_atom = path_to_atom.to_string_lossy().to_string();
+    let mut instances = Vec::new();
+    for entry in state.instances.iter() {
+        let GameInstance::GenericInstance(instance) = entry.value() else {
+            continue;
+        };
+        let bootstrap = tokio::fs::read_to_string(instance.path().await.join("run.ts"))
+            .await
+            .unwrap_or_default();
+        if bootstrap.contains(&path_to_atom) {
+            instances.push(instance.name().await);
+        }
+    }
+    instances
+}
+
+async fn uninstall_extension(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((r#type, id)): Path<(ExtensionType, String)>,
+    Query(query): Query<UninstallExtensionQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::InstallExtension,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let manager = extension_manager().await?;
+    let extension = manager.get_extension(&r#type, &id).await?;
+    if r#type == ExtensionType::Atom && !query.force {
+        let instances = instances_using_atom(&state, &manager.path_to_extension(&extension)).await;
+        if !instances.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Atom {} is used by {}, uninstall with force to remove it anyway",
+                    id,
+                    instances.join(", ")
+                ),
+            });
+        }
+    }
+    manager.uninstall_extension(&r#type, &id).await
 }
 
 pub fn get_extension_routes(state: AppState) -> Router {
@@ -100,5 +311,25 @@ pub fn get_extension_routes(state: AppState) -> Router {
         .route("/extension/gitstatus", get(is_git_installed))
         .route("/extension/fetchmanifest", get(fetch_extension_manifest))
         .route("/extension/install", put(install_extension))
+        .route("/extension/macro/list", get(list_macro_extensions))
+        .route(
+            "/extension/macro/:name/instances",
+            put(set_macro_extension_instances),
+        )
+        .route("/extension/list", get(list_extensions))
+        .route(
+            "/extension/check_update/:type/:id",
+            get(check_extension_update),
+        )
+        .route("/extension/update/:type/:id", put(update_extension))
+        .route("/extension/rollback/:type/:id", put(rollback_extension))
+        .route(
+            "/extension/atom/:id/approve_permissions",
+            put(approve_atom_permissions),
+        )
+        .route(
+            "/extension/uninstall/:type/:id",
+            delete(uninstall_extension),
+        )
         .with_state(state)
 }
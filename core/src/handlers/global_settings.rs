@@ -5,7 +5,11 @@ use axum::{
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
 
-use crate::{error::ErrorKind, AppState, Error, GlobalSettingsData};
+use crate::{
+    db::retention::RetentionRule, error::ErrorKind,
+    implementations::minecraft::mods::validate_mod_index, macro_limits::MacroLimits, AppState,
+    Error, GlobalSettingsData,
+};
 
 pub async fn get_core_settings(
     axum::extract::State(state): axum::extract::State<AppState>,
@@ -134,6 +138,109 @@ pub async fn change_core_playit_enabled(
     Ok(())
 }
 
+pub async fn change_mod_index(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(mod_index): Json<String>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change the mod index"),
+        });
+    }
+    let mod_index = if mod_index.is_empty() {
+        None
+    } else {
+        validate_mod_index(&mod_index)?;
+        Some(mod_index)
+    };
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_mod_index(mod_index)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_event_retention(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(event_retention): Json<Vec<RetentionRule>>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change event retention"),
+        });
+    }
+    for rule in &event_retention {
+        rule.validate()?;
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_event_retention(event_retention)
+        .await?;
+    Ok(())
+}
+
+pub async fn change_require_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(require_two_factor): Json<bool>,
+) -> Result<(), Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change the two-factor policy"),
+        });
+    }
+    if require_two_factor && !requester.two_factor_enabled() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Enable two-factor authentication for yourself first"),
+        });
+    }
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_require_two_factor_for_unsafe(require_two_factor)
+        .await?;
+    users_manager.set_two_factor_policy(require_two_factor);
+    Ok(())
+}
+
+pub async fn change_macro_limits(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(macro_limits): Json<MacroLimits>,
+) -> Result<(), Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Not authorized to change macro limits"),
+        });
+    }
+    macro_limits.validate()?;
+    state
+        .global_settings
+        .lock()
+        .await
+        .set_macro_limits(macro_limits)
+        .await?;
+    state.macro_executor.set_limits(macro_limits);
+    Ok(())
+}
+
 pub fn get_global_settings_routes(state: AppState) -> Router {
     Router::new()
         .route("/global_settings", get(get_core_settings))
@@ -144,5 +251,15 @@ pub fn get_global_settings_routes(state: AppState) -> Router {
             "/global_settings/playit_enabled",
             put(change_core_playit_enabled),
         )
+        .route("/global_settings/mod_index", put(change_mod_index))
+        .route(
+            "/global_settings/event_retention",
+            put(change_event_retention),
+        )
+        .route(
+            "/global_settings/require_two_factor",
+            put(change_require_two_factor),
+        )
+        .route("/global_settings/macro_limits", put(change_macro_limits))
         .with_state(state)
 }
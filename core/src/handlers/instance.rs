@@ -1,6 +1,9 @@
 use axum::routing::{delete, get, post};
 use axum::Router;
-use axum::{extract::Path, Json};
+use axum::{
+    extract::{Path, Query},
+    Json,
+};
 use axum_auth::AuthBearer;
 
 use bollard::container::ListContainersOptions;
@@ -8,24 +11,68 @@ use bollard::Docker;
 use color_eyre::eyre::{eyre, Context};
 use serde::Deserialize;
 use tracing::{error, info};
+use ts_rs::TS;
 
-use crate::auth::user::UserAction;
+use crate::auth::user::{User, UserAction};
 use crate::error::{Error, ErrorKind};
 use crate::events::{CausedBy, Event, ProgressionEndValue, ProgressionStartValue};
 
+use crate::implementations::bedrock::{self, BedrockInstance};
 use crate::implementations::generic;
-use crate::traits::t_configurable::GameType;
+use crate::instance_template::{
+    copy_instance_files, get_template, path_to_template_files, restore_copied_instance,
+};
+use crate::traits::t_configurable::{Game, GameType};
 
-use crate::implementations::minecraft::MinecraftInstance;
+use crate::implementations::minecraft::{Flavour, FlavourKind, MinecraftInstance};
 use crate::prelude::{path_to_instances, GameInstance};
 use crate::traits::t_configurable::manifest::SetupValue;
 use crate::traits::t_configurable::Game::Generic;
 use crate::traits::{t_configurable::TConfigurable, t_server::TServer, InstanceInfo, TInstance};
-use crate::types::{DotLodestoneConfig, InstanceUuid};
+use crate::types::{DotLodestoneConfig, InstanceUuid, Snowflake};
 use crate::{implementations::minecraft, traits::t_server::State, AppState};
 
 use super::instance_setup_configs::HandlerGameType;
 
+/// Generate a uuid whose short form, used in the instance directory name, is not taken yet
+fn new_instance_uuid(state: &AppState) -> InstanceUuid {
+    let mut instance_uuid = InstanceUuid::default();
+    for entry in state.instances.iter() {
+        if let Some(uuid) = entry.key().as_ref().get(0..8) {
+            if uuid == &instance_uuid.no_prefix()[0..8] {
+                instance_uuid = InstanceUuid::default();
+            }
+        }
+    }
+    instance_uuid
+}
+
+/// Give the user who created an instance full access to it
+async fn grant_creator_permissions(state: &AppState, creator: &User, uuid: &InstanceUuid) {
+    let mut users_manager = state.users_manager.write().await;
+    // the stored grants, the creator may be narrowed by an API token or the two-factor policy
+    let Some(mut perm) = users_manager
+        .get_user(&creator.uid)
+        .map(|user| user.permissions)
+    else {
+        return;
+    };
+    perm.can_start_instance.insert(uuid.clone());
+    perm.can_stop_instance.insert(uuid.clone());
+    perm.can_view_instance.insert(uuid.clone());
+    perm.can_read_instance_file.insert(uuid.clone());
+    perm.can_write_instance_file.insert(uuid.clone());
+    perm.can_manage_instance_players.insert(uuid.clone());
+    // ignore errors since we don't care if the permissions update fails
+    let _ = users_manager
+        .update_permissions(&creator.uid, perm, CausedBy::System)
+        .await
+        .map_err(|e| {
+            error!("Failed to update permissions: {:?}", e);
+            e
+        });
+}
+
 pub async fn get_instance_list(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
@@ -67,10 +114,23 @@ pub async fn get_instance_info(
     Ok(Json(instance.get_instance_info().await))
 }
 
+/// The setup config of either edition of Minecraft
+enum MinecraftSetupConfig {
+    Java(minecraft::SetupConfig),
+    Bedrock(bedrock::SetupConfig),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateInstanceQuery {
+    /// create the instance from the files of this template instead of a fresh download
+    template_id: Option<Snowflake>,
+}
+
 pub async fn create_minecraft_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     AuthBearer(token): AuthBearer,
     Path(game_type): Path<HandlerGameType>,
+    Query(query): Query<CreateInstanceQuery>,
     Json(manifest_value): Json<SetupValue>,
 ) -> Result<Json<InstanceUuid>, Error> {
     let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
@@ -78,29 +138,50 @@ pub async fn create_minecraft_instance(
         &UserAction::CreateInstance,
         state.global_settings.lock().await.safe_mode(),
     )?;
-    let mut perm = requester.permissions;
 
-    let mut instance_uuid = InstanceUuid::default();
-
-    for entry in state.instances.iter() {
-        if let Some(uuid) = entry.key().as_ref().get(0..8) {
-            if uuid == &instance_uuid.no_prefix()[0..8] {
-                instance_uuid = InstanceUuid::default();
+    let template = match query.template_id {
+        Some(template_id) => {
+            let template = get_template(&template_id).await?;
+            let game = match game_type {
+                HandlerGameType::MinecraftBedrock => Game::MinecraftBedrock,
+                _ => Flavour::from(FlavourKind::try_from(game_type)?).into(),
+            };
+            if template.game_type != game {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Template {} is not for this game type", template.name),
+                });
             }
+            Some(template)
         }
-    }
-
-    let instance_uuid = instance_uuid;
+        None => None,
+    };
 
-    let flavour = game_type.try_into()?;
+    let instance_uuid = new_instance_uuid(&state);
 
-    let setup_config = MinecraftInstance::construct_setup_config(manifest_value, flavour).await?;
+    let (name, port, setup_config) = match game_type {
+        HandlerGameType::MinecraftBedrock => {
+            let setup_config = BedrockInstance::construct_setup_config(manifest_value).await?;
+            (
+                setup_config.name.clone(),
+                setup_config.port,
+                MinecraftSetupConfig::Bedrock(setup_config),
+            )
+        }
+        _ => {
+            let setup_config =
+                MinecraftInstance::construct_setup_config(manifest_value, game_type.try_into()?)
+                    .await?;
+            (
+                setup_config.name.clone(),
+                setup_config.port,
+                MinecraftSetupConfig::Java(setup_config),
+            )
+        }
+    };
 
-    let setup_path = path_to_instances().join(format!(
-        "{}-{}",
-        setup_config.name,
-        &instance_uuid.no_prefix()[0..8]
-    ));
+    let setup_path =
+        path_to_instances().join(format!("{}-{}", name, &instance_uuid.no_prefix()[0..8]));
 
     tokio::fs::create_dir_all(&setup_path)
         .await
@@ -119,7 +200,7 @@ pub async fn create_minecraft_instance(
 
     tokio::task::spawn({
         let uuid = instance_uuid.clone();
-        let instance_name = setup_config.name.clone();
+        let instance_name = name;
         let event_broadcaster = state.event_broadcaster.clone();
         let caused_by = CausedBy::User {
             user_id: requester.uid.clone(),
@@ -135,16 +216,60 @@ pub async fn create_minecraft_instance(
                 caused_by,
             );
             event_broadcaster.send(progression_start_event);
-            let minecraft_instance = match minecraft::MinecraftInstance::new(
-                setup_config.clone(),
-                dot_lodestone_config,
-                setup_path.clone(),
-                &event_id,
-                state.event_broadcaster.clone(),
-                state.macro_executor.clone(),
-            )
-            .await
-            {
+            let minecraft_instance: Result<GameInstance, Error> = match (template, setup_config) {
+                // the template brings its own version and settings,
+                // only the name and port are taken from the setup
+                (Some(template), _) => {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        &event_id,
+                        format!("Copying files from template {}", template.name),
+                        5.0,
+                    ));
+                    match copy_instance_files(
+                        &path_to_template_files(&template.id),
+                        &setup_path,
+                        &[],
+                    )
+                    .await
+                    {
+                        Ok(()) => {
+                            restore_copied_instance(
+                                setup_path.clone(),
+                                dot_lodestone_config,
+                                instance_name.clone(),
+                                port,
+                                state.event_broadcaster.clone(),
+                                state.macro_executor.clone(),
+                            )
+                            .await
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                (None, MinecraftSetupConfig::Java(setup_config)) => {
+                    minecraft::MinecraftInstance::new(
+                        setup_config,
+                        dot_lodestone_config,
+                        setup_path.clone(),
+                        &event_id,
+                        state.event_broadcaster.clone(),
+                        state.macro_executor.clone(),
+                    )
+                    .await
+                    .map(Into::into)
+                }
+                (None, MinecraftSetupConfig::Bedrock(setup_config)) => BedrockInstance::new(
+                    setup_config,
+                    dot_lodestone_config,
+                    setup_path.clone(),
+                    &event_id,
+                    state.event_broadcaster.clone(),
+                    state.macro_executor.clone(),
+                )
+                .await
+                .map(Into::into),
+            };
+            let minecraft_instance = match minecraft_instance {
                 Ok(v) => {
                     event_broadcaster.send(Event::new_progression_event_end(
                         event_id,
@@ -170,27 +295,9 @@ pub async fn create_minecraft_instance(
                     return;
                 }
             };
-            let mut port_manager = state.port_manager.lock().await;
-            port_manager.add_port(setup_config.port);
-            perm.can_start_instance.insert(uuid.clone());
-            perm.can_stop_instance.insert(uuid.clone());
-            perm.can_view_instance.insert(uuid.clone());
-            perm.can_read_instance_file.insert(uuid.clone());
-            perm.can_write_instance_file.insert(uuid.clone());
-            // ignore errors since we don't care if the permissions update fails
-            let _ = state
-                .users_manager
-                .write()
-                .await
-                .update_permissions(&requester.uid, perm, CausedBy::System)
-                .await
-                .map_err(|e| {
-                    error!("Failed to update permissions: {:?}", e);
-                    e
-                });
-            state
-                .instances
-                .insert(uuid.clone(), minecraft_instance.into());
+            state.port_manager.lock().await.add_port(port);
+            grant_creator_permissions(&state, &requester, &uuid).await;
+            state.instances.insert(uuid.clone(), minecraft_instance);
         }
     });
     Ok(Json(instance_uuid))
@@ -212,16 +319,7 @@ pub async fn create_generic_instance(
         &UserAction::CreateInstance,
         state.global_settings.lock().await.safe_mode(),
     )?;
-    let mut instance_uuid = InstanceUuid::default();
-    for entry in state.instances.iter() {
-        if let Some(uuid) = entry.key().as_ref().get(0..8) {
-            if uuid == &instance_uuid.no_prefix()[0..8] {
-                instance_uuid = InstanceUuid::default();
-            }
-        }
-    }
-
-    let instance_uuid = instance_uuid;
+    let instance_uuid = new_instance_uuid(&state);
 
     let setup_path = path_to_instances().join(format!(
         "{}-{}",
@@ -306,6 +404,128 @@ pub async fn create_generic_instance(
     Ok(Json(()))
 }
 
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
+pub struct CloneInstanceRequest {
+    /// defaults to the name of the source instance followed by "(copy)"
+    pub name: Option<String>,
+}
+
+pub async fn clone_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<CloneInstanceRequest>,
+) -> Result<Json<InstanceUuid>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    requester.try_action(&UserAction::CreateInstance, safe_mode)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()), safe_mode)?;
+    let source = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    if source.state().await != State::Stopped {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Instance must be stopped before cloning"),
+        });
+    }
+    let game_type = GameType::from(&source.game_type().await);
+    if game_type == GameType::Generic {
+        return Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Generic instances cannot be cloned"),
+        });
+    }
+    let source_name = source.name().await;
+    let name = request
+        .name
+        .unwrap_or_else(|| format!("{} (copy)", source_name));
+
+    let instance_uuid = new_instance_uuid(&state);
+    let port = state
+        .port_manager
+        .lock()
+        .await
+        .allocate(source.port().await);
+    let setup_path =
+        path_to_instances().join(format!("{}-{}", name, &instance_uuid.no_prefix()[0..8]));
+    let dot_lodestone_config = DotLodestoneConfig::new(instance_uuid.clone(), game_type);
+
+    tokio::task::spawn({
+        let uuid = instance_uuid.clone();
+        let event_broadcaster = state.event_broadcaster.clone();
+        let caused_by = CausedBy::User {
+            user_id: requester.uid.clone(),
+            user_name: requester.username.clone(),
+        };
+        async move {
+            let (progression_start_event, event_id) = Event::new_progression_event_start(
+                format!("Cloning {source_name} into {name}"),
+                Some(10.0),
+                Some(ProgressionStartValue::InstanceCreation {
+                    instance_uuid: uuid.clone(),
+                }),
+                caused_by,
+            );
+            event_broadcaster.send(progression_start_event);
+            let instance = match copy_instance_files(&source.path().await, &setup_path, &[]).await {
+                Ok(()) => {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        &event_id,
+                        "Files copied",
+                        5.0,
+                    ));
+                    restore_copied_instance(
+                        setup_path.clone(),
+                        dot_lodestone_config,
+                        name,
+                        port,
+                        state.event_broadcaster.clone(),
+                        state.macro_executor.clone(),
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            let instance = match instance {
+                Ok(v) => {
+                    event_broadcaster.send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some("Instance cloned successfully"),
+                        Some(ProgressionEndValue::InstanceCreation(
+                            v.get_instance_info().await,
+                        )),
+                    ));
+                    v
+                }
+                Err(e) => {
+                    event_broadcaster.send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&format!("Instance cloning failed: {e}")),
+                        None,
+                    ));
+                    state.port_manager.lock().await.deallocate(port);
+                    if let Err(e) = crate::util::fs::remove_dir_all(setup_path).await {
+                        error!("Failed to remove directory after instance cloning failed: {e}");
+                    }
+                    return;
+                }
+            };
+            grant_creator_permissions(&state, &requester, &uuid).await;
+            state.instances.insert(uuid, instance);
+        }
+    });
+    Ok(Json(instance_uuid))
+}
+
 pub async fn delete_instance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -350,6 +570,7 @@ pub async fn delete_instance(
                     .context("Failed to delete .lodestone_config file. Instance not deleted")
                     .map_err(Into::into);
             }
+            state.scheduler.remove_instance(&uuid).await;
 
             state
                 .port_manager
@@ -402,5 +623,6 @@ pub fn get_instance_routes(state: AppState) -> Router {
         .route("/instance/create_generic", post(create_generic_instance))
         .route("/instance/:uuid", delete(delete_instance))
         .route("/instance/:uuid/info", get(get_instance_info))
+        .route("/instance/:uuid/clone", post(clone_instance))
         .with_state(state)
 }
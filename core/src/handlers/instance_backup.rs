@@ -0,0 +1,214 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use tracing::error;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    events::CausedBy,
+    traits::{
+        t_backup::{BackupEntry, BackupRetention, TBackup},
+        t_configurable::TConfigurable,
+    },
+    types::InstanceUuid,
+    AppState,
+};
+
+pub async fn get_backups(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BackupEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadResource(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.list_backups().await?))
+}
+
+pub async fn create_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::WriteResource(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    // progress is reported through progression events
+    tokio::spawn(async move {
+        if let Err(e) = instance.create_backup(caused_by).await {
+            error!("Failed to create backup: {}", e);
+        }
+    });
+    Ok(Json(()))
+}
+
+pub async fn delete_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, backup_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::WriteResource(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.delete_backup(&backup_name).await?;
+    Ok(Json(()))
+}
+
+pub async fn restore_backup(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, backup_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    requester.try_action(&UserAction::WriteResource(uuid.clone()), safe_mode)?;
+    // restoring stops and restarts the instance
+    requester.try_action(&UserAction::StopInstance(uuid.clone()), safe_mode)?;
+    requester.try_action(&UserAction::StartInstance(uuid.clone()), safe_mode)?;
+    let instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = instance.restore_backup(&backup_name, caused_by).await {
+            error!("Failed to restore backup {}: {}", backup_name, e);
+        }
+    });
+    Ok(Json(()))
+}
+
+pub async fn get_backup_retention(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<BackupRetention>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadResource(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.backup_retention().await?))
+}
+
+pub async fn set_backup_retention(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(retention): Json<BackupRetention>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::WriteResource(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.set_backup_retention(retention).await?;
+    Ok(Json(()))
+}
+
+pub async fn get_backup_period(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Option<u32>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ReadResource(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.backup_period().await))
+}
+
+pub async fn set_backup_period(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(backup_period): Json<Option<u32>>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.set_backup_period(backup_period).await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_backup_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/backups",
+            get(get_backups).post(create_backup),
+        )
+        .route(
+            "/instance/:uuid/backups/retention",
+            get(get_backup_retention).put(set_backup_retention),
+        )
+        .route(
+            "/instance/:uuid/backups/period",
+            get(get_backup_period).put(set_backup_period),
+        )
+        .route(
+            "/instance/:uuid/backups/:backup_name",
+            delete(delete_backup),
+        )
+        .route(
+            "/instance/:uuid/backups/:backup_name/restore",
+            post(restore_backup),
+        )
+        .with_state(state)
+}
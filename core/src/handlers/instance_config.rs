@@ -1,22 +1,41 @@
 use axum::{
     extract::Path,
-    routing::{get, put},
+    routing::{get, post, put},
     Json, Router,
 };
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use tracing::error;
+use ts_rs::TS;
 
 use crate::{
     auth::user::UserAction,
     error::{Error, ErrorKind},
-    traits::t_configurable::{
-        manifest::{ConfigurableManifest, ConfigurableValue},
-        TConfigurable,
+    events::CausedBy,
+    handlers::instance_setup_configs::HandlerGameType,
+    implementations::minecraft::FlavourKind,
+    log_rules::{CompiledLogRules, InstanceLogRules, LogRule, LogRuleTest, LogRuleTestResult},
+    prelude::GameInstance,
+    traits::{
+        t_configurable::{
+            manifest::{ConfigurableManifest, ConfigurableValue},
+            TConfigurable,
+        },
+        t_server::{State, TServer},
     },
     types::InstanceUuid,
     AppState,
 };
 
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct UpgradeRequest {
+    pub version: String,
+    /// switch to this flavour, keeps the current flavour if not set
+    pub flavour: Option<HandlerGameType>,
+}
+
 pub async fn get_instance_configurable_manifest(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -145,6 +164,141 @@ pub async fn change_version(
     Ok(Json(()))
 }
 
+pub async fn upgrade_instance(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<UpgradeRequest>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = match state.instances.get(&uuid).as_deref() {
+        Some(GameInstance::MinecraftInstance(instance)) => instance.clone(),
+        Some(_) => {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Upgrades are only supported for minecraft java instances"),
+            })
+        }
+        None => {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })
+        }
+    };
+    if instance.state().await != State::Stopped {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Cannot upgrade an instance that is not stopped"),
+        });
+    }
+    let flavour = request
+        .flavour
+        .map(FlavourKind::try_from)
+        .transpose()
+        .map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Cannot switch a java instance to bedrock"),
+        })?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    // the verification boot can take minutes, progress is reported through progression events
+    tokio::spawn(async move {
+        if let Err(e) = instance
+            .upgrade(request.flavour, request.version, caused_by)
+            .await
+        {
+            error!("Failed to upgrade instance: {}", e);
+        }
+    });
+    Ok(Json(()))
+}
+
+pub async fn get_log_rules(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceLogRules>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    Ok(Json(instance.log_rules().await?))
+}
+
+pub async fn set_log_rules(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(rules): Json<Vec<LogRule>>,
+) -> Result<Json<InstanceLogRules>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.set_log_rules(Some(rules)).await?;
+    Ok(Json(instance.log_rules().await?))
+}
+
+pub async fn reset_log_rules(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<InstanceLogRules>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("Instance not found"),
+    })?;
+    instance.set_log_rules(None).await?;
+    Ok(Json(instance.log_rules().await?))
+}
+
+/// Run sample lines through the rules of an instance, or through rules not saved yet
+pub async fn test_log_rules(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(test): Json<LogRuleTest>,
+) -> Result<Json<Vec<LogRuleTestResult>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessSetting(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let rules = match test.rules {
+        Some(rules) => rules,
+        None => {
+            let instance = state.instances.get(&uuid).ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Instance not found"),
+            })?;
+            instance.log_rules().await?.rules
+        }
+    };
+    Ok(Json(CompiledLogRules::new(rules)?.test(test.lines)))
+}
+
 pub fn get_instance_config_routes(state: AppState) -> Router {
     Router::new()
         .route(
@@ -152,6 +306,7 @@ pub fn get_instance_config_routes(state: AppState) -> Router {
             get(get_instance_configurable_manifest),
         )
         .route("/instance/:uuid/version/:new_version", put(change_version))
+        .route("/instance/:uuid/upgrade", put(upgrade_instance))
         .route("/instance/:uuid/settings", get(get_instance_settings))
         .route(
             "/instance/:uuid/settings/:section_id/:setting_id",
@@ -159,5 +314,12 @@ pub fn get_instance_config_routes(state: AppState) -> Router {
         )
         .route("/instance/:uuid/name", put(set_instance_name))
         .route("/instance/:uuid/description", put(set_instance_description))
+        .route(
+            "/instance/:uuid/log_rules",
+            get(get_log_rules)
+                .put(set_log_rules)
+                .delete(reset_log_rules),
+        )
+        .route("/instance/:uuid/log_rules/test", post(test_log_rules))
         .with_state(state)
 }
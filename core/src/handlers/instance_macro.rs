@@ -1,26 +1,38 @@
+use std::sync::Arc;
+
 use axum::{
-    extract::Path,
+    extract::{ws::WebSocket, Path, Query, WebSocketUpgrade},
+    response::{IntoResponse, Response},
     routing::{get, post, put},
     Json, Router,
 };
 
 use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use futures::{SinkExt, StreamExt};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    RwLock,
+};
+use tracing::{debug, error};
 use ts_rs::TS;
 
 use crate::traits::t_configurable::manifest::SettingManifest;
 use crate::{
-    auth::user::UserAction,
+    auth::user::{User, UserAction, UsersManager},
     error::{Error, ErrorKind},
     events::CausedBy,
     macro_executor::MacroPID,
+    macro_log::{MacroLogLine, MacroLogSnapshot},
     traits::t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry},
     types::InstanceUuid,
     AppState,
 };
 
+use super::util::parse_bearer_token;
+
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
 #[ts(export)]
 pub struct GetConfigResponse {
@@ -47,6 +59,113 @@ pub async fn get_instance_task_list(
     Ok(Json(tasks))
 }
 
+#[derive(Deserialize)]
+pub struct TaskLogQuery {
+    /// `Bearer <token>`, for websockets which cannot set headers
+    token: Option<String>,
+}
+
+/// What a task printed, or a live stream of it when the request is a websocket upgrade
+pub async fn get_task_log(
+    ws: Option<WebSocketUpgrade>,
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, pid)): Path<(InstanceUuid, MacroPID)>,
+    bearer: Option<AuthBearer>,
+    Query(query): Query<TaskLogQuery>,
+) -> Result<Response, Error> {
+    let token = bearer
+        .map(|AuthBearer(token)| token)
+        .or_else(|| query.token.as_deref().and_then(parse_bearer_token))
+        .ok_or_else(|| Error {
+            kind: ErrorKind::Unauthorized,
+            source: eyre!("Token error"),
+        })?;
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::AccessMacro(Some(uuid.clone())),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let not_found = || Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("No log kept for task {}", pid),
+    };
+    let macro_logs = state.macro_executor.macro_logs();
+    // a task of another instance is not shown
+    if macro_logs.instance_of(pid).flatten().as_ref() != Some(&uuid) {
+        return Err(not_found());
+    }
+    match ws {
+        Some(ws) => {
+            let (snapshot, rx) = macro_logs.subscribe(pid).ok_or_else(not_found)?;
+            Ok(ws.on_upgrade(move |socket| {
+                task_log_ws(socket, snapshot, rx, requester, state.users_manager)
+            }))
+        }
+        None => Ok(Json(macro_logs.snapshot(pid).ok_or_else(not_found)?).into_response()),
+    }
+}
+
+async fn task_log_ws(
+    stream: WebSocket,
+    snapshot: MacroLogSnapshot,
+    rx: Option<Receiver<MacroLogLine>>,
+    user: User,
+    users_manager: Arc<RwLock<UsersManager>>,
+) {
+    let (mut sender, mut receiver) = stream.split();
+    // the stable sort keeps the order of lines printed in the same millisecond
+    let mut lines = snapshot.stdout;
+    lines.extend(snapshot.stderr);
+    lines.sort_by_key(|line| line.time);
+    for line in lines {
+        if let Err(e) = sender
+            .send(axum::extract::ws::Message::Text(
+                serde_json::to_string(&line).unwrap(),
+            ))
+            .await
+        {
+            error!("Failed to send task log: {}", e);
+            return;
+        }
+    }
+    // the task already exited
+    let Some(mut rx) = rx else {
+        let _ = sender.close().await;
+        return;
+    };
+    loop {
+        tokio::select! {
+            line = rx.recv() => match line {
+                Ok(line) => {
+                    if users_manager.read().await.refresh_user(&user).is_none() {
+                        break;
+                    }
+                    if let Err(e) = sender
+                        .send(axum::extract::ws::Message::Text(
+                            serde_json::to_string(&line).unwrap(),
+                        ))
+                        .await
+                    {
+                        error!("Failed to send task log: {}", e);
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => {
+                    let _ = sender.close().await;
+                    break;
+                }
+            },
+            Some(Ok(ws_msg)) = receiver.next() => {
+                match sender.send(ws_msg).await {
+                    Ok(_) => debug!("Replied to ping"),
+                    Err(_) => break,
+                };
+            }
+        }
+    }
+}
+
 pub async fn get_instance_macro_list(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -231,6 +350,7 @@ pub fn get_instance_macro_routes(state: AppState) -> Router {
             post(store_config_to_local),
         )
         .route("/instance/:uuid/task/list", get(get_instance_task_list))
+        .route("/instance/:uuid/task/:pid/log", get(get_task_log))
         .route(
             "/instance/:uuid/history/list",
             get(get_instance_history_list),
@@ -0,0 +1,124 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    implementations::minecraft::{mods::ModEntry, MinecraftInstance},
+    prelude::GameInstance,
+    types::InstanceUuid,
+    AppState,
+};
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct ModInstallRequest {
+    pub project_id: String,
+    /// install this version instead of the newest compatible one
+    pub version_id: Option<String>,
+}
+
+async fn get_minecraft_instance(
+    state: &AppState,
+    uuid: &InstanceUuid,
+    token: &str,
+) -> Result<MinecraftInstance, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(token)?;
+    requester.try_action(
+        &UserAction::WriteResource(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    match state.instances.get(uuid).as_deref() {
+        Some(GameInstance::MinecraftInstance(instance)) => Ok(instance.clone()),
+        Some(_) => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Mods and plugins are only supported for minecraft java instances"),
+        }),
+        None => Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        }),
+    }
+}
+
+pub async fn get_mods(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ModEntry>>, Error> {
+    let instance = get_minecraft_instance(&state, &uuid, &token).await?;
+    Ok(Json(instance.list_mods().await?))
+}
+
+pub async fn install_mod(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<ModInstallRequest>,
+) -> Result<Json<Vec<ModEntry>>, Error> {
+    let instance = get_minecraft_instance(&state, &uuid, &token).await?;
+    let index = state.global_settings.lock().await.mod_index();
+    Ok(Json(
+        instance
+            .install_mod(&index, &request.project_id, request.version_id.as_deref())
+            .await?,
+    ))
+}
+
+pub async fn update_mod(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, file_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ModEntry>>, Error> {
+    let instance = get_minecraft_instance(&state, &uuid, &token).await?;
+    let index = state.global_settings.lock().await.mod_index();
+    Ok(Json(instance.update_mod(&index, &file_name).await?))
+}
+
+pub async fn enable_mod(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, file_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let instance = get_minecraft_instance(&state, &uuid, &token).await?;
+    instance.set_mod_enabled(&file_name, true).await?;
+    Ok(Json(()))
+}
+
+pub async fn disable_mod(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, file_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let instance = get_minecraft_instance(&state, &uuid, &token).await?;
+    instance.set_mod_enabled(&file_name, false).await?;
+    Ok(Json(()))
+}
+
+pub async fn delete_mod(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, file_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let instance = get_minecraft_instance(&state, &uuid, &token).await?;
+    instance.delete_mod(&file_name).await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_mods_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/:uuid/mods", get(get_mods))
+        .route("/instance/:uuid/mods/install", post(install_mod))
+        .route("/instance/:uuid/mods/:file_name", delete(delete_mod))
+        .route("/instance/:uuid/mods/:file_name/update", post(update_mod))
+        .route("/instance/:uuid/mods/:file_name/enable", put(enable_mod))
+        .route("/instance/:uuid/mods/:file_name/disable", put(disable_mod))
+        .with_state(state)
+}
@@ -1,15 +1,42 @@
 use std::collections::HashSet;
 
-use axum::{extract::Path, routing::get, Json, Router};
+use axum::{
+    extract::{Path, Query},
+    routing::{get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
 use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
 
 use crate::{
+    auth::user::UserAction,
+    db::{
+        read::{get_player_sessions, get_player_stats},
+        types::{PlayerSession, PlayerStatsReport},
+    },
     error::{Error, ErrorKind},
-    traits::t_player::{Player, TPlayerManagement},
+    events::CausedBy,
+    traits::t_player::{BanEntry, Player, TPlayerManagement},
     types::InstanceUuid,
     AppState,
 };
 
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct KickPlayerRequest {
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct BanPlayerRequest {
+    pub reason: Option<String>,
+    /// unix timestamp at which the ban is lifted, `None` for a permanent ban
+    pub expires: Option<i64>,
+}
+
 pub async fn get_player_count(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(uuid): Path<InstanceUuid>,
@@ -75,6 +102,312 @@ pub async fn get_player_list(
         .map(Json)
 }
 
+pub async fn kick_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<KickPlayerRequest>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePlayers(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .kick_player(&player_name, request.reason, caused_by)
+        .await
+        .map(Json)
+}
+
+pub async fn get_ban_list(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<BanEntry>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .get_ban_list()
+        .await
+        .map(Json)
+}
+
+pub async fn ban_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<BanPlayerRequest>,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePlayers(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .ban_player(&player_name, request.reason, request.expires, caused_by)
+        .await
+        .map(Json)
+}
+
+pub async fn pardon_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePlayers(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .pardon_player(&player_name, caused_by)
+        .await
+        .map(Json)
+}
+
+pub async fn get_op_list(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<HashSet<Player>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .get_op_list()
+        .await
+        .map(Json)
+}
+
+pub async fn op_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePlayers(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .op_player(&player_name, caused_by)
+        .await
+        .map(Json)
+}
+
+pub async fn deop_player(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePlayers(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .deop_player(&player_name, caused_by)
+        .await
+        .map(Json)
+}
+
+pub async fn get_whitelist(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<HashSet<Player>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .get_whitelist()
+        .await
+        .map(Json)
+}
+
+pub async fn add_to_whitelist(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePlayers(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .add_to_whitelist(&player_name, caused_by)
+        .await
+        .map(Json)
+}
+
+pub async fn remove_from_whitelist(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, player_name)): Path<(InstanceUuid, String)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePlayers(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .remove_from_whitelist(&player_name, caused_by)
+        .await
+        .map(Json)
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct PlayerHistoryQuery {
+    pub player_name: Option<String>,
+    /// unix timestamp, only sessions overlapping `start..end` are returned
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+pub async fn get_player_history(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    Query(query): Query<PlayerHistoryQuery>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PlayerSession>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    get_player_sessions(
+        &state.sqlite_pool,
+        &uuid,
+        query.player_name.as_deref(),
+        query.start,
+        query.end,
+    )
+    .await
+    .map(Json)
+}
+
+pub async fn get_player_statistics(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<PlayerStatsReport>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ViewInstance(uuid.clone()),
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    if !state.instances.contains_key(&uuid) {
+        return Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        });
+    }
+    get_player_stats(&state.sqlite_pool, &uuid).await.map(Json)
+}
+
 pub fn get_instance_players_routes(state: AppState) -> Router {
     Router::new()
         .route("/instance/:uuid/players/count", get(get_player_count))
@@ -83,5 +416,26 @@ pub fn get_instance_players_routes(state: AppState) -> Router {
             get(get_max_player_count).put(set_max_player_count),
         )
         .route("/instance/:uuid/players", get(get_player_list))
+        .route(
+            "/instance/:uuid/players/kick/:player_name",
+            post(kick_player),
+        )
+        .route("/instance/:uuid/players/history", get(get_player_history))
+        .route("/instance/:uuid/players/stats", get(get_player_statistics))
+        .route("/instance/:uuid/players/bans", get(get_ban_list))
+        .route(
+            "/instance/:uuid/players/bans/:player_name",
+            put(ban_player).delete(pardon_player),
+        )
+        .route("/instance/:uuid/players/ops", get(get_op_list))
+        .route(
+            "/instance/:uuid/players/ops/:player_name",
+            put(op_player).delete(deop_player),
+        )
+        .route("/instance/:uuid/players/whitelist", get(get_whitelist))
+        .route(
+            "/instance/:uuid/players/whitelist/:player_name",
+            put(add_to_whitelist).delete(remove_from_whitelist),
+        )
         .with_state(state)
 }
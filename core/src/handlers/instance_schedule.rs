@@ -0,0 +1,111 @@
+use axum::{
+    extract::Path,
+    routing::{get, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+
+use crate::{
+    auth::user::{User, UserAction},
+    error::{Error, ErrorKind},
+    prelude::GameInstance,
+    scheduler::{ScheduledJob, ScheduledJobConfig},
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+/// Managing the schedule requires access to the settings of the instance,
+/// plus whatever the scheduled action would require if the user ran it themselves
+fn check_schedule_permissions(
+    state: &AppState,
+    requester: &User,
+    uuid: &InstanceUuid,
+    config: Option<&ScheduledJobConfig>,
+    safe_mode: bool,
+) -> Result<GameInstance, Error> {
+    requester.try_action(&UserAction::AccessSetting(uuid.clone()), safe_mode)?;
+    if let Some(config) = config {
+        for action in config.action.required_permissions(uuid) {
+            requester.try_action(&action, safe_mode)?;
+        }
+    }
+    Ok(state
+        .instances
+        .get(uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone())
+}
+
+pub async fn get_schedule(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<ScheduledJob>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    check_schedule_permissions(&state, &requester, &uuid, None, safe_mode)?;
+    Ok(Json(state.scheduler.list_jobs(&uuid).await))
+}
+
+pub async fn create_scheduled_job(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<ScheduledJobConfig>,
+) -> Result<Json<ScheduledJob>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    let instance = check_schedule_permissions(&state, &requester, &uuid, Some(&config), safe_mode)?;
+    Ok(Json(
+        state
+            .scheduler
+            .add_job(&instance, config, requester.uid)
+            .await?,
+    ))
+}
+
+pub async fn update_scheduled_job(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, job_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<ScheduledJobConfig>,
+) -> Result<Json<ScheduledJob>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    let instance = check_schedule_permissions(&state, &requester, &uuid, Some(&config), safe_mode)?;
+    Ok(Json(
+        state
+            .scheduler
+            .update_job(&instance, &job_id, config, requester.uid)
+            .await?,
+    ))
+}
+
+pub async fn delete_scheduled_job(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uuid, job_id)): Path<(InstanceUuid, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    let instance = check_schedule_permissions(&state, &requester, &uuid, None, safe_mode)?;
+    state.scheduler.delete_job(&instance, &job_id).await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_schedule_routes(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/instance/:uuid/schedule",
+            get(get_schedule).post(create_scheduled_job),
+        )
+        .route(
+            "/instance/:uuid/schedule/:job_id",
+            put(update_scheduled_job).delete(delete_scheduled_job),
+        )
+        .with_state(state)
+}
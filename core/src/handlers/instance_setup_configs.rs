@@ -1,5 +1,6 @@
 use crate::error::Error;
 use crate::error::ErrorKind;
+use crate::implementations::bedrock;
 use crate::implementations::generic;
 use crate::implementations::minecraft;
 use crate::minecraft::FlavourKind;
@@ -24,6 +25,7 @@ pub enum HandlerGameType {
     MinecraftFabric,
     MinecraftForge,
     MinecraftPaper,
+    MinecraftSpigot,
     MinecraftBedrock,
 }
 
@@ -34,6 +36,7 @@ impl From<HandlerGameType> for GameType {
             HandlerGameType::MinecraftFabric => Self::MinecraftJava,
             HandlerGameType::MinecraftForge => Self::MinecraftJava,
             HandlerGameType::MinecraftPaper => Self::MinecraftJava,
+            HandlerGameType::MinecraftSpigot => Self::MinecraftJava,
             HandlerGameType::MinecraftBedrock => Self::MinecraftBedrock,
         }
     }
@@ -48,6 +51,7 @@ impl TryFrom<HandlerGameType> for FlavourKind {
             HandlerGameType::MinecraftFabric => Self::Fabric,
             HandlerGameType::MinecraftForge => Self::Forge,
             HandlerGameType::MinecraftPaper => Self::Paper,
+            HandlerGameType::MinecraftSpigot => Self::Spigot,
             HandlerGameType::MinecraftBedrock => {
                 return Err(Error {
                     kind: ErrorKind::BadRequest,
@@ -64,15 +68,19 @@ pub async fn get_available_games() -> Json<Vec<HandlerGameType>> {
         HandlerGameType::MinecraftFabric,
         HandlerGameType::MinecraftForge,
         HandlerGameType::MinecraftPaper,
+        HandlerGameType::MinecraftSpigot,
+        HandlerGameType::MinecraftBedrock,
     ])
 }
 
 pub async fn get_setup_manifest(
     Path(game_type): Path<HandlerGameType>,
 ) -> Result<Json<SetupManifest>, Error> {
-    minecraft::MinecraftInstance::setup_manifest(&game_type.try_into()?)
-        .await
-        .map(Json)
+    match game_type {
+        HandlerGameType::MinecraftBedrock => bedrock::BedrockInstance::setup_manifest().await,
+        _ => minecraft::MinecraftInstance::setup_manifest(&game_type.try_into()?).await,
+    }
+    .map(Json)
 }
 
 #[derive(Deserialize)]
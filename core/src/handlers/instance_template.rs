@@ -0,0 +1,86 @@
+use axum::{
+    extract::Path,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use ts_rs::TS;
+
+use crate::{
+    auth::user::UserAction,
+    error::{Error, ErrorKind},
+    instance_template::{self, InstanceTemplate},
+    types::{InstanceUuid, Snowflake},
+    AppState,
+};
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct CreateTemplateRequest {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+pub async fn get_templates(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<InstanceTemplate>>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::CreateInstance,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(instance_template::list_templates().await?))
+}
+
+pub async fn create_template(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uuid): Path<InstanceUuid>,
+    AuthBearer(token): AuthBearer,
+    Json(request): Json<CreateTemplateRequest>,
+) -> Result<Json<InstanceTemplate>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    let safe_mode = state.global_settings.lock().await.safe_mode();
+    requester.try_action(&UserAction::CreateInstance, safe_mode)?;
+    requester.try_action(&UserAction::ReadInstanceFile(uuid.clone()), safe_mode)?;
+    let instance = state
+        .instances
+        .get(&uuid)
+        .ok_or_else(|| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Instance not found"),
+        })?
+        .clone();
+    Ok(Json(
+        instance_template::create_template(
+            &instance,
+            request.name,
+            request.description.unwrap_or_default(),
+        )
+        .await?,
+    ))
+}
+
+pub async fn delete_template(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(template_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let requester = state.users_manager.read().await.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::CreateInstance,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    instance_template::delete_template(&template_id).await?;
+    Ok(Json(()))
+}
+
+pub fn get_instance_template_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/instance/templates", get(get_templates))
+        .route("/instance/templates/:template_id", delete(delete_template))
+        .route("/instance/:uuid/template", post(create_template))
+        .with_state(state)
+}
@@ -8,14 +8,19 @@ pub mod gateway;
 pub mod global_fs;
 pub mod global_settings;
 pub mod instance;
+pub mod instance_backup;
 pub mod instance_config;
 pub mod instance_fs;
 pub mod instance_macro;
+pub mod instance_mods;
 pub mod instance_players;
+pub mod instance_schedule;
 pub mod instance_server;
 pub mod instance_setup_configs;
+pub mod instance_template;
 pub mod monitor;
 pub mod playitgg;
+pub mod roles;
 pub mod setup;
 pub mod system;
 pub mod users;
@@ -0,0 +1,130 @@
+use crate::{
+    auth::{
+        role::{Role, RoleAssignment, RoleConfig},
+        user::UserAction,
+        user_id::UserId,
+    },
+    error::Error,
+    events::CausedBy,
+    types::Snowflake,
+    AppState,
+};
+
+use axum::{
+    extract::Path,
+    routing::{get, post, put},
+    Json, Router,
+};
+use axum_auth::AuthBearer;
+
+pub async fn get_roles(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<Role>>, Error> {
+    let users_manager = state.users_manager.read().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePermission,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(users_manager.list_roles()))
+}
+
+pub async fn new_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<RoleConfig>,
+) -> Result<Json<Role>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePermission,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    Ok(Json(
+        users_manager
+            .create_role(&requester, config.name, config.permissions)
+            .await?,
+    ))
+}
+
+pub async fn update_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(role_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<RoleConfig>,
+) -> Result<Json<Role>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePermission,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    Ok(Json(
+        users_manager
+            .update_role(
+                &requester,
+                role_id,
+                config.name,
+                config.permissions,
+                caused_by,
+            )
+            .await?,
+    ))
+}
+
+pub async fn delete_role(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(role_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePermission,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .delete_role(&requester, role_id, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+pub async fn assign_roles(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(assignment): Json<RoleAssignment>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManagePermission,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .assign_roles(&requester, &uid, assignment.roles, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+pub fn get_role_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/role/list", get(get_roles))
+        .route("/role", post(new_role))
+        .route("/role/:role_id", put(update_role).delete(delete_role))
+        .route("/user/:uid/roles", put(assign_roles))
+        .with_state(state)
+}
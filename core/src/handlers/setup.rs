@@ -1,4 +1,9 @@
-use axum::{extract::Path, Json, Router};
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path},
+    Json, Router,
+};
 use color_eyre::eyre::eyre;
 
 use crate::{
@@ -8,7 +13,7 @@ use crate::{
     AppState,
 };
 
-use super::users::LoginReply;
+use super::users::{client_ip, LoginReply};
 
 #[derive(serde::Deserialize)]
 pub struct OwnerSetup {
@@ -18,6 +23,7 @@ pub struct OwnerSetup {
 
 pub async fn setup_owner(
     axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     Path(key): Path<String>,
     Json(owner_setup): Json<OwnerSetup>,
 ) -> Result<Json<LoginReply>, Error> {
@@ -32,14 +38,16 @@ pub async fn setup_owner(
                 false,
                 UserPermission::default(),
             );
-            state
-                .users_manager
-                .write()
-                .await
+            let mut users_manager = state.users_manager.write().await;
+            users_manager
                 .add_user(owner.clone(), CausedBy::System)
                 .await?;
+            let tokens = users_manager
+                .create_session(&owner.uid, client_ip(connect_info))
+                .await?;
             Ok(Json(LoginReply {
-                token: owner.create_jwt()?,
+                token: tokens.token,
+                refresh_token: tokens.refresh_token,
                 user: owner.into(),
             }))
         }
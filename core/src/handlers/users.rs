@@ -1,23 +1,28 @@
 use crate::{
     auth::{
+        api_token::{ApiToken, NewApiToken, NewApiTokenReply, PublicApiToken},
         jwt_token::JwtToken,
         permission::UserPermission,
-        user::{PublicUser, User, UserAction},
+        session::PublicSession,
+        user::{LoginOutcome, PublicUser, User, UserAction},
         user_id::UserId,
     },
     error::{Error, ErrorKind},
     events::CausedBy,
+    types::Snowflake,
     AppState,
 };
 
+use std::net::{IpAddr, SocketAddr};
+
 use axum::{
-    extract::Path,
+    extract::{ConnectInfo, Path},
     routing::{delete, get, post, put},
     Json, Router,
 };
 use axum_auth::{AuthBasic, AuthBearer};
 
-use color_eyre::eyre::eyre;
+use color_eyre::eyre::{eyre, Context};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use ts_rs::TS;
@@ -30,6 +35,7 @@ pub struct NewUser {
 
 pub async fn new_user(
     axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     AuthBearer(token): AuthBearer,
     Json(config): Json<NewUser>,
 ) -> Result<Json<LoginReply>, Error> {
@@ -53,8 +59,12 @@ pub async fn new_user(
     users_manager
         .add_user(user.clone(), caused_by.clone())
         .await?;
+    let tokens = users_manager
+        .create_session(&user.uid, client_ip(connect_info))
+        .await?;
     Ok(Json(LoginReply {
-        token: user.create_jwt()?,
+        token: tokens.token,
+        refresh_token: tokens.refresh_token,
         user: user.into(),
     }))
 }
@@ -96,6 +106,7 @@ pub async fn logout(
     let mut users_manager = state.users_manager.write().await;
 
     let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
 
     if requester.uid != uid && !requester.can_perform_action(&UserAction::ManageUser) {
         return Err(Error {
@@ -126,6 +137,12 @@ pub async fn update_permissions(
         &UserAction::ManagePermission,
         state.global_settings.lock().await.safe_mode(),
     )?;
+    let user = users_manager.get_user(&uid).ok_or_else(|| Error {
+        kind: ErrorKind::NotFound,
+        source: eyre!("User not found"),
+    })?;
+    requester.check_outranks(&user)?;
+    requester.check_role_grant(&new_permissions)?;
     let caused_by = CausedBy::User {
         user_id: requester.uid.clone(),
         user_name: requester.username.clone(),
@@ -184,6 +201,7 @@ pub async fn rename_user(
     let mut users_manager = state.users_manager.write().await;
 
     let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
 
     if requester.uid != uid && !requester.can_perform_action(&UserAction::ManageUser) {
         return Err(Error {
@@ -215,6 +233,7 @@ pub async fn change_password(
     let mut users_manager = state.users_manager.write().await;
 
     let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
 
     if requester.uid != config.uid && !requester.can_perform_action(&UserAction::ManageUser) {
         return Err(Error {
@@ -249,26 +268,70 @@ pub async fn change_password(
 #[derive(Serialize, TS)]
 #[ts(export)]
 pub struct LoginReply {
+    /// Short-lived access token
     pub token: JwtToken,
+    /// Single use token to get a new `token` with once it expires
+    pub refresh_token: JwtToken,
     pub user: PublicUser,
 }
 
+pub fn client_ip(connect_info: Option<ConnectInfo<SocketAddr>>) -> Option<String> {
+    // the server listens on [::], so IPv4 clients show up as mapped IPv6 addresses
+    connect_info.map(|ConnectInfo(addr)| match addr.ip() {
+        IpAddr::V6(ip) => ip
+            .to_ipv4_mapped()
+            .map_or(IpAddr::V6(ip), IpAddr::V4)
+            .to_string(),
+        ip => ip.to_string(),
+    })
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum LoginResult {
+    LoggedIn(LoginReply),
+    /// Finish logging in at `/user/login/two_factor` with the challenge and a code
+    TwoFactorRequired {
+        challenge: JwtToken,
+    },
+}
+
 pub async fn login(
     axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     AuthBasic((username, password)): AuthBasic,
-) -> Result<Json<LoginReply>, Error> {
+) -> Result<Json<LoginResult>, Error> {
     if let Some(password) = password {
-        let users_manager = state.users_manager.read().await;
-
-        Ok(Json(LoginReply {
-            token: users_manager.login(&username, &password)?,
-            user: users_manager
-                .get_user_by_username(&username)
-                .ok_or_else(|| Error {
-                    kind: ErrorKind::NotFound,
-                    source: eyre!("User not found"),
-                })?
-                .into(),
+        let ip = client_ip(connect_info);
+        // every request needs the users manager, so it isn't held while hashing
+        let pending = state
+            .users_manager
+            .read()
+            .await
+            .start_login(&username, ip.as_deref())?;
+        let (pending, verified) = tokio::task::spawn_blocking(move || {
+            let verified = pending.verify(&password);
+            (pending, verified)
+        })
+        .await
+        .context("Failed to verify password in a blocking task")?;
+        let outcome = state
+            .users_manager
+            .write()
+            .await
+            .finish_login(pending, verified, ip)
+            .await?;
+
+        Ok(Json(match outcome {
+            LoginOutcome::LoggedIn(user, tokens) => LoginResult::LoggedIn(LoginReply {
+                token: tokens.token,
+                refresh_token: tokens.refresh_token,
+                user: user.into(),
+            }),
+            LoginOutcome::TwoFactorRequired(challenge) => {
+                LoginResult::TwoFactorRequired { challenge }
+            }
         }))
     } else {
         Err(Error {
@@ -300,6 +363,355 @@ pub async fn get_all_users(
     ))
 }
 
+pub async fn get_api_tokens(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PublicApiToken>>, Error> {
+    let users_manager = state.users_manager.read().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
+    if requester.uid != uid && !requester.can_perform_action(&UserAction::ManageUser) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You are not authorized to view other users' API tokens"),
+        });
+    }
+    Ok(Json(users_manager.list_api_tokens(&uid)?))
+}
+
+pub async fn new_api_token(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<NewApiToken>,
+) -> Result<Json<NewApiTokenReply>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
+    if requester.uid != uid {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You can only create API tokens for yourself"),
+        });
+    }
+    if config.name.trim().is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("API token name cannot be empty"),
+        });
+    }
+    if config
+        .expiry
+        .map_or(false, |expiry| expiry <= chrono::Utc::now().timestamp())
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("API token expiry must be in the future"),
+        });
+    }
+    if requester.restrict_permissions(&config.permissions) != config.permissions {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("API token permissions must be a subset of your own"),
+        });
+    }
+
+    let api_token = ApiToken::new(config.name, config.permissions, config.expiry);
+    let reply = NewApiTokenReply {
+        token: api_token.create_jwt(&uid)?,
+        api_token: PublicApiToken::from(&api_token),
+    };
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .add_api_token(&uid, api_token, caused_by)
+        .await?;
+    Ok(Json(reply))
+}
+
+pub async fn revoke_api_token(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path((uid, token_id)): Path<(UserId, Snowflake)>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
+    if requester.uid != uid && !requester.can_perform_action(&UserAction::ManageUser) {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("You are not authorized to revoke other users' API tokens"),
+        });
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .revoke_api_token(&uid, token_id, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+pub async fn refresh(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<Json<LoginReply>, Error> {
+    let (user, tokens) = state
+        .users_manager
+        .write()
+        .await
+        .refresh_session(&request.refresh_token, client_ip(connect_info))
+        .await?;
+    Ok(Json(LoginReply {
+        token: tokens.token,
+        refresh_token: tokens.refresh_token,
+        user: user.into(),
+    }))
+}
+
+pub async fn get_sessions(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<Vec<PublicSession>>, Error> {
+    let users_manager = state.users_manager.read().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
+    Ok(Json(users_manager.list_sessions(&requester)?))
+}
+
+pub async fn revoke_session(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(session_id): Path<Snowflake>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .revoke_session(&requester.uid, session_id, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct SignOutEverywhere {
+    /// Also revoke every API token
+    pub revoke_api_tokens: bool,
+}
+
+pub async fn sign_out_everywhere(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<SignOutEverywhere>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner can sign out every user"),
+        });
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .sign_out_everywhere(config.revoke_api_tokens, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorLogin {
+    pub challenge: String,
+    pub code: String,
+}
+
+pub async fn login_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Json(config): Json<TwoFactorLogin>,
+) -> Result<Json<LoginReply>, Error> {
+    let (user, tokens) = state
+        .users_manager
+        .write()
+        .await
+        .login_two_factor(&config.challenge, &config.code, client_ip(connect_info))
+        .await?;
+    Ok(Json(LoginReply {
+        token: tokens.token,
+        refresh_token: tokens.refresh_token,
+        user: user.into(),
+    }))
+}
+
+#[derive(Serialize, TS)]
+#[ts(export)]
+pub struct TwoFactorEnrollment {
+    /// Base32 encoded, for entering into an authenticator app by hand
+    pub secret: String,
+    /// `otpauth://` URI, usually shown as a QR code
+    pub provisioning_uri: String,
+}
+
+#[derive(Deserialize, TS)]
+#[ts(export)]
+pub struct TwoFactorCode {
+    /// From the authenticator app, or a recovery code
+    pub code: String,
+}
+
+pub async fn enroll_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<TwoFactorEnrollment>, Error> {
+    let issuer = state.global_settings.lock().await.core_name();
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
+    let (secret, provisioning_uri) = users_manager
+        .enroll_two_factor(&requester.uid, &issuer)
+        .await?;
+    Ok(Json(TwoFactorEnrollment {
+        secret,
+        provisioning_uri,
+    }))
+}
+
+pub async fn confirm_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<TwoFactorCode>,
+) -> Result<Json<Vec<String>>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    Ok(Json(
+        users_manager
+            .confirm_two_factor(&requester.uid, &config.code, caused_by)
+            .await?,
+    ))
+}
+
+pub async fn regenerate_recovery_codes(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<TwoFactorCode>,
+) -> Result<Json<Vec<String>>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    Ok(Json(
+        users_manager
+            .regenerate_recovery_codes(&requester.uid, &config.code, caused_by)
+            .await?,
+    ))
+}
+
+pub async fn disable_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    AuthBearer(token): AuthBearer,
+    Json(config): Json<TwoFactorCode>,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_manage_account()?;
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .disable_two_factor(&requester.uid, Some(&config.code), caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+/// For users who lost both their authenticator app and recovery codes
+pub async fn reset_two_factor(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    requester.try_action(
+        &UserAction::ManageUser,
+        state.global_settings.lock().await.safe_mode(),
+    )?;
+    if uid == requester.uid {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Use your own two-factor code to disable two-factor authentication"),
+        });
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager
+        .disable_two_factor(&uid, None, caused_by)
+        .await?;
+    Ok(Json(()))
+}
+
+/// Lift the lockout after too many failed logins
+pub async fn unlock_user(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(uid): Path<UserId>,
+    AuthBearer(token): AuthBearer,
+) -> Result<Json<()>, Error> {
+    let mut users_manager = state.users_manager.write().await;
+
+    let requester = users_manager.try_auth_or_err(&token)?;
+    if !requester.is_owner {
+        return Err(Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("Only the owner can unlock accounts"),
+        });
+    }
+    let caused_by = CausedBy::User {
+        user_id: requester.uid.clone(),
+        user_name: requester.username.clone(),
+    };
+    users_manager.unlock_user(&uid, caused_by).await?;
+    Ok(Json(()))
+}
+
 // return the thing created by Router::new() so we can nest it in main
 pub fn get_user_routes(state: AppState) -> Router {
     Router::new()
@@ -312,6 +724,25 @@ pub fn get_user_routes(state: AppState) -> Router {
         .route("/user/:uid/rename", put(rename_user))
         .route("/user/:uid/password", put(change_password))
         .route("/user/login", post(login))
+        .route("/user/login/two_factor", post(login_two_factor))
+        .route("/user/two_factor/enroll", post(enroll_two_factor))
+        .route("/user/two_factor/confirm", post(confirm_two_factor))
+        .route(
+            "/user/two_factor/recovery_codes",
+            post(regenerate_recovery_codes),
+        )
+        .route("/user/two_factor/disable", post(disable_two_factor))
+        .route("/user/:uid/two_factor", delete(reset_two_factor))
+        .route("/user/:uid/unlock", post(unlock_user))
         .route("/user/logout/:uid", post(logout))
+        .route("/user/refresh", post(refresh))
+        .route("/user/sessions", get(get_sessions))
+        .route("/user/sessions/:session_id", delete(revoke_session))
+        .route("/user/sign_out_everywhere", post(sign_out_everywhere))
+        .route(
+            "/user/:uid/api_tokens",
+            get(get_api_tokens).post(new_api_token),
+        )
+        .route("/user/:uid/api_tokens/:token_id", delete(revoke_api_token))
         .with_state(state)
 }
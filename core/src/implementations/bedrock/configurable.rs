@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use color_eyre::eyre::eyre;
+
+use crate::error::{Error, ErrorKind};
+use crate::implementations::minecraft::configurable::ServerPropertySetting;
+use crate::log_rules::{InstanceLogRules, LogRule};
+use crate::traits::t_configurable::manifest::{ConfigurableManifest, ConfigurableValue};
+use crate::traits::t_configurable::{Game, TConfigurable};
+
+use crate::types::InstanceUuid;
+
+use super::BedrockInstance;
+
+#[async_trait]
+impl TConfigurable for BedrockInstance {
+    async fn uuid(&self) -> InstanceUuid {
+        self.uuid.clone()
+    }
+
+    async fn name(&self) -> String {
+        self.config.lock().await.name.clone()
+    }
+
+    async fn game_type(&self) -> Game {
+        Game::MinecraftBedrock
+    }
+
+    async fn version(&self) -> String {
+        self.config.lock().await.version.clone()
+    }
+
+    async fn description(&self) -> String {
+        self.config.lock().await.description.clone()
+    }
+
+    async fn port(&self) -> u32 {
+        self.config.lock().await.port
+    }
+
+    async fn creation_time(&self) -> i64 {
+        self.creation_time
+    }
+
+    async fn path(&self) -> std::path::PathBuf {
+        self.path_to_instance.clone()
+    }
+
+    async fn auto_start(&self) -> bool {
+        self.config.lock().await.auto_start
+    }
+
+    async fn restart_on_crash(&self) -> bool {
+        self.config.lock().await.restart_on_crash
+    }
+
+    async fn set_name(&self, name: String) -> Result<(), Error> {
+        if name.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Name cannot be empty"),
+            });
+        }
+        if name.len() > 100 {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Name cannot be longer than 100 characters"),
+            });
+        }
+        self.config.lock().await.name = name;
+        self.write_config_to_file().await?;
+        Ok(())
+    }
+
+    async fn set_description(&self, description: String) -> Result<(), Error> {
+        self.config.lock().await.description = description;
+        self.write_config_to_file().await?;
+        Ok(())
+    }
+
+    async fn set_port(&self, port: u32) -> Result<(), Error> {
+        self.set_port_property(port).await?;
+        self.config.lock().await.port = port;
+        self.write_config_to_file().await
+    }
+
+    async fn set_auto_start(&self, auto_start: bool) -> Result<(), Error> {
+        self.config.lock().await.auto_start = auto_start;
+        self.write_config_to_file().await
+    }
+
+    async fn set_restart_on_crash(&self, restart_on_crash: bool) -> Result<(), Error> {
+        self.config.lock().await.restart_on_crash = restart_on_crash;
+        self.write_config_to_file().await
+    }
+
+    async fn log_rules(&self) -> Result<InstanceLogRules, Error> {
+        Ok(self.log_rules.lock().await.get())
+    }
+
+    async fn set_log_rules(&self, rules: Option<Vec<LogRule>>) -> Result<(), Error> {
+        self.log_rules.lock().await.set(rules).await
+    }
+
+    async fn configurable_manifest(&self) -> ConfigurableManifest {
+        self.configurable_manifest
+            .lock()
+            .await
+            .clear_section(ServerPropertySetting::get_section_id());
+        let _ = self.read_properties().await;
+        self.configurable_manifest.lock().await.clone()
+    }
+
+    async fn update_configurable(
+        &self,
+        section_id: &str,
+        setting_id: &str,
+        value: ConfigurableValue,
+    ) -> Result<(), Error> {
+        let _ = self.read_properties().await;
+        self.configurable_manifest
+            .lock()
+            .await
+            .update_setting_value(section_id, setting_id, value.clone())?;
+        self.sync_configurable_to_restore_config().await;
+        self.write_config_to_file().await?;
+        self.write_properties_to_file().await
+    }
+}
@@ -0,0 +1,96 @@
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+
+use crate::log_rules::{LogRule, LogRuleAction};
+
+/// Strip the `[2023-06-07 12:00:00:000 INFO] ` prefix BDS puts before every message
+pub fn parse_system_msg(msg: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^\[[^\]]+\] (.+)").unwrap();
+    }
+    RE.captures(msg.trim_end())
+        .ok()?
+        .map(|caps| caps.get(1).unwrap().as_str().to_string())
+}
+
+/// The built-in rules for BDS output, players are identified by their xuid,
+/// which offline mode servers leave empty
+pub fn default_log_rules() -> Vec<LogRule> {
+    vec![
+        LogRule::new(
+            "Server started",
+            r"^\[[^\]]+\] Server started\.$",
+            LogRuleAction::ServerStarted,
+        ),
+        LogRule::new(
+            "Player joined",
+            r"^\[[^\]]+\] Player connected: (?P<player>.+?), xuid: ?(?P<id>\d*)",
+            LogRuleAction::PlayerJoined,
+        ),
+        LogRule::new(
+            "Player left",
+            r"^\[[^\]]+\] Player disconnected: (?P<player>.+?), xuid: ?\d*",
+            LogRuleAction::PlayerLeft,
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_rules::{CompiledLogRules, LogMatch};
+
+    #[test]
+    fn test_parse_system_msg() {
+        assert_eq!(
+            parse_system_msg("[2023-06-07 12:00:00:000 INFO] Server started.\n"),
+            Some("Server started.".to_string())
+        );
+        assert_eq!(
+            parse_system_msg("NO LOG FILE! - setting up server logging..."),
+            None
+        );
+    }
+
+    #[test]
+    fn test_default_log_rules() {
+        let rules = CompiledLogRules::new(default_log_rules()).unwrap();
+        let parse = |line: &str| rules.parse(line).map(|(_, log_match)| log_match);
+        assert_eq!(
+            parse("[2023-06-07 12:00:00:000 INFO] Server started.\n"),
+            Some(LogMatch::ServerStarted)
+        );
+        assert_eq!(
+            parse("[2023-06-07 12:00:00:000 INFO] Starting Server"),
+            None
+        );
+        assert_eq!(
+            parse("[2023-06-07 12:00:01:000 INFO] Player connected: Steve, xuid: 2535412345678901"),
+            Some(LogMatch::PlayerJoined {
+                player: "Steve".to_string(),
+                id: Some("2535412345678901".to_string()),
+            })
+        );
+        // offline mode servers do not report a xuid
+        assert_eq!(
+            parse("[2023-06-07 12:00:01:000 INFO] Player connected: Some Player, xuid: "),
+            Some(LogMatch::PlayerJoined {
+                player: "Some Player".to_string(),
+                id: None,
+            })
+        );
+        assert_eq!(
+            parse("[2023-06-07 12:00:01:000 INFO] Player Spawned: Steve xuid: 1"),
+            None
+        );
+        // newer versions also print the player's pfid
+        assert_eq!(
+            parse(
+                "[2023-06-07 12:00:02:000 INFO] Player disconnected: Steve, xuid: 2535412345678901, pfid: 1a2b3c4d5e6f7a8b"
+            ),
+            Some(LogMatch::PlayerLeft {
+                player: "Steve".to_string(),
+            })
+        );
+    }
+}
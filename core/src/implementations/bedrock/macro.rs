@@ -0,0 +1,157 @@
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Context};
+use indexmap::IndexMap;
+
+use crate::implementations::minecraft::r#macro::{
+    compose_config_code, macro_extension_entries, resolve_macro, store_local_config,
+    validate_local_config,
+};
+use crate::macro_executor::MacroExecutor;
+use crate::macro_limits::read_macro_limits;
+use crate::traits::t_configurable::manifest::{SettingLocalCache, SettingManifest};
+use crate::{
+    error::Error,
+    events::CausedBy,
+    macro_executor::{DefaultWorkerOptionGenerator, MacroPID, SpawnResult},
+    traits::t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry},
+};
+
+use super::BedrockInstance;
+
+#[async_trait]
+impl TMacro for BedrockInstance {
+    async fn get_macro_list(&self) -> Result<Vec<MacroEntry>, Error> {
+        let mut ret = Vec::new();
+        for entry in
+            (std::fs::read_dir(&self.path_to_macros).context("Failed to read macro dir")?).flatten()
+        {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if path.is_dir() && (path.join("index.ts").exists() || path.join("index.js").exists()) {
+                ret.push(MacroEntry {
+                    last_run: self.macro_name_to_last_run.lock().await.get(&name).cloned(),
+                    name,
+                    path,
+                })
+            }
+        }
+        let extension_entries =
+            macro_extension_entries(&self.uuid, &ret, &self.macro_name_to_last_run.lock().await);
+        ret.extend(extension_entries);
+        ret.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ret)
+    }
+
+    async fn get_task_list(&self) -> Result<Vec<TaskEntry>, Error> {
+        let mut ret = Vec::new();
+        for (pid, task_entry) in self.pid_to_task_entry.lock().await.iter() {
+            if self.macro_executor.get_macro_status(*pid).await.is_none() {
+                ret.push(task_entry.clone());
+            }
+        }
+        ret.sort_by(|a, b| a.creation_time.cmp(&b.creation_time));
+        Ok(ret)
+    }
+
+    async fn get_history_list(&self) -> Result<Vec<HistoryEntry>, Error> {
+        let mut ret = Vec::new();
+        for (pid, task_entry) in self.pid_to_task_entry.lock().await.iter() {
+            if let Some(exit_status) = self.macro_executor.get_macro_status(*pid).await {
+                ret.push(HistoryEntry {
+                    task: task_entry.clone(),
+                    exit_status,
+                });
+            }
+        }
+        ret.sort_by(|a, b| b.exit_status.time().cmp(&a.exit_status.time()));
+        Ok(ret)
+    }
+
+    async fn delete_macro(&self, name: &str) -> Result<(), Error> {
+        crate::util::fs::remove_file(self.path_to_macros.join(name)).await?;
+        Ok(())
+    }
+
+    async fn create_macro(&self, name: &str, content: &str) -> Result<(), Error> {
+        crate::util::fs::write_all(self.path_to_macros.join(name), content.as_bytes().to_vec())
+            .await
+    }
+
+    async fn run_macro(
+        &self,
+        name: &str,
+        args: Vec<String>,
+        configs: Option<IndexMap<String, SettingLocalCache>>,
+        caused_by: CausedBy,
+    ) -> Result<TaskEntry, Error> {
+        let (path_to_macro, permissions) = resolve_macro(&self.path_to_macros, &self.uuid, name)
+            .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
+
+        let config_code = compose_config_code(configs)?;
+        let limits = read_macro_limits(&path_to_macro).await?;
+
+        let SpawnResult { macro_pid: pid, .. } = self
+            .macro_executor
+            .spawn(
+                path_to_macro,
+                args,
+                caused_by,
+                Box::new(DefaultWorkerOptionGenerator),
+                config_code,
+                permissions,
+                Some(self.uuid.clone()),
+                Some(limits),
+            )
+            .await?;
+        let entry = TaskEntry {
+            pid,
+            name: name.to_string(),
+            creation_time: chrono::Utc::now().timestamp(),
+        };
+        self.pid_to_task_entry
+            .lock()
+            .await
+            .insert(pid, entry.clone());
+        self.macro_name_to_last_run
+            .lock()
+            .await
+            .insert(name.to_string(), chrono::Utc::now().timestamp());
+
+        Ok(entry)
+    }
+
+    async fn kill_macro(&self, pid: MacroPID) -> Result<(), Error> {
+        self.macro_executor.abort_macro(pid)?;
+        Ok(())
+    }
+
+    async fn add_task(&self, task: TaskEntry) -> Result<(), Error> {
+        self.pid_to_task_entry.lock().await.insert(task.pid, task);
+        Ok(())
+    }
+
+    async fn get_macro_config(
+        &self,
+        name: &str,
+    ) -> Result<IndexMap<String, SettingManifest>, Error> {
+        let (path_to_macro, _) = resolve_macro(&self.path_to_macros, &self.uuid, name)
+            .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
+        MacroExecutor::get_config_manifest(&path_to_macro).await
+    }
+
+    async fn store_macro_config_to_local(
+        &self,
+        name: &str,
+        config_to_store: &IndexMap<String, SettingManifest>,
+    ) -> Result<(), Error> {
+        store_local_config(&self.path_to_macros, name, config_to_store)
+    }
+
+    async fn validate_local_config(
+        &self,
+        name: &str,
+        config_to_validate: Option<&IndexMap<String, SettingManifest>>,
+    ) -> Result<IndexMap<String, SettingLocalCache>, Error> {
+        validate_local_config(&self.path_to_macros, &self.uuid, name, config_to_validate).await
+    }
+}
@@ -0,0 +1,515 @@
+pub mod configurable;
+mod line_parser;
+pub mod r#macro;
+pub mod player;
+pub mod server;
+pub mod util;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use color_eyre::eyre::{eyre, Context};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::to_string_pretty;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Child;
+use tokio::sync::Mutex;
+use tracing::error;
+
+use crate::error::Error;
+use crate::event_broadcaster::EventBroadcaster;
+use crate::events::{Event, ProgressionEventID};
+use crate::implementations::crash_restart::CrashHistory;
+use crate::implementations::minecraft::configurable::ServerPropertySetting;
+use crate::implementations::minecraft::players_manager::PlayersManager;
+use crate::implementations::minecraft::util::read_properties_from_path;
+use crate::log_rules::LogRules;
+use crate::macro_executor::{MacroExecutor, MacroPID};
+use crate::traits::t_backup::TBackup;
+use crate::traits::t_configurable::manifest::{
+    ConfigurableManifest, ConfigurableValue, ConfigurableValueType, SectionManifest,
+    SettingManifest, SetupManifest, SetupValue,
+};
+use crate::traits::t_macro::TaskEntry;
+use crate::traits::t_server::State;
+use crate::traits::TInstance;
+use crate::types::{DotLodestoneConfig, InstanceUuid};
+use crate::util::{
+    download_file, format_byte, format_byte_download, unzip_file_async, UnzipOption,
+};
+
+use self::util::{bds_executable_name, get_bedrock_server_url, get_bedrock_versions};
+
+/// Files shipped in the BDS archive that hold user data and must never be overwritten
+pub const BDS_USER_FILES: [&str; 4] = [
+    "server.properties",
+    "allowlist.json",
+    "permissions.json",
+    "worlds",
+];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SetupConfig {
+    pub name: String,
+    pub version: String,
+    pub port: u32,
+    pub description: Option<String>,
+    pub auto_start: Option<bool>,
+    pub restart_on_crash: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RestoreConfig {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub port: u32,
+    pub auto_start: bool,
+    pub restart_on_crash: bool,
+    pub has_started: bool,
+}
+
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct BedrockInstance {
+    config: Arc<Mutex<RestoreConfig>>,
+    uuid: InstanceUuid,
+    creation_time: i64,
+    state: Arc<Mutex<State>>,
+    event_broadcaster: EventBroadcaster,
+    // file paths
+    path_to_instance: PathBuf,
+    path_to_config: PathBuf,
+    path_to_properties: PathBuf,
+
+    // directory paths
+    path_to_macros: PathBuf,
+
+    crash_history: CrashHistory,
+    process: Arc<Mutex<Option<Child>>>,
+    stdin: Arc<Mutex<Option<tokio::process::ChildStdin>>>,
+    system: Arc<Mutex<sysinfo::System>>,
+    players_manager: Arc<Mutex<PlayersManager>>,
+    configurable_manifest: Arc<Mutex<ConfigurableManifest>>,
+    macro_executor: MacroExecutor,
+    macro_name_to_last_run: Arc<Mutex<HashMap<String, i64>>>,
+    pid_to_task_entry: Arc<Mutex<IndexMap<MacroPID, TaskEntry>>>,
+    log_rules: Arc<Mutex<LogRules>>,
+}
+
+impl BedrockInstance {
+    pub async fn setup_manifest() -> Result<SetupManifest, Error> {
+        let versions = get_bedrock_versions()
+            .await
+            .context("Failed to get bedrock versions")?;
+
+        let version_setting = SettingManifest::new_value_with_type(
+            "version".to_string(),
+            "Version".to_string(),
+            "The version of Bedrock Dedicated Server to use".to_string(),
+            versions.first().cloned().map(ConfigurableValue::Enum),
+            ConfigurableValueType::Enum { options: versions },
+            None,
+            false,
+            true,
+        );
+
+        let port_setting = SettingManifest::new_value_with_type(
+            "port".to_string(),
+            "Port".to_string(),
+            "The UDP port to run the server on".to_string(),
+            Some(ConfigurableValue::UnsignedInteger(19132)),
+            ConfigurableValueType::UnsignedInteger {
+                min: Some(0),
+                max: Some(65535),
+            },
+            Some(ConfigurableValue::UnsignedInteger(19132)),
+            false,
+            true,
+        );
+
+        let mut section_1_map = IndexMap::new();
+
+        section_1_map.insert("version".to_string(), version_setting);
+        section_1_map.insert("port".to_string(), port_setting);
+
+        let section_1 = SectionManifest::new(
+            "section_1".to_string(),
+            "Basic Settings".to_string(),
+            "Basic settings for the server.".to_string(),
+            section_1_map,
+        );
+
+        let mut sections = IndexMap::new();
+
+        sections.insert("section_1".to_string(), section_1);
+
+        Ok(SetupManifest {
+            setting_sections: sections,
+        })
+    }
+
+    pub async fn construct_setup_config(setup_value: SetupValue) -> Result<SetupConfig, Error> {
+        Self::setup_manifest()
+            .await?
+            .validate_setup_value(&setup_value)?;
+
+        // ALL of the following unwraps are safe because we just validated the manifest value
+        let version = setup_value
+            .get_unique_setting("version")
+            .unwrap()
+            .get_value()
+            .unwrap()
+            .try_as_enum()
+            .unwrap();
+
+        let port = setup_value
+            .get_unique_setting("port")
+            .unwrap()
+            .get_value()
+            .unwrap()
+            .try_as_unsigned_integer()
+            .unwrap();
+
+        Ok(SetupConfig {
+            name: setup_value.name.clone(),
+            description: setup_value.description.clone(),
+            version: version.clone(),
+            port,
+            auto_start: Some(setup_value.auto_start),
+            restart_on_crash: Some(setup_value.restart_on_crash),
+        })
+    }
+
+    fn init_configurable_manifest() -> ConfigurableManifest {
+        let server_properties_section_manifest = SectionManifest::new(
+            ServerPropertySetting::get_section_id().to_string(),
+            "Server Properties Settings".to_string(),
+            "All settings in the server.properties file can be configured here".to_string(),
+            IndexMap::new(),
+        );
+
+        let mut setting_sections = IndexMap::new();
+
+        setting_sections.insert(
+            ServerPropertySetting::get_section_id().to_string(),
+            server_properties_section_manifest,
+        );
+
+        ConfigurableManifest::new(false, false, setting_sections)
+    }
+
+    /// Download the BDS archive of the given version and unpack it into `path_to_instance`,
+    /// leaving the files in `BDS_USER_FILES` untouched if they already exist
+    async fn install_server(
+        version: &str,
+        path_to_instance: &std::path::Path,
+        on_progress: &(dyn Fn(String, f64) + Send + Sync),
+    ) -> Result<(), Error> {
+        let url = get_bedrock_server_url(version)?;
+        let temp_dir = tempfile::tempdir_in(crate::prelude::path_to_tmp())
+            .context("Failed to create temp dir")?;
+        let downloaded = download_file(
+            &url,
+            temp_dir.path(),
+            Some("bedrock-server.zip"),
+            &move |dl| {
+                if let Some(total) = dl.total {
+                    on_progress(
+                        format!(
+                            "Downloading Bedrock Dedicated Server {}",
+                            format_byte_download(dl.downloaded, total)
+                        ),
+                        (dl.step as f64 / total as f64) * 4.0,
+                    );
+                } else {
+                    on_progress(
+                        format!(
+                            "Downloading Bedrock Dedicated Server {}",
+                            format_byte(dl.downloaded)
+                        ),
+                        0.0,
+                    );
+                }
+            },
+            true,
+        )
+        .await?;
+        let path_to_unpacked = temp_dir.path().join("bedrock-server");
+        unzip_file_async(&downloaded, UnzipOption::ToDir(path_to_unpacked.clone())).await?;
+
+        for entry in std::fs::read_dir(&path_to_unpacked)
+            .context("Failed to read unpacked Bedrock Dedicated Server")?
+            .flatten()
+        {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let dest = path_to_instance.join(&file_name);
+            if dest.exists() {
+                if BDS_USER_FILES.contains(&file_name.as_str()) {
+                    continue;
+                }
+                if dest.is_dir() {
+                    crate::util::fs::remove_dir_all(&dest).await?;
+                } else {
+                    crate::util::fs::remove_file(&dest).await?;
+                }
+            }
+            crate::util::fs::rename(entry.path(), dest).await?;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path_to_executable = path_to_instance.join(bds_executable_name());
+            tokio::fs::set_permissions(&path_to_executable, std::fs::Permissions::from_mode(0o755))
+                .await
+                .context(format!(
+                    "Failed to make {} executable",
+                    path_to_executable.display()
+                ))?;
+        }
+        Ok(())
+    }
+
+    pub async fn new(
+        config: SetupConfig,
+        dot_lodestone_config: DotLodestoneConfig,
+        path_to_instance: PathBuf,
+        progression_event_id: &ProgressionEventID,
+        event_broadcaster: EventBroadcaster,
+        macro_executor: MacroExecutor,
+    ) -> Result<BedrockInstance, Error> {
+        let path_to_config = path_to_instance.join(".lodestone_bedrock_config.json");
+        let path_to_macros = path_to_instance.join("macros");
+        let path_to_properties = path_to_instance.join("server.properties");
+
+        // Step 1: Create Directories
+        event_broadcaster.send(Event::new_progression_event_update(
+            progression_event_id,
+            "1/3: Creating directories",
+            1.0,
+        ));
+        tokio::fs::create_dir_all(&path_to_instance)
+            .await
+            .and(tokio::fs::create_dir_all(&path_to_macros).await)
+            .context("Could not create some files or directories for instance")
+            .map_err(|e| {
+                error!("{e}");
+                e
+            })?;
+
+        // Step 2: Download and unpack the server
+        Self::install_server(&config.version, &path_to_instance, &{
+            let event_broadcaster = event_broadcaster.clone();
+            move |message: String, progress: f64| {
+                event_broadcaster.send(Event::new_progression_event_update(
+                    progression_event_id,
+                    format!("2/3: {message}"),
+                    progress,
+                ));
+            }
+        })
+        .await?;
+
+        // Step 3: Finishing Up
+        event_broadcaster.send(Event::new_progression_event_update(
+            progression_event_id,
+            "3/3: Finishing up",
+            1.0,
+        ));
+
+        let restore_config = RestoreConfig {
+            name: config.name,
+            version: config.version,
+            description: config.description.unwrap_or_default(),
+            port: config.port,
+            auto_start: config.auto_start.unwrap_or(false),
+            restart_on_crash: config.restart_on_crash.unwrap_or(false),
+            has_started: false,
+        };
+        // create config file
+        tokio::fs::write(
+            &path_to_config,
+            to_string_pretty(&restore_config).context(
+                "Failed to serialize config to string. This is a bug, please report it.",
+            )?,
+        )
+        .await
+        .context(format!(
+            "Failed to write config file at {}",
+            &path_to_config.display()
+        ))?;
+        let instance = BedrockInstance::restore(
+            path_to_instance,
+            dot_lodestone_config,
+            event_broadcaster,
+            macro_executor,
+        )
+        .await?;
+        // the archive ships its own server.properties, make it use the port the user picked
+        instance.set_port_property(restore_config.port).await?;
+        Ok(instance)
+    }
+
+    pub async fn restore(
+        path_to_instance: PathBuf,
+        dot_lodestone_config: DotLodestoneConfig,
+        event_broadcaster: EventBroadcaster,
+        macro_executor: MacroExecutor,
+    ) -> Result<BedrockInstance, Error> {
+        let path_to_config = path_to_instance.join(".lodestone_bedrock_config.json");
+        let restore_config: RestoreConfig =
+            serde_json::from_reader(std::fs::File::open(&path_to_config).context(format!(
+                "Failed to open config file at {}",
+                &path_to_config.display()
+            ))?)
+            .context(
+                "Failed to deserialize config from string. Was the config file modified manually?",
+            )?;
+        let path_to_macros = path_to_instance.join("macros");
+        let path_to_properties = path_to_instance.join("server.properties");
+        // if the properties file doesn't exist, create it
+        if !path_to_properties.exists() {
+            tokio::fs::write(
+                &path_to_properties,
+                format!("server-port={}", restore_config.port),
+            )
+            .await
+            .context("Failed to write to server.properties")?;
+        };
+
+        let log_rules = LogRules::load(&path_to_instance, line_parser::default_log_rules()).await?;
+
+        let instance = BedrockInstance {
+            state: Arc::new(Mutex::new(State::Stopped)),
+            uuid: dot_lodestone_config.uuid().clone(),
+            creation_time: dot_lodestone_config.creation_time(),
+            players_manager: Arc::new(Mutex::new(PlayersManager::new(
+                event_broadcaster.clone(),
+                dot_lodestone_config.uuid().clone(),
+            ))),
+            config: Arc::new(Mutex::new(restore_config)),
+            path_to_instance,
+            path_to_config,
+            path_to_properties,
+            path_to_macros,
+            macro_executor,
+            event_broadcaster,
+            crash_history: CrashHistory::default(),
+            process: Arc::new(Mutex::new(None)),
+            system: Arc::new(Mutex::new(sysinfo::System::new_all())),
+            stdin: Arc::new(Mutex::new(None)),
+            configurable_manifest: Arc::new(Mutex::new(Self::init_configurable_manifest())),
+            macro_name_to_last_run: Arc::new(Mutex::new(HashMap::new())),
+            pid_to_task_entry: Arc::new(Mutex::new(IndexMap::new())),
+            log_rules: Arc::new(Mutex::new(log_rules)),
+        };
+        instance
+            .read_properties()
+            .await
+            .context("Failed to read properties")?;
+        Ok(instance)
+    }
+
+    async fn write_config_to_file(&self) -> Result<(), Error> {
+        tokio::fs::write(
+            &self.path_to_config,
+            to_string_pretty(&*self.config.lock().await)
+                .context("Failed to serialize config to string, this is a bug, please report it")?,
+        )
+        .await
+        .context(format!(
+            "Failed to write config to file at {}",
+            &self.path_to_config.display()
+        ))?;
+        Ok(())
+    }
+
+    async fn read_properties(&self) -> Result<(), Error> {
+        let properties = read_properties_from_path(&self.path_to_properties).await?;
+        let mut lock = self.configurable_manifest.lock().await;
+        for (key, value) in properties.iter() {
+            let _ = lock
+                .set_setting(
+                    ServerPropertySetting::get_section_id(),
+                    match ServerPropertySetting::from_key_val(key, value) {
+                        Ok(v) => v.into(),
+                        Err(e) => {
+                            error!(
+                                "Failed to parse property {} with value {}: {}",
+                                key, value, e
+                            );
+                            continue;
+                        }
+                    },
+                )
+                .map_err(|e| {
+                    error!("Failed to set property {} to {}: {}", key, value, e);
+                });
+        }
+        Ok(())
+    }
+
+    async fn write_properties_to_file(&self) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(&self.path_to_properties)
+            .await
+            .context(format!(
+                "Failed to open properties file at {}",
+                &self.path_to_properties.display()
+            ))?;
+        let mut setting_str = "".to_string();
+        for (key, value) in self
+            .configurable_manifest
+            .lock()
+            .await
+            .get_section(ServerPropertySetting::get_section_id())
+            .unwrap()
+            .all_settings()
+            .iter()
+        {
+            setting_str.push_str(&format!(
+                "{}={}\n",
+                key,
+                value
+                    .get_value()
+                    .expect("Programming error, value is not set")
+                    .to_string()
+            ));
+        }
+        file.write_all(setting_str.as_bytes())
+            .await
+            .context(format!(
+                "Failed to write properties to file at {}",
+                &self.path_to_properties.display()
+            ))?;
+        Ok(())
+    }
+
+    async fn set_port_property(&self, port: u32) -> Result<(), Error> {
+        let port = u16::try_from(port).map_err(|_| eyre!("Port {} is out of range", port))?;
+        self.configurable_manifest.lock().await.set_setting(
+            ServerPropertySetting::get_section_id(),
+            ServerPropertySetting::ServerPort(port).into(),
+        )?;
+        self.write_properties_to_file().await
+    }
+
+    async fn sync_configurable_to_restore_config(&self) {
+        let port = self
+            .configurable_manifest
+            .lock()
+            .await
+            .get_unique_setting_key("server-port")
+            .and_then(|v| v.get_value().cloned())
+            .and_then(|v| v.try_as_unsigned_integer().ok());
+        if let Some(port) = port {
+            self.config.lock().await.port = port;
+        }
+    }
+}
+
+impl TBackup for BedrockInstance {}
+
+impl TInstance for BedrockInstance {}
@@ -0,0 +1,220 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::implementations::minecraft::configurable::ServerPropertySetting;
+use crate::implementations::minecraft::player::MinecraftPlayer;
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_player::{ModerationAction, Player, TPlayerManagement};
+use crate::traits::t_server::{State, TServer};
+use crate::types::Snowflake;
+
+use super::BedrockInstance;
+
+const ALLOWLIST_FILE: &str = "allowlist.json";
+
+/// An entry of BDS's `allowlist.json`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct AllowlistEntry {
+    #[serde(default)]
+    ignores_player_limit: bool,
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    xuid: Option<String>,
+}
+
+/// Gamertags end up in console commands, so only allow what Xbox Live allows
+fn validate_gamertag(player_name: &str) -> Result<(), Error> {
+    if player_name.is_empty()
+        || player_name.len() > 16
+        || player_name.starts_with(' ')
+        || !player_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '_')
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} is not a valid gamertag", player_name),
+        });
+    }
+    Ok(())
+}
+
+impl BedrockInstance {
+    async fn read_allowlist(&self) -> Result<Vec<AllowlistEntry>, Error> {
+        let path = self.path_to_instance.join(ALLOWLIST_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = crate::util::fs::read_to_string(&path).await?;
+        if content.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(
+            serde_json::from_str(&content)
+                .context(format!("Failed to parse {}", path.display()))?,
+        )
+    }
+
+    /// Apply a moderation action, through the console if the server is running,
+    /// or by editing `allowlist.json` directly if it is not
+    async fn moderate(
+        &self,
+        player_name: &str,
+        command: String,
+        allowlist_edit: Option<bool>,
+        action: ModerationAction,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        validate_gamertag(player_name)?;
+        if self.state().await == State::Running {
+            self.send_command(&command, caused_by.clone()).await?;
+        } else if let Some(add) = allowlist_edit {
+            let mut entries = self.read_allowlist().await?;
+            entries.retain(|e| !e.name.eq_ignore_ascii_case(player_name));
+            if add {
+                entries.push(AllowlistEntry {
+                    ignores_player_limit: false,
+                    name: player_name.to_string(),
+                    xuid: None,
+                });
+            }
+            crate::util::fs::write_all(
+                self.path_to_instance.join(ALLOWLIST_FILE),
+                serde_json::to_string_pretty(&entries)
+                    .context("Failed to serialize allowlist, this is a bug, please report it")?,
+            )
+            .await?;
+        } else {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Instance is not running"),
+            });
+        }
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: self.uuid.clone(),
+                instance_name: self.name().await,
+                instance_event_inner: InstanceEventInner::PlayerModeration {
+                    player: player_name.to_string(),
+                    action,
+                },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TPlayerManagement for BedrockInstance {
+    async fn get_player_count(&self) -> Result<u32, Error> {
+        Ok(self.players_manager.lock().await.count())
+    }
+
+    async fn get_max_player_count(&self) -> Result<u32, Error> {
+        self.configurable_manifest
+            .lock()
+            .await
+            .get_unique_setting_key(&ServerPropertySetting::MaxPlayers(0).get_identifier())
+            .and_then(|v| v.get_value().map(|v| v.try_as_unsigned_integer()))
+            .unwrap_or(Ok(10))
+    }
+
+    async fn get_player_list(&self) -> Result<HashSet<Player>, Error> {
+        Ok(self.players_manager.lock().await.clone().into())
+    }
+
+    async fn kick_player(
+        &self,
+        player_name: &str,
+        reason: Option<String>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let reason = reason
+            .map(|r| r.replace(['\n', '\r'], " ").trim().to_string())
+            .filter(|r| !r.is_empty());
+        self.moderate(
+            player_name,
+            format!(
+                "kick \"{}\" {}",
+                player_name,
+                reason.clone().unwrap_or_default()
+            ),
+            None,
+            ModerationAction::Kick { reason },
+            caused_by,
+        )
+        .await
+    }
+
+    async fn get_whitelist(&self) -> Result<HashSet<Player>, Error> {
+        Ok(self
+            .read_allowlist()
+            .await?
+            .into_iter()
+            .map(|entry| MinecraftPlayer::new(entry.name, entry.xuid).into())
+            .collect())
+    }
+
+    async fn add_to_whitelist(&self, player_name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        self.moderate(
+            player_name,
+            format!("allowlist add \"{}\"", player_name),
+            Some(true),
+            ModerationAction::WhitelistAdd,
+            caused_by,
+        )
+        .await
+    }
+
+    async fn remove_from_whitelist(
+        &self,
+        player_name: &str,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        self.moderate(
+            player_name,
+            format!("allowlist remove \"{}\"", player_name),
+            Some(false),
+            ModerationAction::WhitelistRemove,
+            caused_by,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_gamertag() {
+        assert!(validate_gamertag("Steve").is_ok());
+        assert!(validate_gamertag("Some Player 42").is_ok());
+        assert!(validate_gamertag("").is_err());
+        assert!(validate_gamertag(" Steve").is_err());
+        assert!(validate_gamertag("Steve\" op \"Alex").is_err());
+        assert!(validate_gamertag("Steve\nop Alex").is_err());
+    }
+
+    #[test]
+    fn test_allowlist_entry_format() {
+        let entries: Vec<AllowlistEntry> = serde_json::from_str(
+            r#"[{"ignoresPlayerLimit":false,"name":"Steve","xuid":"2535412345678901"},{"name":"Alex"}]"#,
+        )
+        .unwrap();
+        assert_eq!(entries[0].xuid.as_deref(), Some("2535412345678901"));
+        assert_eq!(
+            serde_json::to_string(&entries[1]).unwrap(),
+            r#"{"ignoresPlayerLimit":false,"name":"Alex"}"#
+        );
+    }
+}
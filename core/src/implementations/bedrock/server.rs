@@ -0,0 +1,781 @@
+use std::collections::VecDeque;
+use std::process::Stdio;
+
+use color_eyre::eyre::{eyre, Context};
+use sysinfo::{Pid, PidExt, ProcessExt, SystemExt};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::implementations::crash_restart::restart_after_crash;
+use crate::implementations::minecraft::player::MinecraftPlayer;
+use crate::implementations::minecraft::r#macro::resolve_macro_invocation;
+use crate::log_rules::LogMatch;
+use crate::macro_executor::{DefaultWorkerOptionGenerator, SpawnResult};
+use crate::macro_limits::{read_macro_limits, MacroLimits};
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_macro::TaskEntry;
+use crate::traits::t_server::{MonitorReport, State, StateAction, TServer};
+
+use crate::types::Snowflake;
+use crate::util::dont_spawn_terminal;
+
+use super::line_parser::parse_system_msg;
+use super::util::bds_executable_name;
+use super::BedrockInstance;
+use tracing::{error, info, warn};
+
+/// Number of console lines included in the error event when an instance crashes
+const CRASH_REPORT_LINES: usize = 20;
+
+impl BedrockInstance {
+    fn state_transition_event(
+        &self,
+        name: String,
+        state: State,
+        details: &str,
+        caused_by: CausedBy,
+    ) {
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: name,
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: InstanceEventInner::StateTransition { to: state },
+            }),
+            snowflake: Snowflake::default(),
+            details: details.to_string(),
+            caused_by,
+        });
+    }
+
+    async fn run_prelaunch(&self, name: &str) {
+        let Some(prelaunch) = resolve_macro_invocation(&self.path_to_instance, "prelaunch") else {
+            info!("[{}] No prelaunch script found, skipping", name);
+            return;
+        };
+        // a malformed header still runs under the global limits
+        let limits = read_macro_limits(&prelaunch)
+            .await
+            .unwrap_or(MacroLimits::UNLIMITED);
+        if let Ok(SpawnResult {
+            macro_pid: pid,
+            exit_future,
+            detach_future,
+        }) = self
+            .macro_executor
+            .spawn(
+                prelaunch,
+                Vec::new(),
+                CausedBy::System,
+                Box::new(DefaultWorkerOptionGenerator),
+                None,
+                None,
+                Some(self.uuid.clone()),
+                Some(limits),
+            )
+            .await
+        {
+            self.pid_to_task_entry.lock().await.insert(
+                pid,
+                TaskEntry {
+                    pid,
+                    name: "prelaunch".to_string(),
+                    creation_time: chrono::Utc::now().timestamp(),
+                },
+            );
+            tokio::select! {
+                _ = exit_future => {
+                    info!("Prelaunch script exited");
+                }
+                _ = detach_future => {
+                    info!("Prelaunch script requested detach");
+                }
+            }
+        }
+    }
+
+    /// Handle a line of BDS output, returns true if the line signals the server has started
+    async fn handle_line(&self, line: &str, name: &str) -> bool {
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: self.uuid.clone(),
+                instance_event_inner: InstanceEventInner::InstanceOutput {
+                    message: line.to_string(),
+                },
+                instance_name: name.to_string(),
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by: CausedBy::System,
+        });
+        if parse_system_msg(line).is_some() {
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: self.uuid.clone(),
+                    instance_event_inner: InstanceEventInner::SystemMessage {
+                        message: line.to_string(),
+                    },
+                    instance_name: name.to_string(),
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: CausedBy::System,
+            });
+        }
+        let log_match = {
+            let log_rules = self.log_rules.lock().await.compiled();
+            log_rules.parse(line).map(|(_, log_match)| log_match)
+        };
+        let instance_event_inner = match log_match {
+            Some(LogMatch::ServerStarted) => return true,
+            Some(LogMatch::PlayerJoined { player, id }) => {
+                self.players_manager
+                    .lock()
+                    .await
+                    .add_player(MinecraftPlayer::new(player, id), name.to_string());
+                None
+            }
+            Some(LogMatch::PlayerLeft { player }) => {
+                self.players_manager
+                    .lock()
+                    .await
+                    .remove_by_name(player, name.to_string());
+                None
+            }
+            Some(LogMatch::PlayerMessage { player, message }) => {
+                Some(InstanceEventInner::PlayerMessage {
+                    player,
+                    player_message: message,
+                })
+            }
+            Some(LogMatch::Warning { message }) => {
+                Some(InstanceEventInner::InstanceWarning { message })
+            }
+            Some(LogMatch::Error { message }) => {
+                Some(InstanceEventInner::InstanceError { message })
+            }
+            None => None,
+        };
+        if let Some(instance_event_inner) = instance_event_inner {
+            self.event_broadcaster.send(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: self.uuid.clone(),
+                    instance_event_inner,
+                    instance_name: name.to_string(),
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: CausedBy::System,
+            });
+        }
+        false
+    }
+}
+
+#[async_trait::async_trait]
+impl TServer for BedrockInstance {
+    async fn start(&self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
+        let config = self.config.lock().await.clone();
+        self.state.lock().await.try_transition(
+            StateAction::UserStart,
+            Some(&|state| {
+                self.state_transition_event(
+                    config.name.clone(),
+                    state,
+                    "Starting server",
+                    cause_by.clone(),
+                )
+            }),
+        )?;
+
+        // BDS listens on UDP, so a TCP port check is not enough
+        if std::net::UdpSocket::bind(("0.0.0.0", config.port as u16)).is_err() {
+            *self.state.lock().await = State::Stopped;
+            self.state_transition_event(
+                config.name.clone(),
+                State::Stopped,
+                "Port already in use",
+                cause_by.clone(),
+            );
+            return Err(Error {
+                kind: ErrorKind::Internal,
+                source: eyre!("Port {} is already in use", config.port),
+            });
+        }
+
+        self.run_prelaunch(&config.name).await;
+
+        let mut server_start_command =
+            Command::new(self.path_to_instance.join(bds_executable_name()));
+        // BDS ships its own shared libraries next to the executable
+        if std::env::consts::OS == "linux" {
+            server_start_command.env("LD_LIBRARY_PATH", &self.path_to_instance);
+        }
+        let server_start_command = server_start_command.current_dir(&self.path_to_instance);
+
+        // subscribe before spawning so the "started" transition cannot be missed
+        let mut rx = self.event_broadcaster.subscribe();
+
+        let mut proc = match dont_spawn_terminal(server_start_command)
+            .stdout(Stdio::piped())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(proc) => proc,
+            Err(e) => {
+                error!("Failed to start server, {}", e);
+                self.state
+                    .lock()
+                    .await
+                    .try_transition(
+                        StateAction::InstanceStop,
+                        Some(&|state| {
+                            self.state_transition_event(
+                                config.name.clone(),
+                                state,
+                                "Failed to start server",
+                                cause_by.clone(),
+                            )
+                        }),
+                    )
+                    .unwrap();
+                Err(e).context("Failed to start server")?;
+                unreachable!();
+            }
+        };
+        let stdin = proc
+            .stdin
+            .take()
+            .ok_or_else(|| eyre!("Failed to take stdin during startup"))?;
+        self.stdin.lock().await.replace(stdin);
+        let stdout = proc
+            .stdout
+            .take()
+            .ok_or_else(|| eyre!("Failed to take stdout during startup"))?;
+        let stderr = proc
+            .stderr
+            .take()
+            .ok_or_else(|| eyre!("Failed to take stderr during startup"))?;
+        *self.process.lock().await = Some(proc);
+
+        tokio::task::spawn({
+            let __self = self.clone();
+            let name = config.name.clone();
+            let cause_by = cause_by.clone();
+            async move {
+                let mut did_start = false;
+                let mut last_lines = VecDeque::with_capacity(CRASH_REPORT_LINES);
+                let mut stdout_lines = BufReader::new(stdout).lines();
+                let mut stderr_lines = BufReader::new(stderr).lines();
+
+                let (mut stdout_done, mut stderr_done) = (false, false);
+                while !(stdout_done && stderr_done) {
+                    let (line, is_stdout) = tokio::select! {
+                        line = stdout_lines.next_line(), if !stdout_done => (line, true),
+                        line = stderr_lines.next_line(), if !stderr_done => (line, false),
+                    };
+                    let line = match line {
+                        Ok(Some(line)) => line,
+                        Ok(None) | Err(_) => {
+                            if let Err(e) = line {
+                                error!("[{}] Failed to read from stdout/stderr: {}", name, e);
+                            }
+                            // keep draining the other stream, it may still hold the crash output
+                            if is_stdout {
+                                stdout_done = true;
+                            } else {
+                                stderr_done = true;
+                            }
+                            continue;
+                        }
+                    };
+                    if !is_stdout {
+                        warn!("[{}] {}", name, line);
+                    }
+                    if last_lines.len() == CRASH_REPORT_LINES {
+                        last_lines.pop_front();
+                    }
+                    last_lines.push_back(line.clone());
+                    if __self.handle_line(&line, &name).await && !did_start {
+                        did_start = true;
+                        let _ = __self.state.lock().await.try_transition(
+                            StateAction::InstanceStart,
+                            Some(&|state| {
+                                __self.state_transition_event(
+                                    name.clone(),
+                                    state,
+                                    "Server started",
+                                    cause_by.clone(),
+                                )
+                            }),
+                        );
+                        info!("[{}] Instance started", name);
+                    }
+                }
+                info!("Instance {} process shutdown", name);
+                let exit_code = match __self.process.lock().await.take() {
+                    Some(mut proc) => proc.wait().await.ok().and_then(|s| s.code()),
+                    None => None,
+                };
+                __self.stdin.lock().await.take();
+                // a process that exits while we are not stopping it has crashed
+                let crashed = __self.state().await.crashed_on_exit();
+                let _ = __self.state.lock().await.try_transition(
+                    if crashed {
+                        StateAction::InstanceCrash
+                    } else {
+                        StateAction::InstanceStop
+                    },
+                    Some(&|state| {
+                        __self.state_transition_event(
+                            name.clone(),
+                            state,
+                            if crashed {
+                                "Instance crashed as server process exited unexpectedly"
+                            } else {
+                                "Instance stopping as server process exited"
+                            },
+                            cause_by.clone(),
+                        )
+                    }),
+                );
+                __self.players_manager.lock().await.clear(name.clone());
+                if crashed {
+                    error!("[{}] Instance crashed with exit code {:?}", name, exit_code);
+                    __self.event_broadcaster.send(Event {
+                        event_inner: EventInner::InstanceEvent(InstanceEvent {
+                            instance_name: name.clone(),
+                            instance_uuid: __self.uuid.clone(),
+                            instance_event_inner: InstanceEventInner::InstanceError {
+                                message: format!(
+                                    "Instance crashed with exit code {}. Last console output:\n{}",
+                                    exit_code
+                                        .map(|c| c.to_string())
+                                        .unwrap_or_else(|| "unknown".to_string()),
+                                    Vec::from(last_lines).join("\n")
+                                ),
+                            },
+                        }),
+                        snowflake: Snowflake::default(),
+                        details: "".to_string(),
+                        caused_by: CausedBy::System,
+                    });
+                    restart_after_crash(&__self, &__self.crash_history, &__self.event_broadcaster)
+                        .await;
+                }
+            }
+        });
+        self.config.lock().await.has_started = true;
+        self.write_config_to_file().await?;
+
+        if block {
+            while let Ok(event) = rx.recv().await {
+                if let EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: event_instance_uuid,
+                    instance_event_inner: InstanceEventInner::StateTransition { to },
+                    ..
+                }) = event.event_inner
+                {
+                    if self.uuid == event_instance_uuid {
+                        if to == State::Running {
+                            return Ok(()); // Instance started successfully
+                        } else if to == State::Stopped || to == State::Error {
+                            return Err(
+                                eyre!("Instance exited unexpectedly before starting").into()
+                            );
+                        }
+                    }
+                }
+            }
+            Err(eyre!("Sender shutdown").into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn stop(&self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
+        let name = self.name().await;
+        self.state.lock().await.try_transition(
+            StateAction::UserStop,
+            Some(&|state| {
+                self.state_transition_event(
+                    name.clone(),
+                    state,
+                    "Stopping server",
+                    cause_by.clone(),
+                )
+            }),
+        )?;
+        let mut rx = self.event_broadcaster.subscribe();
+        self.stdin
+            .lock()
+            .await
+            .as_mut()
+            .ok_or_else(|| {
+                error!("[{}] Failed to stop instance: stdin not available", name);
+                eyre!("Failed to stop instance: stdin not available")
+            })?
+            .write_all(b"stop\n")
+            .await
+            .context("Failed to write to stdin")
+            .map_err(|e| {
+                error!("[{}] Failed to stop instance: {}", name, e);
+                e
+            })?;
+
+        if block {
+            while let Ok(event) = rx.recv().await {
+                if let EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: event_instance_uuid,
+                    instance_event_inner: InstanceEventInner::StateTransition { to },
+                    ..
+                }) = event.event_inner
+                {
+                    if self.uuid == event_instance_uuid && to == State::Stopped {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(eyre!("Sender shutdown").into())
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn restart(&self, caused_by: CausedBy, block: bool) -> Result<(), Error> {
+        if block {
+            self.stop(caused_by.clone(), block).await?;
+            self.start(caused_by, block).await
+        } else {
+            self.state
+                .lock()
+                .await
+                .try_new_state(StateAction::UserStop, None)?;
+
+            let __self = self.clone();
+            tokio::task::spawn(async move {
+                if let Err(e) = __self.stop(caused_by.clone(), true).await {
+                    error!("Failed to stop instance for restart: {}", e);
+                    return;
+                }
+                if let Err(e) = __self.start(caused_by, false).await {
+                    error!("Failed to start instance for restart: {}", e);
+                }
+            });
+            Ok(())
+        }
+    }
+
+    async fn kill(&self, _cause_by: CausedBy) -> Result<(), Error> {
+        let name = self.name().await;
+        if self.state().await == State::Stopped {
+            warn!("[{}] Instance is already stopped", name);
+            return Err(eyre!("Instance is already stopped").into());
+        }
+        // so the process exiting is not mistaken for a crash
+        *self.state.lock().await = State::Stopping;
+        match self.process.lock().await.as_mut() {
+            Some(process) => {
+                process
+                    .kill()
+                    .await
+                    .context("Failed to kill process")
+                    .map_err(|e| {
+                        error!("[{}] Failed to kill instance: {}", name, e);
+                        e
+                    })?;
+                Ok(())
+            }
+            None => {
+                error!(
+                    "[{}] Process not available, assuming instance is stopped",
+                    name
+                );
+                *self.state.lock().await = State::Stopped;
+                self.event_broadcaster
+                    .send(Event::new_instance_state_transition(
+                        self.uuid.clone(),
+                        name,
+                        State::Stopped,
+                    ));
+                Err(eyre!("Process not available, assuming instance is stopped").into())
+            }
+        }
+    }
+
+    async fn state(&self) -> State {
+        *self.state.lock().await
+    }
+
+    async fn send_command(&self, command: &str, cause_by: CausedBy) -> Result<(), Error> {
+        let name = self.name().await;
+        if self.state().await == State::Stopped {
+            return Err(eyre!("Instance is stopped").into());
+        }
+        match self.stdin.lock().await.as_mut() {
+            Some(stdin) => {
+                if command == "stop" {
+                    self.state.lock().await.try_transition(
+                        StateAction::UserStop,
+                        Some(&|state| {
+                            self.state_transition_event(
+                                name.clone(),
+                                state,
+                                "Stopping server",
+                                cause_by.clone(),
+                            )
+                        }),
+                    )?;
+                }
+                stdin
+                    .write_all(format!("{}\n", command).as_bytes())
+                    .await
+                    .context("Failed to send command to instance")
+                    .map_err(|e| {
+                        warn!("[{}] Failed to send command to instance: {}", name, e);
+                        e
+                    })?;
+                Ok(())
+            }
+            None => {
+                let err_msg =
+                    "Failed to write to stdin because stdin is None. Please report this bug.";
+                error!("[{}] {}", name, err_msg);
+                Err(eyre!(err_msg).into())
+            }
+        }
+    }
+
+    async fn monitor(&self) -> MonitorReport {
+        let mut sys = self.system.lock().await;
+        sys.refresh_memory();
+        if let Some(pid) = self.process.lock().await.as_ref().and_then(|p| p.id()) {
+            sys.refresh_process(Pid::from_u32(pid));
+            if let Some(proc) = sys.process(Pid::from_u32(pid)) {
+                MonitorReport {
+                    memory_usage: Some(proc.memory()),
+                    disk_usage: Some(proc.disk_usage().into()),
+                    cpu_usage: Some(proc.cpu_usage() / sys.cpus().len() as f32),
+                    start_time: Some(proc.start_time()),
+                }
+            } else {
+                MonitorReport::default()
+            }
+        } else {
+            MonitorReport::default()
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use tokio::sync::broadcast::Receiver;
+
+    use super::*;
+    use crate::event_broadcaster::EventBroadcaster;
+    use crate::implementations::bedrock::RestoreConfig;
+    use crate::macro_executor::MacroExecutor;
+    use crate::traits::t_configurable::GameType;
+    use crate::traits::t_player::{Player, TPlayerManagement};
+    use crate::types::{DotLodestoneConfig, InstanceUuid};
+
+    /// Mimics the console of a Bedrock Dedicated Server, with extra commands to fake player activity
+    const FAKE_BDS: &str = r#"#!/bin/sh
+echo "NO LOG FILE! - setting up server logging..."
+echo "[2023-06-07 12:00:00:000 INFO] Starting Server"
+echo "[2023-06-07 12:00:00:000 INFO] Version 1.20.1.02"
+echo "[2023-06-07 12:00:00:000 INFO] Server started."
+while read -r cmd player xuid; do
+    case "$cmd" in
+        fake-join) echo "[2023-06-07 12:00:01:000 INFO] Player connected: $player, xuid: $xuid" ;;
+        fake-leave) echo "[2023-06-07 12:00:02:000 INFO] Player disconnected: $player, xuid: $xuid, pfid: 1a2b3c4d" ;;
+        fake-crash) echo "Segmentation fault" >&2; exit 139 ;;
+        stop) echo "[2023-06-07 12:00:03:000 INFO] Server stop requested."; echo "Quit correctly"; exit 0 ;;
+    esac
+done
+"#;
+
+    fn free_udp_port() -> u32 {
+        std::net::UdpSocket::bind(("0.0.0.0", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port() as u32
+    }
+
+    async fn fake_instance(path: &Path) -> (BedrockInstance, Receiver<Event>) {
+        let port = free_udp_port();
+        std::fs::write(path.join(bds_executable_name()), FAKE_BDS).unwrap();
+        std::fs::set_permissions(
+            path.join(bds_executable_name()),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        std::fs::create_dir_all(path.join("macros")).unwrap();
+        std::fs::write(
+            path.join("server.properties"),
+            format!("server-name=Dedicated Server\nserver-port={port}\nmax-players=10\n"),
+        )
+        .unwrap();
+        std::fs::write(
+            path.join(".lodestone_bedrock_config.json"),
+            serde_json::to_string(&RestoreConfig {
+                name: "bedrock".to_string(),
+                version: "1.20.1.02".to_string(),
+                description: "".to_string(),
+                port,
+                auto_start: false,
+                restart_on_crash: false,
+                has_started: false,
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        let (event_broadcaster, rx) = EventBroadcaster::new(64);
+        let macro_executor =
+            MacroExecutor::new(event_broadcaster.clone(), tokio::runtime::Handle::current());
+        let instance = BedrockInstance::restore(
+            path.to_owned(),
+            DotLodestoneConfig::new(InstanceUuid::default(), GameType::MinecraftBedrock),
+            event_broadcaster,
+            macro_executor,
+        )
+        .await
+        .unwrap();
+        (instance, rx)
+    }
+
+    async fn wait_for_player_change(rx: &mut Receiver<Event>) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let EventInner::InstanceEvent(InstanceEvent {
+                    instance_event_inner: InstanceEventInner::PlayerChange { .. },
+                    ..
+                }) = rx.recv().await.unwrap().event_inner
+                {
+                    return;
+                }
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fake_bds_lifecycle() {
+        let temp_dir = tempdir::TempDir::new("bedrock_test").unwrap();
+        let (instance, mut rx) = fake_instance(temp_dir.path()).await;
+        assert_eq!(instance.get_max_player_count().await.unwrap(), 10);
+
+        instance.start(CausedBy::System, true).await.unwrap();
+        assert_eq!(instance.state().await, State::Running);
+
+        instance
+            .send_command("fake-join Steve 2535412345678901", CausedBy::System)
+            .await
+            .unwrap();
+        wait_for_player_change(&mut rx).await;
+        assert_eq!(instance.get_player_count().await.unwrap(), 1);
+        let players = instance.get_player_list().await.unwrap();
+        assert_eq!(
+            players.into_iter().next().unwrap(),
+            Player::from(MinecraftPlayer::new(
+                "Steve".to_string(),
+                Some("2535412345678901".to_string())
+            ))
+        );
+
+        instance
+            .send_command("fake-leave Steve 2535412345678901", CausedBy::System)
+            .await
+            .unwrap();
+        wait_for_player_change(&mut rx).await;
+        assert_eq!(instance.get_player_count().await.unwrap(), 0);
+
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            instance.stop(CausedBy::System, true),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(instance.state().await, State::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_fake_bds_crash() {
+        let temp_dir = tempdir::TempDir::new("bedrock_test").unwrap();
+        let (instance, mut rx) = fake_instance(temp_dir.path()).await;
+        instance.start(CausedBy::System, true).await.unwrap();
+        instance
+            .send_command("fake-crash", CausedBy::System)
+            .await
+            .unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let EventInner::InstanceEvent(InstanceEvent {
+                    instance_event_inner: InstanceEventInner::InstanceError { message },
+                    ..
+                }) = rx.recv().await.unwrap().event_inner
+                {
+                    return message;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(message.contains("exit code 139"));
+        assert!(message.contains("Segmentation fault"));
+        assert_eq!(instance.state().await, State::Error);
+    }
+
+    #[tokio::test]
+    async fn test_fake_bds_console_stop() {
+        let temp_dir = tempdir::TempDir::new("bedrock_test").unwrap();
+        let (instance, mut rx) = fake_instance(temp_dir.path()).await;
+        instance.start(CausedBy::System, true).await.unwrap();
+        instance
+            .send_command("stop", CausedBy::System)
+            .await
+            .unwrap();
+        assert_eq!(instance.state().await, State::Stopping);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match rx.recv().await.unwrap().event_inner {
+                    EventInner::InstanceEvent(InstanceEvent {
+                        instance_event_inner: InstanceEventInner::StateTransition { to },
+                        ..
+                    }) if to == State::Stopped => return,
+                    EventInner::InstanceEvent(InstanceEvent {
+                        instance_event_inner: InstanceEventInner::InstanceError { message },
+                        ..
+                    }) => panic!("stopping from the console was taken for a crash: {message}"),
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(instance.state().await, State::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_fake_bds_properties() {
+        let temp_dir = tempdir::TempDir::new("bedrock_test").unwrap();
+        let (instance, _rx) = fake_instance(temp_dir.path()).await;
+        let port = free_udp_port();
+        instance.set_port(port).await.unwrap();
+        let properties =
+            std::fs::read_to_string(temp_dir.path().join("server.properties")).unwrap();
+        assert!(properties.contains(&format!("server-port={port}")));
+        // properties lodestone does not know about are kept
+        assert!(properties.contains("server-name=Dedicated Server"));
+        assert_eq!(instance.port().await, port);
+    }
+}
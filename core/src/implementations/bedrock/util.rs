@@ -0,0 +1,110 @@
+use color_eyre::eyre::{eyre, Context, ContextCompat};
+use serde_json::Value;
+
+use crate::error::{Error, ErrorKind};
+
+/// Mojang does not publish a list of past Bedrock Dedicated Server releases,
+/// so we rely on the list maintained by Bedrock-OSS
+const BDS_VERSIONS_URL: &str =
+    "https://raw.githubusercontent.com/Bedrock-OSS/BDS-Versions/main/versions.json";
+
+/// The platform name used by Mojang in the download urls and by the version list
+fn bds_platform() -> Result<&'static str, Error> {
+    match std::env::consts::OS {
+        "linux" => Ok("linux"),
+        "windows" => Ok("windows"),
+        os => Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Bedrock Dedicated Server is not available for {}", os),
+        }),
+    }
+}
+
+pub fn bds_executable_name() -> &'static str {
+    if std::env::consts::OS == "windows" {
+        "bedrock_server.exe"
+    } else {
+        "bedrock_server"
+    }
+}
+
+/// Compare two BDS versions (e.g. `1.20.10.01`) component by component
+fn cmp_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let parse = |v: &str| -> Vec<u64> { v.split('.').filter_map(|s| s.parse().ok()).collect() };
+    parse(a).cmp(&parse(b))
+}
+
+/// Get the stable BDS versions available for the current platform, newest first
+pub async fn get_bedrock_versions() -> Result<Vec<String>, Error> {
+    let platform = bds_platform()?;
+    let http = reqwest::Client::new();
+    let response: Value = serde_json::from_str(
+        http.get(BDS_VERSIONS_URL)
+            .send()
+            .await
+            .context("Failed to get bedrock versions")?
+            .text()
+            .await
+            .context("Failed to get bedrock versions")?
+            .as_str(),
+    )
+    .context("Failed to get bedrock versions")?;
+
+    let mut versions = response
+        .get(platform)
+        .and_then(|v| v.get("versions"))
+        .and_then(|v| v.as_array())
+        .context("Failed to get bedrock versions, response does not contain versions")?
+        .iter()
+        .map(|v| {
+            v.as_str()
+                .map(|v| v.to_string())
+                .context("Failed to get bedrock versions. Version string is not a string")
+        })
+        .collect::<Result<Vec<String>, _>>()?;
+    versions.sort_by(|a, b| cmp_versions(b, a));
+    Ok(versions)
+}
+
+pub fn get_bedrock_server_url(version: &str) -> Result<String, Error> {
+    // versions end up in the url, only allow what Mojang uses
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} is not a valid bedrock version", version),
+        });
+    }
+    let platform = match bds_platform()? {
+        "windows" => "win",
+        platform => platform,
+    };
+    Ok(format!(
+        "https://www.minecraft.net/bedrockdedicatedserver/bin-{platform}/bedrock-server-{version}.zip"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmp_versions() {
+        let mut versions = vec!["1.19.83.01", "1.20.1.02", "1.20.10.01", "1.9.0.15"];
+        versions.sort_by(|a, b| cmp_versions(b, a));
+        assert_eq!(
+            versions,
+            vec!["1.20.10.01", "1.20.1.02", "1.19.83.01", "1.9.0.15"]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_get_bedrock_server_url() {
+        assert_eq!(
+            get_bedrock_server_url("1.20.10.01").unwrap(),
+            "https://www.minecraft.net/bedrockdedicatedserver/bin-linux/bedrock-server-1.20.10.01.zip"
+        );
+        assert!(get_bedrock_server_url("1.20/../../evil").is_err());
+        assert!(get_bedrock_server_url("").is_err());
+    }
+}
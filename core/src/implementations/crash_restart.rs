@@ -0,0 +1,116 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::event_broadcaster::EventBroadcaster;
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_server::{State, TServer};
+use crate::types::Snowflake;
+
+/// Delay before the first automatic restart, doubled for each crash in the window
+const CRASH_RESTART_BASE_DELAY: u64 = 5;
+/// Maximum number of automatic restarts within `CRASH_RESTART_WINDOW`
+const CRASH_RESTART_MAX_RETRIES: usize = 5;
+/// Length of the window, in seconds, in which automatic restarts are counted
+const CRASH_RESTART_WINDOW: i64 = 60 * 60;
+
+/// Timestamps of the recent crashes of an instance, used to back off automatic restarts
+#[derive(Clone, Debug, Default)]
+pub struct CrashHistory(Arc<Mutex<Vec<i64>>>);
+
+impl CrashHistory {
+    /// Record a crash, returns the number of crashes in the window including this one
+    async fn record(&self, now: i64) -> usize {
+        let mut crash_history = self.0.lock().await;
+        crash_history.retain(|t| now - t < CRASH_RESTART_WINDOW);
+        crash_history.push(now);
+        crash_history.len()
+    }
+}
+
+/// Seconds to wait before the `retries`th restart, `None` once we should give up
+fn restart_delay(retries: usize) -> Option<u64> {
+    if retries > CRASH_RESTART_MAX_RETRIES {
+        return None;
+    }
+    Some(CRASH_RESTART_BASE_DELAY * 2_u64.pow(retries as u32 - 1))
+}
+
+/// Restart a crashed instance if it is configured to do so, backing off exponentially
+/// and giving up after too many crashes in a short period of time
+pub async fn restart_after_crash<T>(
+    instance: &T,
+    crash_history: &CrashHistory,
+    event_broadcaster: &EventBroadcaster,
+) where
+    T: TServer + TConfigurable + Clone + Send + Sync + 'static,
+{
+    if !instance.restart_on_crash().await {
+        return;
+    }
+    let retries = crash_history.record(chrono::Utc::now().timestamp()).await;
+    let name = instance.name().await;
+    let Some(delay) = restart_delay(retries) else {
+        error!(
+            "[{}] Instance crashed {} times in the last {} minutes, not restarting",
+            name,
+            retries,
+            CRASH_RESTART_WINDOW / 60
+        );
+        event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_name: name,
+                instance_uuid: instance.uuid().await,
+                instance_event_inner: InstanceEventInner::InstanceError {
+                    message: format!(
+                        "Instance crashed {} times in the last {} minutes, giving up on restarting it",
+                        retries,
+                        CRASH_RESTART_WINDOW / 60
+                    ),
+                },
+            }),
+            snowflake: Snowflake::default(),
+            details: "".to_string(),
+            caused_by: CausedBy::System,
+        });
+        return;
+    };
+    info!(
+        "[{}] Restarting crashed instance in {} seconds (attempt {}/{})",
+        name, delay, retries, CRASH_RESTART_MAX_RETRIES
+    );
+    let instance = instance.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(delay)).await;
+        // the user may have started or killed the instance in the meantime
+        if instance.state().await != State::Error {
+            return;
+        }
+        if let Err(e) = instance.start(CausedBy::System, false).await {
+            error!("[{}] Failed to restart crashed instance: {}", name, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_restart_backoff() {
+        let crash_history = CrashHistory::default();
+        let now = chrono::Utc::now().timestamp();
+        // crashes outside the window are forgotten
+        crash_history.record(now - CRASH_RESTART_WINDOW).await;
+        let mut delays = Vec::new();
+        for _ in 0..=CRASH_RESTART_MAX_RETRIES {
+            delays.push(restart_delay(crash_history.record(now).await));
+        }
+        assert_eq!(delays[0], Some(CRASH_RESTART_BASE_DELAY));
+        assert_eq!(delays[1], Some(CRASH_RESTART_BASE_DELAY * 2));
+        assert_eq!(delays[CRASH_RESTART_MAX_RETRIES], None);
+    }
+}
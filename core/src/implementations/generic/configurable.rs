@@ -9,6 +9,7 @@ use crate::error::{Error, ErrorKind};
 use crate::implementations::generic::bridge::procedure_call::{
     ProcedureCallInner, ProcedureCallResultInner,
 };
+use crate::log_rules::{InstanceLogRules, LogRule};
 use crate::traits::t_configurable::manifest::{ConfigurableManifest, ConfigurableValue};
 use crate::traits::t_configurable::GameType;
 use crate::traits::t_configurable::{Game, TConfigurable};
@@ -144,6 +145,14 @@ impl TConfigurable for GenericInstance {
         })
     }
 
+    async fn log_rules(&self) -> Result<InstanceLogRules, Error> {
+        Ok(self.log_rules.lock().await.get())
+    }
+
+    async fn set_log_rules(&self, rules: Option<Vec<LogRule>>) -> Result<(), Error> {
+        self.log_rules.lock().await.set(rules).await
+    }
+
     async fn configurable_manifest(&self) -> ConfigurableManifest {
         self.procedure_bridge
             .call(ProcedureCallInner::GetConfigurableManifest)
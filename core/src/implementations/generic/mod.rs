@@ -1,7 +1,12 @@
-use std::{path::PathBuf, rc::Rc, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
 
 use async_trait::async_trait;
 use color_eyre::eyre::Context;
+use tokio::sync::Mutex;
 use tracing::{debug, error};
 
 use self::{
@@ -12,8 +17,11 @@ use crate::{
     error::Error,
     event_broadcaster::EventBroadcaster,
     events::{CausedBy, ProgressionEventID},
+    extension::atom::atom_permissions_options,
+    log_rules::LogRules,
     macro_executor::{self, MacroExecutor, MacroPID, SpawnResult, WorkerOptionGenerator},
     traits::{
+        t_backup::TBackup,
         t_configurable::{
             manifest::{SetupManifest, SetupValue},
             TConfigurable,
@@ -29,6 +37,7 @@ use std::io::Write;
 mod bridge;
 pub mod configurable;
 mod r#macro;
+mod output_parser;
 pub mod player;
 pub mod server;
 
@@ -42,6 +51,7 @@ pub struct GenericInstance {
     path: PathBuf,
     core_macro_pid: MacroPID,
     drop_guard: Arc<GenericDropGuard>,
+    log_rules: Arc<Mutex<LogRules>>,
 }
 
 /// RAII guard for dropping a generic instance
@@ -86,6 +96,16 @@ impl WorkerOptionGenerator for InitWorkerGenerator {
     }
 }
 
+/// The atom a bootstrap written from `js/main/bootstrap.ts` imports
+pub fn path_to_source(bootstrap: &str) -> Option<PathBuf> {
+    bootstrap.lines().find_map(|line| {
+        let path_to_main = line
+            .strip_prefix("import * as a from \"")?
+            .strip_suffix("\";")?;
+        Some(Path::new(path_to_main).parent()?.to_path_buf())
+    })
+}
+
 impl GenericInstance {
     pub async fn new(
         path_to_source: PathBuf,
@@ -101,8 +121,10 @@ impl GenericInstance {
             &path.display()
         ))?;
         let path_to_config = path.join(".lodestone_config");
-        let run_ts_content =
-            include_str!("js/main/bootstrap.ts").replace("REPLACE_ME_WITH_URL", &path_to_source.join("main.ts").as_os_str().to_string_lossy());
+        let run_ts_content = include_str!("js/main/bootstrap.ts").replace(
+            "REPLACE_ME_WITH_URL",
+            &path_to_source.join("main.ts").as_os_str().to_string_lossy(),
+        );
 
         let path_to_bootstrap = path.join("run.ts");
         tokio::fs::write(&path_to_bootstrap, run_ts_content)
@@ -136,8 +158,9 @@ impl GenericInstance {
                 CausedBy::System,
                 Box::new(GenericMainWorkerGenerator::new(procedure_bridge.clone())),
                 None,
-                None,
+                Some(atom_permissions_options(&path_to_source)),
                 Some(dot_lodestone_config.uuid().clone()),
+                None,
             )
             .await?;
         detach_future.await;
@@ -149,6 +172,12 @@ impl GenericInstance {
                 path: path.clone(),
             })
             .await?;
+        let log_rules = Arc::new(Mutex::new(LogRules::load(&path, Vec::new()).await?));
+        output_parser::spawn_output_parser(
+            dot_lodestone_config.uuid().clone(),
+            event_broadcaster.clone(),
+            Arc::downgrade(&log_rules),
+        );
         Ok(GenericInstance {
             dot_lodestone_config,
             procedure_bridge,
@@ -160,6 +189,7 @@ impl GenericInstance {
                 core_macro_pid,
                 macro_executor: core_macro_executor,
             }),
+            log_rules,
         })
     }
 
@@ -170,19 +200,27 @@ impl GenericInstance {
         core_macro_executor: MacroExecutor,
     ) -> Result<Self, Error> {
         let procedure_bridge = bridge::procedure_call::ProcedureBridge::new();
+        let path_to_bootstrap = path_to_instance.join("run.ts");
+        let permissions = tokio::fs::read_to_string(&path_to_bootstrap)
+            .await
+            .ok()
+            .and_then(|bootstrap| path_to_source(&bootstrap))
+            .map(|path_to_source| atom_permissions_options(&path_to_source))
+            .unwrap_or_default();
         let SpawnResult {
             macro_pid: core_macro_pid,
             detach_future,
             exit_future,
         } = core_macro_executor
             .spawn(
-                path_to_instance.join("run.ts"),
+                path_to_bootstrap,
                 Vec::new(),
                 CausedBy::System,
                 Box::new(GenericMainWorkerGenerator::new(procedure_bridge.clone())),
                 None,
-                None,
+                Some(permissions),
                 Some(dot_lodestone_config.uuid().clone()),
+                None,
             )
             .await?;
 
@@ -201,6 +239,14 @@ impl GenericInstance {
                 path: path_to_instance.clone(),
             })
             .await?;
+        let log_rules = Arc::new(Mutex::new(
+            LogRules::load(&path_to_instance, Vec::new()).await?,
+        ));
+        output_parser::spawn_output_parser(
+            dot_lodestone_config.uuid().clone(),
+            event_broadcaster.clone(),
+            Arc::downgrade(&log_rules),
+        );
         Ok(GenericInstance {
             dot_lodestone_config,
             procedure_bridge,
@@ -212,6 +258,7 @@ impl GenericInstance {
                 core_macro_pid,
                 macro_executor: core_macro_executor,
             }),
+            log_rules,
         })
     }
 
@@ -241,6 +288,12 @@ impl GenericInstance {
                     bridge: procedure_bridge.clone(),
                 }),
                 None,
+                Some(
+                    Path::new(link_to_source)
+                        .parent()
+                        .map(atom_permissions_options)
+                        .unwrap_or_default(),
+                ),
                 None,
                 None,
             )
@@ -274,6 +327,8 @@ impl GenericInstance {
     }
 }
 
+impl TBackup for GenericInstance {}
+
 #[async_trait]
 impl TInstance for GenericInstance {
     async fn get_instance_info(&self) -> InstanceInfo {
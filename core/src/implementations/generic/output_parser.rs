@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+use std::sync::Weak;
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Mutex;
+
+use crate::{
+    event_broadcaster::EventBroadcaster,
+    events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner},
+    log_rules::{LogMatch, LogRules},
+    traits::t_server::State,
+    types::{InstanceUuid, Snowflake},
+};
+
+use super::player::GenericPlayer;
+
+/// Apply the log rules of a generic instance to the output it emits.
+///
+/// Generic instances report their own state, so `ServerStarted` rules have no effect here.
+/// Players are tracked from the matches alone and forgotten once the instance stops.
+/// The listener ends with the first output after the instance is dropped.
+pub fn spawn_output_parser(
+    instance_uuid: InstanceUuid,
+    event_broadcaster: EventBroadcaster,
+    log_rules: Weak<Mutex<LogRules>>,
+) {
+    let mut rx = event_broadcaster.subscribe();
+    tokio::spawn(async move {
+        let mut players: HashSet<GenericPlayer> = HashSet::new();
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            let EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: uuid,
+                instance_name,
+                instance_event_inner,
+            }) = event.event_inner
+            else {
+                continue;
+            };
+            if uuid != instance_uuid {
+                continue;
+            }
+            let instance_event_inner = match instance_event_inner {
+                InstanceEventInner::InstanceOutput { message } => {
+                    let Some(rules) = log_rules.upgrade() else {
+                        break;
+                    };
+                    let compiled = rules.lock().await.compiled();
+                    let Some((_, log_match)) = compiled.parse(&message) else {
+                        continue;
+                    };
+                    match log_match {
+                        LogMatch::PlayerJoined { player, .. } => {
+                            let player = GenericPlayer {
+                                id: player.clone(),
+                                name: player,
+                            };
+                            if !players.insert(player.clone()) {
+                                continue;
+                            }
+                            InstanceEventInner::PlayerChange {
+                                player_list: players.iter().map(|p| p.clone().into()).collect(),
+                                players_joined: HashSet::from([player.into()]),
+                                players_left: HashSet::new(),
+                            }
+                        }
+                        LogMatch::PlayerLeft { player } => {
+                            let player = GenericPlayer {
+                                id: player.clone(),
+                                name: player,
+                            };
+                            if !players.remove(&player) {
+                                continue;
+                            }
+                            InstanceEventInner::PlayerChange {
+                                player_list: players.iter().map(|p| p.clone().into()).collect(),
+                                players_joined: HashSet::new(),
+                                players_left: HashSet::from([player.into()]),
+                            }
+                        }
+                        LogMatch::PlayerMessage { player, message } => {
+                            InstanceEventInner::PlayerMessage {
+                                player,
+                                player_message: message,
+                            }
+                        }
+                        LogMatch::Warning { message } => {
+                            InstanceEventInner::InstanceWarning { message }
+                        }
+                        LogMatch::Error { message } => {
+                            InstanceEventInner::InstanceError { message }
+                        }
+                        LogMatch::ServerStarted => continue,
+                    }
+                }
+                InstanceEventInner::StateTransition {
+                    to: State::Stopped | State::Error,
+                } if !players.is_empty() => InstanceEventInner::PlayerChange {
+                    player_list: HashSet::new(),
+                    players_joined: HashSet::new(),
+                    players_left: players.drain().map(|p| p.into()).collect(),
+                },
+                _ => continue,
+            };
+            event_broadcaster.send(Event {
+                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                    instance_uuid: instance_uuid.clone(),
+                    instance_name,
+                    instance_event_inner,
+                }),
+                details: "".to_string(),
+                snowflake: Snowflake::default(),
+                caused_by: CausedBy::Instance {
+                    instance_uuid: instance_uuid.clone(),
+                },
+            });
+        }
+    });
+}
@@ -0,0 +1,614 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use color_eyre::eyre::{eyre, Context};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, warn};
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{
+    CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, ProgressionEventID,
+};
+use crate::prelude::path_to_tmp;
+use crate::traits::t_backup::{BackupEntry, BackupRetention, TBackup};
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_server::{State, TServer};
+use crate::util::{list_dir, scoped_join_win_safe, unzip_file_async, zip_files_async, UnzipOption};
+
+use super::util::read_properties_from_path;
+use super::MinecraftInstance;
+
+impl MinecraftInstance {
+    fn path_to_backups(&self) -> PathBuf {
+        self.path_to_instance.join("backups")
+    }
+
+    fn path_to_backup(&self, name: &str) -> Result<PathBuf, Error> {
+        let path = scoped_join_win_safe(self.path_to_backups(), name)?;
+        if !is_backup(&path) {
+            return Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Backup {} not found", name),
+            });
+        }
+        Ok(path)
+    }
+
+    /// Flush the world to disk and turn off autosave so the world files are not
+    /// modified while they are being archived
+    async fn flush_world(&self, caused_by: CausedBy) -> Result<(), Error> {
+        let mut rx = self.event_broadcaster.subscribe();
+        self.send_command("save-off", caused_by.clone()).await?;
+        self.send_command("save-all flush", caused_by).await?;
+        tokio::time::timeout(Duration::from_secs(60), async {
+            loop {
+                match rx.recv().await {
+                    Ok(Event {
+                        event_inner:
+                            EventInner::InstanceEvent(InstanceEvent {
+                                instance_uuid,
+                                instance_event_inner: InstanceEventInner::InstanceOutput { message },
+                                ..
+                            }),
+                        ..
+                    }) => {
+                        // older versions print "Saved the world" instead
+                        if instance_uuid == self.uuid
+                            && (message.contains("Saved the game")
+                                || message.contains("Saved the world"))
+                        {
+                            return Ok(());
+                        }
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Err(eyre!("Event broadcaster closed")),
+                }
+            }
+        })
+        .await
+        .map_err(|_| eyre!("Timed out waiting for the server to save the world"))??;
+        Ok(())
+    }
+
+    /// Archive everything in the instance folder except the backups themselves
+    pub(super) async fn archive_instance(
+        &self,
+        prefix: &str,
+        caused_by: CausedBy,
+    ) -> Result<PathBuf, Error> {
+        let path_to_backups = self.path_to_backups();
+        crate::util::fs::create_dir_all(&path_to_backups).await?;
+
+        let is_running = self.state().await == State::Running;
+        if is_running {
+            if let Err(e) = self.flush_world(caused_by.clone()).await {
+                let _ = self.send_command("save-on", caused_by).await;
+                return Err(e);
+            }
+        }
+
+        let files: Vec<PathBuf> = list_dir(&self.path_to_instance, None)
+            .await?
+            .into_iter()
+            .filter(|path| path != &path_to_backups)
+            .collect();
+        let archive_name = format!(
+            "{prefix}_{}.zip",
+            chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+        );
+        let res = zip_files_async(&files, path_to_backups.join(archive_name), false).await;
+
+        if is_running {
+            let _ = self.send_command("save-on", caused_by).await.map_err(|e| {
+                error!(
+                    "[{}] Failed to re-enable autosave after backup: {}",
+                    self.uuid, e
+                );
+            });
+        }
+        res
+    }
+
+    async fn level_name(&self) -> String {
+        read_properties_from_path(&self.path_to_properties)
+            .await
+            .ok()
+            .and_then(|properties| properties.get("level-name").cloned())
+            .filter(|level_name| !level_name.is_empty())
+            .unwrap_or_else(|| "world".to_string())
+    }
+
+    /// Replace the instance files with the content of a backup archive or folder
+    pub(super) async fn restore_files_from(&self, source: &Path) -> Result<(), Error> {
+        let staging = tempfile::tempdir_in(path_to_tmp())
+            .context("Failed to create temporary directory for restoring")?;
+        if source.is_dir() {
+            let from = source.to_owned();
+            let to = staging.path().to_owned();
+            tokio::task::spawn_blocking(move || {
+                let mut options = fs_extra::dir::CopyOptions::new();
+                options.content_only = true;
+                fs_extra::dir::copy(&from, &to, &options)
+            })
+            .await
+            .context("Failed to copy backup in a blocking task")?
+            .context(format!("Failed to copy {}", source.display()))?;
+        } else {
+            unzip_file_async(source, UnzipOption::ToDir(staging.path().to_owned())).await?;
+        }
+        let path_to_instance = self.path_to_instance.clone();
+        let level_name = self.level_name().await;
+        let staging_path = staging.path().to_owned();
+        tokio::task::spawn_blocking(move || {
+            restore_files(&staging_path, &path_to_instance, &level_name)
+        })
+        .await
+        .context("Failed to restore files in a blocking task")??;
+        // the backup may come with its own server.properties
+        let _ = self.read_properties().await;
+        Ok(())
+    }
+
+    async fn restore_from(
+        &self,
+        source: PathBuf,
+        progression_event_id: &ProgressionEventID,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let was_running = match self.state().await {
+            State::Running => true,
+            State::Stopped | State::Error => false,
+            State::Starting | State::Stopping => {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Cannot restore an instance that is starting or stopping"),
+                })
+            }
+        };
+        if was_running {
+            self.event_broadcaster
+                .send(Event::new_progression_event_update(
+                    progression_event_id,
+                    "1/4: Stopping instance",
+                    1.0,
+                ));
+            self.stop(caused_by.clone(), true).await?;
+        } else {
+            self.event_broadcaster
+                .send(Event::new_progression_event_update(
+                    progression_event_id,
+                    "1/4: Instance is not running, skipping stop",
+                    1.0,
+                ));
+        }
+
+        self.event_broadcaster
+            .send(Event::new_progression_event_update(
+                progression_event_id,
+                "2/4: Taking a safety snapshot",
+                1.0,
+            ));
+        let snapshot = self
+            .archive_instance("pre_restore", caused_by.clone())
+            .await
+            .context("Failed to take a safety snapshot, aborting restore")?;
+
+        self.event_broadcaster
+            .send(Event::new_progression_event_update(
+                progression_event_id,
+                "3/4: Unpacking backup",
+                1.0,
+            ));
+        self.restore_files_from(&source).await.map_err(|e| Error {
+            kind: e.kind,
+            source: e.source.wrap_err(format!(
+                "Restore failed, the previous state was saved to {}",
+                snapshot.file_name().unwrap_or_default().to_string_lossy()
+            )),
+        })?;
+
+        if was_running {
+            self.event_broadcaster
+                .send(Event::new_progression_event_update(
+                    progression_event_id,
+                    "4/4: Restarting instance",
+                    1.0,
+                ));
+            self.start_server(caused_by, false).await?;
+        } else {
+            self.event_broadcaster
+                .send(Event::new_progression_event_update(
+                    progression_event_id,
+                    "4/4: Instance was not running, not restarting",
+                    1.0,
+                ));
+        }
+        Ok(())
+    }
+
+    async fn prune_backups(&self) -> Result<(), Error> {
+        let retention = self.config.lock().await.backup_retention;
+        // safety snapshots and the auto-backup macro's copies are never pruned
+        let backups: Vec<BackupEntry> = self
+            .list_backups()
+            .await?
+            .into_iter()
+            .filter(|backup| backup.managed)
+            .collect();
+        for backup in retention.prune(&backups) {
+            self.delete_backup(&backup.name).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TBackup for MinecraftInstance {
+    async fn backup_period(&self) -> Option<u32> {
+        self.config.lock().await.backup_period
+    }
+
+    async fn backup_in_progress(&self) -> bool {
+        self.backup_lock.try_lock().is_err()
+    }
+
+    async fn list_backups(&self) -> Result<Vec<BackupEntry>, Error> {
+        let path_to_backups = self.path_to_backups();
+        if !path_to_backups.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut ret = Vec::new();
+        for path in list_dir(&path_to_backups, None).await? {
+            if !is_backup(&path) {
+                continue;
+            }
+            let metadata = tokio::fs::metadata(&path)
+                .await
+                .context(format!("Failed to read metadata of {}", path.display()))?;
+            let size = if metadata.is_dir() {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || fs_extra::dir::get_size(path))
+                    .await
+                    .context("Failed to get backup size in a blocking task")?
+                    .unwrap_or_default()
+            } else {
+                metadata.len()
+            };
+            ret.push(BackupEntry {
+                name: path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                creation_time: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or_default(),
+                size,
+                managed: is_prunable(&path),
+            });
+        }
+        ret.sort_by(|a, b| b.creation_time.cmp(&a.creation_time));
+        Ok(ret)
+    }
+
+    async fn create_backup(&self, caused_by: CausedBy) -> Result<BackupEntry, Error> {
+        let _guard = self.backup_lock.try_lock().map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("A backup is already in progress for this instance"),
+        })?;
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!("Backing up {}", self.name().await),
+            Some(2.0),
+            None,
+            caused_by.clone(),
+        );
+        self.event_broadcaster.send(progression_start_event);
+
+        self.event_broadcaster
+            .send(Event::new_progression_event_update(
+                &event_id,
+                "1/2: Archiving instance files",
+                1.0,
+            ));
+        let archive = match self.archive_instance(BACKUP_PREFIX, caused_by).await {
+            Ok(v) => v,
+            Err(e) => {
+                self.event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&format!("Backup failed: {e}")),
+                        None,
+                    ));
+                return Err(e);
+            }
+        };
+
+        self.event_broadcaster
+            .send(Event::new_progression_event_update(
+                &event_id,
+                "2/2: Applying retention policy",
+                1.0,
+            ));
+        if let Err(e) = self.prune_backups().await {
+            warn!("[{}] Failed to prune old backups: {}", self.uuid, e);
+        }
+
+        let name = archive
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let metadata = tokio::fs::metadata(&archive)
+            .await
+            .context(format!("Failed to read metadata of {}", archive.display()))?;
+        self.event_broadcaster
+            .send(Event::new_progression_event_end(
+                event_id,
+                true,
+                Some(&format!("Backup {name} created")),
+                None,
+            ));
+        Ok(BackupEntry {
+            name,
+            creation_time: chrono::Utc::now().timestamp(),
+            size: metadata.len(),
+            managed: true,
+        })
+    }
+
+    async fn delete_backup(&self, name: &str) -> Result<(), Error> {
+        let path = self.path_to_backup(name)?;
+        if path.is_dir() {
+            crate::util::fs::remove_dir_all(path).await
+        } else {
+            crate::util::fs::remove_file(path).await
+        }
+    }
+
+    async fn restore_backup(&self, name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        let path = self.path_to_backup(name)?;
+        let _guard = self.backup_lock.try_lock().map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("A backup or restore is already in progress for this instance"),
+        })?;
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!("Restoring {} from {}", self.name().await, name),
+            Some(4.0),
+            None,
+            caused_by.clone(),
+        );
+        self.event_broadcaster.send(progression_start_event);
+
+        match self.restore_from(path, &event_id, caused_by).await {
+            Ok(_) => {
+                self.event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        true,
+                        Some(&format!("Restored from {name}")),
+                        None,
+                    ));
+                Ok(())
+            }
+            Err(e) => {
+                self.event_broadcaster
+                    .send(Event::new_progression_event_end(
+                        event_id,
+                        false,
+                        Some(&format!("Restore failed: {e}")),
+                        None,
+                    ));
+                Err(e)
+            }
+        }
+    }
+
+    async fn backup_retention(&self) -> Result<BackupRetention, Error> {
+        Ok(self.config.lock().await.backup_retention)
+    }
+
+    async fn set_backup_retention(&self, retention: BackupRetention) -> Result<(), Error> {
+        self.config.lock().await.backup_retention = retention;
+        self.write_config_to_file().await
+    }
+}
+
+/// Prefix of the archives made by [`TBackup::create_backup`], the only ones retention prunes
+const BACKUP_PREFIX: &str = "backup";
+
+/// Backups are zip or tar.gz archives, or plain copies of the world or instance folder
+/// such as the ones made by the auto-backup macro
+fn is_backup(path: &Path) -> bool {
+    if path.is_dir() {
+        return path.join("level.dat").is_file() || path.join("server.properties").is_file();
+    }
+    path.is_file()
+        && matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("zip") | Some("gz") | Some("tgz")
+        )
+}
+
+/// Whether the backup is an archive made by [`TBackup::create_backup`]
+fn is_prunable(path: &Path) -> bool {
+    let file_name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    path.is_file()
+        && file_name.starts_with(&format!("{BACKUP_PREFIX}_"))
+        && file_name.ends_with(".zip")
+}
+
+/// Move the content of an unpacked backup into the instance folder.
+///
+/// A backup containing `level.dat` at its root is a copy of the world folder and only
+/// replaces the world, otherwise it is treated as a copy of the whole instance.
+fn restore_files(unpacked: &Path, path_to_instance: &Path, level_name: &str) -> Result<(), Error> {
+    let read_dir = |dir: &Path| -> Result<Vec<PathBuf>, Error> {
+        Ok(std::fs::read_dir(dir)
+            .context(format!("Failed to read directory {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|v| v.path()))
+            .collect())
+    };
+    let mut root = unpacked.to_path_buf();
+    let mut entries = read_dir(&root)?;
+    // uploaded archives are often a single folder wrapping the actual backup
+    if entries.len() == 1 && entries[0].is_dir() && !root.join("level.dat").is_file() {
+        root = entries.remove(0);
+        entries = read_dir(&root)?;
+    }
+
+    let dest = if root.join("level.dat").is_file() {
+        path_to_instance.join(level_name)
+    } else if root.join(level_name).is_dir() || root.join("server.properties").is_file() {
+        path_to_instance.to_path_buf()
+    } else {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Backup does not look like a Minecraft world or instance"),
+        });
+    };
+
+    if dest != path_to_instance && dest.exists() {
+        std::fs::remove_dir_all(&dest).context(format!("Failed to remove {}", dest.display()))?;
+    }
+    std::fs::create_dir_all(&dest).context(format!("Failed to create {}", dest.display()))?;
+
+    // never overwrite the existing backups or the instance's own config
+    let entries: Vec<PathBuf> = entries
+        .into_iter()
+        .filter(|path| {
+            let file_name = path.file_name().unwrap_or_default();
+            dest != path_to_instance || (file_name != "backups" && file_name != ".lodestone_config")
+        })
+        .collect();
+    for entry in entries.iter() {
+        let target = dest.join(entry.file_name().unwrap_or_default());
+        if target.is_dir() {
+            std::fs::remove_dir_all(&target)
+                .context(format!("Failed to remove {}", target.display()))?;
+        } else if target.exists() {
+            std::fs::remove_file(&target)
+                .context(format!("Failed to remove {}", target.display()))?;
+        }
+    }
+    fs_extra::move_items(&entries, &dest, &fs_extra::dir::CopyOptions::new()).context(format!(
+        "Failed to move restored files into {}",
+        dest.display()
+    ))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, path.to_string_lossy().as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_only_own_archives_are_pruned() {
+        let backups = tempfile::tempdir().unwrap();
+        let own = backups.path().join("backup_2023-01-02_12-00-00.zip");
+        let snapshot = backups.path().join("pre_upgrade_2023-01-02_12-00-00.zip");
+        let uploaded = backups.path().join("my_world.zip");
+        let macro_copy = backups.path().join("backup_2023-01-02_12-00-00");
+        let unrelated = backups.path().join("notes");
+        touch(&own);
+        touch(&snapshot);
+        touch(&uploaded);
+        touch(&macro_copy.join("level.dat"));
+        touch(&unrelated.join("todo.txt"));
+
+        for path in [&own, &snapshot, &uploaded, &macro_copy] {
+            assert!(is_backup(path), "{} is a backup", path.display());
+        }
+        assert!(!is_backup(&unrelated));
+
+        assert!(is_prunable(&own));
+        for path in [&snapshot, &uploaded, &macro_copy, &unrelated] {
+            assert!(!is_prunable(path), "{} is not prunable", path.display());
+        }
+    }
+
+    #[test]
+    fn test_restore_world_copy() {
+        let unpacked = tempfile::tempdir().unwrap();
+        let instance = tempfile::tempdir().unwrap();
+        touch(&unpacked.path().join("level.dat"));
+        touch(&unpacked.path().join("region").join("r.0.0.mca"));
+        touch(&instance.path().join("world").join("stale.dat"));
+        touch(&instance.path().join("server.properties"));
+
+        restore_files(unpacked.path(), instance.path(), "world").unwrap();
+
+        assert!(instance.path().join("world").join("level.dat").is_file());
+        assert!(instance
+            .path()
+            .join("world")
+            .join("region")
+            .join("r.0.0.mca")
+            .is_file());
+        assert!(!instance.path().join("world").join("stale.dat").exists());
+        // only the world is replaced
+        assert!(instance.path().join("server.properties").is_file());
+    }
+
+    #[test]
+    fn test_restore_instance_copy() {
+        let unpacked = tempfile::tempdir().unwrap();
+        let instance = tempfile::tempdir().unwrap();
+        // a single folder wrapping the backup is unwrapped
+        let root = unpacked.path().join("my_server");
+        touch(&root.join("server.properties"));
+        touch(&root.join("world").join("level.dat"));
+        touch(&root.join("backups").join("from_backup.zip"));
+        touch(&root.join(".lodestone_config"));
+        touch(&instance.path().join("world").join("stale.dat"));
+        touch(&instance.path().join("backups").join("backup_1.zip"));
+        touch(&instance.path().join(".lodestone_config"));
+
+        restore_files(unpacked.path(), instance.path(), "world").unwrap();
+
+        assert!(instance.path().join("server.properties").is_file());
+        assert!(instance.path().join("world").join("level.dat").is_file());
+        assert!(!instance.path().join("world").join("stale.dat").exists());
+        // the instance keeps its own backups and config
+        assert!(instance
+            .path()
+            .join("backups")
+            .join("backup_1.zip")
+            .is_file());
+        assert!(!instance
+            .path()
+            .join("backups")
+            .join("from_backup.zip")
+            .exists());
+        assert_eq!(
+            std::fs::read_to_string(instance.path().join(".lodestone_config")).unwrap(),
+            instance
+                .path()
+                .join(".lodestone_config")
+                .to_string_lossy()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_restore_rejects_unknown_content() {
+        let unpacked = tempfile::tempdir().unwrap();
+        let instance = tempfile::tempdir().unwrap();
+        touch(&unpacked.path().join("readme.txt"));
+        touch(&unpacked.path().join("other.txt"));
+        assert!(restore_files(unpacked.path(), instance.path(), "world").is_err());
+    }
+}
@@ -5,17 +5,15 @@ use async_trait::async_trait;
 use color_eyre::eyre::{eyre, Context, ContextCompat};
 
 use crate::error::{Error, ErrorKind};
-use crate::prelude::path_to_tmp;
+use crate::events::CausedBy;
+use crate::log_rules::{InstanceLogRules, LogRule};
 use crate::traits::t_configurable::manifest::{
     ConfigurableManifest, ConfigurableValue, ConfigurableValueType, SettingManifest,
 };
 use crate::traits::t_configurable::{Game, TConfigurable};
-use crate::traits::t_server::State;
 
 use crate::types::InstanceUuid;
-use crate::util::download_file;
 
-use super::util::{get_fabric_jar_url, get_paper_jar_url, get_vanilla_jar_url};
 use super::MinecraftInstance;
 
 #[async_trait]
@@ -109,69 +107,29 @@ impl TConfigurable for MinecraftInstance {
         self.write_config_to_file().await
     }
 
-    async fn change_version(&self, version: String) -> Result<(), Error> {
-        if *self.state.lock().await != State::Stopped {
+    async fn set_backup_period(&self, backup_period: Option<u32>) -> Result<(), Error> {
+        if backup_period == Some(0) {
             return Err(Error {
                 kind: ErrorKind::BadRequest,
-                source: eyre!("Cannot change version while server is running"),
+                source: eyre!("Backup period must be greater than 0"),
             });
         }
-        if version == self.config.lock().await.version {
-            return Ok(());
-        }
-        let (url, _) = match self.config.lock().await.flavour {
-            super::Flavour::Vanilla => get_vanilla_jar_url(&version).await.ok_or_else(|| {
-                let error_msg =
-                    format!("Cannot get the vanilla jar version for version {}", version);
-                Error {
-                    kind: ErrorKind::BadRequest,
-                    source: eyre!(error_msg),
-                }
-            })?,
-            super::Flavour::Fabric { .. } => get_fabric_jar_url(&version, &None, &None)
-                .await
-                .ok_or_else(|| {
-                    let error_msg =
-                        format!("Cannot get the fabric jar version for version {}", version);
-                    Error {
-                        kind: ErrorKind::BadRequest,
-                        source: eyre!(error_msg),
-                    }
-                })?,
-            super::Flavour::Paper { .. } => {
-                get_paper_jar_url(&version, &None).await.ok_or_else(|| {
-                    let error_msg =
-                        format!("Cannot get the paper jar version for version {}", version);
-                    Error {
-                        kind: ErrorKind::BadRequest,
-                        source: eyre!(error_msg),
-                    }
-                })?
-            }
-            super::Flavour::Spigot => todo!(),
-            super::Flavour::Forge { .. } => {
-                return Err(Error {
-                    kind: ErrorKind::UnsupportedOperation,
-                    source: eyre!("Changing versions is unsupported for forge servers"),
-                })
-            }
-        };
-        let lodestone_tmp = path_to_tmp().clone();
-        let temp_dir = tempfile::tempdir_in(lodestone_tmp).context("Failed to create temp dir")?;
-        download_file(
-            &url,
-            temp_dir.path(),
-            Some("server.jar"),
-            &Box::new(|_| {}),
-            true,
-        )
-        .await?;
-        let jar_path = temp_dir.path().join("server.jar");
-        crate::util::fs::rename(jar_path, self.path().await.join("server.jar")).await?;
-        self.config.lock().await.version = version;
+        self.config.lock().await.backup_period = backup_period;
         self.write_config_to_file().await
     }
 
+    async fn change_version(&self, version: String) -> Result<(), Error> {
+        self.upgrade(None, version, CausedBy::System).await
+    }
+
+    async fn log_rules(&self) -> Result<InstanceLogRules, Error> {
+        Ok(self.log_rules.lock().await.get())
+    }
+
+    async fn set_log_rules(&self, rules: Option<Vec<LogRule>>) -> Result<(), Error> {
+        self.log_rules.lock().await.set(rules).await
+    }
+
     async fn configurable_manifest(&self) -> ConfigurableManifest {
         self.configurable_manifest
             .lock()
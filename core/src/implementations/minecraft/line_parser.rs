@@ -1,10 +1,7 @@
 use fancy_regex::Regex;
 use lazy_static::lazy_static;
 
-pub struct PlayerMessage {
-    pub player: String,
-    pub message: String,
-}
+use crate::log_rules::{LogRule, LogRuleAction};
 
 pub fn parse_system_msg(msg: &str) -> Option<String> {
     lazy_static! {
@@ -19,57 +16,68 @@ pub fn parse_system_msg(msg: &str) -> Option<String> {
     }
 }
 
-pub fn parse_player_msg(msg: &str) -> Option<PlayerMessage> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"\[.+\]+: <(.+)> (.+)").unwrap();
-    }
-    if RE.is_match(msg).unwrap() {
-        if let Some(cap) = RE.captures(msg).ok()? {
-            Some(PlayerMessage {
-                player: cap.get(1)?.as_str().to_string(),
-                message: cap.get(2)?.as_str().to_string(),
-            })
-        } else {
-            None
-        }
-    } else {
-        None
-    }
+/// The built-in rules for vanilla output, plugins and mods changing it can override them
+pub fn default_log_rules() -> Vec<LogRule> {
+    vec![
+        LogRule::new(
+            "Server started",
+            r"Done \(.+\)!",
+            LogRuleAction::ServerStarted,
+        ),
+        LogRule::new(
+            "Player message",
+            r"\[.+\]+: <(?P<player>.+)> (?P<message>.+)",
+            LogRuleAction::PlayerMessage,
+        ),
+        LogRule::new(
+            "Player joined",
+            r"\[.+\]+: (?!<)(?P<player>.+) joined the game",
+            LogRuleAction::PlayerJoined,
+        ),
+        LogRule::new(
+            "Player left",
+            r"\[.+\]+: (?!<)(?P<player>.+) left the game",
+            LogRuleAction::PlayerLeft,
+        ),
+    ]
 }
 
-pub fn parse_player_joined(system_msg: &str) -> Option<String> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"(.+) joined the game").unwrap();
-    }
-    if RE.is_match(system_msg).unwrap() {
-        if let Some(cap) = RE.captures(system_msg).ok()? {
-            Some(cap.get(1)?.as_str().to_string())
-        } else {
-            None
-        }
-    } else {
-        None
-    }
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_rules::{CompiledLogRules, LogMatch};
 
-pub fn parse_player_left(system_msg: &str) -> Option<String> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"(.+) left the game").unwrap();
-    }
-    if RE.is_match(system_msg).unwrap() {
-        if let Some(cap) = RE.captures(system_msg).ok()? {
-            Some(cap.get(1)?.as_str().to_string())
-        } else {
+    #[test]
+    fn test_default_log_rules() {
+        let rules = CompiledLogRules::new(default_log_rules()).unwrap();
+        let parse = |line: &str| rules.parse(line).map(|(_, log_match)| log_match);
+        assert_eq!(
+            parse("[12:00:00] [Server thread/INFO]: Done (3.254s)! For help, type \"help\"\n"),
+            Some(LogMatch::ServerStarted)
+        );
+        assert_eq!(
+            parse("[12:00:00] [Server thread/INFO]: Steve joined the game\n"),
+            Some(LogMatch::PlayerJoined {
+                player: "Steve".to_string(),
+                id: None,
+            })
+        );
+        assert_eq!(
+            parse("[12:00:00] [Server thread/INFO]: Steve left the game"),
+            Some(LogMatch::PlayerLeft {
+                player: "Steve".to_string()
+            })
+        );
+        assert_eq!(
+            parse("[12:00:00] [Server thread/INFO]: <Steve> Alex joined the game"),
+            Some(LogMatch::PlayerMessage {
+                player: "Steve".to_string(),
+                message: "Alex joined the game".to_string()
+            })
+        );
+        assert_eq!(
+            parse("[12:00:00] [Server thread/INFO]: Stopping server"),
             None
-        }
-    } else {
-        None
-    }
-}
-
-pub fn parse_server_started(system_msg: &str) -> bool {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r#"Done \(.+\)!"#).unwrap();
+        );
     }
-    RE.is_match(system_msg).unwrap()
 }
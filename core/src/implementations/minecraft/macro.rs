@@ -1,11 +1,17 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
 use color_eyre::eyre::{eyre, Context};
+use deno_runtime::permissions::PermissionsOptions;
 use indexmap::IndexMap;
 
 use crate::error::ErrorKind;
+use crate::extension::r#macro::{
+    available_macro_extensions, path_to_macro_extensions, resolve_macro_extension,
+};
 use crate::macro_executor::MacroExecutor;
+use crate::macro_limits::read_macro_limits;
 use crate::traits::t_configurable::manifest::{
     ConfigurableValue, SettingLocalCache, SettingManifest,
 };
@@ -14,6 +20,7 @@ use crate::{
     events::CausedBy,
     macro_executor::{DefaultWorkerOptionGenerator, MacroPID, SpawnResult},
     traits::t_macro::{HistoryEntry, MacroEntry, TMacro, TaskEntry},
+    types::InstanceUuid,
 };
 
 use super::MinecraftInstance;
@@ -41,6 +48,182 @@ pub fn resolve_macro_invocation(path_to_macro: &Path, macro_name: &str) -> Optio
     None
 }
 
+/// Resolve a macro of the instance, or else a macro extension available to it.
+///
+/// Macro extensions come with the permissions declared in their manifest,
+/// the instance's own macros are trusted.
+pub fn resolve_macro(
+    path_to_macros: &Path,
+    instance_uuid: &InstanceUuid,
+    macro_name: &str,
+) -> Option<(PathBuf, Option<PermissionsOptions>)> {
+    match resolve_macro_invocation(path_to_macros, macro_name) {
+        Some(path) => Some((path, None)),
+        None => resolve_macro_extension(instance_uuid, macro_name)
+            .map(|(path, permissions)| (path, Some(permissions))),
+    }
+}
+
+/// Entries for the macro extensions available to an instance,
+/// except those shadowed by one of the instance's own macros
+pub fn macro_extension_entries(
+    instance_uuid: &InstanceUuid,
+    own_macros: &[MacroEntry],
+    macro_name_to_last_run: &HashMap<String, i64>,
+) -> Vec<MacroEntry> {
+    available_macro_extensions(instance_uuid)
+        .into_iter()
+        .filter(|macro_extension| {
+            !own_macros
+                .iter()
+                .any(|entry| entry.name == macro_extension.manifest.name)
+        })
+        .map(|macro_extension| MacroEntry {
+            last_run: macro_name_to_last_run
+                .get(&macro_extension.manifest.name)
+                .cloned(),
+            path: path_to_macro_extensions().join(&macro_extension.id),
+            name: macro_extension.manifest.name,
+        })
+        .collect()
+}
+
+/// Compose the code declaring the macro's config variable, to be injected before the macro runs
+pub fn compose_config_code(
+    configs: Option<IndexMap<String, SettingLocalCache>>,
+) -> Result<Option<String>, Error> {
+    match configs {
+        Some(config_map) => {
+            let tokens: Vec<_> = config_map
+                .get_index(0)
+                .unwrap()
+                .1
+                .get_identifier()
+                .split('|')
+                .collect();
+            let config_var_name = tokens[0];
+            let mut code_string = format!("let {config_var_name} = {{\r\n");
+
+            for (var_name, meta) in config_map {
+                let value_code = match meta.get_value() {
+                    Some(val) => match val {
+                        ConfigurableValue::String(str_val) => format!("\'{str_val}\'"),
+                        ConfigurableValue::Enum(str_val) => format!("\'{str_val}\'"),
+                        ConfigurableValue::Boolean(b_val) => b_val.to_string(),
+                        ConfigurableValue::Float(num) => num.to_string(),
+                        _ => {
+                            return Err(Error {
+                                kind: ErrorKind::Internal,
+                                source: eyre!("Unsupported config data type"),
+                            })
+                        }
+                    },
+                    None => "undefined".to_string(),
+                };
+                code_string.push_str(&format!("  {var_name}: {value_code},\r\n"))
+            }
+
+            code_string.push_str("};\r\n");
+
+            Ok(Some(code_string))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Cache the values of a macro's config next to the macro
+pub fn store_local_config(
+    path_to_macros: &Path,
+    name: &str,
+    config_to_store: &IndexMap<String, SettingManifest>,
+) -> Result<(), Error> {
+    let mut local_configs: IndexMap<String, SettingLocalCache> = IndexMap::new();
+    config_to_store.iter().for_each(|(var_name, config)| {
+        local_configs.insert(var_name.clone(), SettingLocalCache::from(config));
+    });
+
+    let config_file_path = path_to_macros
+        .join(name)
+        .join(format!("{name}_config"))
+        .with_extension("json");
+    // macro extensions have no folder in the instance until their config is stored
+    if let Some(parent) = config_file_path.parent() {
+        std::fs::create_dir_all(parent).context("failed to create the config directory")?;
+    }
+    std::fs::write(
+        config_file_path,
+        serde_json::to_string_pretty(&local_configs).unwrap(),
+    )
+    .context("failed to create the config file")?;
+
+    Ok(())
+}
+
+/// Read the cached values of a macro's config, checking they still match the macro's config types
+pub async fn validate_local_config(
+    path_to_macros: &Path,
+    instance_uuid: &InstanceUuid,
+    name: &str,
+    config_to_validate: Option<&IndexMap<String, SettingManifest>>,
+) -> Result<IndexMap<String, SettingLocalCache>, Error> {
+    let (path_to_macro, _) = resolve_macro(path_to_macros, instance_uuid, name)
+        .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
+
+    let is_config_needed = match config_to_validate {
+        None => std::fs::read_to_string(&path_to_macro)
+            .context("failed to read macro file")?
+            .contains("LodestoneConfig"),
+        Some(_) => true,
+    };
+
+    // if the macro does not need a config, pass the validation ("vacuously true")
+    if !is_config_needed {
+        return Ok(IndexMap::new());
+    }
+
+    let config_file_path = path_to_macros
+        .join(name)
+        .join(format!("{name}_config"))
+        .with_extension("json");
+    match std::fs::read_to_string(config_file_path) {
+        Ok(config_string) => {
+            let local_configs: IndexMap<String, SettingLocalCache> =
+                serde_json::from_str(&config_string)
+                    .context("failed to parse local config cache")?;
+
+            let configs = match config_to_validate {
+                Some(config) => config.clone(),
+                None => MacroExecutor::get_config_manifest(&path_to_macro).await?,
+            };
+
+            let validation_result = local_configs.iter().fold(
+                local_configs.len() == configs.len(),
+                |partial_result, (setting_id, local_cache)| {
+                    if !partial_result {
+                        return false;
+                    }
+                    local_cache.validate_type(configs.get(setting_id))
+                },
+            );
+
+            if !validation_result {
+                return Err(Error {
+                    kind: ErrorKind::Internal,
+                    source: eyre!(
+                        "There is a mismatch between a config type and its locally-stored value"
+                    ),
+                });
+            }
+
+            Ok(local_configs)
+        }
+        Err(_) => Err(Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Local config cache is not found"),
+        }),
+    }
+}
+
 #[async_trait]
 impl TMacro for MinecraftInstance {
     async fn get_macro_list(&self) -> Result<Vec<MacroEntry>, Error> {
@@ -64,6 +247,9 @@ impl TMacro for MinecraftInstance {
                 }
             }
         }
+        let extension_entries =
+            macro_extension_entries(&self.uuid, &ret, &self.macro_name_to_last_run.lock().await);
+        ret.extend(extension_entries);
         ret.sort_by(|a, b| a.name.cmp(&b.name));
         Ok(ret)
     }
@@ -110,47 +296,11 @@ impl TMacro for MinecraftInstance {
         configs: Option<IndexMap<String, SettingLocalCache>>,
         caused_by: CausedBy,
     ) -> Result<TaskEntry, Error> {
-        let path_to_macro = resolve_macro_invocation(&self.path_to_macros, name)
+        let (path_to_macro, permissions) = resolve_macro(&self.path_to_macros, &self.uuid, name)
             .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
 
-        // compose config injection code
-        let config_code = match configs {
-            Some(config_map) => {
-                let tokens: Vec<_> = config_map
-                    .get_index(0)
-                    .unwrap()
-                    .1
-                    .get_identifier()
-                    .split('|')
-                    .collect();
-                let config_var_name = tokens[0];
-                let mut code_string = format!("let {config_var_name} = {{\r\n");
-
-                for (var_name, meta) in config_map {
-                    let value_code = match meta.get_value() {
-                        Some(val) => match val {
-                            ConfigurableValue::String(str_val) => format!("\'{str_val}\'"),
-                            ConfigurableValue::Enum(str_val) => format!("\'{str_val}\'"),
-                            ConfigurableValue::Boolean(b_val) => b_val.to_string(),
-                            ConfigurableValue::Float(num) => num.to_string(),
-                            _ => {
-                                return Err(Error {
-                                    kind: ErrorKind::Internal,
-                                    source: eyre!("Unsupported config data type"),
-                                })
-                            }
-                        },
-                        None => "undefined".to_string(),
-                    };
-                    code_string.push_str(&format!("  {var_name}: {value_code},\r\n"))
-                }
-
-                code_string.push_str("};\r\n");
-
-                Some(code_string)
-            }
-            None => None,
-        };
+        let config_code = compose_config_code(configs)?;
+        let limits = read_macro_limits(&path_to_macro).await?;
 
         let SpawnResult { macro_pid: pid, .. } = self
             .macro_executor
@@ -160,8 +310,9 @@ impl TMacro for MinecraftInstance {
                 caused_by,
                 Box::new(DefaultWorkerOptionGenerator),
                 config_code,
-                None,
+                permissions,
                 Some(self.uuid.clone()),
+                Some(limits),
             )
             .await?;
         let entry = TaskEntry {
@@ -186,11 +337,16 @@ impl TMacro for MinecraftInstance {
         Ok(())
     }
 
+    async fn add_task(&self, task: TaskEntry) -> Result<(), Error> {
+        self.pid_to_task_entry.lock().await.insert(task.pid, task);
+        Ok(())
+    }
+
     async fn get_macro_config(
         &self,
         name: &str,
     ) -> Result<IndexMap<String, SettingManifest>, Error> {
-        let path_to_macro = resolve_macro_invocation(&self.path_to_macros, name)
+        let (path_to_macro, _) = resolve_macro(&self.path_to_macros, &self.uuid, name)
             .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
         MacroExecutor::get_config_manifest(&path_to_macro).await
     }
@@ -200,23 +356,7 @@ impl TMacro for MinecraftInstance {
         name: &str,
         config_to_store: &IndexMap<String, SettingManifest>,
     ) -> Result<(), Error> {
-        let mut local_configs: IndexMap<String, SettingLocalCache> = IndexMap::new();
-        config_to_store.iter().for_each(|(var_name, config)| {
-            local_configs.insert(var_name.clone(), SettingLocalCache::from(config));
-        });
-
-        let config_file_path = self
-            .path_to_macros
-            .join(name)
-            .join(format!("{name}_config"))
-            .with_extension("json");
-        std::fs::write(
-            config_file_path,
-            serde_json::to_string_pretty(&local_configs).unwrap(),
-        )
-        .context("failed to create the config file")?;
-
-        Ok(())
+        store_local_config(&self.path_to_macros, name, config_to_store)
     }
 
     async fn validate_local_config(
@@ -224,60 +364,6 @@ impl TMacro for MinecraftInstance {
         name: &str,
         config_to_validate: Option<&IndexMap<String, SettingManifest>>,
     ) -> Result<IndexMap<String, SettingLocalCache>, Error> {
-        let path_to_macro = resolve_macro_invocation(&self.path_to_macros, name)
-            .ok_or_else(|| eyre!("Failed to resolve macro invocation for {}", name))?;
-
-        let is_config_needed = match config_to_validate {
-            None => std::fs::read_to_string(path_to_macro)
-                .context("failed to read macro file")?
-                .contains("LodestoneConfig"),
-            Some(_) => true,
-        };
-
-        // if the macro does not need a config, pass the validation ("vacuously true")
-        if !is_config_needed {
-            return Ok(IndexMap::new());
-        }
-
-        let config_file_path = self
-            .path_to_macros
-            .join(name)
-            .join(format!("{name}_config"))
-            .with_extension("json");
-        match std::fs::read_to_string(config_file_path) {
-            Ok(config_string) => {
-                let local_configs: IndexMap<String, SettingLocalCache> =
-                    serde_json::from_str(&config_string)
-                        .context("failed to parse local config cache")?;
-
-                let configs = match config_to_validate {
-                    Some(config) => config.clone(),
-                    None => self.get_macro_config(name).await?,
-                };
-
-                let validation_result = local_configs.iter().fold(
-                    local_configs.len() == configs.len(),
-                    |partial_result, (setting_id, local_cache)| {
-                        if !partial_result {
-                            return false;
-                        }
-                        local_cache.validate_type(configs.get(setting_id))
-                    },
-                );
-
-                if !validation_result {
-                    return Err(Error {
-                        kind: ErrorKind::Internal,
-                        source: eyre!("There is a mismatch between a config type and its locally-stored value"),
-                    });
-                }
-
-                Ok(local_configs)
-            }
-            Err(_) => Err(Error {
-                kind: ErrorKind::NotFound,
-                source: eyre!("Local config cache is not found"),
-            }),
-        }
+        validate_local_config(&self.path_to_macros, &self.uuid, name, config_to_validate).await
     }
 }
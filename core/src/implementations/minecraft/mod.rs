@@ -1,12 +1,17 @@
+mod backup;
 pub mod configurable;
 pub mod fabric;
 mod forge;
 mod line_parser;
 pub mod r#macro;
+mod moderation;
+pub mod mods;
 mod paper;
 pub mod player;
-mod players_manager;
+pub(crate) mod players_manager;
 pub mod server;
+mod spigot;
+mod upgrade;
 pub mod util;
 mod vanilla;
 pub mod versions;
@@ -36,6 +41,8 @@ use ts_rs::TS;
 use crate::error::Error;
 use crate::event_broadcaster::EventBroadcaster;
 use crate::events::{Event, ProgressionEventID};
+use crate::implementations::crash_restart::CrashHistory;
+use crate::log_rules::LogRules;
 use crate::macro_executor::{MacroExecutor, MacroPID};
 use crate::prelude::path_to_binaries;
 use crate::traits::t_configurable::PathBuf;
@@ -45,21 +52,21 @@ use crate::traits::t_configurable::manifest::{
     SettingManifest, SetupManifest, SetupValue,
 };
 
+use crate::traits::t_backup::BackupRetention;
 use crate::traits::t_macro::TaskEntry;
 use crate::traits::t_server::State;
 use crate::traits::TInstance;
 use crate::types::{DotLodestoneConfig, InstanceUuid};
-use crate::util::{
-    dont_spawn_terminal, download_file, format_byte, format_byte_download, unzip_file_async,
-    UnzipOption,
-};
+use crate::util::{dont_spawn_terminal, download_file, format_byte, format_byte_download};
 
 use self::configurable::{CmdArgSetting, ServerPropertySetting};
 use self::fabric::get_fabric_minecraft_versions;
 use self::forge::get_forge_minecraft_versions;
 use self::paper::get_paper_minecraft_versions;
 use self::players_manager::PlayersManager;
-use self::util::{get_jre_url, get_server_jar_url, read_properties_from_path};
+use self::util::{
+    download_jre, get_jre_url, get_server_jar_url, path_to_java, read_properties_from_path,
+};
 use self::vanilla::get_vanilla_minecraft_versions;
 
 #[derive(Debug, Clone, TS, Serialize, Deserialize, PartialEq)]
@@ -165,6 +172,11 @@ pub struct RestoreConfig {
     pub auto_start: bool,
     pub restart_on_crash: bool,
     pub backup_period: Option<u32>,
+    #[serde(default)]
+    pub backup_retention: BackupRetention,
+    /// player name to unix timestamp at which their ban is lifted
+    #[serde(default)]
+    pub ban_expiry: HashMap<String, i64>,
     pub jre_major_version: u64,
     pub has_started: bool,
 }
@@ -188,7 +200,9 @@ pub struct MinecraftInstance {
     // variables which can be changed at runtime
     auto_start: Arc<AtomicBool>,
     restart_on_crash: Arc<AtomicBool>,
-    backup_period: Option<u32>,
+    backup_lock: Arc<Mutex<()>>,
+    mods_lock: Arc<Mutex<()>>,
+    crash_history: CrashHistory,
     process: Arc<Mutex<Option<Child>>>,
     stdin: Arc<Mutex<Option<tokio::process::ChildStdin>>>,
     system: Arc<Mutex<sysinfo::System>>,
@@ -198,6 +212,7 @@ pub struct MinecraftInstance {
     rcon_conn: Arc<Mutex<Option<rcon::Connection<tokio::net::TcpStream>>>>,
     macro_name_to_last_run: Arc<Mutex<HashMap<String, i64>>>,
     pid_to_task_entry: Arc<Mutex<IndexMap<MacroPID, TaskEntry>>>,
+    log_rules: Arc<Mutex<LogRules>>,
 }
 
 #[tokio::test]
@@ -215,7 +230,7 @@ impl MinecraftInstance {
             FlavourKind::Vanilla => get_vanilla_minecraft_versions().await,
             FlavourKind::Fabric => get_fabric_minecraft_versions().await,
             FlavourKind::Paper => get_paper_minecraft_versions().await,
-            FlavourKind::Spigot => todo!(),
+            FlavourKind::Spigot => spigot::get_spigot_minecraft_versions().await,
             FlavourKind::Forge => get_forge_minecraft_versions().await,
         }
         .context("Failed to get minecraft versions")?;
@@ -467,15 +482,76 @@ impl MinecraftInstance {
         let (url, jre_major_version) = get_jre_url(config.version.as_str())
             .await
             .context("Could not get JRE URL")?;
-        if !path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", jre_major_version))
-            .exists()
-        {
-            let downloaded = download_file(
-                &url,
-                &path_to_runtimes.join("java"),
-                None,
+        let downloaded = download_jre(&path_to_runtimes, &url, jre_major_version, {
+            let event_broadcaster = event_broadcaster.clone();
+            &move |dl| {
+                if let Some(total) = dl.total {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        progression_event_id,
+                        format!(
+                            "2/4: Downloading JRE {}",
+                            format_byte_download(dl.downloaded, total)
+                        ),
+                        (dl.step as f64 / total as f64) * 4.0,
+                    ));
+                }
+            }
+        })
+        .await?;
+        if !downloaded {
+            event_broadcaster.send(Event::new_progression_event_update(
+                progression_event_id,
+                "2/4: JRE already downloaded",
+                4.0,
+            ));
+        }
+
+        let jre = path_to_java(&path_to_runtimes, jre_major_version);
+
+        // Step 3: Download server.jar
+        let flavour_name = config.flavour.to_string();
+        let flavour = if let Flavour::Spigot = config.flavour {
+            // Spigot can't be redistributed, so it is built locally with BuildTools
+            let spigot_jar = spigot::get_spigot_jar(config.version.as_str(), &jre, {
+                let event_broadcaster = event_broadcaster.clone();
+                let version = config.version.clone();
+                &move |line: String, progress: f64| {
+                    event_broadcaster.send(Event::new_progression_event_update(
+                        progression_event_id,
+                        format!("3/4: Building Spigot {}: {}", version, line),
+                        progress * 3.0,
+                    ));
+                }
+            })
+            .await?;
+            tokio::fs::copy(&spigot_jar, path_to_instance.join("server.jar"))
+                .await
+                .context(format!(
+                    "Could not copy {} to instance",
+                    spigot_jar.display()
+                ))?;
+            Flavour::Spigot
+        } else {
+            let (jar_url, flavour) = get_server_jar_url(config.version.as_str(), &config.flavour)
+                .await
+                .ok_or_else({
+                    || {
+                        eyre!(
+                            "Could not find a {} server.jar for version {}",
+                            flavour_name,
+                            config.version
+                        )
+                    }
+                })?;
+            let jar_name = match flavour {
+                Flavour::Forge { .. } => "forge-installer.jar",
+                _ => "server.jar",
+            };
+
+            download_file(
+                jar_url.as_str(),
+                &path_to_instance,
+                Some(jar_name),
                 {
                     let event_broadcaster = event_broadcaster.clone();
                     &move |dl| {
@@ -483,10 +559,23 @@ impl MinecraftInstance {
                             event_broadcaster.send(Event::new_progression_event_update(
                                 progression_event_id,
                                 format!(
-                                    "2/4: Downloading JRE {}",
-                                    format_byte_download(dl.downloaded, total)
+                                    "3/4: Downloading {} {} {}",
+                                    flavour_name,
+                                    jar_name,
+                                    format_byte_download(dl.downloaded, total),
+                                ),
+                                (dl.step as f64 / total as f64) * 3.0,
+                            ));
+                        } else {
+                            event_broadcaster.send(Event::new_progression_event_update(
+                                progression_event_id,
+                                format!(
+                                    "3/4: Downloading {} {} {}",
+                                    flavour_name,
+                                    jar_name,
+                                    format_byte(dl.downloaded),
                                 ),
-                                (dl.step as f64 / total as f64) * 4.0,
+                                0.0,
                             ));
                         }
                     }
@@ -494,106 +583,8 @@ impl MinecraftInstance {
                 true,
             )
             .await?;
-
-            let unzipped_content = unzip_file_async(
-                &downloaded,
-                UnzipOption::ToDir(path_to_runtimes.join("java")),
-            )
-            .await?;
-            if unzipped_content.len() != 1 {
-                return Err(eyre!(
-                    "Expected only one file in the JRE archive, got {}",
-                    unzipped_content.len()
-                )
-                .into());
-            }
-
-            tokio::fs::remove_file(&downloaded).await.context(format!(
-                "Could not remove downloaded JRE file {}",
-                downloaded.display()
-            ))?;
-
-            tokio::fs::rename(
-                unzipped_content.iter().last().unwrap(),
-                path_to_runtimes
-                    .join("java")
-                    .join(format!("jre{}", jre_major_version)),
-            )
-            .await
-            .context(format!(
-                "Could not rename JRE directory {}",
-                unzipped_content.iter().last().unwrap().display()
-            ))?;
-        } else {
-            event_broadcaster.send(Event::new_progression_event_update(
-                progression_event_id,
-                "2/4: JRE already downloaded",
-                4.0,
-            ));
-        }
-
-        // Step 3: Download server.jar
-        let flavour_name = config.flavour.to_string();
-        let (jar_url, flavour) = get_server_jar_url(config.version.as_str(), &config.flavour)
-            .await
-            .ok_or_else({
-                || {
-                    eyre!(
-                        "Could not find a {} server.jar for version {}",
-                        flavour_name,
-                        config.version
-                    )
-                }
-            })?;
-        let jar_name = match flavour {
-            Flavour::Forge { .. } => "forge-installer.jar",
-            _ => "server.jar",
+            flavour
         };
-
-        download_file(
-            jar_url.as_str(),
-            &path_to_instance,
-            Some(jar_name),
-            {
-                let event_broadcaster = event_broadcaster.clone();
-                &move |dl| {
-                    if let Some(total) = dl.total {
-                        event_broadcaster.send(Event::new_progression_event_update(
-                            progression_event_id,
-                            format!(
-                                "3/4: Downloading {} {} {}",
-                                flavour_name,
-                                jar_name,
-                                format_byte_download(dl.downloaded, total),
-                            ),
-                            (dl.step as f64 / total as f64) * 3.0,
-                        ));
-                    } else {
-                        event_broadcaster.send(Event::new_progression_event_update(
-                            progression_event_id,
-                            format!(
-                                "3/4: Downloading {} {} {}",
-                                flavour_name,
-                                jar_name,
-                                format_byte(dl.downloaded),
-                            ),
-                            0.0,
-                        ));
-                    }
-                }
-            },
-            true,
-        )
-        .await?;
-        let jre = path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", jre_major_version))
-            .join(if std::env::consts::OS == "macos" {
-                "Contents/Home/bin"
-            } else {
-                "bin"
-            })
-            .join("java");
         // Step 3 (part 2): Forge Setup
         if let Flavour::Forge { .. } = flavour.clone() {
             event_broadcaster.send(Event::new_progression_event_update(
@@ -650,6 +641,8 @@ impl MinecraftInstance {
             auto_start: config.auto_start.unwrap_or(false),
             restart_on_crash: config.restart_on_crash.unwrap_or(false),
             backup_period: config.backup_period,
+            backup_retention: BackupRetention::default(),
+            ban_expiry: HashMap::new(),
             jre_major_version,
             has_started: false,
             java_cmd: Some(jre.to_string_lossy().to_string()),
@@ -702,28 +695,24 @@ impl MinecraftInstance {
             .await
             .expect("failed to write to server.properties");
         };
-        let java_path = path_to_runtimes
-            .join("java")
-            .join(format!("jre{}", restore_config.jre_major_version))
-            .join(if std::env::consts::OS == "macos" {
-                "Contents/Home/bin"
-            } else {
-                "bin"
-            })
-            .join("java");
+        let java_path = path_to_java(&path_to_runtimes, restore_config.jre_major_version);
 
         let configurable_manifest = Arc::new(Mutex::new(Self::init_configurable_manifest(
             &restore_config,
             java_path.to_string_lossy().to_string(),
         )));
 
+        let log_rules = LogRules::load(&path_to_instance, line_parser::default_log_rules()).await?;
+
         let instance = MinecraftInstance {
             state: Arc::new(Mutex::new(State::Stopped)),
             uuid: dot_lodestone_config.uuid().clone(),
             creation_time: dot_lodestone_config.creation_time(),
             auto_start: Arc::new(AtomicBool::new(restore_config.auto_start)),
             restart_on_crash: Arc::new(AtomicBool::new(restore_config.restart_on_crash)),
-            backup_period: restore_config.backup_period,
+            backup_lock: Arc::new(Mutex::new(())),
+            mods_lock: Arc::new(Mutex::new(())),
+            crash_history: CrashHistory::default(),
             players_manager: Arc::new(Mutex::new(PlayersManager::new(
                 event_broadcaster.clone(),
                 dot_lodestone_config.uuid().clone(),
@@ -743,11 +732,15 @@ impl MinecraftInstance {
             configurable_manifest,
             macro_name_to_last_run: Arc::new(Mutex::new(HashMap::new())),
             pid_to_task_entry: Arc::new(Mutex::new(IndexMap::new())),
+            log_rules: Arc::new(Mutex::new(log_rules)),
         };
         instance
             .read_properties()
             .await
             .context("Failed to read properties")?;
+        for (player_name, expires) in instance.config.lock().await.ban_expiry.clone() {
+            instance.schedule_ban_expiry(player_name, expires);
+        }
         Ok(instance)
     }
 
@@ -0,0 +1,215 @@
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::error;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_player::{ModerationAction, TPlayerManagement};
+use crate::traits::t_server::{State, TServer};
+use crate::types::Snowflake;
+
+use super::util::name_to_uuid;
+use super::MinecraftInstance;
+
+pub(super) const OPS_FILE: &str = "ops.json";
+pub(super) const WHITELIST_FILE: &str = "whitelist.json";
+pub(super) const BANNED_PLAYERS_FILE: &str = "banned-players.json";
+
+/// An entry of `ops.json`, `whitelist.json` or `banned-players.json`
+///
+/// Fields specific to each list (level, reason, expires...) are kept in `extra` untouched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct PlayerListEntry {
+    pub uuid: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Player names end up in console commands, so only allow what Minecraft allows
+pub(super) fn validate_player_name(player_name: &str) -> Result<(), Error> {
+    if player_name.is_empty()
+        || player_name.len() > 16
+        || !player_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} is not a valid player name", player_name),
+        });
+    }
+    Ok(())
+}
+
+pub(super) fn sanitize_reason(reason: Option<String>) -> Option<String> {
+    reason
+        .map(|r| r.replace(['\n', '\r'], " ").trim().to_string())
+        .filter(|r| !r.is_empty())
+}
+
+/// Mojang's API returns uuids without dashes, but the server's json files use dashes
+fn dashed_uuid(uuid: &str) -> String {
+    if uuid.len() != 32 {
+        return uuid.to_string();
+    }
+    format!(
+        "{}-{}-{}-{}-{}",
+        &uuid[0..8],
+        &uuid[8..12],
+        &uuid[12..16],
+        &uuid[16..20],
+        &uuid[20..32]
+    )
+}
+
+pub(super) async fn read_player_list(path: &Path) -> Result<Vec<PlayerListEntry>, Error> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = crate::util::fs::read_to_string(path).await?;
+    if content.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_str(&content).context(format!("Failed to parse {}", path.display()))?)
+}
+
+async fn write_player_list(path: &Path, entries: &[PlayerListEntry]) -> Result<(), Error> {
+    crate::util::fs::write_all(
+        path,
+        serde_json::to_string_pretty(entries)
+            .context("Failed to serialize player list, this is a bug, please report it")?,
+    )
+    .await
+}
+
+impl MinecraftInstance {
+    /// Apply a moderation action, through the console if the server is running,
+    /// or by editing the player list file directly if it is not
+    pub(super) async fn moderate(
+        &self,
+        player_name: &str,
+        command: String,
+        list_edit: Option<(&str, Option<Map<String, Value>>)>,
+        action: ModerationAction,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        validate_player_name(player_name)?;
+        if self.state().await == State::Running {
+            self.send_command(&command, caused_by.clone()).await?;
+        } else if let Some((file, entry)) = list_edit {
+            self.edit_player_list(file, player_name, entry).await?;
+        } else {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Instance is not running"),
+            });
+        }
+        self.event_broadcaster.send(Event {
+            event_inner: EventInner::InstanceEvent(InstanceEvent {
+                instance_uuid: self.uuid.clone(),
+                instance_name: self.name().await,
+                instance_event_inner: InstanceEventInner::PlayerModeration {
+                    player: player_name.to_string(),
+                    action,
+                },
+            }),
+            details: "".to_string(),
+            snowflake: Snowflake::default(),
+            caused_by,
+        });
+        Ok(())
+    }
+
+    /// Add the player to the list with the given extra fields, or remove them if `entry` is `None`
+    async fn edit_player_list(
+        &self,
+        file: &str,
+        player_name: &str,
+        entry: Option<Map<String, Value>>,
+    ) -> Result<(), Error> {
+        let path = self.path_to_instance.join(file);
+        let mut entries = read_player_list(&path).await?;
+        entries.retain(|e| !e.name.eq_ignore_ascii_case(player_name));
+        if let Some(extra) = entry {
+            let uuid = name_to_uuid(player_name).await.ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Could not find a Minecraft account named {}", player_name),
+            })?;
+            entries.push(PlayerListEntry {
+                uuid: dashed_uuid(&uuid),
+                name: player_name.to_string(),
+                extra,
+            });
+        }
+        write_player_list(&path, &entries).await
+    }
+
+    pub(super) fn ban_list_entry(reason: Option<&str>, caused_by: &CausedBy) -> Map<String, Value> {
+        let source = match caused_by {
+            CausedBy::User { user_name, .. } => user_name.clone(),
+            _ => "Lodestone".to_string(),
+        };
+        let entry = json!({
+            "created": chrono::Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string(),
+            "source": source,
+            // expiry is handled by lodestone so it also works while the server is running
+            "expires": "forever",
+            "reason": reason.unwrap_or("Banned by an operator."),
+        });
+        match entry {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Pardon the player once the ban expires, unless the ban was changed in the meantime
+    pub(super) fn schedule_ban_expiry(&self, player_name: String, expires: i64) {
+        let __self = self.clone();
+        tokio::spawn(async move {
+            let delay = (expires - chrono::Utc::now().timestamp()).max(0) as u64;
+            tokio::time::sleep(Duration::from_secs(delay)).await;
+            if __self.config.lock().await.ban_expiry.get(&player_name) != Some(&expires) {
+                return;
+            }
+            if let Err(e) = __self.pardon_player(&player_name, CausedBy::System).await {
+                error!(
+                    "[{}] Failed to lift expired ban of {}: {}",
+                    __self.uuid, player_name, e
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_player_name() {
+        assert!(validate_player_name("Notch").is_ok());
+        assert!(validate_player_name("some_player_123").is_ok());
+        assert!(validate_player_name("").is_err());
+        assert!(validate_player_name("a_name_that_is_too_long").is_err());
+        assert!(validate_player_name("Notch\nop Notch").is_err());
+        assert!(validate_player_name("two words").is_err());
+    }
+
+    #[test]
+    fn test_dashed_uuid() {
+        assert_eq!(
+            dashed_uuid("069a79f444e94726a5befca90e38aaf5"),
+            "069a79f4-44e9-4726-a5be-fca90e38aaf5"
+        );
+        assert_eq!(
+            dashed_uuid("069a79f4-44e9-4726-a5be-fca90e38aaf5"),
+            "069a79f4-44e9-4726-a5be-fca90e38aaf5"
+        );
+    }
+}
@@ -0,0 +1,1137 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use indexmap::IndexMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::prelude::path_to_tmp;
+use crate::util::{download_file, list_dir, scoped_join_win_safe};
+
+use super::{Flavour, MinecraftInstance};
+
+/// Index used to install mods when none is configured in the global settings
+pub const DEFAULT_MOD_INDEX: &str = "https://api.modrinth.com/v2";
+
+/// Keeps track of which index project and version each installed jar came from
+const MOD_SOURCES_FILE: &str = ".lodestone_mods.json";
+/// Sub folder of the mods or plugins folder holding disabled jars, the server doesn't look into it
+const DISABLED_FOLDER: &str = "disabled";
+
+/// Mod ids every mod of a loader depends on, not worth listing as dependencies
+const PLATFORM_IDS: [&str; 6] = [
+    "minecraft",
+    "java",
+    "fabricloader",
+    "fabric-loader",
+    "forge",
+    "neoforge",
+];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum ModLoader {
+    Fabric,
+    Forge,
+    /// Bukkit plugins, run by Spigot and Paper
+    Bukkit,
+}
+
+/// Metadata read from the `fabric.mod.json`, `META-INF/mods.toml` or `plugin.yml` of a jar
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct ModMetadata {
+    pub loader: ModLoader,
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub authors: Vec<String>,
+    /// ids of the mods or plugins this one requires
+    pub dependencies: Vec<String>,
+}
+
+/// Where an installed jar came from, if it was installed from a mod index
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct ModSource {
+    pub project_id: String,
+    pub version_id: String,
+    pub version_number: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct ModEntry {
+    pub file_name: String,
+    pub enabled: bool,
+    pub size: u64,
+    /// `None` if the jar has no metadata Lodestone understands
+    pub metadata: Option<ModMetadata>,
+    pub source: Option<ModSource>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FabricModJson {
+    id: String,
+    version: String,
+    name: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    authors: Vec<FabricPerson>,
+    #[serde(default)]
+    depends: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum FabricPerson {
+    Name(String),
+    Detailed { name: String },
+}
+
+fn parse_fabric_mod_json(content: &str) -> Option<ModMetadata> {
+    let mod_json: FabricModJson = serde_json::from_str(content).ok()?;
+    let mut dependencies: Vec<String> = mod_json
+        .depends
+        .into_keys()
+        .filter(|id| !PLATFORM_IDS.contains(&id.as_str()))
+        .collect();
+    dependencies.sort();
+    Some(ModMetadata {
+        loader: ModLoader::Fabric,
+        name: mod_json.name.unwrap_or_else(|| mod_json.id.clone()),
+        id: mod_json.id,
+        version: mod_json.version,
+        description: mod_json.description.filter(|d| !d.is_empty()),
+        authors: mod_json
+            .authors
+            .into_iter()
+            .map(|author| match author {
+                FabricPerson::Name(name) | FabricPerson::Detailed { name } => name,
+            })
+            .collect(),
+        dependencies,
+    })
+}
+
+/// Read a main attribute of a jar's `META-INF/MANIFEST.MF`
+fn manifest_attribute(manifest: &str, attribute: &str) -> Option<String> {
+    manifest.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == attribute).then(|| value.trim().to_string())
+    })
+}
+
+fn parse_mods_toml(content: &str, jar_version: Option<&str>) -> Option<ModMetadata> {
+    let mods_toml: toml::Table = toml::from_str(content).ok()?;
+    let first_mod = mods_toml.get("mods")?.as_array()?.first()?.as_table()?;
+    let get_str = |key: &str| {
+        first_mod
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(String::from)
+    };
+    let id = get_str("modId")?;
+    let mut version = get_str("version").unwrap_or_else(|| "${file.jarVersion}".to_string());
+    // forge fills this in from the manifest when it loads the mod
+    if version == "${file.jarVersion}" {
+        version = jar_version.unwrap_or("unknown").to_string();
+    }
+    let dependencies = mods_toml
+        .get("dependencies")
+        .and_then(|v| v.get(&id))
+        .and_then(|v| v.as_array())
+        .map(|deps| {
+            deps.iter()
+                .filter_map(|dep| dep.as_table())
+                .filter(|dep| {
+                    // `mandatory` was replaced by `type` in newer forge versions
+                    dep.get("mandatory")
+                        .and_then(|v| v.as_bool())
+                        .unwrap_or(false)
+                        || dep.get("type").and_then(|v| v.as_str()) == Some("required")
+                })
+                .filter_map(|dep| dep.get("modId").and_then(|v| v.as_str()))
+                .filter(|dep_id| !PLATFORM_IDS.contains(dep_id))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(ModMetadata {
+        loader: ModLoader::Forge,
+        name: get_str("displayName").unwrap_or_else(|| id.clone()),
+        description: get_str("description")
+            .map(|d| d.trim().to_string())
+            .filter(|d| !d.is_empty()),
+        authors: get_str("authors").into_iter().collect(),
+        id,
+        version,
+        dependencies,
+    })
+}
+
+fn unquote_yaml(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return value[1..value.len() - 1].to_string();
+        }
+    }
+    // drop trailing comments of unquoted scalars
+    match value.split_once(" #") {
+        Some((value, _)) => value.trim_end().to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// Read the top level keys of a `plugin.yml`
+///
+/// Only scalars and lists of scalars are supported, which is all a plugin description needs.
+/// Block scalars and nested maps are skipped.
+fn parse_yaml_top_level(content: &str) -> HashMap<String, Vec<String>> {
+    let mut ret: HashMap<String, Vec<String>> = HashMap::new();
+    let mut current_list: Option<String> = None;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some(item) = trimmed.strip_prefix("- ") {
+            if let Some(key) = &current_list {
+                ret.entry(key.clone()).or_default().push(unquote_yaml(item));
+            }
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        current_list = None;
+        let (key, value) = match line.split_once(':') {
+            Some(v) => v,
+            None => continue,
+        };
+        let (key, value) = (key.trim().to_string(), value.trim());
+        if value.is_empty() {
+            current_list = Some(key);
+        } else if value.starts_with('[') && value.ends_with(']') {
+            ret.insert(
+                key,
+                value[1..value.len() - 1]
+                    .split(',')
+                    .map(unquote_yaml)
+                    .filter(|v| !v.is_empty())
+                    .collect(),
+            );
+        } else if !value.starts_with('|') && !value.starts_with('>') {
+            ret.insert(key, vec![unquote_yaml(value)]);
+        }
+    }
+    ret
+}
+
+fn parse_plugin_yml(content: &str) -> Option<ModMetadata> {
+    let mut yaml = parse_yaml_top_level(content);
+    let mut take_scalar = |key: &str| yaml.remove(key).and_then(|v| v.into_iter().next());
+    let name = take_scalar("name")?;
+    let version = take_scalar("version").unwrap_or_else(|| "unknown".to_string());
+    let description = take_scalar("description");
+    let mut authors: Vec<String> = yaml.remove("author").unwrap_or_default();
+    authors.extend(yaml.remove("authors").unwrap_or_default());
+    Some(ModMetadata {
+        loader: ModLoader::Bukkit,
+        id: name.clone(),
+        name,
+        version,
+        description,
+        authors,
+        dependencies: yaml.remove("depend").unwrap_or_default(),
+    })
+}
+
+fn read_zip_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Option<String> {
+    let mut entry = archive.by_name(name).ok()?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+fn read_jar_metadata(path: &Path) -> Result<Option<ModMetadata>, Error> {
+    let file = File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let mut archive =
+        zip::ZipArchive::new(file).context(format!("{} is not a valid jar", path.display()))?;
+    if let Some(content) = read_zip_entry(&mut archive, "fabric.mod.json") {
+        return Ok(parse_fabric_mod_json(&content));
+    }
+    if let Some(content) = read_zip_entry(&mut archive, "META-INF/mods.toml") {
+        let jar_version = read_zip_entry(&mut archive, "META-INF/MANIFEST.MF")
+            .and_then(|manifest| manifest_attribute(&manifest, "Implementation-Version"));
+        return Ok(parse_mods_toml(&content, jar_version.as_deref()));
+    }
+    if let Some(content) = read_zip_entry(&mut archive, "plugin.yml")
+        .or_else(|| read_zip_entry(&mut archive, "paper-plugin.yml"))
+    {
+        return Ok(parse_plugin_yml(&content));
+    }
+    Ok(None)
+}
+
+/// Jar names end up in paths, only allow plain file names
+fn validate_jar_name(file_name: &str) -> Result<(), Error> {
+    if file_name.is_empty()
+        || file_name.starts_with('.')
+        || file_name.contains(['/', '\\'])
+        || !file_name.to_lowercase().ends_with(".jar")
+    {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("{} is not a valid jar file name", file_name),
+        });
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum DependencyType {
+    Required,
+    Optional,
+    Incompatible,
+    Embedded,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct IndexDependency {
+    version_id: Option<String>,
+    project_id: Option<String>,
+    dependency_type: DependencyType,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct IndexFile {
+    url: String,
+    filename: String,
+    #[serde(default)]
+    primary: bool,
+    size: Option<u64>,
+}
+
+/// A version of a project, in the format of Modrinth's `/version` endpoints
+#[derive(Debug, Clone, Deserialize)]
+struct IndexVersion {
+    id: String,
+    project_id: String,
+    version_number: String,
+    #[serde(default)]
+    date_published: String,
+    #[serde(default)]
+    game_versions: Vec<String>,
+    #[serde(default)]
+    loaders: Vec<String>,
+    files: Vec<IndexFile>,
+    #[serde(default)]
+    dependencies: Vec<IndexDependency>,
+}
+
+impl IndexVersion {
+    fn is_compatible(&self, loaders: &[&str], game_version: &str) -> bool {
+        self.loaders.iter().any(|l| loaders.contains(&l.as_str()))
+            && self.game_versions.iter().any(|v| v == game_version)
+    }
+
+    fn primary_file(&self) -> Result<&IndexFile, Error> {
+        self.files
+            .iter()
+            .find(|f| f.primary)
+            .or_else(|| self.files.first())
+            .ok_or_else(|| {
+                eyre!(
+                    "Version {} of {} has no files",
+                    self.version_number,
+                    self.project_id
+                )
+                .into()
+            })
+    }
+}
+
+fn newest_compatible(
+    versions: Vec<IndexVersion>,
+    loaders: &[&str],
+    game_version: &str,
+) -> Option<IndexVersion> {
+    versions
+        .into_iter()
+        .filter(|v| v.is_compatible(loaders, game_version))
+        .max_by(|a, b| a.date_published.cmp(&b.date_published))
+}
+
+/// A Modrinth style mod index
+///
+/// Either the Modrinth API itself, an http mirror of it, or a local directory laid out like it
+/// (`project/<id>/version.json` and `version/<id>.json`)
+enum ModIndex {
+    Remote(String),
+    Local(PathBuf),
+}
+
+impl ModIndex {
+    fn new(index: &str) -> Result<Self, Error> {
+        if index.starts_with("https://") || index.starts_with("http://") {
+            return Ok(Self::Remote(index.trim_end_matches('/').to_string()));
+        }
+        let path = PathBuf::from(index);
+        if path.is_absolute() {
+            Ok(Self::Local(path))
+        } else {
+            Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Mod index must be an http(s) URL or an absolute path"),
+            })
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, segments: &[&str]) -> Result<T, Error> {
+        for segment in segments {
+            if segment.is_empty()
+                || !segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("{} is not a valid project or version id", segment),
+                });
+            }
+        }
+        match self {
+            Self::Remote(base) => {
+                let url = format!("{}/{}", base, segments.join("/"));
+                let response = reqwest::get(&url)
+                    .await
+                    .context(format!("Failed to reach mod index at {}", base))?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Err(Error {
+                        kind: ErrorKind::NotFound,
+                        source: eyre!("{} not found in mod index", segments.join("/")),
+                    });
+                }
+                Ok(response
+                    .error_for_status()
+                    .context(format!("Mod index failed to answer {}", url))?
+                    .json()
+                    .await
+                    .context(format!("Invalid response from mod index for {}", url))?)
+            }
+            Self::Local(dir) => {
+                let path = dir.join(format!("{}.json", segments.join("/")));
+                if !path.is_file() {
+                    return Err(Error {
+                        kind: ErrorKind::NotFound,
+                        source: eyre!("{} not found in mod index", segments.join("/")),
+                    });
+                }
+                Ok(
+                    serde_json::from_str(&crate::util::fs::read_to_string(&path).await?)
+                        .context(format!("Failed to parse {}", path.display()))?,
+                )
+            }
+        }
+    }
+
+    async fn project_versions(&self, project_id: &str) -> Result<Vec<IndexVersion>, Error> {
+        self.get(&["project", project_id, "version"]).await
+    }
+
+    async fn version(&self, version_id: &str) -> Result<IndexVersion, Error> {
+        self.get(&["version", version_id]).await
+    }
+
+    /// Fetch a file of a version into `dir`
+    async fn fetch_file(&self, file: &IndexFile, dir: &Path) -> Result<PathBuf, Error> {
+        validate_jar_name(&file.filename)?;
+        let path = if file.url.starts_with("https://") || file.url.starts_with("http://") {
+            download_file(&file.url, dir, Some(&file.filename), &|_| {}, true).await?
+        } else if let Self::Local(index_dir) = self {
+            // local indexes may refer to files relative to the index
+            let path = dir.join(&file.filename);
+            tokio::fs::copy(scoped_join_win_safe(index_dir, &file.url)?, &path)
+                .await
+                .context(format!("Failed to copy {} from mod index", file.url))?;
+            path
+        } else {
+            return Err(eyre!("Mod index returned an invalid file url {}", file.url).into());
+        };
+        if let Some(size) = file.size {
+            let actual = tokio::fs::metadata(&path)
+                .await
+                .context(format!("Failed to read metadata of {}", path.display()))?
+                .len();
+            if actual != size {
+                return Err(Error {
+                    kind: ErrorKind::External,
+                    source: eyre!(
+                        "{} is {} bytes, but the mod index says it should be {} bytes",
+                        file.filename,
+                        actual,
+                        size
+                    ),
+                });
+            }
+        }
+        Ok(path)
+    }
+}
+
+pub fn validate_mod_index(index: &str) -> Result<(), Error> {
+    ModIndex::new(index).map(|_| ())
+}
+
+/// Resolve the versions to install for a project, along with its missing required dependencies
+///
+/// Dependencies that are already installed are left as they are.
+async fn resolve_install_plan(
+    index: &ModIndex,
+    project_id: &str,
+    version_id: Option<&str>,
+    loaders: &[&str],
+    game_version: &str,
+    sources: &IndexMap<String, ModSource>,
+) -> Result<Vec<IndexVersion>, Error> {
+    let installed: HashSet<&str> = sources.values().map(|s| s.project_id.as_str()).collect();
+    let mut plan: Vec<IndexVersion> = Vec::new();
+    let mut queue = VecDeque::from([(Some(project_id.to_string()), version_id.map(String::from))]);
+    while let Some((project_id, version_id)) = queue.pop_front() {
+        let is_root = plan.is_empty();
+        let version = match (project_id, version_id) {
+            (_, Some(version_id)) => {
+                let version = index.version(&version_id).await?;
+                if !version.is_compatible(loaders, game_version) {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!(
+                            "Version {} of {} is not compatible with minecraft {} on {}",
+                            version.version_number,
+                            version.project_id,
+                            game_version,
+                            loaders.join("/")
+                        ),
+                    });
+                }
+                version
+            }
+            (Some(project_id), None) => newest_compatible(
+                index.project_versions(&project_id).await?,
+                loaders,
+                game_version,
+            )
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "No version of {} is compatible with minecraft {} on {}",
+                    project_id,
+                    game_version,
+                    loaders.join("/")
+                ),
+            })?,
+            // dependencies that are only known by file name can't be resolved
+            (None, None) => continue,
+        };
+        if plan.iter().any(|p| p.project_id == version.project_id)
+            || (!is_root && installed.contains(version.project_id.as_str()))
+        {
+            continue;
+        }
+        if let Some(conflict) = plan.iter().find(|p| {
+            p.dependencies.iter().any(|d| {
+                d.dependency_type == DependencyType::Incompatible
+                    && d.project_id.as_deref() == Some(version.project_id.as_str())
+            })
+        }) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "{} is incompatible with {}",
+                    conflict.project_id,
+                    version.project_id
+                ),
+            });
+        }
+        for dependency in &version.dependencies {
+            match dependency.dependency_type {
+                DependencyType::Required => {
+                    if let Some(dep_project) = &dependency.project_id {
+                        if installed.contains(dep_project.as_str())
+                            || plan.iter().any(|p| &p.project_id == dep_project)
+                        {
+                            continue;
+                        }
+                    }
+                    queue.push_back((dependency.project_id.clone(), dependency.version_id.clone()));
+                }
+                DependencyType::Incompatible => {
+                    if let Some(dep_project) = &dependency.project_id {
+                        if installed.contains(dep_project.as_str())
+                            || plan.iter().any(|p| &p.project_id == dep_project)
+                        {
+                            return Err(Error {
+                                kind: ErrorKind::BadRequest,
+                                source: eyre!(
+                                    "{} is incompatible with {}, which is already installed",
+                                    version.project_id,
+                                    dep_project
+                                ),
+                            });
+                        }
+                    }
+                }
+                DependencyType::Optional | DependencyType::Embedded => {}
+            }
+        }
+        plan.push(version);
+    }
+    Ok(plan)
+}
+
+impl MinecraftInstance {
+    /// The loader of the instance, and the index loaders whose versions it can run
+    async fn mod_loader(&self) -> Result<(ModLoader, &'static [&'static str]), Error> {
+        const FABRIC: &[&str] = &["fabric"];
+        const FORGE: &[&str] = &["forge"];
+        const PAPER: &[&str] = &["paper", "spigot", "bukkit"];
+        const SPIGOT: &[&str] = &["spigot", "bukkit"];
+        match self.config.lock().await.flavour {
+            Flavour::Fabric { .. } => Ok((ModLoader::Fabric, FABRIC)),
+            Flavour::Forge { .. } => Ok((ModLoader::Forge, FORGE)),
+            Flavour::Paper { .. } => Ok((ModLoader::Bukkit, PAPER)),
+            Flavour::Spigot => Ok((ModLoader::Bukkit, SPIGOT)),
+            Flavour::Vanilla => Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Vanilla servers do not support mods or plugins"),
+            }),
+        }
+    }
+
+    async fn path_to_mods(&self) -> Result<PathBuf, Error> {
+        Ok(self
+            .path_to_instance
+            .join(match self.mod_loader().await?.0 {
+                ModLoader::Bukkit => "plugins",
+                ModLoader::Fabric | ModLoader::Forge => "mods",
+            }))
+    }
+
+    async fn read_mod_sources(&self) -> Result<IndexMap<String, ModSource>, Error> {
+        let path = self.path_to_instance.join(MOD_SOURCES_FILE);
+        if !path.is_file() {
+            return Ok(IndexMap::new());
+        }
+        Ok(
+            serde_json::from_str(&crate::util::fs::read_to_string(&path).await?)
+                .context(format!("Failed to parse {}", path.display()))?,
+        )
+    }
+
+    async fn write_mod_sources(
+        &self,
+        mut sources: IndexMap<String, ModSource>,
+    ) -> Result<(), Error> {
+        // forget about jars that were removed by hand
+        let path_to_mods = self.path_to_mods().await?;
+        sources.retain(|file_name, _| {
+            path_to_mods.join(file_name).is_file()
+                || path_to_mods.join(DISABLED_FOLDER).join(file_name).is_file()
+        });
+        crate::util::fs::write_all(
+            self.path_to_instance.join(MOD_SOURCES_FILE),
+            serde_json::to_string_pretty(&sources)
+                .context("Failed to serialize mod sources, this is a bug, please report it")?,
+        )
+        .await
+    }
+
+    /// Find an installed jar, returning its path and whether it is enabled
+    async fn find_mod(&self, file_name: &str) -> Result<(PathBuf, bool), Error> {
+        validate_jar_name(file_name)?;
+        let path_to_mods = self.path_to_mods().await?;
+        if path_to_mods.join(file_name).is_file() {
+            Ok((path_to_mods.join(file_name), true))
+        } else if path_to_mods.join(DISABLED_FOLDER).join(file_name).is_file() {
+            Ok((path_to_mods.join(DISABLED_FOLDER).join(file_name), false))
+        } else {
+            Err(Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("{} is not installed", file_name),
+            })
+        }
+    }
+
+    pub async fn list_mods(&self) -> Result<Vec<ModEntry>, Error> {
+        let path_to_mods = self.path_to_mods().await?;
+        let sources = self.read_mod_sources().await?;
+        let mut ret = Vec::new();
+        for (dir, enabled) in [
+            (path_to_mods.clone(), true),
+            (path_to_mods.join(DISABLED_FOLDER), false),
+        ] {
+            if !dir.is_dir() {
+                continue;
+            }
+            for path in list_dir(&dir, Some(false)).await? {
+                let file_name = match path.file_name() {
+                    Some(name) => name.to_string_lossy().to_string(),
+                    None => continue,
+                };
+                if !file_name.to_lowercase().ends_with(".jar") {
+                    continue;
+                }
+                let size = tokio::fs::metadata(&path)
+                    .await
+                    .context(format!("Failed to read metadata of {}", path.display()))?
+                    .len();
+                let metadata = tokio::task::spawn_blocking({
+                    let path = path.clone();
+                    move || read_jar_metadata(&path)
+                })
+                .await
+                .context("Failed to read jar metadata")?
+                .unwrap_or_else(|e| {
+                    warn!("Failed to read metadata of {}: {}", path.display(), e);
+                    None
+                });
+                ret.push(ModEntry {
+                    source: sources.get(&file_name).cloned(),
+                    file_name,
+                    enabled,
+                    size,
+                    metadata,
+                });
+            }
+        }
+        ret.sort_by(|a, b| a.file_name.to_lowercase().cmp(&b.file_name.to_lowercase()));
+        Ok(ret)
+    }
+
+    /// Enable or disable a jar by moving it in or out of the disabled folder
+    pub async fn set_mod_enabled(&self, file_name: &str, enabled: bool) -> Result<(), Error> {
+        let _guard = self.mods_lock.lock().await;
+        let (path, is_enabled) = self.find_mod(file_name).await?;
+        if is_enabled == enabled {
+            return Ok(());
+        }
+        let path_to_mods = self.path_to_mods().await?;
+        let destination = if enabled {
+            path_to_mods.join(file_name)
+        } else {
+            crate::util::fs::create_dir_all(path_to_mods.join(DISABLED_FOLDER)).await?;
+            path_to_mods.join(DISABLED_FOLDER).join(file_name)
+        };
+        if destination.exists() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{} already exists", destination.display()),
+            });
+        }
+        crate::util::fs::rename(path, destination).await
+    }
+
+    pub async fn delete_mod(&self, file_name: &str) -> Result<(), Error> {
+        let _guard = self.mods_lock.lock().await;
+        let (path, _) = self.find_mod(file_name).await?;
+        crate::util::fs::remove_file(path).await?;
+        let sources = self.read_mod_sources().await?;
+        self.write_mod_sources(sources).await
+    }
+
+    /// Install a project from a mod index along with its missing required dependencies
+    ///
+    /// Installs the newest version compatible with the instance unless `version_id` is given.
+    /// If the project is already installed, it is replaced, keeping it disabled if it was.
+    /// Returns the jars that were installed.
+    pub async fn install_mod(
+        &self,
+        index: &str,
+        project_id: &str,
+        version_id: Option<&str>,
+    ) -> Result<Vec<ModEntry>, Error> {
+        let index = ModIndex::new(index)?;
+        let guard = self.mods_lock.lock().await;
+        let (_, loaders) = self.mod_loader().await?;
+        let game_version = self.config.lock().await.version.clone();
+        let path_to_mods = self.path_to_mods().await?;
+        let mut sources = self.read_mod_sources().await?;
+
+        let plan = resolve_install_plan(
+            &index,
+            project_id,
+            version_id,
+            loaders,
+            &game_version,
+            &sources,
+        )
+        .await?;
+        // the requested version is already installed
+        if let Some(root) = plan.first() {
+            if sources.values().any(|s| s.version_id == root.id) {
+                return Ok(Vec::new());
+            }
+        }
+
+        // fetch everything before touching the instance so a failed download doesn't leave it half updated
+        let temp_dir = tempfile::tempdir_in(path_to_tmp()).context("Failed to create temp dir")?;
+        let mut fetched = Vec::new();
+        for version in plan {
+            let path = index
+                .fetch_file(version.primary_file()?, temp_dir.path())
+                .await?;
+            fetched.push((version, path));
+        }
+
+        crate::util::fs::create_dir_all(&path_to_mods).await?;
+        let mut installed = Vec::new();
+        for (version, path) in fetched {
+            let file_name = version.primary_file()?.filename.clone();
+            let previous = sources
+                .iter()
+                .find(|(_, s)| s.project_id == version.project_id)
+                .map(|(file_name, _)| file_name.clone());
+            let mut enabled = true;
+            if let Some(previous) = previous {
+                sources.shift_remove(&previous);
+                if let Ok((previous_path, previous_enabled)) = self.find_mod(&previous).await {
+                    crate::util::fs::remove_file(previous_path).await?;
+                    enabled = previous_enabled;
+                }
+            }
+            let destination = if enabled {
+                path_to_mods.join(&file_name)
+            } else {
+                path_to_mods.join(DISABLED_FOLDER).join(&file_name)
+            };
+            crate::util::fs::rename(path, destination).await?;
+            sources.insert(
+                file_name.clone(),
+                ModSource {
+                    project_id: version.project_id,
+                    version_id: version.id,
+                    version_number: version.version_number,
+                },
+            );
+            installed.push(file_name);
+        }
+        self.write_mod_sources(sources).await?;
+        drop(guard);
+
+        Ok(self
+            .list_mods()
+            .await?
+            .into_iter()
+            .filter(|entry| installed.contains(&entry.file_name))
+            .collect())
+    }
+
+    /// Update a jar installed from a mod index to the newest compatible version
+    pub async fn update_mod(&self, index: &str, file_name: &str) -> Result<Vec<ModEntry>, Error> {
+        self.find_mod(file_name).await?;
+        let source = self
+            .read_mod_sources()
+            .await?
+            .get(file_name)
+            .cloned()
+            .ok_or_else(|| Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "{} was not installed from a mod index and can't be updated",
+                    file_name
+                ),
+            })?;
+        self.install_mod(index, &source.project_id, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fabric_mod_json() {
+        let metadata = parse_fabric_mod_json(
+            r#"{
+                "schemaVersion": 1,
+                "id": "lithium",
+                "version": "0.11.2",
+                "name": "Lithium",
+                "description": "No-compromises game logic optimization mod",
+                "authors": ["jellysquid3", {"name": "2No2Name", "contact": {}}],
+                "depends": {"fabricloader": ">=0.14.0", "minecraft": "1.20.x", "fabric-api": "*"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(metadata.loader, ModLoader::Fabric);
+        assert_eq!(metadata.id, "lithium");
+        assert_eq!(metadata.version, "0.11.2");
+        assert_eq!(metadata.authors, vec!["jellysquid3", "2No2Name"]);
+        assert_eq!(metadata.dependencies, vec!["fabric-api"]);
+    }
+
+    #[test]
+    fn test_parse_mods_toml() {
+        let metadata = parse_mods_toml(
+            r#"
+modLoader="javafml"
+loaderVersion="[47,)"
+license="MIT"
+
+[[mods]]
+modId="jei"
+version="${file.jarVersion}"
+displayName="Just Enough Items"
+authors="mezz"
+description='''
+JEI is an item and recipe viewing mod for Minecraft.
+'''
+
+[[dependencies.jei]]
+    modId="forge"
+    mandatory=true
+    versionRange="[47,)"
+[[dependencies.jei]]
+    modId="architectury"
+    type="required"
+[[dependencies.jei]]
+    modId="curios"
+    mandatory=false
+"#,
+            Some("15.2.0.27"),
+        )
+        .unwrap();
+        assert_eq!(metadata.loader, ModLoader::Forge);
+        assert_eq!(metadata.id, "jei");
+        assert_eq!(metadata.name, "Just Enough Items");
+        assert_eq!(metadata.version, "15.2.0.27");
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("JEI is an item and recipe viewing mod for Minecraft.")
+        );
+        assert_eq!(metadata.dependencies, vec!["architectury"]);
+    }
+
+    #[test]
+    fn test_parse_plugin_yml() {
+        let metadata = parse_plugin_yml(
+            r#"
+# a comment
+name: EssentialsX
+main: com.earth2me.essentials.Essentials
+version: "2.20.1"
+description: The essential plugin suite # trailing comment
+authors: [zenexer, 'ementalo']
+author: md_5
+depend:
+  - Vault
+  - ProtocolLib
+softdepend: [LuckPerms]
+commands:
+  home:
+    description: Teleport home
+    usage: /<command>
+"#,
+        )
+        .unwrap();
+        assert_eq!(metadata.loader, ModLoader::Bukkit);
+        assert_eq!(metadata.id, "EssentialsX");
+        assert_eq!(metadata.version, "2.20.1");
+        assert_eq!(
+            metadata.description.as_deref(),
+            Some("The essential plugin suite")
+        );
+        assert_eq!(metadata.authors, vec!["md_5", "zenexer", "ementalo"]);
+        assert_eq!(metadata.dependencies, vec!["Vault", "ProtocolLib"]);
+        assert!(parse_plugin_yml("main: some.Plugin").is_none());
+    }
+
+    #[test]
+    fn test_manifest_attribute() {
+        let manifest = "Manifest-Version: 1.0\r\nImplementation-Version: 1.2.3\r\n";
+        assert_eq!(
+            manifest_attribute(manifest, "Implementation-Version").as_deref(),
+            Some("1.2.3")
+        );
+        assert_eq!(manifest_attribute(manifest, "Main-Class"), None);
+    }
+
+    #[test]
+    fn test_validate_jar_name() {
+        assert!(validate_jar_name("sodium-fabric-0.5.3.jar").is_ok());
+        assert!(validate_jar_name("../server.jar").is_err());
+        assert!(validate_jar_name("mods\\evil.jar").is_err());
+        assert!(validate_jar_name(".hidden.jar").is_err());
+        assert!(validate_jar_name("readme.txt").is_err());
+    }
+
+    fn version(id: &str, date: &str, game_versions: &[&str], loaders: &[&str]) -> IndexVersion {
+        IndexVersion {
+            id: id.to_string(),
+            project_id: "project".to_string(),
+            version_number: id.to_string(),
+            date_published: date.to_string(),
+            game_versions: game_versions.iter().map(|v| v.to_string()).collect(),
+            loaders: loaders.iter().map(|v| v.to_string()).collect(),
+            files: Vec::new(),
+            dependencies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_newest_compatible() {
+        let versions = vec![
+            version("old", "2023-01-01T00:00:00Z", &["1.20.1"], &["fabric"]),
+            version("new", "2023-06-01T00:00:00Z", &["1.20.1"], &["fabric"]),
+            version("forge", "2023-07-01T00:00:00Z", &["1.20.1"], &["forge"]),
+            version("next", "2023-08-01T00:00:00Z", &["1.20.2"], &["fabric"]),
+        ];
+        assert_eq!(
+            newest_compatible(versions.clone(), &["fabric"], "1.20.1")
+                .unwrap()
+                .id,
+            "new"
+        );
+        assert!(newest_compatible(versions, &["fabric"], "1.19.4").is_none());
+    }
+
+    fn write_index_version(dir: &Path, version: serde_json::Value) {
+        let id = version["id"].as_str().unwrap().to_string();
+        let project_id = version["project_id"].as_str().unwrap().to_string();
+        std::fs::create_dir_all(dir.join("version")).unwrap();
+        std::fs::write(
+            dir.join("version").join(format!("{id}.json")),
+            version.to_string(),
+        )
+        .unwrap();
+        let project_dir = dir.join("project").join(&project_id);
+        std::fs::create_dir_all(&project_dir).unwrap();
+        let versions_path = project_dir.join("version.json");
+        let mut versions: Vec<serde_json::Value> = std::fs::read_to_string(&versions_path)
+            .map(|s| serde_json::from_str(&s).unwrap())
+            .unwrap_or_default();
+        versions.push(version);
+        std::fs::write(versions_path, serde_json::to_string(&versions).unwrap()).unwrap();
+    }
+
+    fn index_version(
+        id: &str,
+        project_id: &str,
+        dependencies: serde_json::Value,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "project_id": project_id,
+            "version_number": "1.0.0",
+            "date_published": "2023-06-01T00:00:00Z",
+            "game_versions": ["1.20.1"],
+            "loaders": ["fabric"],
+            "files": [{"url": format!("files/{id}.jar"), "filename": format!("{project_id}.jar"), "primary": true}],
+            "dependencies": dependencies,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_resolve_install_plan() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        write_index_version(
+            temp_dir.path(),
+            index_version(
+                "a1",
+                "a",
+                serde_json::json!([
+                    {"project_id": "b", "version_id": null, "dependency_type": "required"},
+                    {"project_id": "c", "version_id": null, "dependency_type": "optional"},
+                    {"project_id": null, "version_id": "d1", "dependency_type": "required"},
+                ]),
+            ),
+        );
+        write_index_version(
+            temp_dir.path(),
+            index_version(
+                "b1",
+                "b",
+                serde_json::json!([{"project_id": "d", "version_id": null, "dependency_type": "required"}]),
+            ),
+        );
+        write_index_version(
+            temp_dir.path(),
+            index_version("d1", "d", serde_json::json!([])),
+        );
+        write_index_version(
+            temp_dir.path(),
+            index_version(
+                "e1",
+                "e",
+                serde_json::json!([{"project_id": "d", "version_id": null, "dependency_type": "incompatible"}]),
+            ),
+        );
+        let index = ModIndex::new(temp_dir.path().to_str().unwrap()).unwrap();
+
+        let plan = resolve_install_plan(&index, "a", None, &["fabric"], "1.20.1", &IndexMap::new())
+            .await
+            .unwrap();
+        let ids: Vec<&str> = plan.iter().map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, vec!["a1", "b1", "d1"]);
+
+        // installed dependencies are left alone
+        let mut sources = IndexMap::new();
+        sources.insert(
+            "b.jar".to_string(),
+            ModSource {
+                project_id: "b".to_string(),
+                version_id: "b0".to_string(),
+                version_number: "0.9.0".to_string(),
+            },
+        );
+        let plan = resolve_install_plan(&index, "a", None, &["fabric"], "1.20.1", &sources)
+            .await
+            .unwrap();
+        let ids: Vec<&str> = plan.iter().map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, vec!["a1", "d1"]);
+
+        // incompatible with an installed project
+        sources.insert(
+            "d.jar".to_string(),
+            ModSource {
+                project_id: "d".to_string(),
+                version_id: "d1".to_string(),
+                version_number: "1.0.0".to_string(),
+            },
+        );
+        assert!(
+            resolve_install_plan(&index, "e", None, &["fabric"], "1.20.1", &sources)
+                .await
+                .is_err()
+        );
+
+        // wrong game version or loader
+        assert!(
+            resolve_install_plan(&index, "d", None, &["fabric"], "1.19.4", &IndexMap::new())
+                .await
+                .is_err()
+        );
+        assert!(resolve_install_plan(
+            &index,
+            "d",
+            Some("d1"),
+            &["forge"],
+            "1.20.1",
+            &IndexMap::new()
+        )
+        .await
+        .is_err());
+        assert!(ModIndex::new("relative/index").is_err());
+    }
+}
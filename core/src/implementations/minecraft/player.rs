@@ -1,13 +1,19 @@
 use async_trait::async_trait;
 
+use color_eyre::eyre::eyre;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-use crate::traits::t_player::Player;
+use crate::error::ErrorKind;
+use crate::events::CausedBy;
+use crate::traits::t_player::{BanEntry, ModerationAction, Player};
 use crate::traits::t_player::{TPlayer, TPlayerManagement};
 use crate::Error;
 
 use super::configurable::ServerPropertySetting;
+use super::moderation::{
+    read_player_list, sanitize_reason, BANNED_PLAYERS_FILE, OPS_FILE, WHITELIST_FILE,
+};
 use super::MinecraftInstance;
 
 #[derive(Eq, Debug, Clone, Serialize, Deserialize, TS)]
@@ -69,4 +75,194 @@ impl TPlayerManagement for MinecraftInstance {
     async fn get_player_list(&self) -> Result<HashSet<Player>, Error> {
         Ok(self.players_manager.lock().await.clone().into())
     }
+
+    async fn kick_player(
+        &self,
+        player_name: &str,
+        reason: Option<String>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let reason = sanitize_reason(reason);
+        self.moderate(
+            player_name,
+            format!(
+                "kick {} {}",
+                player_name,
+                reason.clone().unwrap_or_default()
+            ),
+            None,
+            ModerationAction::Kick { reason },
+            caused_by,
+        )
+        .await
+    }
+
+    async fn get_ban_list(&self) -> Result<Vec<BanEntry>, Error> {
+        let ban_expiry = self.config.lock().await.ban_expiry.clone();
+        Ok(
+            read_player_list(&self.path_to_instance.join(BANNED_PLAYERS_FILE))
+                .await?
+                .into_iter()
+                .map(|entry| {
+                    let field = |key: &str| {
+                        entry
+                            .extra
+                            .get(key)
+                            .and_then(|v| v.as_str())
+                            .map(|v| v.to_string())
+                    };
+                    let expires = ban_expiry
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(&entry.name))
+                        .map(|(_, expires)| *expires)
+                        .or_else(|| {
+                            chrono::DateTime::parse_from_str(
+                                &field("expires")?,
+                                "%Y-%m-%d %H:%M:%S %z",
+                            )
+                            .ok()
+                            .map(|t| t.timestamp())
+                        });
+                    BanEntry {
+                        reason: field("reason"),
+                        source: field("source"),
+                        expires,
+                        player: MinecraftPlayer::new(entry.name, Some(entry.uuid)).into(),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    async fn ban_player(
+        &self,
+        player_name: &str,
+        reason: Option<String>,
+        expires: Option<i64>,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        if let Some(expires) = expires {
+            if expires <= chrono::Utc::now().timestamp() {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Ban expiry must be in the future"),
+                });
+            }
+        }
+        let reason = sanitize_reason(reason);
+        self.moderate(
+            player_name,
+            format!("ban {} {}", player_name, reason.clone().unwrap_or_default()),
+            Some((
+                BANNED_PLAYERS_FILE,
+                Some(Self::ban_list_entry(reason.as_deref(), &caused_by)),
+            )),
+            ModerationAction::Ban {
+                reason: reason.clone(),
+                expires,
+            },
+            caused_by,
+        )
+        .await?;
+        {
+            let mut config = self.config.lock().await;
+            config
+                .ban_expiry
+                .retain(|name, _| !name.eq_ignore_ascii_case(player_name));
+            if let Some(expires) = expires {
+                config.ban_expiry.insert(player_name.to_string(), expires);
+            }
+        }
+        self.write_config_to_file().await?;
+        if let Some(expires) = expires {
+            self.schedule_ban_expiry(player_name.to_string(), expires);
+        }
+        Ok(())
+    }
+
+    async fn pardon_player(&self, player_name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        self.moderate(
+            player_name,
+            format!("pardon {}", player_name),
+            Some((BANNED_PLAYERS_FILE, None)),
+            ModerationAction::Pardon,
+            caused_by,
+        )
+        .await?;
+        self.config
+            .lock()
+            .await
+            .ban_expiry
+            .retain(|name, _| !name.eq_ignore_ascii_case(player_name));
+        self.write_config_to_file().await
+    }
+
+    async fn get_op_list(&self) -> Result<HashSet<Player>, Error> {
+        Ok(read_player_list(&self.path_to_instance.join(OPS_FILE))
+            .await?
+            .into_iter()
+            .map(|entry| MinecraftPlayer::new(entry.name, Some(entry.uuid)).into())
+            .collect())
+    }
+
+    async fn op_player(&self, player_name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        let mut entry = serde_json::Map::new();
+        entry.insert("level".to_string(), 4.into());
+        entry.insert("bypassesPlayerLimit".to_string(), false.into());
+        self.moderate(
+            player_name,
+            format!("op {}", player_name),
+            Some((OPS_FILE, Some(entry))),
+            ModerationAction::Op,
+            caused_by,
+        )
+        .await
+    }
+
+    async fn deop_player(&self, player_name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        self.moderate(
+            player_name,
+            format!("deop {}", player_name),
+            Some((OPS_FILE, None)),
+            ModerationAction::Deop,
+            caused_by,
+        )
+        .await
+    }
+
+    async fn get_whitelist(&self) -> Result<HashSet<Player>, Error> {
+        Ok(
+            read_player_list(&self.path_to_instance.join(WHITELIST_FILE))
+                .await?
+                .into_iter()
+                .map(|entry| MinecraftPlayer::new(entry.name, Some(entry.uuid)).into())
+                .collect(),
+        )
+    }
+
+    async fn add_to_whitelist(&self, player_name: &str, caused_by: CausedBy) -> Result<(), Error> {
+        self.moderate(
+            player_name,
+            format!("whitelist add {}", player_name),
+            Some((WHITELIST_FILE, Some(serde_json::Map::new()))),
+            ModerationAction::WhitelistAdd,
+            caused_by,
+        )
+        .await
+    }
+
+    async fn remove_from_whitelist(
+        &self,
+        player_name: &str,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        self.moderate(
+            player_name,
+            format!("whitelist remove {}", player_name),
+            Some((WHITELIST_FILE, None)),
+            ModerationAction::WhitelistRemove,
+            caused_by,
+        )
+        .await
+    }
 }
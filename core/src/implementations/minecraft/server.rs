@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::process::Stdio;
 use std::time::Duration;
@@ -9,13 +10,13 @@ use tokio::process::Command;
 
 use crate::error::{Error, ErrorKind};
 use crate::events::{CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner};
-use crate::implementations::minecraft::line_parser::{
-    parse_player_joined, parse_player_left, parse_player_msg, parse_server_started,
-    parse_system_msg, PlayerMessage,
-};
+use crate::implementations::crash_restart::restart_after_crash;
+use crate::implementations::minecraft::line_parser::parse_system_msg;
 use crate::implementations::minecraft::player::MinecraftPlayer;
 use crate::implementations::minecraft::util::name_to_uuid;
+use crate::log_rules::LogMatch;
 use crate::macro_executor::{DefaultWorkerOptionGenerator, SpawnResult};
+use crate::macro_limits::{read_macro_limits, MacroLimits};
 use crate::traits::t_configurable::TConfigurable;
 use crate::traits::t_macro::TaskEntry;
 use crate::traits::t_server::{MonitorReport, State, StateAction, TServer};
@@ -24,12 +25,17 @@ use crate::types::Snowflake;
 use crate::util::{dont_spawn_terminal, list_dir};
 
 use super::r#macro::resolve_macro_invocation;
+use super::util::path_to_java;
 use super::{Flavour, ForgeBuildVersion, MinecraftInstance};
 use tracing::{error, info, warn};
 
-#[async_trait::async_trait]
-impl TServer for MinecraftInstance {
-    async fn start(&self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
+/// Number of console lines included in the error event when an instance crashes
+const CRASH_REPORT_LINES: usize = 20;
+
+impl MinecraftInstance {
+    /// Start the server process, used directly by the backup, restore and upgrade paths
+    /// that already hold `backup_lock`
+    pub(super) async fn start_server(&self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
         self.state.lock().await.try_transition(
             StateAction::UserStart,
@@ -56,6 +62,10 @@ impl TServer for MinecraftInstance {
 
         let prelaunch = resolve_macro_invocation(&self.path_to_instance, "prelaunch");
         if let Some(prelaunch) = prelaunch {
+            // a malformed header still runs under the global limits
+            let limits = read_macro_limits(&prelaunch)
+                .await
+                .unwrap_or(MacroLimits::UNLIMITED);
             let res: Result<SpawnResult, Error> = self
                 .macro_executor
                 .spawn(
@@ -66,6 +76,7 @@ impl TServer for MinecraftInstance {
                     None,
                     None,
                     Some(self.uuid.clone()),
+                    Some(limits),
                 )
                 .await;
 
@@ -102,15 +113,7 @@ impl TServer for MinecraftInstance {
         let jre = if let Some(jre) = &config.java_cmd {
             PathBuf::from(jre)
         } else {
-            self.path_to_runtimes
-                .join("java")
-                .join(format!("jre{}", config.jre_major_version))
-                .join(if std::env::consts::OS == "macos" {
-                    "Contents/Home/bin"
-                } else {
-                    "bin"
-                })
-                .join("java")
+            path_to_java(&self.path_to_runtimes, config.jre_major_version)
         };
 
         let mut server_start_command = Command::new(&jre);
@@ -242,6 +245,7 @@ impl TServer for MinecraftInstance {
                     let players_manager = __self.players_manager.clone();
                     async move {
                         let mut did_start = false;
+                        let mut last_lines = VecDeque::with_capacity(CRASH_REPORT_LINES);
 
                         let mut stdout_reader = BufReader::new(stdout);
                         let mut stderr_reader = BufReader::new(stderr);
@@ -279,6 +283,10 @@ impl TServer for MinecraftInstance {
                             if let Ok(line) = line_res {
                                 if let Some(line) = line {
                                     let line = String::from_utf8_lossy(&line).to_string();
+                                    if last_lines.len() == CRASH_REPORT_LINES {
+                                        last_lines.pop_front();
+                                    }
+                                    last_lines.push_back(line.trim_end().to_string());
                                     if !is_stdout {
                                         // info!("[{}] {}", name, line);
                                         warn!("[{}] {}", name, line);
@@ -297,7 +305,11 @@ impl TServer for MinecraftInstance {
                                         caused_by: CausedBy::System,
                                     });
 
-                                    if parse_server_started(&line) && !did_start {
+                                    let log_match = {
+                                        let log_rules = __self.log_rules.lock().await.compiled();
+                                        log_rules.parse(&line).map(|(_, log_match)| log_match)
+                                    };
+                                    if log_match == Some(LogMatch::ServerStarted) && !did_start {
                                         did_start = true;
                                         __self
                                             .state
@@ -387,7 +399,7 @@ impl TServer for MinecraftInstance {
                                             __self.rcon_conn.lock().await.take();
                                         }
                                     }
-                                    if let Some(system_msg) = parse_system_msg(&line) {
+                                    if parse_system_msg(&line).is_some() {
                                         let _ = event_broadcaster.send(Event {
                                             event_inner: EventInner::InstanceEvent(InstanceEvent {
                                                 instance_uuid: uuid.clone(),
@@ -401,34 +413,44 @@ impl TServer for MinecraftInstance {
                                             snowflake: Snowflake::default(),
                                             caused_by: CausedBy::System,
                                         });
-                                        if let Some(player_name) = parse_player_joined(&system_msg)
-                                        {
+                                    }
+                                    let instance_event_inner = match log_match {
+                                        Some(LogMatch::PlayerJoined { player, .. }) => {
                                             players_manager.lock().await.add_player(
                                                 MinecraftPlayer {
-                                                    name: player_name.clone(),
-                                                    uuid: name_to_uuid(&player_name).await,
+                                                    name: player.clone(),
+                                                    uuid: name_to_uuid(&player).await,
                                                 },
                                                 __self.name().await,
                                             );
-                                        } else if let Some(player_name) =
-                                            parse_player_left(&system_msg)
-                                        {
+                                            None
+                                        }
+                                        Some(LogMatch::PlayerLeft { player }) => {
                                             players_manager
                                                 .lock()
                                                 .await
-                                                .remove_by_name(&player_name, __self.name().await);
+                                                .remove_by_name(&player, __self.name().await);
+                                            None
+                                        }
+                                        Some(LogMatch::PlayerMessage { player, message }) => {
+                                            Some(InstanceEventInner::PlayerMessage {
+                                                player,
+                                                player_message: message,
+                                            })
+                                        }
+                                        Some(LogMatch::Warning { message }) => {
+                                            Some(InstanceEventInner::InstanceWarning { message })
                                         }
-                                    } else if let Some(PlayerMessage { player, message }) =
-                                        parse_player_msg(&line)
-                                    {
+                                        Some(LogMatch::Error { message }) => {
+                                            Some(InstanceEventInner::InstanceError { message })
+                                        }
+                                        Some(LogMatch::ServerStarted) | None => None,
+                                    };
+                                    if let Some(instance_event_inner) = instance_event_inner {
                                         event_broadcaster.send(Event {
                                             event_inner: EventInner::InstanceEvent(InstanceEvent {
                                                 instance_uuid: uuid.clone(),
-                                                instance_event_inner:
-                                                    InstanceEventInner::PlayerMessage {
-                                                        player,
-                                                        player_message: message,
-                                                    },
+                                                instance_event_inner,
                                                 instance_name: name.clone(),
                                             }),
                                             details: "".to_string(),
@@ -442,12 +464,22 @@ impl TServer for MinecraftInstance {
                             }
                         }
                         info!("Instance {} process shutdown", name);
+                        let exit_code = match __self.process.lock().await.take() {
+                            Some(mut proc) => proc.wait().await.ok().and_then(|s| s.code()),
+                            None => None,
+                        };
+                        // a process that exits while we are not stopping it has crashed
+                        let crashed = __self.state().await.crashed_on_exit();
                         __self
                             .state
                             .lock()
                             .await
                             .try_transition(
-                                StateAction::InstanceStop,
+                                if crashed {
+                                    StateAction::InstanceCrash
+                                } else {
+                                    StateAction::InstanceStop
+                                },
                                 Some(&|state| {
                                     event_broadcaster.send(Event {
                                         event_inner: EventInner::InstanceEvent(InstanceEvent {
@@ -457,15 +489,42 @@ impl TServer for MinecraftInstance {
                                                 InstanceEventInner::StateTransition { to: state },
                                         }),
                                         snowflake: Snowflake::default(),
-                                        details: "Instance stopping as server process exited"
-                                            .to_string(),
+                                        details: if crashed {
+                                            "Instance crashed as server process exited unexpectedly"
+                                        } else {
+                                            "Instance stopping as server process exited"
+                                        }
+                                        .to_string(),
                                         caused_by: cause_by.clone(),
                                     });
                                 }),
                             )
                             .unwrap();
-                        __self.players_manager.lock().await.clear(name);
+                        __self.players_manager.lock().await.clear(name.clone());
                         __self.rcon_conn.lock().await.take();
+                        if crashed {
+                            error!("[{}] Instance crashed with exit code {:?}", name, exit_code);
+                            event_broadcaster.send(Event {
+                                event_inner: EventInner::InstanceEvent(InstanceEvent {
+                                    instance_name: name.clone(),
+                                    instance_uuid: uuid.clone(),
+                                    instance_event_inner: InstanceEventInner::InstanceError {
+                                        message: format!(
+                                            "Instance crashed with exit code {}. Last console output:\n{}",
+                                            exit_code
+                                                .map(|c| c.to_string())
+                                                .unwrap_or_else(|| "unknown".to_string()),
+                                            Vec::from(last_lines).join("\n")
+                                        ),
+                                    },
+                                }),
+                                snowflake: Snowflake::default(),
+                                details: "".to_string(),
+                                caused_by: CausedBy::System,
+                            });
+                            restart_after_crash(&__self, &__self.crash_history, &event_broadcaster)
+                                .await;
+                        }
                     }
                 });
                 self.config.lock().await.has_started = true;
@@ -526,6 +585,23 @@ impl TServer for MinecraftInstance {
             }
         }
     }
+}
+
+#[async_trait::async_trait]
+impl TServer for MinecraftInstance {
+    async fn start(&self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
+        // the instance files may be in the middle of being replaced
+        if self.backup_lock.try_lock().is_err() {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!(
+                    "Cannot start an instance while a backup, restore or upgrade is in progress"
+                ),
+            });
+        }
+        self.start_server(cause_by, block).await
+    }
+
     async fn stop(&self, cause_by: CausedBy, block: bool) -> Result<(), Error> {
         let config = self.config.lock().await.clone();
 
@@ -610,31 +686,35 @@ impl TServer for MinecraftInstance {
             warn!("[{}] Instance is already stopped", config.name.clone());
             return Err(eyre!("Instance is already stopped").into());
         }
-        if let Some(process) = self.process.lock().await.as_mut() {
-            process
-                .kill()
-                .await
-                .context("Failed to kill process")
-                .map_err(|e| {
-                    error!("[{}] Failed to kill instance: {}", config.name.clone(), e);
-                    e
-                })?;
-        }
-        {
-            error!(
-                "[{}] Process not available, assuming instance is stopped",
-                config.name.clone()
-            );
-            *self.state.lock().await = State::Stopped;
-            self.event_broadcaster
-                .send(Event::new_instance_state_transition(
-                    self.uuid.clone(),
-                    config.name.clone(),
-                    State::Stopped,
-                ));
-            Err(eyre!("Process not available, assuming instance is stopped"))?;
+        // so the process exiting is not mistaken for a crash
+        *self.state.lock().await = State::Stopping;
+        match self.process.lock().await.as_mut() {
+            Some(process) => {
+                process
+                    .kill()
+                    .await
+                    .context("Failed to kill process")
+                    .map_err(|e| {
+                        error!("[{}] Failed to kill instance: {}", config.name.clone(), e);
+                        e
+                    })?;
+                Ok(())
+            }
+            None => {
+                error!(
+                    "[{}] Process not available, assuming instance is stopped",
+                    config.name.clone()
+                );
+                *self.state.lock().await = State::Stopped;
+                self.event_broadcaster
+                    .send(Event::new_instance_state_transition(
+                        self.uuid.clone(),
+                        config.name.clone(),
+                        State::Stopped,
+                    ));
+                Err(eyre!("Process not available, assuming instance is stopped").into())
+            }
         }
-        Ok(())
     }
 
     async fn state(&self) -> State {
@@ -649,7 +729,7 @@ impl TServer for MinecraftInstance {
             match self.stdin.lock().await.as_mut() {
                 Some(stdin) => match {
                     if command == "stop" {
-                        self.state.lock().await.try_new_state(
+                        self.state.lock().await.try_transition(
                             StateAction::UserStop,
                             Some(&|state| {
                                 self.event_broadcaster.send(Event {
@@ -661,7 +741,7 @@ impl TServer for MinecraftInstance {
                                         },
                                     }),
                                     snowflake: Snowflake::default(),
-                                    details: "Starting server".to_string(),
+                                    details: "Stopping server".to_string(),
                                     caused_by: cause_by.clone(),
                                 });
                             }),
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::error::Error;
+use crate::prelude::path_to_binaries;
+use crate::util::{dont_spawn_terminal, download_file};
+
+const BUILD_TOOLS_URL: &str =
+    "https://hub.spigotmc.org/jenkins/job/BuildTools/lastSuccessfulBuild/artifact/target/BuildTools.jar";
+
+/// Number of BuildTools output lines included in the error when a build fails
+const BUILD_TOOLS_ERROR_LINES: usize = 20;
+
+/// Lines of BuildTools output marking a step of the build, with the share of the
+/// build's progress the step stands for.
+///
+/// BuildTools clones, updates and compiles each of its projects in turn, only the first
+/// time a step is reached counts.
+const BUILD_TOOLS_MILESTONES: [(&str, f64); 5] = [
+    ("Starting clone of", 0.1),
+    ("Pulling updates for", 0.1),
+    ("Applying patches", 0.2),
+    ("Compiling", 0.4),
+    ("Success! Everything completed successfully", 0.2),
+];
+
+lazy_static! {
+    /// BuildTools keeps its git checkouts in a shared work directory,
+    /// so only one build may run at a time
+    static ref BUILD_TOOLS_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Spigot versions are not published as a list, but BuildTools reads the build info of each
+/// version from `hub.spigotmc.org/versions/<version>.json`, so the directory listing has them all
+fn parse_spigot_versions(listing: &str) -> Vec<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r#"href="(1\.\d+(?:\.\d+)?)\.json""#).unwrap();
+    }
+    let parse = |v: &str| -> Vec<u64> { v.split('.').filter_map(|s| s.parse().ok()).collect() };
+    let mut versions: Vec<String> = RE
+        .captures_iter(listing)
+        .filter_map(|caps| Some(caps.ok()?.get(1)?.as_str().to_string()))
+        .collect();
+    versions.sort_by_key(|v| std::cmp::Reverse(parse(v)));
+    versions.dedup();
+    versions
+}
+
+pub async fn get_spigot_minecraft_versions() -> Result<Vec<String>, Error> {
+    let http = reqwest::Client::new();
+
+    let listing = http
+        .get("https://hub.spigotmc.org/versions/")
+        .send()
+        .await
+        .context("Failed to get spigot versions")?
+        .text()
+        .await
+        .context("Failed to get spigot versions")?;
+
+    let versions = parse_spigot_versions(&listing);
+    if versions.is_empty() {
+        return Err(
+            eyre!("Failed to get spigot versions, listing does not contain versions").into(),
+        );
+    }
+    Ok(versions)
+}
+
+fn build_tools_milestone(
+    line: &str,
+    reached: &mut [bool; BUILD_TOOLS_MILESTONES.len()],
+) -> Option<f64> {
+    let index = BUILD_TOOLS_MILESTONES
+        .iter()
+        .position(|(prefix, _)| line.starts_with(prefix))?;
+    if std::mem::replace(&mut reached[index], true) {
+        return None;
+    }
+    Some(BUILD_TOOLS_MILESTONES[index].1)
+}
+
+pub fn path_to_spigot_jar(version: &str) -> PathBuf {
+    path_to_binaries()
+        .join("spigot")
+        .join(format!("spigot-{version}.jar"))
+}
+
+/// Get the Spigot server jar of `version`, building it with BuildTools if it is not cached yet
+///
+/// `on_progress` is called with each line BuildTools outputs, along with the share of the
+/// build that line completes
+pub async fn get_spigot_jar(
+    version: &str,
+    jre: &Path,
+    on_progress: &(dyn Fn(String, f64) + Send + Sync),
+) -> Result<PathBuf, Error> {
+    // the version ends up in BuildTools' arguments
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return Err(eyre!("{} is not a valid spigot version", version).into());
+    }
+    let path_to_jar = path_to_spigot_jar(version);
+    if path_to_jar.is_file() {
+        on_progress(format!("Using cached Spigot {version}"), 1.0);
+        return Ok(path_to_jar);
+    }
+
+    let _lock = BUILD_TOOLS_LOCK.lock().await;
+    // another build may have produced the jar while we were waiting
+    if path_to_jar.is_file() {
+        on_progress(format!("Using cached Spigot {version}"), 1.0);
+        return Ok(path_to_jar);
+    }
+
+    let path_to_build_tools = path_to_binaries().join("spigot").join("build_tools");
+    tokio::fs::create_dir_all(&path_to_build_tools)
+        .await
+        .context("Failed to create BuildTools directory")?;
+    on_progress("Downloading BuildTools".to_string(), 0.0);
+    // always fetch the latest BuildTools, older ones can't build newer versions
+    let build_tools_jar = download_file(
+        BUILD_TOOLS_URL,
+        &path_to_build_tools,
+        Some("BuildTools.jar"),
+        &|_| {},
+        true,
+    )
+    .await?;
+
+    let path_to_output =
+        tempfile::tempdir_in(crate::prelude::path_to_tmp()).context("Failed to create temp dir")?;
+    info!("Building Spigot {} with BuildTools", version);
+    let mut process = dont_spawn_terminal(
+        Command::new(jre)
+            .arg("-jar")
+            .arg(&build_tools_jar)
+            .arg("--rev")
+            .arg(version)
+            .arg("--output-dir")
+            .arg(path_to_output.path())
+            .arg("--final-name")
+            .arg(format!("spigot-{version}.jar"))
+            .current_dir(&path_to_build_tools),
+    )
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .context("Failed to start BuildTools")?;
+
+    let mut stdout_lines = BufReader::new(process.stdout.take().unwrap()).lines();
+    let mut stderr_lines = BufReader::new(process.stderr.take().unwrap()).lines();
+    let (mut stdout_done, mut stderr_done) = (false, false);
+    let mut last_lines = VecDeque::with_capacity(BUILD_TOOLS_ERROR_LINES);
+    let mut reached = [false; BUILD_TOOLS_MILESTONES.len()];
+    while !(stdout_done && stderr_done) {
+        let (line, is_stdout) = tokio::select! {
+            line = stdout_lines.next_line(), if !stdout_done => (line, true),
+            line = stderr_lines.next_line(), if !stderr_done => (line, false),
+        };
+        let line = match line {
+            Ok(Some(line)) => line,
+            _ => {
+                if is_stdout {
+                    stdout_done = true;
+                } else {
+                    stderr_done = true;
+                }
+                continue;
+            }
+        };
+        if last_lines.len() == BUILD_TOOLS_ERROR_LINES {
+            last_lines.pop_front();
+        }
+        last_lines.push_back(line.clone());
+        on_progress(
+            line.clone(),
+            build_tools_milestone(&line, &mut reached).unwrap_or(0.0),
+        );
+    }
+
+    let status = process.wait().await.context("BuildTools failed")?;
+    let built_jar = path_to_output.path().join(format!("spigot-{version}.jar"));
+    if !status.success() || !built_jar.is_file() {
+        warn!("BuildTools failed to build Spigot {}", version);
+        return Err(eyre!(
+            "BuildTools failed to build Spigot {} ({}). Last output:\n{}",
+            version,
+            status,
+            Vec::from(last_lines).join("\n")
+        )
+        .into());
+    }
+    crate::util::fs::rename(&built_jar, &path_to_jar).await?;
+    Ok(path_to_jar)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_spigot_versions() {
+        let listing = r#"<html><body><pre>
+<a href="../">../</a>
+<a href="1.10.2.json">1.10.2.json</a>    10-Jun-2016 18:31    1181
+<a href="1.20.1.json">1.20.1.json</a>    12-Jun-2023 12:00    1181
+<a href="1.8.json">1.8.json</a>          06-Dec-2014 22:12    1181
+<a href="1.20.json">1.20.json</a>        07-Jun-2023 12:00    1181
+<a href="3800.json">3800.json</a>        07-Jun-2023 12:00    1181
+<a href="latest.json">latest.json</a>    12-Jun-2023 12:00    1181
+<a href="1.20-pre1.json">1.20-pre1.json</a>    01-Jun-2023 12:00    1181
+</pre></body></html>"#;
+        assert_eq!(
+            parse_spigot_versions(listing),
+            vec!["1.20.1", "1.20", "1.10.2", "1.8"]
+        );
+    }
+
+    #[test]
+    fn test_build_tools_milestone() {
+        let mut reached = [false; BUILD_TOOLS_MILESTONES.len()];
+        assert_eq!(
+            build_tools_milestone(
+                "Starting clone of https://hub.spigotmc.org/stash/scm/spigot/bukkit.git to Bukkit",
+                &mut reached
+            ),
+            Some(0.1)
+        );
+        assert_eq!(
+            build_tools_milestone("Compiling Bukkit", &mut reached),
+            Some(0.4)
+        );
+        assert_eq!(
+            build_tools_milestone("[INFO] Building Bukkit", &mut reached),
+            None
+        );
+        // every project is compiled, the step only counts once
+        assert_eq!(
+            build_tools_milestone("Compiling CraftBukkit", &mut reached),
+            None
+        );
+        let total: f64 = BUILD_TOOLS_MILESTONES.iter().map(|(_, share)| share).sum();
+        assert!((total - 1.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,458 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Context};
+use tokio::process::Command;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+use crate::error::{Error, ErrorKind};
+use crate::events::{
+    CausedBy, Event, EventInner, InstanceEvent, InstanceEventInner, ProgressionEventID,
+};
+use crate::prelude::path_to_tmp;
+use crate::traits::t_configurable::manifest::ConfigurableValue;
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_server::{State, TServer};
+use crate::util::{dont_spawn_terminal, download_file, format_byte_download, list_dir};
+
+use super::configurable::CmdArgSetting;
+use super::spigot::get_spigot_jar;
+use super::util::{download_jre, get_jre_url, get_server_jar_url, path_to_java};
+use super::versions::get_versions;
+use super::{Flavour, FlavourKind, MinecraftInstance, RestoreConfig};
+
+/// How long the verification boot may take, the first boot on a new version
+/// may have to upgrade the whole world
+const VERIFICATION_BOOT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How long to wait for the verification boot to shut down before killing it
+const VERIFICATION_STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl MinecraftInstance {
+    fn send_upgrade_progress(
+        &self,
+        progression_event_id: &ProgressionEventID,
+        message: impl AsRef<str>,
+        progress: f64,
+    ) {
+        self.event_broadcaster
+            .send(Event::new_progression_event_update(
+                progression_event_id,
+                message,
+                progress,
+            ));
+    }
+
+    async fn set_java_cmd(&self, java_cmd: Option<String>) -> Result<(), Error> {
+        // the configurable manifest is synced back to the config, so it has to be kept in step
+        if let Some(java_cmd) = &java_cmd {
+            self.configurable_manifest
+                .lock()
+                .await
+                .update_setting_value(
+                    CmdArgSetting::get_section_id(),
+                    CmdArgSetting::JavaCmd(Default::default()).get_identifier(),
+                    ConfigurableValue::String(java_cmd.clone()),
+                )?;
+        }
+        self.config.lock().await.java_cmd = java_cmd;
+        Ok(())
+    }
+
+    /// Put the server jar of a version into the instance, running the forge installer if needed
+    ///
+    /// Returns the flavour with its build information filled in
+    async fn install_server_jar(
+        &self,
+        flavour: FlavourKind,
+        version: &str,
+        jre: &Path,
+        progression_event_id: &ProgressionEventID,
+    ) -> Result<Flavour, Error> {
+        let staging = tempfile::tempdir_in(path_to_tmp()).context("Failed to create temp dir")?;
+        let (jar, flavour) = if let FlavourKind::Spigot = flavour {
+            let jar = get_spigot_jar(version, jre, &|line: String, progress: f64| {
+                self.send_upgrade_progress(
+                    progression_event_id,
+                    format!("4/6: Building Spigot {}: {}", version, line),
+                    progress,
+                );
+            })
+            .await?;
+            (jar, Flavour::Spigot)
+        } else {
+            let (url, flavour) = get_server_jar_url(version, &flavour.into())
+                .await
+                .ok_or_else(|| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Could not find a {} server jar for version {}",
+                        flavour.to_string(),
+                        version
+                    ),
+                })?;
+            let jar_name = match flavour {
+                Flavour::Forge { .. } => "forge-installer.jar",
+                _ => "server.jar",
+            };
+            let jar = download_file(
+                &url,
+                staging.path(),
+                Some(jar_name),
+                &|dl| {
+                    if let Some(total) = dl.total {
+                        self.send_upgrade_progress(
+                            progression_event_id,
+                            format!(
+                                "4/6: Downloading {} {}",
+                                jar_name,
+                                format_byte_download(dl.downloaded, total)
+                            ),
+                            dl.step as f64 / total as f64,
+                        );
+                    }
+                },
+                true,
+            )
+            .await?;
+            (jar, flavour)
+        };
+
+        if let Flavour::Forge { .. } = flavour {
+            self.send_upgrade_progress(progression_event_id, "4/6: Installing Forge Server", 0.0);
+            // the installer lays the libraries and run scripts of the new version out in the instance
+            if !dont_spawn_terminal(
+                Command::new(jre)
+                    .arg("-jar")
+                    .arg(&jar)
+                    .arg("--installServer")
+                    .arg(&self.path_to_instance)
+                    .current_dir(&self.path_to_instance),
+            )
+            .stderr(Stdio::null())
+            .stdout(Stdio::null())
+            .stdin(Stdio::null())
+            .spawn()
+            .context("Failed to start forge-installer.jar")?
+            .wait()
+            .await
+            .context("forge-installer.jar failed")?
+            .success()
+            {
+                return Err(eyre!("Failed to install forge server").into());
+            }
+            let user_jvm_args = self.path_to_instance.join("user_jvm_args.txt");
+            if !user_jvm_args.exists() {
+                tokio::fs::write(
+                    &user_jvm_args,
+                    "# Generated by Lodestone\n# This file is ignored by Lodestone\n# Please set arguments using Lodestone",
+                )
+                .await
+                .context("Could not create user_jvm_args.txt")?;
+            }
+        } else {
+            tokio::fs::copy(&jar, self.path_to_instance.join("server.jar"))
+                .await
+                .context(format!("Could not copy {} to instance", jar.display()))?;
+        }
+        Ok(flavour)
+    }
+
+    /// Boot the instance once and wait for it to finish starting, then shut it down again
+    async fn verification_boot(&self, caused_by: CausedBy) -> Result<(), Error> {
+        let mut rx = self.event_broadcaster.subscribe();
+        self.start_server(caused_by.clone(), false).await?;
+        // the instance transitions to running once a `ServerStarted` log rule matches its output
+        let started = tokio::time::timeout(VERIFICATION_BOOT_TIMEOUT, async {
+            loop {
+                match rx.recv().await {
+                    Ok(Event {
+                        event_inner:
+                            EventInner::InstanceEvent(InstanceEvent {
+                                instance_uuid,
+                                instance_event_inner: InstanceEventInner::StateTransition { to },
+                                ..
+                            }),
+                        ..
+                    }) if instance_uuid == self.uuid => match to {
+                        State::Running => return Ok(()),
+                        State::Stopped | State::Error => {
+                            return Err(eyre!("Server exited before it finished starting"))
+                        }
+                        State::Starting | State::Stopping => continue,
+                    },
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Err(eyre!("Event broadcaster closed")),
+                }
+            }
+        })
+        .await
+        .map_err(|_| {
+            eyre!(
+                "Server did not finish starting within {} minutes",
+                VERIFICATION_BOOT_TIMEOUT.as_secs() / 60
+            )
+        })
+        .and_then(|res| res);
+
+        if matches!(self.state().await, State::Starting | State::Running) {
+            let stopped = tokio::time::timeout(
+                VERIFICATION_STOP_TIMEOUT,
+                self.stop(caused_by.clone(), true),
+            )
+            .await;
+            if !matches!(stopped, Ok(Ok(()))) {
+                warn!(
+                    "[{}] Verification boot did not stop in time, killing it",
+                    self.uuid
+                );
+                let mut rx = self.event_broadcaster.subscribe();
+                if self.kill(caused_by).await.is_ok() {
+                    // the instance is stopped once its process exited
+                    let _ = tokio::time::timeout(VERIFICATION_STOP_TIMEOUT, async {
+                        loop {
+                            match rx.recv().await {
+                                Ok(Event {
+                                    event_inner:
+                                        EventInner::InstanceEvent(InstanceEvent {
+                                            instance_uuid,
+                                            instance_event_inner:
+                                                InstanceEventInner::StateTransition {
+                                                    to: State::Stopped | State::Error,
+                                                },
+                                            ..
+                                        }),
+                                    ..
+                                }) if instance_uuid == self.uuid => return,
+                                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                                Err(RecvError::Closed) => return,
+                            }
+                        }
+                    })
+                    .await;
+                }
+            }
+        }
+        if !matches!(self.state().await, State::Stopped | State::Error) {
+            return Err(eyre!("Failed to stop the verification boot").into());
+        }
+        started?;
+        Ok(())
+    }
+
+    async fn apply_upgrade(
+        &self,
+        flavour: FlavourKind,
+        version: &str,
+        progression_event_id: &ProgressionEventID,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let (jre_url, jre_major_version) = get_jre_url(version)
+            .await
+            .ok_or_else(|| eyre!("Could not get a JRE URL for version {}", version))?;
+        let downloaded = download_jre(&self.path_to_runtimes, &jre_url, jre_major_version, &|dl| {
+            if let Some(total) = dl.total {
+                self.send_upgrade_progress(
+                    progression_event_id,
+                    format!(
+                        "3/6: Downloading JRE {}",
+                        format_byte_download(dl.downloaded, total)
+                    ),
+                    dl.step as f64 / total as f64,
+                );
+            }
+        })
+        .await?;
+        if !downloaded {
+            self.send_upgrade_progress(progression_event_id, "3/6: JRE already downloaded", 1.0);
+        }
+        let (previous_jre_major_version, previous_java_cmd) = {
+            let config = self.config.lock().await;
+            (config.jre_major_version, config.java_cmd.clone())
+        };
+        let previous_managed_java =
+            path_to_java(&self.path_to_runtimes, previous_jre_major_version);
+        let managed_java = path_to_java(&self.path_to_runtimes, jre_major_version);
+        // keep a java the user picked, but move off the JRE we installed for the old version
+        let java_cmd = match previous_java_cmd {
+            Some(java_cmd) if PathBuf::from(&java_cmd) != previous_managed_java => java_cmd,
+            _ => managed_java.to_string_lossy().to_string(),
+        };
+
+        let flavour = self
+            .install_server_jar(flavour, version, Path::new(&java_cmd), progression_event_id)
+            .await?;
+
+        self.send_upgrade_progress(progression_event_id, "5/6: Booting new version", 1.0);
+        self.set_java_cmd(Some(java_cmd)).await?;
+        let restart_on_crash = {
+            let mut config = self.config.lock().await;
+            config.version = version.to_string();
+            config.flavour = flavour;
+            config.jre_major_version = jre_major_version;
+            // a failed verification boot is rolled back, not restarted
+            std::mem::replace(&mut config.restart_on_crash, false)
+        };
+        self.write_config_to_file().await?;
+        let res = self.verification_boot(caused_by).await;
+        self.config.lock().await.restart_on_crash = restart_on_crash;
+        self.write_config_to_file().await?;
+        res
+    }
+
+    async fn rollback_upgrade(
+        &self,
+        snapshot: &Path,
+        snapshot_files: &[PathBuf],
+        previous_config: RestoreConfig,
+    ) -> Result<(), Error> {
+        // restoring only overwrites what the snapshot holds, the files the upgrade added
+        // such as the libraries and run scripts of the forge installer have to go first
+        for path in list_dir(&self.path_to_instance, None).await? {
+            if snapshot_files.contains(&path) {
+                continue;
+            }
+            if path.is_dir() {
+                crate::util::fs::remove_dir_all(&path).await?;
+            } else {
+                crate::util::fs::remove_file(&path).await?;
+            }
+        }
+        self.restore_files_from(snapshot).await?;
+        let java_cmd = previous_config.java_cmd.clone();
+        *self.config.lock().await = previous_config;
+        self.set_java_cmd(java_cmd).await?;
+        self.write_config_to_file().await
+    }
+
+    async fn upgrade_with_rollback(
+        &self,
+        flavour: FlavourKind,
+        version: &str,
+        progression_event_id: &ProgressionEventID,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        self.send_upgrade_progress(progression_event_id, "1/6: Checking version", 1.0);
+        if !get_versions(&flavour).await?.contains(version) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("{} is not a known {} version", version, flavour.to_string()),
+            });
+        }
+
+        self.send_upgrade_progress(progression_event_id, "2/6: Backing up instance", 1.0);
+        let snapshot = self
+            .archive_instance("pre_upgrade", caused_by.clone())
+            .await
+            .context("Failed to back up the instance, aborting upgrade")?;
+        let snapshot_files = list_dir(&self.path_to_instance, None).await?;
+        let previous_config = self.config.lock().await.clone();
+
+        match self
+            .apply_upgrade(flavour, version, progression_event_id, caused_by)
+            .await
+        {
+            Ok(()) => {
+                self.send_upgrade_progress(progression_event_id, "6/6: Upgrade verified", 1.0);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("[{}] Upgrade failed, rolling back: {}", self.uuid, e);
+                self.send_upgrade_progress(progression_event_id, "6/6: Rolling back", 1.0);
+                let snapshot_name = snapshot
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                match self
+                    .rollback_upgrade(&snapshot, &snapshot_files, previous_config)
+                    .await
+                {
+                    Ok(()) => Err(Error {
+                        kind: e.kind,
+                        source: e.source.wrap_err("Upgrade failed and was rolled back"),
+                    }),
+                    Err(rollback_error) => {
+                        error!(
+                            "[{}] Failed to roll back upgrade: {}",
+                            self.uuid, rollback_error
+                        );
+                        Err(Error {
+                            kind: ErrorKind::Internal,
+                            source: e.source.wrap_err(format!(
+                                "Upgrade failed and could not be rolled back ({}), the previous state was saved to {}",
+                                rollback_error, snapshot_name
+                            )),
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move the instance to another version and/or flavour of minecraft
+    ///
+    /// The instance is backed up first, then booted once on the new version.
+    /// If anything fails, including that boot, the backup is restored.
+    /// The current flavour is kept if `flavour` is `None`.
+    pub async fn upgrade(
+        &self,
+        flavour: Option<FlavourKind>,
+        version: String,
+        caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        let _guard = self.backup_lock.try_lock().map_err(|_| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("A backup, restore or upgrade is already in progress for this instance"),
+        })?;
+        // checked once the lock is held, so the instance can't be started in between
+        if !matches!(self.state().await, State::Stopped | State::Error) {
+            return Err(Error {
+                kind: ErrorKind::BadRequest,
+                source: eyre!("Cannot upgrade an instance that is not stopped"),
+            });
+        }
+        let flavour = {
+            let config = self.config.lock().await;
+            let current_flavour = FlavourKind::from(&config.flavour);
+            if config.version == version && flavour.unwrap_or(current_flavour) == current_flavour {
+                return Ok(());
+            }
+            flavour.unwrap_or(current_flavour)
+        };
+        info!(
+            "[{}] Upgrading to {} {}",
+            self.uuid,
+            flavour.to_string(),
+            version
+        );
+        let (progression_start_event, event_id) = Event::new_progression_event_start(
+            format!(
+                "Upgrading {} to {} {}",
+                self.name().await,
+                flavour.to_string(),
+                version
+            ),
+            Some(6.0),
+            None,
+            caused_by.clone(),
+        );
+        self.event_broadcaster.send(progression_start_event);
+
+        let res = self
+            .upgrade_with_rollback(flavour, &version, &event_id, caused_by)
+            .await;
+        self.event_broadcaster
+            .send(Event::new_progression_event_end(
+                event_id,
+                res.is_ok(),
+                Some(&match &res {
+                    Ok(()) => format!("Upgraded to {} {}", flavour.to_string(), version),
+                    Err(e) => format!("Upgrade failed: {e}"),
+                }),
+                None,
+            ));
+        res
+    }
+}
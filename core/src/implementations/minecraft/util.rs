@@ -1,13 +1,18 @@
 use color_eyre::eyre::{eyre, Context, ContextCompat};
 use indexmap::IndexMap;
 use serde_json::{self, Value};
-use std::{collections::BTreeMap, path::Path, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use tokio::io::AsyncBufReadExt;
 
 use super::{
     FabricInstallerVersion, FabricLoaderVersion, Flavour, ForgeBuildVersion, PaperBuildVersion,
 };
 use crate::error::Error;
+use crate::util::{download_file, unzip_file_async, DownloadProgress, UnzipOption};
 
 pub async fn read_properties_from_path(
     path_to_properties: &Path,
@@ -59,7 +64,8 @@ pub async fn get_server_jar_url(version: &str, flavour: &Flavour) -> Option<(Str
             installer_version,
         } => get_fabric_jar_url(version, loader_version, installer_version).await,
         Flavour::Paper { build_version } => get_paper_jar_url(version, build_version).await,
-        Flavour::Spigot => todo!(),
+        // Spigot has no downloadable jar, it is built with BuildTools instead
+        Flavour::Spigot => None,
         Flavour::Forge { build_version } => get_forge_jar_url(version, build_version).await.ok(),
     }
 }
@@ -355,6 +361,64 @@ pub async fn get_forge_jar_url(
     ))
 }
 
+/// Path to the java executable of a JRE managed by Lodestone
+pub fn path_to_java(path_to_runtimes: &Path, jre_major_version: u64) -> PathBuf {
+    path_to_runtimes
+        .join("java")
+        .join(format!("jre{}", jre_major_version))
+        .join(if std::env::consts::OS == "macos" {
+            "Contents/Home/bin"
+        } else {
+            "bin"
+        })
+        .join("java")
+}
+
+/// Download and unpack a JRE into the runtimes directory, unless it is already there
+///
+/// Returns whether the JRE was downloaded
+pub async fn download_jre(
+    path_to_runtimes: &Path,
+    url: &str,
+    jre_major_version: u64,
+    on_download: &(dyn Fn(DownloadProgress) + Send + Sync),
+) -> Result<bool, Error> {
+    let path_to_jre = path_to_runtimes
+        .join("java")
+        .join(format!("jre{}", jre_major_version));
+    if path_to_jre.exists() {
+        return Ok(false);
+    }
+    let downloaded =
+        download_file(url, &path_to_runtimes.join("java"), None, on_download, true).await?;
+
+    let unzipped_content = unzip_file_async(
+        &downloaded,
+        UnzipOption::ToDir(path_to_runtimes.join("java")),
+    )
+    .await?;
+    if unzipped_content.len() != 1 {
+        return Err(eyre!(
+            "Expected only one file in the JRE archive, got {}",
+            unzipped_content.len()
+        )
+        .into());
+    }
+
+    tokio::fs::remove_file(&downloaded).await.context(format!(
+        "Could not remove downloaded JRE file {}",
+        downloaded.display()
+    ))?;
+
+    tokio::fs::rename(unzipped_content.iter().last().unwrap(), &path_to_jre)
+        .await
+        .context(format!(
+            "Could not rename JRE directory {}",
+            unzipped_content.iter().last().unwrap().display()
+        ))?;
+    Ok(true)
+}
+
 pub async fn get_jre_url(version: &str) -> Option<(String, u64)> {
     let client = reqwest::Client::new();
     let os = if std::env::consts::OS == "macos" {
@@ -5,6 +5,9 @@ use ts_rs::TS;
 
 use crate::error::Error;
 
+use super::spigot::get_spigot_minecraft_versions;
+use super::FlavourKind;
+
 #[derive(Serialize, Deserialize, Debug, TS)]
 #[ts(export)]
 pub struct MinecraftVersions {
@@ -13,6 +16,24 @@ pub struct MinecraftVersions {
     pub release: Vec<String>,
 }
 
+impl MinecraftVersions {
+    pub fn contains(&self, version: &str) -> bool {
+        [&self.release, &self.snapshot, &self.old_alpha]
+            .iter()
+            .any(|versions| versions.iter().any(|v| v == version))
+    }
+}
+
+pub async fn get_versions(flavour: &FlavourKind) -> Result<MinecraftVersions, Error> {
+    match flavour {
+        FlavourKind::Vanilla => get_vanilla_versions().await,
+        FlavourKind::Fabric => get_fabric_versions().await,
+        FlavourKind::Paper => get_paper_versions().await,
+        FlavourKind::Spigot => get_spigot_versions().await,
+        FlavourKind::Forge => get_forge_versions().await,
+    }
+}
+
 pub async fn get_vanilla_versions() -> Result<MinecraftVersions, Error> {
     let http = reqwest::Client::new();
     let response: Value = serde_json::from_str(
@@ -184,6 +205,11 @@ pub async fn get_forge_versions() -> Result<MinecraftVersions, Error> {
     group_minecraft_versions(&versions).await
 }
 
+pub async fn get_spigot_versions() -> Result<MinecraftVersions, Error> {
+    let versions = get_spigot_minecraft_versions().await?;
+    group_minecraft_versions(&versions.iter().map(|s| s.as_str()).collect()).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -198,4 +224,17 @@ mod tests {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(get_forge_versions()).unwrap();
     }
+
+    #[test]
+    fn test_minecraft_versions_contains() {
+        let versions = MinecraftVersions {
+            old_alpha: vec!["b1.7.3".to_string()],
+            snapshot: vec!["23w18a".to_string()],
+            release: vec!["1.20.1".to_string(), "1.19.4".to_string()],
+        };
+        assert!(versions.contains("1.19.4"));
+        assert!(versions.contains("23w18a"));
+        assert!(versions.contains("b1.7.3"));
+        assert!(!versions.contains("1.19"));
+    }
 }
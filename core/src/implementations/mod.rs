@@ -1,2 +1,4 @@
+pub mod bedrock;
+pub mod crash_restart;
 pub mod generic;
 pub mod minecraft;
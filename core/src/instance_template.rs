@@ -0,0 +1,239 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::event_broadcaster::EventBroadcaster;
+use crate::implementations::bedrock::BedrockInstance;
+use crate::implementations::minecraft::util::read_properties_from_path;
+use crate::implementations::minecraft::MinecraftInstance;
+use crate::macro_executor::MacroExecutor;
+use crate::prelude::{path_to_stores, GameInstance};
+use crate::traits::t_configurable::{Game, GameType, TConfigurable};
+use crate::types::{DotLodestoneConfig, Snowflake};
+
+/// Files of an instance that must not be carried over to a copy of it
+const INSTANCE_ONLY_FILES: [&str; 2] = [".lodestone_config", "backups"];
+
+/// Files of an instance that are left out of a template, on top of the world
+const NON_TEMPLATE_FILES: [&str; 3] = ["logs", "crash-reports", "usercache.json"];
+
+const TEMPLATE_METADATA_FILE: &str = "template.json";
+
+/// A snapshot of a configured instance without its world, used to create new instances
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct InstanceTemplate {
+    pub id: Snowflake,
+    pub name: String,
+    pub description: String,
+    pub game_type: Game,
+    pub version: String,
+    pub creation_time: i64,
+}
+
+fn path_to_templates() -> PathBuf {
+    path_to_stores().join("templates")
+}
+
+fn path_to_template(id: &Snowflake) -> PathBuf {
+    path_to_templates().join(id.to_string())
+}
+
+/// Path to the instance files stored in a template
+pub fn path_to_template_files(id: &Snowflake) -> PathBuf {
+    path_to_template(id).join("files")
+}
+
+/// Names of the world directories of an instance, taken from `level-name` in its server.properties
+async fn world_dirs(path_to_instance: &Path, game_type: &Game) -> Vec<String> {
+    let level_name = read_properties_from_path(&path_to_instance.join("server.properties"))
+        .await
+        .ok()
+        .and_then(|properties| properties.get("level-name").cloned())
+        .unwrap_or_else(|| "world".to_string());
+    match game_type {
+        // bedrock keeps every level under one directory
+        Game::MinecraftBedrock => vec!["worlds".to_string()],
+        _ => vec![
+            format!("{level_name}_nether"),
+            format!("{level_name}_the_end"),
+            level_name,
+        ],
+    }
+}
+
+/// Copy the top level entries of `from` into `to`, except the ones named in `exclude`
+pub async fn copy_instance_files(from: &Path, to: &Path, exclude: &[String]) -> Result<(), Error> {
+    let from = from.to_owned();
+    let to = to.to_owned();
+    let exclude = exclude.to_owned();
+    tokio::task::spawn_blocking(move || -> Result<(), Error> {
+        std::fs::create_dir_all(&to).context(format!("Failed to create {}", to.display()))?;
+        let mut items = Vec::new();
+        for entry in
+            std::fs::read_dir(&from).context(format!("Failed to read {}", from.display()))?
+        {
+            let entry = entry.context(format!("Failed to read {}", from.display()))?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if INSTANCE_ONLY_FILES.contains(&name.as_str()) || exclude.contains(&name) {
+                continue;
+            }
+            items.push(entry.path());
+        }
+        fs_extra::copy_items(&items, &to, &fs_extra::dir::CopyOptions::new()).context(format!(
+            "Failed to copy {} to {}",
+            from.display(),
+            to.display()
+        ))?;
+        Ok(())
+    })
+    .await
+    .context("Failed to copy instance files in a blocking task")?
+}
+
+/// Turn a directory holding a copy of an instance's files into a new instance
+///
+/// The copy keeps the name and port of the instance it came from until they are set here
+pub async fn restore_copied_instance(
+    path_to_instance: PathBuf,
+    dot_lodestone_config: DotLodestoneConfig,
+    name: String,
+    port: u32,
+    event_broadcaster: EventBroadcaster,
+    macro_executor: MacroExecutor,
+) -> Result<GameInstance, Error> {
+    tokio::fs::write(
+        path_to_instance.join(".lodestone_config"),
+        serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+    )
+    .await
+    .context("Failed to write .lodestone_config file")?;
+    let game_type = *dot_lodestone_config.game_type();
+    let instance: GameInstance = match game_type {
+        GameType::MinecraftJava => MinecraftInstance::restore(
+            path_to_instance,
+            dot_lodestone_config,
+            event_broadcaster,
+            macro_executor,
+        )
+        .await?
+        .into(),
+        GameType::MinecraftBedrock => BedrockInstance::restore(
+            path_to_instance,
+            dot_lodestone_config,
+            event_broadcaster,
+            macro_executor,
+        )
+        .await?
+        .into(),
+        GameType::Generic => {
+            return Err(Error {
+                kind: ErrorKind::UnsupportedOperation,
+                source: eyre!("Generic instances cannot be copied"),
+            })
+        }
+    };
+    instance.set_name(name).await?;
+    instance.set_port(port).await?;
+    Ok(instance)
+}
+
+pub async fn list_templates() -> Result<Vec<InstanceTemplate>, Error> {
+    let path_to_templates = path_to_templates();
+    if !path_to_templates.exists() {
+        return Ok(Vec::new());
+    }
+    let mut templates = Vec::new();
+    let mut entries = tokio::fs::read_dir(&path_to_templates)
+        .await
+        .context("Failed to read templates directory")?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context("Failed to read templates directory")?
+    {
+        let Ok(metadata) = tokio::fs::read_to_string(entry.path().join(TEMPLATE_METADATA_FILE)).await else {
+            continue;
+        };
+        match serde_json::from_str::<InstanceTemplate>(&metadata) {
+            Ok(template) => templates.push(template),
+            Err(e) => tracing::warn!(
+                "Ignoring template at {} with invalid metadata: {}",
+                entry.path().display(),
+                e
+            ),
+        }
+    }
+    templates.sort_by_key(|template| template.creation_time);
+    Ok(templates)
+}
+
+pub async fn get_template(id: &Snowflake) -> Result<InstanceTemplate, Error> {
+    let metadata = tokio::fs::read_to_string(path_to_template(id).join(TEMPLATE_METADATA_FILE))
+        .await
+        .map_err(|_| Error {
+            kind: ErrorKind::NotFound,
+            source: eyre!("Template not found"),
+        })?;
+    Ok(serde_json::from_str(&metadata).context("Failed to parse template metadata")?)
+}
+
+/// Save the files of `instance`, except its world, as a template
+pub async fn create_template(
+    instance: &GameInstance,
+    name: String,
+    description: String,
+) -> Result<InstanceTemplate, Error> {
+    if name.trim().is_empty() {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Template name cannot be empty"),
+        });
+    }
+    let game_type = instance.game_type().await;
+    if let Game::Generic { .. } = game_type {
+        return Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Generic instances cannot be saved as templates"),
+        });
+    }
+    let template = InstanceTemplate {
+        id: Snowflake::new(),
+        name,
+        description,
+        version: instance.version().await,
+        game_type: game_type.clone(),
+        creation_time: chrono::Utc::now().timestamp(),
+    };
+    let path_to_instance = instance.path().await;
+    let mut exclude = world_dirs(&path_to_instance, &game_type).await;
+    exclude.extend(NON_TEMPLATE_FILES.iter().map(|s| s.to_string()));
+
+    let path_to_template = path_to_template(&template.id);
+    if let Err(e) = copy_instance_files(
+        &path_to_instance,
+        &path_to_template_files(&template.id),
+        &exclude,
+    )
+    .await
+    {
+        let _ = crate::util::fs::remove_dir_all(&path_to_template).await;
+        return Err(e);
+    }
+    tokio::fs::write(
+        path_to_template.join(TEMPLATE_METADATA_FILE),
+        serde_json::to_string_pretty(&template).unwrap(),
+    )
+    .await
+    .context("Failed to write template metadata")?;
+    Ok(template)
+}
+
+pub async fn delete_template(id: &Snowflake) -> Result<(), Error> {
+    // make sure the id points to a template before removing anything
+    get_template(id).await?;
+    crate::util::fs::remove_dir_all(path_to_template(id)).await
+}
@@ -2,32 +2,41 @@
 
 use crate::error::ErrorKind;
 use crate::event_broadcaster::EventBroadcaster;
+use crate::extension::atom::{migrate_approved_permissions, path_to_atoms};
 use crate::handlers::extension::get_extension_routes;
 use crate::migration::migrate;
 use crate::prelude::{
     init_app_state, init_paths, lodestone_path, path_to_global_settings, path_to_stores,
     path_to_tmp, path_to_users, VERSION,
 };
+use crate::traits::t_backup::TBackup;
 use crate::traits::t_configurable::GameType;
+use crate::traits::t_macro::ExitStatus;
 use crate::traits::t_server::State;
 use crate::{
-    db::write::write_event_to_db_task,
+    db::{
+        retention::prune_events_task,
+        write::{write_event_to_db_task, write_player_sessions_task},
+    },
     global_settings::GlobalSettingsData,
     handlers::{
         checks::get_checks_routes, core_info::get_core_info_routes, events::get_events_routes,
         gateway::get_gateway_routes, global_fs::get_global_fs_routes,
         global_settings::get_global_settings_routes, instance::*,
-        instance_config::get_instance_config_routes, instance_fs::get_instance_fs_routes,
-        instance_macro::get_instance_macro_routes, instance_players::get_instance_players_routes,
+        instance_backup::get_instance_backup_routes, instance_config::get_instance_config_routes,
+        instance_fs::get_instance_fs_routes, instance_macro::get_instance_macro_routes,
+        instance_mods::get_instance_mods_routes, instance_players::get_instance_players_routes,
+        instance_schedule::get_instance_schedule_routes,
         instance_server::get_instance_server_routes,
-        instance_setup_configs::get_instance_setup_config_routes, monitor::get_monitor_routes,
-        playitgg::get_playitgg_routes, setup::get_setup_route, system::get_system_routes,
-        users::get_user_routes,
+        instance_setup_configs::get_instance_setup_config_routes,
+        instance_template::get_instance_template_routes, monitor::get_monitor_routes,
+        playitgg::get_playitgg_routes, roles::get_role_routes, setup::get_setup_route,
+        system::get_system_routes, users::get_user_routes,
     },
     util::rand_alphanumeric,
 };
 
-use auth::user::UsersManager;
+use auth::{jwt_token::JwtToken, user::UsersManager};
 use axum::Router;
 
 use axum_server::tls_rustls::RustlsConfig;
@@ -36,16 +45,17 @@ use color_eyre::eyre::Context;
 use color_eyre::Report;
 use dashmap::DashMap;
 use error::Error;
-use events::{CausedBy, Event};
+use events::{CausedBy, Event, MacroEvent, MacroEventInner};
 use futures::Future;
 use global_settings::GlobalSettings;
-use implementations::{generic, minecraft};
+use implementations::{bedrock, generic, minecraft};
 use macro_executor::MacroExecutor;
 use playitgg::utils::is_valid_secret_key;
 use port_manager::PortManager;
 use prelude::GameInstance;
 use reqwest::{header, Method};
 use ringbuffer::{AllocRingBuffer, RingBufferWrite};
+use scheduler::Scheduler;
 
 use fs3::FileExt;
 use semver::Version;
@@ -77,6 +87,7 @@ use uuid::Uuid;
 
 pub mod auth;
 mod command_console;
+mod console_history;
 pub mod db;
 mod deno_ops;
 mod docker_bridge;
@@ -87,12 +98,17 @@ mod extension;
 pub mod global_settings;
 mod handlers;
 pub mod implementations;
+mod instance_template;
+mod log_rules;
 pub mod macro_executor;
+pub mod macro_limits;
+pub mod macro_log;
 mod migration;
 mod output_types;
 pub mod playitgg;
 mod port_manager;
 pub mod prelude;
+mod scheduler;
 pub mod tauri_export;
 mod traits;
 pub mod types;
@@ -116,9 +132,12 @@ pub struct AppState {
     playitgg_key: Arc<Mutex<Option<String>>>,
     download_urls: Arc<Mutex<HashMap<String, DownloadableFile>>>,
     macro_executor: MacroExecutor,
+    scheduler: Scheduler,
     sqlite_pool: sqlx::SqlitePool,
     docker_bridge: docker_bridge::DockerBridge,
     playit_keep_running: Arc<Mutex<Option<Arc<AtomicBool>>>>,
+    /// refresh token of the session the desktop app signs the owner in with
+    owner_refresh_token: Arc<Mutex<Option<JwtToken>>>,
 }
 
 impl AppState {
@@ -142,6 +161,17 @@ async fn restore_instances(
 ) -> Result<DashMap<InstanceUuid, GameInstance>, Error> {
     let ret: DashMap<InstanceUuid, GameInstance> = DashMap::new();
 
+    // before any atom is run with only the approved permissions
+    let paths_to_sources: Vec<PathBuf> = instances_path
+        .read_dir()
+        .context("Failed to read instances directory")?
+        .filter_map(|entry| std::fs::read_to_string(entry.ok()?.path().join("run.ts")).ok())
+        .filter_map(|bootstrap| generic::path_to_source(&bootstrap))
+        .collect();
+    if let Err(e) = migrate_approved_permissions(&path_to_atoms(), paths_to_sources).await {
+        error!("Failed to approve the permissions of existing atoms: {}", e);
+    }
+
     for entry in instances_path
         .read_dir()
         .context("Failed to read instances directory")?
@@ -214,7 +244,27 @@ async fn restore_instances(
                 debug!("Restored Generic instance successfully");
                 (dot_lodestone_config.uuid().to_owned(), instance.into())
             }
-            GameType::MinecraftBedrock => todo!(),
+            GameType::MinecraftBedrock => {
+                let instance = match bedrock::BedrockInstance::restore(
+                    path.to_owned(),
+                    dot_lodestone_config.clone(),
+                    event_broadcaster.clone(),
+                    macro_executor.clone(),
+                )
+                .await
+                {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!(
+                            "Error while restoring Minecraft Bedrock instance {} : {e}",
+                            path.display()
+                        );
+                        continue;
+                    }
+                };
+                debug!("Restored Minecraft Bedrock instance successfully");
+                (dot_lodestone_config.uuid().to_owned(), instance.into())
+            }
         };
         let uuid = uuid_instance.0;
         let instance = uuid_instance.1;
@@ -485,6 +535,7 @@ pub async fn run(
     );
 
     global_settings.load_from_file().await?;
+    users_manager.set_two_factor_policy(global_settings.require_two_factor_for_unsafe());
 
     let first_time_setup_key = if !users_manager.as_ref().iter().any(|(_, user)| user.is_owner) {
         let key = rand_alphanumeric(16);
@@ -522,6 +573,7 @@ pub async fn run(
     };
 
     let macro_executor = MacroExecutor::new(tx.clone(), tokio::runtime::Handle::current());
+    macro_executor.set_limits(global_settings.macro_limits());
     let instances = restore_instances(&path_to_instances, tx.clone(), macro_executor.clone())
         .await
         .map_err(|_| Error {
@@ -530,8 +582,16 @@ pub async fn run(
         })?;
 
     let mut allocated_ports = HashSet::new();
+    let scheduler = Scheduler::new();
     for instance_entry in instances.iter() {
         allocated_ports.insert(instance_entry.value().port().await);
+        if let Err(e) = scheduler.load(instance_entry.value()).await {
+            error!(
+                "Failed to load scheduled jobs of instance {}: {}",
+                instance_entry.key(),
+                e
+            );
+        }
     }
     let shared_state = AppState {
         instances: Arc::new(instances),
@@ -548,8 +608,10 @@ pub async fn run(
         system: Arc::new(Mutex::new(sysinfo::System::new_all())),
         download_urls: Arc::new(Mutex::new(HashMap::new())),
         playit_keep_running: Arc::new(Mutex::new(None)),
+        owner_refresh_token: Arc::new(Mutex::new(None)),
         global_settings: Arc::new(Mutex::new(global_settings)),
         macro_executor,
+        scheduler,
         sqlite_pool: Pool::connect_with(
             SqliteConnectOptions::from_str(&format!(
                 "sqlite://{}/data.db",
@@ -627,6 +689,55 @@ pub async fn run(
 
     let write_to_db_task = write_event_to_db_task(tx.subscribe(), shared_state.sqlite_pool.clone());
 
+    let player_sessions_task = write_player_sessions_task(
+        tx.subscribe(),
+        shared_state.sqlite_pool.clone(),
+        shared_state.up_since,
+    );
+
+    // a denied permission stops the macro, the instance it runs for should say why
+    let permission_warning_task = {
+        let instances = shared_state.instances.clone();
+        let event_broadcaster = tx.clone();
+        let mut event_receiver = tx.subscribe();
+        async move {
+            loop {
+                let event = match event_receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let Some(MacroEvent {
+                    instance_uuid: Some(instance_uuid),
+                    macro_pid,
+                    macro_event_inner:
+                        MacroEventInner::Stopped {
+                            exit_status: ExitStatus::Error { error_msg, .. },
+                        },
+                }) = event.try_macro_event()
+                else {
+                    continue;
+                };
+                let Some(denial) = macro_executor::permission_denial(error_msg) else {
+                    continue;
+                };
+                let Some(instance) = instances.get(instance_uuid).map(|entry| entry.clone()) else {
+                    continue;
+                };
+                event_broadcaster.send(Event::new_instance_warning(
+                    instance_uuid.clone(),
+                    instance.name().await,
+                    format!("Macro {} was denied a permission: {}", macro_pid.0, denial),
+                ));
+            }
+        }
+    };
+
+    let prune_events_task = prune_events_task(
+        shared_state.sqlite_pool.clone(),
+        shared_state.global_settings.clone(),
+    );
+
     let monitor_report_task = {
         let monitor_buffer = shared_state.monitor_buffer.clone();
         let instances = shared_state.instances.clone();
@@ -647,6 +758,60 @@ pub async fn run(
         }
     };
 
+    let scheduler_task = shared_state.scheduler.clone().run(
+        shared_state.instances.clone(),
+        shared_state.macro_executor.clone(),
+        shared_state.users_manager.clone(),
+        shared_state.global_settings.clone(),
+    );
+
+    let backup_task = {
+        let instances = shared_state.instances.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                for entry in instances.iter() {
+                    let instance = entry.value().clone();
+                    tokio::spawn(async move {
+                        let backup_period = match instance.backup_period().await {
+                            Some(v) => v as i64,
+                            None => return,
+                        };
+                        // the backup still running from an earlier tick is not an error
+                        if instance.state().await != State::Running
+                            || instance.backup_in_progress().await
+                        {
+                            return;
+                        }
+                        // restores, upgrades and uploads leave archives that don't count
+                        let last_backup = match instance.list_backups().await {
+                            Ok(backups) => {
+                                backups.iter().find(|b| b.managed).map(|b| b.creation_time)
+                            }
+                            Err(e) => {
+                                error!("Failed to list backups: {}", e);
+                                return;
+                            }
+                        };
+                        if let Some(last_backup) = last_backup {
+                            if chrono::Utc::now().timestamp() - last_backup < backup_period {
+                                return;
+                            }
+                        }
+                        if let Err(e) = instance.create_backup(CausedBy::System).await {
+                            error!(
+                                "Failed to create scheduled backup for {}: {}",
+                                instance.name().await,
+                                e
+                            );
+                        }
+                    });
+                }
+            }
+        }
+    };
+
     let tls_config_result = RustlsConfig::from_pem_file(
         lodestone_path.join("tls").join("cert.pem"),
         lodestone_path.join("tls").join("key.pem"),
@@ -678,10 +843,15 @@ pub async fn run(
                     .merge(get_instance_server_routes(shared_state.clone()))
                     .merge(get_instance_config_routes(shared_state.clone()))
                     .merge(get_instance_players_routes(shared_state.clone()))
+                    .merge(get_instance_backup_routes(shared_state.clone()))
+                    .merge(get_instance_mods_routes(shared_state.clone()))
+                    .merge(get_instance_template_routes(shared_state.clone()))
+                    .merge(get_instance_schedule_routes(shared_state.clone()))
                     .merge(get_instance_routes(shared_state.clone()))
                     .merge(get_system_routes(shared_state.clone()))
                     .merge(get_checks_routes(shared_state.clone()))
                     .merge(get_user_routes(shared_state.clone()))
+                    .merge(get_role_routes(shared_state.clone()))
                     .merge(get_core_info_routes(shared_state.clone()))
                     .merge(get_setup_route(shared_state.clone()))
                     .merge(get_monitor_routes(shared_state.clone()))
@@ -719,7 +889,7 @@ pub async fn run(
                                 info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
                                 axum_server::bind_rustls(addr, config)
                                     .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
+                                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                                     .await
                             }
                             Err(e) => {
@@ -728,7 +898,7 @@ pub async fn run(
                                 info!("Note that Lodestone Core does not host the web dashboard itself. Please visit https://www.lodestone.cc for setup instructions.");
                                 axum_server::bind(addr)
                                     .handle(axum_server_handle)
-                                    .serve(app.into_make_service())
+                                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
                                     .await
                             }
                         }
@@ -739,8 +909,13 @@ pub async fn run(
                 let _lock_file = lock_file;
                 select! {
                     _ = write_to_db_task => info!("Write to db task exited"),
+                    _ = player_sessions_task => info!("Player sessions task exited"),
+                    _ = permission_warning_task => info!("Permission warning task exited"),
+                    _ = prune_events_task => info!("Prune events task exited"),
                     _ = event_buffer_task => info!("Event buffer task exited"),
                     _ = monitor_report_task => info!("Monitor report task exited"),
+                    _ = backup_task => info!("Backup task exited"),
+                    _ = scheduler_task => info!("Scheduler task exited"),
                     _ = shutdown_rx => info!("Shutdown signal received"),
                     _ = tokio::signal::ctrl_c() => info!("Ctrl+C received"),
                 }
@@ -750,6 +925,15 @@ pub async fn run(
                 // cleanup
                 let mut handles = vec![];
                 shared_state.download_urls.lock().await.clear();
+                if let Err(e) = shared_state
+                    .users_manager
+                    .write()
+                    .await
+                    .save_last_used()
+                    .await
+                {
+                    error!("Failed to save session and API token usage : {}", e);
+                }
                 let _ = tokio::fs::remove_dir_all(path_to_tmp()).await.map_err(|e| {
                     error!("Failed to remove tmp dir : {}", e);
                     e
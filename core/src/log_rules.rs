@@ -0,0 +1,356 @@
+//! User definable rules turning lines of console output into structured events.
+//!
+//! Each rule is a regex plus an action, the first rule matching a line decides what it means.
+//! Player names and messages are taken from the named capture groups `player` and `message`.
+
+use std::{path::Path, path::PathBuf, sync::Arc};
+
+use color_eyre::eyre::{eyre, Context};
+use fancy_regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum LogRuleAction {
+    /// emits a `PlayerChange` with the `player` group joining, identified by the optional `id` group
+    PlayerJoined,
+    /// emits a `PlayerChange` with the `player` group leaving
+    PlayerLeft,
+    /// emits a `PlayerMessage` from the `player` and `message` groups
+    PlayerMessage,
+    /// marks the server as started, only used by instances whose state the core tracks
+    ServerStarted,
+    /// raises an `InstanceWarning` with the `message` group, or the whole line
+    Warning,
+    /// raises an `InstanceError` with the `message` group, or the whole line
+    Error,
+}
+
+impl LogRuleAction {
+    fn required_groups(&self) -> &'static [&'static str] {
+        match self {
+            LogRuleAction::PlayerJoined | LogRuleAction::PlayerLeft => &["player"],
+            LogRuleAction::PlayerMessage => &["player", "message"],
+            LogRuleAction::ServerStarted | LogRuleAction::Warning | LogRuleAction::Error => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct LogRule {
+    pub name: String,
+    pub pattern: String,
+    pub action: LogRuleAction,
+}
+
+impl LogRule {
+    pub fn new(name: &str, pattern: &str, action: LogRuleAction) -> Self {
+        Self {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            action,
+        }
+    }
+}
+
+/// What a line of output was recognized as
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, TS)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[ts(export)]
+pub enum LogMatch {
+    PlayerJoined { player: String, id: Option<String> },
+    PlayerLeft { player: String },
+    PlayerMessage { player: String, message: String },
+    ServerStarted,
+    Warning { message: String },
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct InstanceLogRules {
+    /// the rules in effect, in the order they are tried
+    pub rules: Vec<LogRule>,
+    /// the built-in rules of the instance, in effect unless overridden
+    pub defaults: Vec<LogRule>,
+    pub is_default: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, TS)]
+#[ts(export)]
+pub struct LogRuleTest {
+    /// rules to test, the ones in effect if absent
+    pub rules: Option<Vec<LogRule>>,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct LogRuleTestResult {
+    pub line: String,
+    /// name of the first rule that matched
+    pub rule: Option<String>,
+    #[serde(rename = "match")]
+    pub log_match: Option<LogMatch>,
+}
+
+pub struct CompiledLogRules {
+    rules: Vec<(LogRule, Regex)>,
+}
+
+impl CompiledLogRules {
+    pub fn new(rules: Vec<LogRule>) -> Result<Self, Error> {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let regex = Regex::new(&rule.pattern).map_err(|e| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Invalid pattern for rule \"{}\": {}", rule.name, e),
+                })?;
+                for group in rule.action.required_groups() {
+                    if !regex.capture_names().flatten().any(|name| name == *group) {
+                        return Err(Error {
+                            kind: ErrorKind::BadRequest,
+                            source: eyre!(
+                                "Rule \"{}\" needs a capture group named \"{}\"",
+                                rule.name,
+                                group
+                            ),
+                        });
+                    }
+                }
+                Ok((rule, regex))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self { rules })
+    }
+
+    /// The first rule matching `line` and what it made of it
+    pub fn parse(&self, line: &str) -> Option<(&LogRule, LogMatch)> {
+        let line = line.trim_end();
+        self.rules.iter().find_map(|(rule, regex)| {
+            // a line too costly to match doesn't match
+            let caps = regex.captures(line).ok()??;
+            let group = |name: &str| caps.name(name).map(|m| m.as_str().to_string());
+            let log_match = match rule.action {
+                LogRuleAction::PlayerJoined => LogMatch::PlayerJoined {
+                    player: group("player")?,
+                    id: group("id").filter(|id| !id.is_empty()),
+                },
+                LogRuleAction::PlayerLeft => LogMatch::PlayerLeft {
+                    player: group("player")?,
+                },
+                LogRuleAction::PlayerMessage => LogMatch::PlayerMessage {
+                    player: group("player")?,
+                    message: group("message")?,
+                },
+                LogRuleAction::ServerStarted => LogMatch::ServerStarted,
+                LogRuleAction::Warning => LogMatch::Warning {
+                    message: group("message").unwrap_or_else(|| line.to_string()),
+                },
+                LogRuleAction::Error => LogMatch::Error {
+                    message: group("message").unwrap_or_else(|| line.to_string()),
+                },
+            };
+            Some((rule, log_match))
+        })
+    }
+
+    pub fn test(&self, lines: Vec<String>) -> Vec<LogRuleTestResult> {
+        lines
+            .into_iter()
+            .map(|line| {
+                let (rule, log_match) = match self.parse(&line) {
+                    Some((rule, log_match)) => (Some(rule.name.clone()), Some(log_match)),
+                    None => (None, None),
+                };
+                LogRuleTestResult {
+                    line,
+                    rule,
+                    log_match,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Compile rules overriding `defaults`.
+///
+/// An instance whose start is told by its output would never be seen as started without
+/// such a rule, so if the defaults have one the override needs one too.
+fn compile(defaults: &[LogRule], rules: Vec<LogRule>) -> Result<CompiledLogRules, Error> {
+    let marks_started = |rules: &[LogRule]| {
+        rules
+            .iter()
+            .any(|rule| rule.action == LogRuleAction::ServerStarted)
+    };
+    if marks_started(defaults) && !marks_started(&rules) {
+        return Err(Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!(
+                "The rules need a server_started rule to tell when the server has started"
+            ),
+        });
+    }
+    CompiledLogRules::new(rules)
+}
+
+/// The log rules of an instance, the built-in defaults unless overridden by `.lodestone_log_rules.json`
+pub struct LogRules {
+    path: PathBuf,
+    defaults: Vec<LogRule>,
+    custom: Option<Vec<LogRule>>,
+    compiled: Arc<CompiledLogRules>,
+}
+
+impl LogRules {
+    pub async fn load(path_to_instance: &Path, defaults: Vec<LogRule>) -> Result<Self, Error> {
+        let path = path_to_instance.join(".lodestone_log_rules.json");
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => Err(e).context(format!("Failed to read log rules at {}", path.display()))?,
+        };
+        // a broken override shouldn't stop the instance from loading
+        let custom: Option<Vec<LogRule>> = content.and_then(|content| {
+            serde_json::from_str(&content)
+                .map_err(|e| warn!("Ignoring log rules at {}: {}", path.display(), e))
+                .ok()
+        });
+        let compiled = match custom.clone().map(|custom| compile(&defaults, custom)) {
+            Some(Ok(compiled)) => compiled,
+            Some(Err(e)) => {
+                warn!(
+                    "Ignoring log rules at {}: {}, using the defaults",
+                    path.display(),
+                    e.source
+                );
+                CompiledLogRules::new(defaults.clone())?
+            }
+            None => CompiledLogRules::new(defaults.clone())?,
+        };
+        Ok(Self {
+            path,
+            defaults,
+            custom,
+            compiled: Arc::new(compiled),
+        })
+    }
+
+    pub fn get(&self) -> InstanceLogRules {
+        InstanceLogRules {
+            rules: self.custom.clone().unwrap_or_else(|| self.defaults.clone()),
+            defaults: self.defaults.clone(),
+            is_default: self.custom.is_none(),
+        }
+    }
+
+    /// Override the defaults, or go back to them with `None`
+    pub async fn set(&mut self, rules: Option<Vec<LogRule>>) -> Result<(), Error> {
+        let compiled = compile(
+            &self.defaults,
+            rules.clone().unwrap_or_else(|| self.defaults.clone()),
+        )?;
+        match &rules {
+            Some(rules) => tokio::fs::write(
+                &self.path,
+                serde_json::to_string_pretty(rules).context(
+                    "Failed to serialize log rules to string, this is a bug, please report it",
+                )?,
+            )
+            .await
+            .context(format!(
+                "Failed to write log rules to {}",
+                self.path.display()
+            ))?,
+            None => match tokio::fs::remove_file(&self.path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).context(format!(
+                    "Failed to remove log rules at {}",
+                    self.path.display()
+                ))?,
+                _ => {}
+            },
+        }
+        self.custom = rules;
+        self.compiled = Arc::new(compiled);
+        Ok(())
+    }
+
+    pub fn compiled(&self) -> Arc<CompiledLogRules> {
+        self.compiled.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_rules() {
+        let rules = CompiledLogRules::new(vec![
+            LogRule::new(
+                "chat",
+                r"^<(?P<player>\w+)> (?P<message>.+)",
+                LogRuleAction::PlayerMessage,
+            ),
+            LogRule::new(
+                "join",
+                r"(?P<player>\w+) joined",
+                LogRuleAction::PlayerJoined,
+            ),
+            LogRule::new("oom", r"OutOfMemoryError", LogRuleAction::Error),
+        ])
+        .unwrap();
+        assert_eq!(
+            rules.parse("<Steve> Alex joined\n").map(|(_, m)| m),
+            Some(LogMatch::PlayerMessage {
+                player: "Steve".to_string(),
+                message: "Alex joined".to_string(),
+            })
+        );
+        assert_eq!(
+            rules
+                .parse("Alex joined")
+                .map(|(rule, _)| rule.name.as_str()),
+            Some("join")
+        );
+        assert_eq!(
+            rules.parse("java.lang.OutOfMemoryError").map(|(_, m)| m),
+            Some(LogMatch::Error {
+                message: "java.lang.OutOfMemoryError".to_string()
+            })
+        );
+        let results = rules.test(vec!["nothing".to_string()]);
+        assert_eq!(results[0].rule, None);
+
+        assert!(CompiledLogRules::new(vec![LogRule::new(
+            "join",
+            r"(\w+) joined",
+            LogRuleAction::PlayerJoined
+        )])
+        .is_err());
+        assert!(
+            CompiledLogRules::new(vec![LogRule::new("bad", r"(", LogRuleAction::Warning)]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_override_keeps_server_started() {
+        let defaults = vec![LogRule::new(
+            "started",
+            r"Done",
+            LogRuleAction::ServerStarted,
+        )];
+        let warning = LogRule::new("lag", r"Can't keep up", LogRuleAction::Warning);
+        assert!(compile(&defaults, vec![warning.clone()]).is_err());
+        assert!(compile(&defaults, vec![warning.clone(), defaults[0].clone()]).is_ok());
+        // instances without a started rule don't need one
+        assert!(compile(&[], vec![warning]).is_ok());
+    }
+}
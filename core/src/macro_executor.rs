@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fmt::{Debug, Display},
     iter::zip,
     path::PathBuf,
@@ -22,12 +23,16 @@ use ts_rs::TS;
 
 use crate::{
     deno_ops::{
-        events::register_all_event_ops, instance_control::register_instance_control_ops,
+        events::register_all_event_ops,
+        instance_control::register_instance_control_ops,
+        macro_log::{register_macro_log_ops, CAPTURE_CONSOLE_CODE},
         prelude::register_prelude_ops,
     },
     error::{Error, ErrorKind},
     event_broadcaster::EventBroadcaster,
-    events::{CausedBy, EventInner, MacroEvent, MacroEventInner},
+    events::{CausedBy, Event, EventInner, MacroEvent, MacroEventInner},
+    macro_limits::{current_thread_stat, thread_cpu_time_secs, MacroLimits, MacroResource},
+    macro_log::{MacroLogs, MacroStream},
     traits::t_macro::ExitStatus,
     types::InstanceUuid,
 };
@@ -225,6 +230,30 @@ pub struct MacroExecutor {
     event_broadcaster: EventBroadcaster,
     next_process_id: Arc<AtomicUsize>,
     rt: tokio::runtime::Handle,
+    /// the limits from global settings, which a macro can only lower
+    limits: Arc<std::sync::RwLock<MacroLimits>>,
+    /// the instance of every limited macro still running
+    limited_table: Arc<std::sync::Mutex<HashMap<MacroPID, InstanceUuid>>>,
+    macro_logs: MacroLogs,
+}
+
+/// Remembers which limit terminated a macro, so it isn't reported as killed by a user
+#[derive(Clone, Default)]
+struct Termination(Arc<std::sync::Mutex<Option<MacroResource>>>);
+
+impl Termination {
+    fn terminate(&self, resource: MacroResource, isolate_handle: &deno_core::v8::IsolateHandle) {
+        self.0.lock().unwrap().get_or_insert(resource);
+        isolate_handle.terminate_execution();
+    }
+
+    fn exit_status(&self) -> ExitStatus {
+        let time = chrono::Utc::now().timestamp();
+        match *self.0.lock().unwrap() {
+            Some(resource) => ExitStatus::ResourceExceeded { time, resource },
+            None => ExitStatus::Killed { time },
+        }
+    }
 }
 
 pub struct SpawnResult {
@@ -238,10 +267,12 @@ impl MacroExecutor {
         let process_table = Arc::new(DashMap::new());
         let process_id = Arc::new(AtomicUsize::new(0));
         let exit_status_table = Arc::new(DashMap::new());
+        let macro_logs = MacroLogs::default();
 
         // spawn a task to listen for exit events and update the exit status table
         tokio::task::spawn({
             let exit_status_table = exit_status_table.clone();
+            let macro_logs = macro_logs.clone();
             let mut rx = event_broadcaster.subscribe();
             async move {
                 loop {
@@ -252,7 +283,11 @@ impl MacroExecutor {
                             ..
                         }) = event.try_macro_event()
                         {
-                            exit_status_table.insert(*macro_pid, exit_status.clone());
+                            // the first status is the one the macro exited with
+                            exit_status_table
+                                .entry(*macro_pid)
+                                .or_insert_with(|| exit_status.clone());
+                            macro_logs.close(*macro_pid);
                         }
                     }
                 }
@@ -266,21 +301,41 @@ impl MacroExecutor {
             exit_status_table,
             next_process_id: process_id,
             rt,
+            limits: Arc::new(std::sync::RwLock::new(MacroLimits::default())),
+            limited_table: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            macro_logs,
         }
     }
 
+    /// What the macros printed, by pid
+    pub fn macro_logs(&self) -> MacroLogs {
+        self.macro_logs.clone()
+    }
+
+    pub fn limits(&self) -> MacroLimits {
+        *self.limits.read().unwrap()
+    }
+
+    /// Only applies to macros spawned afterwards
+    pub fn set_limits(&self, limits: MacroLimits) {
+        *self.limits.write().unwrap() = limits;
+    }
+
+    /// Grant a macro access to its own directory on top of `perm`
     fn add_default_permissions(
         perm: Option<PermissionsOptions>,
         path_to_main: PathBuf,
     ) -> PermissionsOptions {
         let parent = path_to_main.parent().unwrap().to_path_buf();
         if let Some(mut perm) = perm {
-            perm.allow_read
-                .get_or_insert_with(std::vec::Vec::new)
-                .push(parent.clone());
-            perm.allow_write
-                .get_or_insert_with(std::vec::Vec::new)
-                .push(parent);
+            for allowed in [&mut perm.allow_read, &mut perm.allow_write] {
+                // an empty list already grants everything
+                if allowed.as_ref().map_or(true, |paths| !paths.is_empty()) {
+                    allowed
+                        .get_or_insert_with(std::vec::Vec::new)
+                        .push(parent.clone());
+                }
+            }
             perm
         } else {
             PermissionsOptions {
@@ -300,6 +355,12 @@ impl MacroExecutor {
     /// Note that this does not terminate the process, it just stops the handle from waiting for it.
     ///
     /// It is up to the caller to terminate the process if it is still running.
+    ///
+    /// For limits:
+    ///
+    /// If `None`, the macro is not limited, this is for code shipped with or installed in the core.
+    ///
+    /// If `Some(MacroLimits)`, the macro's own limits, lowered to the ones in global settings.
     #[allow(clippy::too_many_arguments)]
     pub async fn spawn(
         &self,
@@ -310,6 +371,7 @@ impl MacroExecutor {
         pre_injection_code: Option<String>,
         permissions: Option<PermissionsOptions>,
         instance_uuid: Option<InstanceUuid>,
+        limits: Option<MacroLimits>,
     ) -> Result<SpawnResult, Error> {
         let pid = MacroPID(self.next_process_id.fetch_add(1, Ordering::SeqCst));
         let exit_future = Box::pin({
@@ -327,30 +389,83 @@ impl MacroExecutor {
             &std::env::current_dir().context("Failed to get current directory")?,
         )
         .context("Failed to resolve path")?;
+        let permissions = match permissions {
+            Some(permissions) => Permissions::from_options(&Self::add_default_permissions(
+                Some(permissions),
+                path_to_main_module.clone(),
+            ))
+            .map_err(|e| eyre!("Failed to set up macro permissions: {}", e))?,
+            // TODO: limit permissions
+            None => Permissions::allow_all(),
+        };
+        let limits = limits.map(|limits| limits.within(&self.limits()));
+        if let (Some(limits), Some(instance_uuid)) = (&limits, &instance_uuid) {
+            let mut limited_table = self.limited_table.lock().unwrap();
+            if let Some(max) = limits.concurrent_per_instance {
+                let running = limited_table
+                    .values()
+                    .filter(|uuid| uuid == &instance_uuid)
+                    .count();
+                if running >= max as usize {
+                    return Err(Error {
+                        kind: ErrorKind::TooManyRequests,
+                        source: eyre!(
+                            "Instance is already running {} macros, the most allowed at once",
+                            running
+                        ),
+                    });
+                }
+            }
+            limited_table.insert(pid, instance_uuid.clone());
+        }
+        self.macro_logs.open(pid, instance_uuid.clone());
+        let termination = Termination::default();
+        let thread_stat = Arc::new(std::sync::Mutex::new(None));
+        if let Some(limits) = limits {
+            // subscribe before the macro can stop
+            let rx = self.event_broadcaster.subscribe();
+            tokio::spawn({
+                let __self = self.clone();
+                let termination = termination.clone();
+                let thread_stat = thread_stat.clone();
+                async move {
+                    __self
+                        .watch_limits(pid, limits, rx, termination, thread_stat)
+                        .await;
+                }
+            });
+        }
+        let heap_mb = limits.and_then(|limits| limits.heap_mb);
 
         std::thread::spawn({
             let process_table = self.macro_process_table.clone();
             let event_broadcaster = self.event_broadcaster.clone();
+            let macro_logs = self.macro_logs.clone();
             let rt = self.rt.clone();
             move || {
+                *thread_stat.lock().unwrap() = current_thread_stat();
                 let _guard = rt.enter();
                 let local = LocalSet::new();
-                local.spawn_local({
+                let macro_task = local.spawn_local({
                     let event_broadcaster = event_broadcaster.clone();
                     let instance_uuid = instance_uuid.clone();
                     async move {
                         let mut worker_option = worker_options_generator.generate();
+                        if let Some(heap_mb) = heap_mb {
+                            worker_option.create_params = Some(
+                                deno_core::v8::CreateParams::default()
+                                    .heap_limits(0, heap_mb as usize * 1024 * 1024),
+                            );
+                        }
                         worker_option.get_error_class_fn = Some(&deno_errors::get_error_class_name);
                         register_prelude_ops(&mut worker_option);
                         register_all_event_ops(&mut worker_option, event_broadcaster.clone());
                         register_instance_control_ops(&mut worker_option);
+                        register_macro_log_ops(&mut worker_option, macro_logs.clone(), pid);
 
                         let mut main_worker = deno_runtime::worker::MainWorker::from_options(
                             main_module,
-                            deno_runtime::permissions::PermissionsContainer::new(
-                                // TODO: limit permissions
-                                Permissions::allow_all(),
-                            ),
+                            deno_runtime::permissions::PermissionsContainer::new(permissions),
                             worker_option,
                         );
                         main_worker.bootstrap(&deno_runtime::BootstrapOptions {
@@ -373,6 +488,12 @@ impl MacroExecutor {
                                 ),
                             )
                             .unwrap();
+                        main_worker
+                            .execute_script(
+                                "log_inject",
+                                deno_core::FastString::Static(CAPTURE_CONSOLE_CODE),
+                            )
+                            .unwrap();
 
                         if let Some(config_code) = pre_injection_code {
                             main_worker
@@ -383,6 +504,18 @@ impl MacroExecutor {
                         let isolate_handle =
                             main_worker.js_runtime.v8_isolate().thread_safe_handle();
 
+                        if heap_mb.is_some() {
+                            let termination = termination.clone();
+                            let isolate_handle = isolate_handle.clone();
+                            main_worker.js_runtime.add_near_heap_limit_callback(
+                                move |current_limit, _| {
+                                    termination.terminate(MacroResource::Heap, &isolate_handle);
+                                    // leave V8 room to unwind instead of aborting the whole core
+                                    current_limit * 2
+                                },
+                            );
+                        }
+
                         process_table.insert(pid, isolate_handle);
 
                         let main_module = match deno_core::resolve_path(
@@ -392,6 +525,19 @@ impl MacroExecutor {
                             Ok(v) => v,
                             Err(e) => {
                                 error!("Error resolving main module: {}", e);
+                                event_broadcaster.send(
+                                    MacroEvent {
+                                        macro_pid: pid,
+                                        macro_event_inner: MacroEventInner::Stopped {
+                                            exit_status: ExitStatus::Error {
+                                                error_msg: e.to_string(),
+                                                time: chrono::Utc::now().timestamp(),
+                                            },
+                                        },
+                                        instance_uuid,
+                                    }
+                                    .into(),
+                                );
                                 return;
                             }
                         };
@@ -407,14 +553,12 @@ impl MacroExecutor {
 
                         if let Err(e) = main_worker.execute_main_module(&main_module).await {
                             if e.to_string() == "Uncaught Error: execution terminated" {
-                                warn!("User terminated macro execution");
+                                warn!("Macro execution terminated");
                                 event_broadcaster.send(
                                     MacroEvent {
                                         macro_pid: pid,
                                         macro_event_inner: MacroEventInner::Stopped {
-                                            exit_status: ExitStatus::Killed {
-                                                time: chrono::Utc::now().timestamp(),
-                                            },
+                                            exit_status: termination.exit_status(),
                                         },
                                         instance_uuid,
                                     }
@@ -422,6 +566,7 @@ impl MacroExecutor {
                                 );
                             } else {
                                 error!("Error executing main module {main_module}: {}", e);
+                                macro_logs.push(pid, MacroStream::Stderr, &e.to_string());
                                 event_broadcaster.send(
                                     MacroEvent {
                                         macro_pid: pid,
@@ -441,14 +586,12 @@ impl MacroExecutor {
 
                         if let Err(e) = main_worker.run_event_loop(false).await {
                             if e.to_string() == "Uncaught Error: execution terminated" {
-                                warn!("User terminated macro execution");
+                                warn!("Macro execution terminated");
                                 event_broadcaster.send(
                                     MacroEvent {
                                         macro_pid: pid,
                                         macro_event_inner: MacroEventInner::Stopped {
-                                            exit_status: ExitStatus::Killed {
-                                                time: chrono::Utc::now().timestamp(),
-                                            },
+                                            exit_status: termination.exit_status(),
                                         },
                                         instance_uuid: instance_uuid.clone(),
                                     }
@@ -456,6 +599,7 @@ impl MacroExecutor {
                                 );
                             } else {
                                 error!("Error running event loops: {}", e);
+                                macro_logs.push(pid, MacroStream::Stderr, &e.to_string());
                                 event_broadcaster.send(
                                     MacroEvent {
                                         macro_pid: pid,
@@ -470,6 +614,7 @@ impl MacroExecutor {
                                     .into(),
                                 );
                             }
+                            return;
                         }
 
                         debug!("Macro event loop exited");
@@ -496,6 +641,10 @@ impl MacroExecutor {
                 // spawned tasks have returned.
                 rt.block_on(local);
                 debug!("MacroExecutor thread exited");
+                // the task sends the exit status itself unless it panicked
+                if !matches!(rt.block_on(macro_task), Err(e) if e.is_panic()) {
+                    return;
+                }
                 event_broadcaster.send(
                     MacroEvent {
                         macro_pid: pid,
@@ -548,6 +697,113 @@ impl MacroExecutor {
         })
     }
 
+    /// Run a task written in Rust as if it were a macro
+    ///
+    /// The task gets a pid and emits the same started and stopped events as a macro,
+    /// so it shows up in task lists and its exit status can be queried. It cannot be aborted.
+    pub fn spawn_native(
+        &self,
+        instance_uuid: Option<InstanceUuid>,
+        task: impl Future<Output = Result<(), Error>> + Send + 'static,
+    ) -> MacroPID {
+        let pid = MacroPID(self.next_process_id.fetch_add(1, Ordering::SeqCst));
+        let event_broadcaster = self.event_broadcaster.clone();
+        let macro_logs = self.macro_logs.clone();
+        macro_logs.open(pid, instance_uuid.clone());
+        event_broadcaster.send(
+            MacroEvent {
+                macro_pid: pid,
+                macro_event_inner: MacroEventInner::Started,
+                instance_uuid: instance_uuid.clone(),
+            }
+            .into(),
+        );
+        tokio::spawn(async move {
+            let exit_status = match task.await {
+                Ok(()) => ExitStatus::Success {
+                    time: chrono::Utc::now().timestamp(),
+                },
+                Err(e) => {
+                    macro_logs.push(pid, MacroStream::Stderr, &e.to_string());
+                    ExitStatus::Error {
+                        time: chrono::Utc::now().timestamp(),
+                        error_msg: e.to_string(),
+                    }
+                }
+            };
+            event_broadcaster.send(
+                MacroEvent {
+                    macro_pid: pid,
+                    macro_event_inner: MacroEventInner::Stopped { exit_status },
+                    instance_uuid,
+                }
+                .into(),
+            );
+        });
+        pid
+    }
+
+    /// Terminate a limited macro once it runs out of time, and stop counting it when it exits
+    async fn watch_limits(
+        &self,
+        pid: MacroPID,
+        limits: MacroLimits,
+        mut rx: tokio::sync::broadcast::Receiver<Event>,
+        termination: Termination,
+        thread_stat: Arc<std::sync::Mutex<Option<PathBuf>>>,
+    ) {
+        let start = tokio::time::Instant::now();
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(event) => {
+                        if let Some(MacroEvent {
+                            macro_pid,
+                            macro_event_inner: MacroEventInner::Stopped { .. },
+                            ..
+                        }) = event.try_macro_event()
+                        {
+                            if *macro_pid == pid {
+                                break;
+                            }
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                _ = interval.tick() => {
+                    // the stopped event may have been missed while lagging
+                    if self.exit_status_table.contains_key(&pid) {
+                        break;
+                    }
+                    let exceeded = if limits.wall_time_secs.map_or(false, |limit| {
+                        start.elapsed() >= Duration::from_secs(limit.into())
+                    }) {
+                        Some(MacroResource::WallTime)
+                    } else if limits.cpu_time_secs.map_or(false, |limit| {
+                        thread_stat
+                            .lock()
+                            .unwrap()
+                            .as_deref()
+                            .and_then(thread_cpu_time_secs)
+                            .map_or(false, |cpu_time| cpu_time >= limit.into())
+                    }) {
+                        Some(MacroResource::CpuTime)
+                    } else {
+                        None
+                    };
+                    if let (Some(resource), Some(isolate_handle)) =
+                        (exceeded, self.macro_process_table.get(&pid))
+                    {
+                        termination.terminate(resource, &isolate_handle);
+                    }
+                }
+            }
+        }
+        self.limited_table.lock().unwrap().remove(&pid);
+    }
+
     /// abort a macro execution
     pub fn abort_macro(&self, pid: MacroPID) -> Result<(), Error> {
         self.macro_process_table
@@ -978,6 +1234,12 @@ fn get_config_value_type(type_str: &str) -> Result<ConfigurableValueType, Error>
     Ok(result)
 }
 
+/// What a macro was denied if it stopped on a permission it wasn't granted
+pub fn permission_denial(error_msg: &str) -> Option<&str> {
+    let (_, denial) = error_msg.split_once("PermissionDenied: ")?;
+    denial.lines().next()
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -1056,6 +1318,7 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
             )
             .await
             .unwrap();
@@ -1098,6 +1361,7 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
             )
             .await
             .unwrap();
@@ -1205,6 +1469,17 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_permission_denial() {
+        assert_eq!(
+            permission_denial(
+                "Uncaught (in promise) PermissionDenied: Requires net access to \"example.com\", run again with the --allow-net flag\n    at file:///macro/index.ts:1:7"
+            ),
+            Some("Requires net access to \"example.com\", run again with the --allow-net flag")
+        );
+        assert_eq!(permission_denial("Uncaught Error: boom"), None);
+    }
 }
 
 mod deno_errors {
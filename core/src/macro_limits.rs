@@ -0,0 +1,222 @@
+//! Limits keeping a macro from taking down the core with it.
+//!
+//! Global settings hold the limits every macro runs under. A macro can lower them for itself
+//! with `@limit` lines in its header comment, e.g. `// @limit cpu_time_secs 30`.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+
+/// What a macro ran out of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum MacroResource {
+    Heap,
+    WallTime,
+    CpuTime,
+}
+
+/// `None` means unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MacroLimits {
+    /// size of the V8 heap, in megabytes
+    pub heap_mb: Option<u32>,
+    pub wall_time_secs: Option<u32>,
+    pub cpu_time_secs: Option<u32>,
+    /// macros an instance can run at once, only set in global settings
+    pub concurrent_per_instance: Option<u32>,
+}
+
+impl Default for MacroLimits {
+    fn default() -> Self {
+        Self {
+            heap_mb: Some(512),
+            wall_time_secs: None,
+            cpu_time_secs: None,
+            concurrent_per_instance: Some(16),
+        }
+    }
+}
+
+fn lowest(a: Option<u32>, b: Option<u32>) -> Option<u32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+impl MacroLimits {
+    pub const UNLIMITED: MacroLimits = MacroLimits {
+        heap_mb: None,
+        wall_time_secs: None,
+        cpu_time_secs: None,
+        concurrent_per_instance: None,
+    };
+
+    /// The limits set by the `@limit` lines of a macro's header
+    pub fn from_header(code: &str) -> Result<Self, Error> {
+        let mut limits = Self::UNLIMITED;
+        let mut in_block_comment = false;
+        for line in code.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            // the header ends with the first line of code, a string can't set limits
+            if !in_block_comment && !trimmed.starts_with("//") && !trimmed.starts_with("/*") {
+                break;
+            }
+            in_block_comment = if in_block_comment {
+                !trimmed.contains("*/")
+            } else {
+                trimmed.starts_with("/*") && !trimmed[2..].contains("*/")
+            };
+            // the header is made of comments, `//`, `/*` and `*` alike
+            let line = line
+                .trim_start()
+                .trim_start_matches(['/', '*'])
+                .trim_start();
+            let Some(limit) = line.strip_prefix("@limit ") else {
+                continue;
+            };
+            let mut tokens = limit.split_whitespace();
+            let (Some(key), Some(value)) = (tokens.next(), tokens.next()) else {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Expected \"@limit <name> <value>\", got \"{}\"", line.trim()),
+                });
+            };
+            let value = value
+                .parse::<u32>()
+                .ok()
+                .filter(|value| *value > 0)
+                .ok_or_else(|| Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!("Limit {} must be a positive integer, got {}", key, value),
+                })?;
+            let field = match key {
+                "heap_mb" => &mut limits.heap_mb,
+                "wall_time_secs" => &mut limits.wall_time_secs,
+                "cpu_time_secs" => &mut limits.cpu_time_secs,
+                _ => {
+                    return Err(Error {
+                        kind: ErrorKind::BadRequest,
+                        source: eyre!("Unknown limit {}", key),
+                    })
+                }
+            };
+            *field = Some(value);
+        }
+        Ok(limits)
+    }
+
+    /// A macro can only lower the limits of the global settings
+    pub fn within(&self, ceiling: &MacroLimits) -> MacroLimits {
+        MacroLimits {
+            heap_mb: lowest(self.heap_mb, ceiling.heap_mb),
+            wall_time_secs: lowest(self.wall_time_secs, ceiling.wall_time_secs),
+            cpu_time_secs: lowest(self.cpu_time_secs, ceiling.cpu_time_secs),
+            concurrent_per_instance: ceiling.concurrent_per_instance,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        for (name, limit) in [
+            ("heap_mb", self.heap_mb),
+            ("wall_time_secs", self.wall_time_secs),
+            ("cpu_time_secs", self.cpu_time_secs),
+            ("concurrent_per_instance", self.concurrent_per_instance),
+        ] {
+            if limit == Some(0) {
+                return Err(Error {
+                    kind: ErrorKind::BadRequest,
+                    source: eyre!(
+                        "Limit {} must be positive, leave it empty for no limit",
+                        name
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The limits set in the header of the macro at `path_to_macro`
+pub async fn read_macro_limits(path_to_macro: &Path) -> Result<MacroLimits, Error> {
+    MacroLimits::from_header(&crate::util::fs::read_to_string(path_to_macro).await?)
+}
+
+/// Where the CPU time of the calling thread can be read from, Linux only
+pub fn current_thread_stat() -> Option<PathBuf> {
+    // resolves to `<pid>/task/<tid>`
+    let thread = std::fs::read_link("/proc/thread-self").ok()?;
+    Some(Path::new("/proc").join(thread).join("stat"))
+}
+
+/// CPU time spent by the thread, in seconds, from its `/proc/<pid>/task/<tid>/stat`
+pub fn thread_cpu_time_secs(path_to_stat: &Path) -> Option<u64> {
+    parse_cpu_time_secs(&std::fs::read_to_string(path_to_stat).ok()?)
+}
+
+fn parse_cpu_time_secs(stat: &str) -> Option<u64> {
+    // the command name may contain spaces, fields are counted from after it
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace();
+    // utime and stime are the 14th and 15th fields, the state right after the name is the 3rd
+    let utime: u64 = fields.nth(11)?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    // clock ticks, which the kernel reports in hundredths of a second
+    Some((utime + stime) / 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_from_header() {
+        let limits = MacroLimits::from_header(
+            r#"
+            // @limit heap_mb 64
+            /**
+             * @limit cpu_time_secs 30
+             */
+            /* @limit wall_time_secs 600 */
+            console.log("@limit is not a header here");
+            // @limit heap_mb 1024
+            "#,
+        )
+        .unwrap();
+        assert_eq!(limits.heap_mb, Some(64));
+        assert_eq!(limits.cpu_time_secs, Some(30));
+        assert_eq!(limits.wall_time_secs, Some(600));
+
+        let effective = limits.within(&MacroLimits {
+            heap_mb: Some(128),
+            wall_time_secs: None,
+            cpu_time_secs: Some(10),
+            concurrent_per_instance: Some(4),
+        });
+        assert_eq!(effective.heap_mb, Some(64));
+        assert_eq!(effective.wall_time_secs, Some(600));
+        assert_eq!(effective.cpu_time_secs, Some(10));
+        assert_eq!(effective.concurrent_per_instance, Some(4));
+
+        assert!(MacroLimits::from_header("// @limit heap_mb lots").is_err());
+        assert!(MacroLimits::from_header("let a = 1;\n// @limit heap_mb lots").is_ok());
+        assert!(MacroLimits::from_header("// @limit heap_mb 0").is_err());
+        assert!(MacroLimits::from_header("// @limit concurrent_per_instance 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_cpu_time() {
+        let stat = "4242 (tokio runtime) S 1 4242 4242 0 -1 4194560 1000 0 0 0 1250 350 0 0 20 0 8 0 100 0 0";
+        assert_eq!(parse_cpu_time_secs(stat), Some(16));
+    }
+}
@@ -0,0 +1,213 @@
+//! What each macro printed, kept per task so a failed run can be looked into after it exits.
+//!
+//! Only the latest lines of a task and the latest tasks are kept.
+
+use std::sync::{Arc, Mutex};
+
+use indexmap::IndexMap;
+use ringbuffer::{AllocRingBuffer, RingBuffer, RingBufferExt, RingBufferWrite};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use ts_rs::TS;
+
+use crate::{macro_executor::MacroPID, types::InstanceUuid};
+
+/// lines kept of each stream of a task, must be a power of 2
+const LINES_PER_STREAM: usize = 1024;
+/// longer lines are cut
+const MAX_LINE_LEN: usize = 4096;
+/// logs of older tasks are dropped, those that exited first
+const MAX_TASKS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum MacroStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MacroLogLine {
+    pub stream: MacroStream,
+    pub line: String,
+    /// unix millis
+    pub time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct MacroLogSnapshot {
+    pub stdout: Vec<MacroLogLine>,
+    pub stderr: Vec<MacroLogLine>,
+    /// whether older lines were dropped to stay within bounds
+    pub truncated: bool,
+    pub running: bool,
+}
+
+struct TaskLog {
+    instance_uuid: Option<InstanceUuid>,
+    stdout: AllocRingBuffer<MacroLogLine>,
+    stderr: AllocRingBuffer<MacroLogLine>,
+    truncated: bool,
+    /// `None` once the task exited, which ends every live stream of it
+    tx: Option<broadcast::Sender<MacroLogLine>>,
+}
+
+impl TaskLog {
+    fn snapshot(&self) -> MacroLogSnapshot {
+        MacroLogSnapshot {
+            stdout: self.stdout.to_vec(),
+            stderr: self.stderr.to_vec(),
+            truncated: self.truncated,
+            running: self.tx.is_some(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MacroLogs {
+    tasks: Arc<Mutex<IndexMap<MacroPID, TaskLog>>>,
+}
+
+impl std::fmt::Debug for MacroLogs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MacroLogs").finish_non_exhaustive()
+    }
+}
+
+fn cut(line: &str) -> &str {
+    if line.len() <= MAX_LINE_LEN {
+        return line;
+    }
+    let mut end = MAX_LINE_LEN;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+impl MacroLogs {
+    /// Start keeping the log of a task
+    pub fn open(&self, pid: MacroPID, instance_uuid: Option<InstanceUuid>) {
+        let mut tasks = self.tasks.lock().unwrap();
+        while tasks.len() >= MAX_TASKS {
+            // with every kept task still running, the oldest one goes
+            let index = tasks
+                .values()
+                .position(|task| task.tx.is_none())
+                .unwrap_or(0);
+            tasks.shift_remove_index(index);
+        }
+        tasks.insert(
+            pid,
+            TaskLog {
+                instance_uuid,
+                stdout: AllocRingBuffer::with_capacity(LINES_PER_STREAM),
+                stderr: AllocRingBuffer::with_capacity(LINES_PER_STREAM),
+                truncated: false,
+                tx: Some(broadcast::channel(LINES_PER_STREAM).0),
+            },
+        );
+    }
+
+    /// Record what a task printed, one entry per line
+    pub fn push(&self, pid: MacroPID, stream: MacroStream, text: &str) {
+        let mut tasks = self.tasks.lock().unwrap();
+        let Some(task) = tasks.get_mut(&pid) else {
+            return;
+        };
+        let time = chrono::Utc::now().timestamp_millis();
+        for line in text.lines() {
+            let line = MacroLogLine {
+                stream,
+                line: cut(line).to_string(),
+                time,
+            };
+            if let Some(tx) = &task.tx {
+                // no one streaming the log is fine
+                let _ = tx.send(line.clone());
+            }
+            let buffer = match stream {
+                MacroStream::Stdout => &mut task.stdout,
+                MacroStream::Stderr => &mut task.stderr,
+            };
+            task.truncated |= buffer.is_full();
+            buffer.push(line);
+        }
+    }
+
+    /// The task exited, nothing more will be printed
+    pub fn close(&self, pid: MacroPID) {
+        if let Some(task) = self.tasks.lock().unwrap().get_mut(&pid) {
+            task.tx = None;
+        }
+    }
+
+    /// The instance a task was run for, `None` if its log is not kept
+    pub fn instance_of(&self, pid: MacroPID) -> Option<Option<InstanceUuid>> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .get(&pid)
+            .map(|task| task.instance_uuid.clone())
+    }
+
+    pub fn snapshot(&self, pid: MacroPID) -> Option<MacroLogSnapshot> {
+        self.tasks.lock().unwrap().get(&pid).map(TaskLog::snapshot)
+    }
+
+    /// What was printed so far, and the lines printed from then on while the task runs
+    pub fn subscribe(
+        &self,
+        pid: MacroPID,
+    ) -> Option<(MacroLogSnapshot, Option<broadcast::Receiver<MacroLogLine>>)> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .get(&pid)
+            .map(|task| (task.snapshot(), task.tx.as_ref().map(|tx| tx.subscribe())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_log_bounds() {
+        let logs = MacroLogs::default();
+        let pid = MacroPID(0);
+        logs.open(pid, None);
+        logs.push(pid, MacroStream::Stdout, "hello\nworld\n");
+        logs.push(pid, MacroStream::Stderr, &"é".repeat(MAX_LINE_LEN));
+        let snapshot = logs.snapshot(pid).unwrap();
+        assert_eq!(snapshot.stdout.len(), 2);
+        assert_eq!(snapshot.stdout[1].line, "world");
+        assert!(snapshot.stderr[0].line.len() <= MAX_LINE_LEN);
+        assert!(!snapshot.truncated);
+        assert!(snapshot.running);
+
+        for i in 0..LINES_PER_STREAM {
+            logs.push(pid, MacroStream::Stdout, &i.to_string());
+        }
+        let snapshot = logs.snapshot(pid).unwrap();
+        assert_eq!(snapshot.stdout.len(), LINES_PER_STREAM);
+        assert_eq!(snapshot.stdout[0].line, "0");
+        assert!(snapshot.truncated);
+
+        logs.close(pid);
+        assert!(!logs.snapshot(pid).unwrap().running);
+        for i in 1..=MAX_TASKS {
+            logs.open(MacroPID(i), None);
+        }
+        assert!(logs.snapshot(pid).is_none());
+        assert_eq!(logs.tasks.lock().unwrap().len(), MAX_TASKS);
+
+        // running tasks are dropped too once there are too many
+        logs.open(MacroPID(MAX_TASKS + 1), None);
+        assert!(logs.snapshot(MacroPID(1)).is_none());
+        assert_eq!(logs.tasks.lock().unwrap().len(), MAX_TASKS);
+    }
+}
@@ -22,6 +22,8 @@ impl From<RestoreConfigV042> for RestoreConfig {
             auto_start: config.auto_start,
             restart_on_crash: config.restart_on_crash,
             backup_period: config.backup_period,
+            backup_retention: Default::default(),
+            ban_expiry: Default::default(),
             jre_major_version: config.jre_major_version,
             has_started: config.has_started,
             java_cmd: None,
@@ -101,6 +101,7 @@ lazy_static! {
         ));
 }
 
+use crate::bedrock::BedrockInstance;
 use crate::generic::GenericInstance;
 use crate::minecraft::MinecraftInstance;
 use crate::AppState;
@@ -111,10 +112,12 @@ use crate::AppState;
     TPlayerManagement,
     TResourceManagement,
     TServer,
+    TBackup,
     TManifest
 )]
 #[derive(Clone)]
 pub enum GameInstance {
     MinecraftInstance,
     GenericInstance,
+    BedrockInstance,
 }
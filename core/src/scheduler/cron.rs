@@ -0,0 +1,210 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, TimeZone, Timelike};
+use color_eyre::eyre::eyre;
+
+use crate::error::{Error, ErrorKind};
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// The values a single cron field matches, as a bit set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CronField {
+    bits: u64,
+    /// whether the field was `*`, which matters for how day of month and day of week combine
+    any: bool,
+}
+
+impl CronField {
+    fn contains(&self, value: u32) -> bool {
+        self.bits & (1 << value) != 0
+    }
+
+    fn parse(field: &str, min: u32, max: u32, names: &[&str]) -> Result<CronField, String> {
+        let parse_value = |value: &str| -> Result<u32, String> {
+            if let Some(index) = names
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(value))
+            {
+                // month names start at 1, weekday names at 0
+                return Ok(index as u32 + min);
+            }
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|v| (min..=max).contains(v))
+                .ok_or_else(|| format!("{value} is not between {min} and {max}"))
+        };
+
+        let mut bits = 0;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => match step.parse::<u32>() {
+                    Ok(step) if step > 0 => (range, step),
+                    _ => return Err(format!("{step} is not a valid step")),
+                },
+                None => (part, 1),
+            };
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some((start, end)) = range.split_once('-') {
+                (parse_value(start)?, parse_value(end)?)
+            } else {
+                let start = parse_value(range)?;
+                // `5/15` means every 15 starting at 5
+                (start, if step > 1 { max } else { start })
+            };
+            if start > end {
+                return Err(format!("{range} is not a valid range"));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(CronField {
+            bits,
+            any: field == "*",
+        })
+    }
+}
+
+/// A standard five field cron expression: minute, hour, day of month, month and day of week
+///
+/// Fields accept `*`, lists, ranges and steps, months and weekdays also accept
+/// their three letter names. `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`
+/// are accepted as shorthands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minute: CronField,
+    hour: CronField,
+    day_of_month: CronField,
+    month: CronField,
+    day_of_week: CronField,
+}
+
+impl CronSchedule {
+    /// Whether the schedule fires in the minute `time` falls in
+    pub fn matches<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        let day_of_month = self.day_of_month.contains(time.day());
+        let day_of_week = self
+            .day_of_week
+            .contains(time.weekday().num_days_from_sunday());
+        // like cron, a restricted day of month and day of week match if either of them does
+        let day = match (self.day_of_month.any, self.day_of_week.any) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+        day && self.minute.contains(time.minute())
+            && self.hour.contains(time.hour())
+            && self.month.contains(time.month())
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = Error;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let invalid = |reason: String| Error {
+            kind: ErrorKind::BadRequest,
+            source: eyre!("Invalid cron expression \"{}\": {}", expression, reason),
+        };
+        if fields.len() != 5 {
+            return Err(invalid(format!(
+                "expected 5 fields, found {}",
+                fields.len()
+            )));
+        }
+        let mut day_of_week = CronField::parse(fields[4], 0, 7, &WEEKDAY_NAMES).map_err(invalid)?;
+        // both 0 and 7 are sunday
+        if day_of_week.contains(7) {
+            day_of_week.bits |= 1;
+        }
+        Ok(CronSchedule {
+            minute: CronField::parse(fields[0], 0, 59, &[]).map_err(invalid)?,
+            hour: CronField::parse(fields[1], 0, 23, &[]).map_err(invalid)?,
+            day_of_month: CronField::parse(fields[2], 1, 31, &[]).map_err(invalid)?,
+            month: CronField::parse(fields[3], 1, 12, &MONTH_NAMES).map_err(invalid)?,
+            day_of_week,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_cron() {
+        let every_15 = CronSchedule::from_str("*/15 * * * *").unwrap();
+        assert!(every_15.matches(&at(2023, 6, 1, 12, 30)));
+        assert!(!every_15.matches(&at(2023, 6, 1, 12, 31)));
+
+        let offset = CronSchedule::from_str("5/20 * * * *").unwrap();
+        assert!(offset.matches(&at(2023, 6, 1, 12, 45)));
+        assert!(!offset.matches(&at(2023, 6, 1, 12, 0)));
+
+        let office_hours = CronSchedule::from_str("0 9-17/4 * * mon-fri").unwrap();
+        // 2023-06-02 is a friday, 2023-06-03 a saturday
+        assert!(office_hours.matches(&at(2023, 6, 2, 13, 0)));
+        assert!(!office_hours.matches(&at(2023, 6, 2, 15, 0)));
+        assert!(!office_hours.matches(&at(2023, 6, 3, 13, 0)));
+
+        let sunday = CronSchedule::from_str("30 4 * * 7").unwrap();
+        assert!(sunday.matches(&at(2023, 6, 4, 4, 30)));
+
+        assert_eq!(
+            CronSchedule::from_str("@daily").unwrap(),
+            CronSchedule::from_str("0 0 * * *").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cron_day_matching() {
+        // the 1st of the month or any monday
+        let schedule = CronSchedule::from_str("0 0 1 * 1").unwrap();
+        assert!(schedule.matches(&at(2023, 6, 1, 0, 0)));
+        assert!(schedule.matches(&at(2023, 6, 5, 0, 0)));
+        assert!(!schedule.matches(&at(2023, 6, 6, 0, 0)));
+
+        // only the 1st of the month
+        let schedule = CronSchedule::from_str("0 0 1 * *").unwrap();
+        assert!(!schedule.matches(&at(2023, 6, 5, 0, 0)));
+    }
+
+    #[test]
+    fn test_invalid_cron() {
+        for expression in [
+            "",
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "*/0 * * * *",
+            "10-5 * * * *",
+            "* * * * funday",
+        ] {
+            assert!(
+                CronSchedule::from_str(expression).is_err(),
+                "{expression} should be invalid"
+            );
+        }
+    }
+}
@@ -0,0 +1,497 @@
+pub mod cron;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Local, Timelike};
+use color_eyre::eyre::{eyre, Context};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{error, info, warn};
+use ts_rs::TS;
+
+use crate::auth::user::{UserAction, UsersManager};
+use crate::auth::user_id::UserId;
+use crate::error::{Error, ErrorKind};
+use crate::events::CausedBy;
+use crate::global_settings::GlobalSettings;
+use crate::macro_executor::MacroExecutor;
+use crate::prelude::GameInstance;
+use crate::traits::t_backup::TBackup;
+use crate::traits::t_configurable::TConfigurable;
+use crate::traits::t_macro::{TMacro, TaskEntry};
+use crate::traits::t_server::TServer;
+use crate::types::{DotLodestoneConfig, InstanceUuid, Snowflake};
+
+use self::cron::CronSchedule;
+
+/// Minutes the scheduler catches up on after falling behind, e.g. when the host was suspended.
+/// Jobs due further back are skipped rather than all run at once.
+const MAX_CATCH_UP_MINUTES: i64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[ts(export)]
+pub enum ScheduledAction {
+    RunMacro { name: String, args: Vec<String> },
+    Command { command: String },
+    Start,
+    Stop,
+    Restart,
+    Backup,
+}
+
+impl ScheduledAction {
+    /// What a user must be allowed to do to schedule this action
+    pub fn required_permissions(&self, uuid: &InstanceUuid) -> Vec<UserAction> {
+        match self {
+            ScheduledAction::RunMacro { .. } => vec![UserAction::AccessMacro(Some(uuid.clone()))],
+            ScheduledAction::Command { .. } => vec![UserAction::AccessConsole(uuid.clone())],
+            ScheduledAction::Start => vec![UserAction::StartInstance(uuid.clone())],
+            ScheduledAction::Stop => vec![UserAction::StopInstance(uuid.clone())],
+            ScheduledAction::Restart => vec![
+                UserAction::StopInstance(uuid.clone()),
+                UserAction::StartInstance(uuid.clone()),
+            ],
+            ScheduledAction::Backup => vec![UserAction::WriteResource(uuid.clone())],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ScheduledJob {
+    pub id: Snowflake,
+    pub name: String,
+    /// five field cron expression, evaluated in the local time of the host
+    pub cron: String,
+    pub action: ScheduledAction,
+    pub enabled: bool,
+    /// the user who last created or edited the job, it runs on their behalf
+    #[serde(default)]
+    pub creator: Option<UserId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ScheduledJobConfig {
+    pub name: String,
+    pub cron: String,
+    pub action: ScheduledAction,
+    /// defaults to true
+    pub enabled: Option<bool>,
+}
+
+struct LoadedJob {
+    job: ScheduledJob,
+    schedule: CronSchedule,
+}
+
+impl TryFrom<ScheduledJob> for LoadedJob {
+    type Error = Error;
+
+    fn try_from(job: ScheduledJob) -> Result<Self, Error> {
+        Ok(LoadedJob {
+            schedule: CronSchedule::from_str(&job.cron)?,
+            job,
+        })
+    }
+}
+
+/// Runs the cron jobs of every instance
+///
+/// Jobs are kept in the `.lodestone_config` of their instance, so they survive restarts
+/// and are removed along with the instance.
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    jobs: Arc<Mutex<HashMap<InstanceUuid, Vec<LoadedJob>>>>,
+}
+
+async fn read_dot_lodestone_config(instance: &GameInstance) -> Result<DotLodestoneConfig, Error> {
+    let path = instance.path().await.join(".lodestone_config");
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .context(format!("Failed to read {}", path.display()))?;
+    Ok(serde_json::from_str(&content).context(format!("Failed to parse {}", path.display()))?)
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler::default()
+    }
+
+    /// Read the jobs of an instance from its `.lodestone_config`
+    pub async fn load(&self, instance: &GameInstance) -> Result<(), Error> {
+        let dot_lodestone_config = read_dot_lodestone_config(instance).await?;
+        let mut jobs = Vec::new();
+        for job in dot_lodestone_config.schedule().iter().cloned() {
+            let name = job.name.clone();
+            match LoadedJob::try_from(job) {
+                Ok(job) => jobs.push(job),
+                Err(e) => error!(
+                    "Ignoring scheduled job {} of {}: {}",
+                    name,
+                    instance.name().await,
+                    e
+                ),
+            }
+        }
+        self.jobs.lock().await.insert(instance.uuid().await, jobs);
+        Ok(())
+    }
+
+    pub async fn remove_instance(&self, uuid: &InstanceUuid) {
+        self.jobs.lock().await.remove(uuid);
+    }
+
+    pub async fn list_jobs(&self, uuid: &InstanceUuid) -> Vec<ScheduledJob> {
+        self.jobs
+            .lock()
+            .await
+            .get(uuid)
+            .map(|jobs| jobs.iter().map(|loaded| loaded.job.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    async fn persist(instance: &GameInstance, jobs: &[LoadedJob]) -> Result<(), Error> {
+        let mut dot_lodestone_config = read_dot_lodestone_config(instance).await?;
+        dot_lodestone_config.set_schedule(jobs.iter().map(|loaded| loaded.job.clone()).collect());
+        tokio::fs::write(
+            instance.path().await.join(".lodestone_config"),
+            serde_json::to_string_pretty(&dot_lodestone_config).unwrap(),
+        )
+        .await
+        .context("Failed to write .lodestone_config file")?;
+        Ok(())
+    }
+
+    pub async fn add_job(
+        &self,
+        instance: &GameInstance,
+        config: ScheduledJobConfig,
+        creator: UserId,
+    ) -> Result<ScheduledJob, Error> {
+        let job = LoadedJob::try_from(ScheduledJob {
+            id: Snowflake::new(),
+            name: config.name,
+            cron: config.cron,
+            action: config.action,
+            enabled: config.enabled.unwrap_or(true),
+            creator: Some(creator),
+        })?;
+        let ret = job.job.clone();
+        let mut lock = self.jobs.lock().await;
+        let jobs = lock.entry(instance.uuid().await).or_default();
+        jobs.push(job);
+        if let Err(e) = Self::persist(instance, jobs).await {
+            jobs.pop();
+            return Err(e);
+        }
+        Ok(ret)
+    }
+
+    pub async fn update_job(
+        &self,
+        instance: &GameInstance,
+        id: &Snowflake,
+        config: ScheduledJobConfig,
+        creator: UserId,
+    ) -> Result<ScheduledJob, Error> {
+        let mut lock = self.jobs.lock().await;
+        let jobs = lock.entry(instance.uuid().await).or_default();
+        let index = jobs
+            .iter()
+            .position(|loaded| &loaded.job.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Scheduled job not found"),
+            })?;
+        let job = LoadedJob::try_from(ScheduledJob {
+            id: *id,
+            name: config.name,
+            cron: config.cron,
+            action: config.action,
+            enabled: config.enabled.unwrap_or(true),
+            creator: Some(creator),
+        })?;
+        let ret = job.job.clone();
+        let previous = std::mem::replace(&mut jobs[index], job);
+        if let Err(e) = Self::persist(instance, jobs).await {
+            jobs[index] = previous;
+            return Err(e);
+        }
+        Ok(ret)
+    }
+
+    pub async fn delete_job(&self, instance: &GameInstance, id: &Snowflake) -> Result<(), Error> {
+        let mut lock = self.jobs.lock().await;
+        let jobs = lock.entry(instance.uuid().await).or_default();
+        let index = jobs
+            .iter()
+            .position(|loaded| &loaded.job.id == id)
+            .ok_or_else(|| Error {
+                kind: ErrorKind::NotFound,
+                source: eyre!("Scheduled job not found"),
+            })?;
+        let removed = jobs.remove(index);
+        if let Err(e) = Self::persist(instance, jobs).await {
+            jobs.insert(index, removed);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The user a job runs on behalf of, if they may still do what it does
+    ///
+    /// Permissions can be revoked after the job was scheduled, so they are checked on every run
+    async fn authorize_job(
+        job: &ScheduledJob,
+        uuid: &InstanceUuid,
+        users_manager: &RwLock<UsersManager>,
+        global_settings: &Mutex<GlobalSettings>,
+    ) -> Result<CausedBy, Error> {
+        let creator = match &job.creator {
+            Some(uid) => users_manager.read().await.get_user(uid),
+            None => None,
+        }
+        .ok_or_else(|| Error {
+            kind: ErrorKind::PermissionDenied,
+            source: eyre!("No user the job could run as, edit the job to run it as yourself"),
+        })?;
+        let safe_mode = global_settings.lock().await.safe_mode();
+        for action in job.action.required_permissions(uuid) {
+            creator.try_action(&action, safe_mode)?;
+        }
+        Ok(CausedBy::User {
+            user_id: creator.uid,
+            user_name: creator.username,
+        })
+    }
+
+    /// Run a job now, regardless of its schedule
+    pub async fn run_job(
+        instance: GameInstance,
+        job: ScheduledJob,
+        caused_by: CausedBy,
+        macro_executor: &MacroExecutor,
+    ) -> Result<TaskEntry, Error> {
+        let ScheduledJob { name, action, .. } = job;
+        let task = match action {
+            ScheduledAction::RunMacro {
+                name: macro_name,
+                args,
+            } => {
+                let configs = instance.validate_local_config(&macro_name, None).await?;
+                let configs = if configs.is_empty() {
+                    None
+                } else {
+                    Some(configs)
+                };
+                return instance
+                    .run_macro(&macro_name, args, configs, caused_by)
+                    .await;
+            }
+            action => {
+                let instance = instance.clone();
+                async move {
+                    match action {
+                        ScheduledAction::Command { command } => {
+                            instance.send_command(&command, caused_by).await
+                        }
+                        ScheduledAction::Start => instance.start(caused_by, false).await,
+                        ScheduledAction::Stop => instance.stop(caused_by, false).await,
+                        ScheduledAction::Restart => instance.restart(caused_by, false).await,
+                        ScheduledAction::Backup => {
+                            instance.create_backup(caused_by).await.map(|_| ())
+                        }
+                        ScheduledAction::RunMacro { .. } => unreachable!(),
+                    }
+                }
+            }
+        };
+        let pid = macro_executor.spawn_native(Some(instance.uuid().await), task);
+        let entry = TaskEntry {
+            name,
+            creation_time: chrono::Utc::now().timestamp(),
+            pid,
+        };
+        // instances without a task list still run the job, it just isn't listed
+        let _ = instance.add_task(entry.clone()).await;
+        Ok(entry)
+    }
+
+    async fn run_due_jobs(
+        &self,
+        time: &DateTime<Local>,
+        instances: &DashMap<InstanceUuid, GameInstance>,
+        macro_executor: &MacroExecutor,
+        users_manager: &Arc<RwLock<UsersManager>>,
+        global_settings: &Arc<Mutex<GlobalSettings>>,
+    ) {
+        let mut due = Vec::new();
+        for (uuid, jobs) in self.jobs.lock().await.iter() {
+            for loaded in jobs {
+                if loaded.job.enabled && loaded.schedule.matches(time) {
+                    due.push((uuid.clone(), loaded.job.clone()));
+                }
+            }
+        }
+        for (uuid, job) in due {
+            let Some(instance) = instances.get(&uuid).map(|instance| instance.clone()) else {
+                continue;
+            };
+            let macro_executor = macro_executor.clone();
+            let users_manager = users_manager.clone();
+            let global_settings = global_settings.clone();
+            tokio::spawn(async move {
+                info!(
+                    "Running scheduled job {} of {}",
+                    job.name,
+                    instance.name().await
+                );
+                let name = job.name.clone();
+                let res = match Self::authorize_job(&job, &uuid, &users_manager, &global_settings)
+                    .await
+                {
+                    Ok(caused_by) => {
+                        Self::run_job(instance.clone(), job, caused_by, &macro_executor)
+                            .await
+                            .map(|_| ())
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    error!(
+                        "Failed to run scheduled job {} of {}: {}",
+                        name,
+                        instance.name().await,
+                        e
+                    );
+                }
+            });
+        }
+    }
+
+    /// Check for due jobs at the start of every minute
+    pub async fn run(
+        self,
+        instances: Arc<DashMap<InstanceUuid, GameInstance>>,
+        macro_executor: MacroExecutor,
+        users_manager: Arc<RwLock<UsersManager>>,
+        global_settings: Arc<Mutex<GlobalSettings>>,
+    ) {
+        let start_of_minute = |time: DateTime<Local>| {
+            time.with_second(0)
+                .and_then(|time| time.with_nanosecond(0))
+                .unwrap_or(time)
+        };
+        let mut last_run = start_of_minute(Local::now());
+        loop {
+            let now = Local::now();
+            let next_run = last_run + chrono::Duration::minutes(1);
+            if now < next_run {
+                tokio::time::sleep((next_run - now).to_std().unwrap_or_default()).await;
+                continue;
+            }
+            if now - next_run > chrono::Duration::minutes(MAX_CATCH_UP_MINUTES) {
+                warn!("Scheduler fell behind, skipping jobs due before {}", now);
+                last_run = start_of_minute(now) - chrono::Duration::minutes(1);
+                continue;
+            }
+            self.run_due_jobs(
+                &next_run,
+                &instances,
+                &macro_executor,
+                &users_manager,
+                &global_settings,
+            )
+            .await;
+            last_run = next_run;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::auth::permission::UserPermission;
+    use crate::auth::user::User;
+    use crate::event_broadcaster::EventBroadcaster;
+    use crate::global_settings::GlobalSettingsData;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_jobs_run_as_their_creator() {
+        let temp_dir = tempdir::TempDir::new("test_scheduler").unwrap();
+        let (tx, _rx) = EventBroadcaster::new(10);
+        let uuid = InstanceUuid::default();
+        let mut permissions = UserPermission::default();
+        permissions.can_start_instance.insert(uuid.clone());
+        let creator = User::new("creator".to_string(), "12345", false, false, permissions);
+        let mut users_manager = UsersManager::new(
+            tx.clone(),
+            HashMap::new(),
+            temp_dir.path().join("users.json"),
+        );
+        users_manager
+            .add_user(creator.clone(), CausedBy::System)
+            .await
+            .unwrap();
+        let users_manager = RwLock::new(users_manager);
+        let global_settings = Mutex::new(GlobalSettings::new(
+            temp_dir.path().join("global_settings.json"),
+            tx,
+            GlobalSettingsData::default(),
+        ));
+        let mut job = ScheduledJob {
+            id: Snowflake::new(),
+            name: "start".to_string(),
+            cron: "0 6 * * *".to_string(),
+            action: ScheduledAction::Start,
+            enabled: true,
+            creator: Some(creator.uid.clone()),
+        };
+
+        let caused_by = Scheduler::authorize_job(&job, &uuid, &users_manager, &global_settings)
+            .await
+            .unwrap();
+        assert_eq!(
+            caused_by,
+            CausedBy::User {
+                user_id: creator.uid.clone(),
+                user_name: "creator".to_string(),
+            }
+        );
+
+        // the job can't do more than its creator is still allowed to
+        job.action = ScheduledAction::Stop;
+        assert!(
+            Scheduler::authorize_job(&job, &uuid, &users_manager, &global_settings)
+                .await
+                .is_err()
+        );
+        job.action = ScheduledAction::Start;
+        users_manager
+            .write()
+            .await
+            .update_permissions(&creator.uid, UserPermission::default(), CausedBy::System)
+            .await
+            .unwrap();
+        assert!(
+            Scheduler::authorize_job(&job, &uuid, &users_manager, &global_settings)
+                .await
+                .is_err()
+        );
+
+        job.creator = None;
+        assert!(
+            Scheduler::authorize_job(&job, &uuid, &users_manager, &global_settings)
+                .await
+                .is_err()
+        );
+    }
+}
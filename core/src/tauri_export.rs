@@ -7,15 +7,32 @@ use crate::{
     AppState,
 };
 
+/// An access token for the owner, the desktop app signs in with it without a password
+///
+/// All calls share one session, refreshed here once the previous access token expired
 pub async fn get_owner_jwt(app_state: &AppState) -> Option<JwtToken> {
-    app_state
-        .users_manager
-        .read()
-        .await
+    let mut owner_refresh_token = app_state.owner_refresh_token.lock().await;
+    let mut users_manager = app_state.users_manager.write().await;
+    if let Some(refresh_token) = owner_refresh_token.take() {
+        // the session may have been revoked or expired, start a new one then
+        if let Ok((user, tokens)) = users_manager
+            .refresh_session(refresh_token.as_ref(), None)
+            .await
+        {
+            if user.is_owner {
+                *owner_refresh_token = Some(tokens.refresh_token);
+                return Some(tokens.token);
+            }
+        }
+    }
+    let owner_uid = users_manager
         .as_ref()
         .iter()
         .find(|(_, user)| user.is_owner)
-        .and_then(|(_, user)| user.create_jwt().ok())
+        .map(|(uid, _)| uid.clone())?;
+    let tokens = users_manager.create_session(&owner_uid, None).await.ok()?;
+    *owner_refresh_token = Some(tokens.refresh_token);
+    Some(tokens.token)
 }
 
 pub async fn is_owner_account_present(app_state: &AppState) -> bool {
@@ -10,9 +10,11 @@ use self::t_configurable::Game;
 use self::t_player::Player;
 use self::t_server::State;
 use self::{
-    t_configurable::TConfigurable, t_macro::TMacro, t_player::TPlayerManagement, t_server::TServer,
+    t_backup::TBackup, t_configurable::TConfigurable, t_macro::TMacro, t_player::TPlayerManagement,
+    t_server::TServer,
 };
 
+pub mod t_backup;
 pub mod t_configurable;
 pub mod t_macro;
 pub mod t_player;
@@ -36,13 +38,16 @@ pub struct InstanceInfo {
     pub max_player_count: Option<u32>,
     pub player_list: Option<HashSet<Player>>,
 }
+use crate::bedrock::BedrockInstance;
 use crate::generic::GenericInstance;
 use crate::minecraft::MinecraftInstance;
 use crate::prelude::GameInstance;
 use crate::types::InstanceUuid;
 #[async_trait]
 #[enum_dispatch::enum_dispatch]
-pub trait TInstance: TConfigurable + TMacro + TPlayerManagement + TServer + Clone {
+pub trait TInstance:
+    TConfigurable + TMacro + TPlayerManagement + TServer + TBackup + Clone
+{
     async fn get_instance_info(&self) -> InstanceInfo {
         InstanceInfo {
             uuid: self.uuid().await,
@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use chrono::Datelike;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::{Error, ErrorKind};
+use crate::events::CausedBy;
+use crate::traits::GameInstance;
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct BackupEntry {
+    /// file name of the archive, relative to the instance's backup folder
+    pub name: String,
+    pub creation_time: i64,
+    pub size: u64,
+    /// whether the archive was made by [`TBackup::create_backup`], safety snapshots,
+    /// copies and uploaded archives are neither pruned nor count as a scheduled backup
+    pub managed: bool,
+}
+
+/// How many backup archives to keep around when pruning
+///
+/// A backup is kept if it is selected by any of the rules.
+/// A retention with every field set to 0 disables pruning.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct BackupRetention {
+    /// keep the n most recent backups
+    pub keep_last: u32,
+    /// keep the most recent backup of each of the last n days that have a backup
+    pub keep_daily: u32,
+    /// keep the most recent backup of each of the last n weeks that have a backup
+    pub keep_weekly: u32,
+}
+
+impl Default for BackupRetention {
+    fn default() -> Self {
+        Self {
+            keep_last: 5,
+            keep_daily: 7,
+            keep_weekly: 4,
+        }
+    }
+}
+
+impl BackupRetention {
+    /// Returns the backups that should be deleted under this retention policy
+    pub fn prune(&self, backups: &[BackupEntry]) -> Vec<BackupEntry> {
+        if self.keep_last == 0 && self.keep_daily == 0 && self.keep_weekly == 0 {
+            return Vec::new();
+        }
+        let mut sorted = backups.to_vec();
+        sorted.sort_by(|a, b| b.creation_time.cmp(&a.creation_time));
+
+        let mut keep: HashSet<String> = HashSet::new();
+        let mut days = HashSet::new();
+        let mut weeks = HashSet::new();
+        for (i, backup) in sorted.iter().enumerate() {
+            if i < self.keep_last as usize {
+                keep.insert(backup.name.clone());
+            }
+            let date = match chrono::NaiveDateTime::from_timestamp_opt(backup.creation_time, 0) {
+                Some(v) => v.date(),
+                None => {
+                    // keep anything we can't reason about
+                    keep.insert(backup.name.clone());
+                    continue;
+                }
+            };
+            if days.len() < self.keep_daily as usize && days.insert(date) {
+                keep.insert(backup.name.clone());
+            }
+            let week = (date.iso_week().year(), date.iso_week().week());
+            if weeks.len() < self.keep_weekly as usize && weeks.insert(week) {
+                keep.insert(backup.name.clone());
+            }
+        }
+        sorted
+            .into_iter()
+            .filter(|backup| !keep.contains(&backup.name))
+            .collect()
+    }
+}
+
+#[async_trait]
+#[enum_dispatch::enum_dispatch]
+pub trait TBackup {
+    /// period between scheduled backups in seconds, `None` if scheduled backups are disabled
+    async fn backup_period(&self) -> Option<u32> {
+        None
+    }
+    /// whether a backup or restore is running right now
+    async fn backup_in_progress(&self) -> bool {
+        false
+    }
+    async fn list_backups(&self) -> Result<Vec<BackupEntry>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    async fn create_backup(&self, _caused_by: CausedBy) -> Result<BackupEntry, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    async fn delete_backup(&self, _name: &str) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    /// Roll the instance back to a backup in its backup folder, taking a snapshot of the current state first
+    async fn restore_backup(&self, _name: &str, _caused_by: CausedBy) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    async fn backup_retention(&self) -> Result<BackupRetention, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+    async fn set_backup_retention(&self, _retention: BackupRetention) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support backups"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn entry(name: &str, creation_time: i64) -> BackupEntry {
+        BackupEntry {
+            name: name.to_string(),
+            creation_time,
+            size: 0,
+            managed: true,
+        }
+    }
+
+    #[test]
+    fn test_prune_keep_last() {
+        let retention = BackupRetention {
+            keep_last: 2,
+            keep_daily: 0,
+            keep_weekly: 0,
+        };
+        let backups = vec![entry("a", 100), entry("b", 300), entry("c", 200)];
+        assert_eq!(retention.prune(&backups), vec![entry("a", 100)]);
+    }
+
+    #[test]
+    fn test_prune_daily_and_weekly() {
+        // 2023-01-02 (a monday) at noon
+        let monday = 1672660800;
+        let retention = BackupRetention {
+            keep_last: 1,
+            keep_daily: 2,
+            keep_weekly: 2,
+        };
+        let backups = vec![
+            entry("mon_1", monday),
+            entry("mon_2", monday + 60),
+            entry("tue", monday + DAY),
+            entry("wed", monday + 2 * DAY),
+            entry("next_mon", monday + 7 * DAY),
+            entry("next_tue", monday + 8 * DAY),
+        ];
+        let mut pruned: Vec<String> = retention
+            .prune(&backups)
+            .into_iter()
+            .map(|b| b.name)
+            .collect();
+        pruned.sort();
+        // next_tue: last + daily + weekly, next_mon: daily, wed: weekly
+        assert_eq!(pruned, vec!["mon_1", "mon_2", "tue"]);
+    }
+
+    #[test]
+    fn test_prune_disabled() {
+        let retention = BackupRetention {
+            keep_last: 0,
+            keep_daily: 0,
+            keep_weekly: 0,
+        };
+        let backups = vec![entry("a", 100), entry("b", 200)];
+        assert!(retention.prune(&backups).is_empty());
+    }
+}
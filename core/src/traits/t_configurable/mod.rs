@@ -13,6 +13,8 @@ use self::manifest::ConfigurableValue;
 use crate::error::Error;
 use crate::error::ErrorKind;
 use crate::implementations::minecraft::Flavour;
+use crate::log_rules::{InstanceLogRules, LogRule};
+use crate::traits::BedrockInstance;
 use crate::traits::GameInstance;
 use crate::traits::GenericInstance;
 use crate::traits::MinecraftInstance;
@@ -126,6 +128,20 @@ pub trait TConfigurable {
         })
     }
 
+    async fn log_rules(&self) -> Result<InstanceLogRules, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support log rules"),
+        })
+    }
+    /// `None` goes back to the built-in rules
+    async fn set_log_rules(&self, _rules: Option<Vec<LogRule>>) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support log rules"),
+        })
+    }
+
     async fn configurable_manifest(&self) -> ConfigurableManifest;
 
     async fn update_configurable(
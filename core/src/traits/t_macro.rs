@@ -8,6 +8,7 @@ use crate::{
     error::{Error, ErrorKind},
     events::CausedBy,
     macro_executor::MacroPID,
+    macro_limits::MacroResource,
     traits::GameInstance,
 };
 
@@ -46,6 +47,7 @@ pub enum ExitStatus {
     Success { time: i64 },
     Killed { time: i64 },
     Error { time: i64, error_msg: String },
+    ResourceExceeded { time: i64, resource: MacroResource },
 }
 
 impl ExitStatus {
@@ -54,6 +56,7 @@ impl ExitStatus {
             ExitStatus::Success { time } => *time,
             ExitStatus::Killed { time } => *time,
             ExitStatus::Error { time, .. } => *time,
+            ExitStatus::ResourceExceeded { time, .. } => *time,
         }
     }
 }
@@ -90,6 +93,13 @@ pub trait TMacro {
             source: eyre!("This instance does not support killing macro"),
         })
     }
+    /// Track a task started outside of `run_macro`, e.g. by the scheduler
+    async fn add_task(&self, _task: TaskEntry) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("This instance does not support tracking tasks"),
+        })
+    }
     async fn get_macro_config(
         &self,
         _name: &str,
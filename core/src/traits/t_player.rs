@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use crate::error::{Error, ErrorKind};
+use crate::events::CausedBy;
 use crate::implementations::generic::player::GenericPlayer;
 use crate::minecraft::player::MinecraftPlayer;
 use crate::traits::GameInstance;
@@ -35,6 +36,35 @@ impl Hash for Player {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, TS, PartialEq, Eq)]
+#[ts(export)]
+pub struct BanEntry {
+    pub player: Player,
+    pub reason: Option<String>,
+    /// who issued the ban
+    pub source: Option<String>,
+    /// unix timestamp after which the ban is lifted, `None` if the ban is permanent
+    pub expires: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, TS, PartialEq, Eq)]
+#[ts(export)]
+#[serde(tag = "type")]
+pub enum ModerationAction {
+    Kick {
+        reason: Option<String>,
+    },
+    Ban {
+        reason: Option<String>,
+        expires: Option<i64>,
+    },
+    Pardon,
+    Op,
+    Deop,
+    WhitelistAdd,
+    WhitelistRemove,
+}
+
 #[async_trait]
 #[enum_dispatch::enum_dispatch]
 pub trait TPlayerManagement {
@@ -63,4 +93,93 @@ pub trait TPlayerManagement {
             source: eyre!("Setting max player count is unsupported for this instance"),
         })
     }
+
+    async fn kick_player(
+        &self,
+        _player_name: &str,
+        _reason: Option<String>,
+        _caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Kicking players is unsupported for this instance"),
+        })
+    }
+
+    async fn get_ban_list(&self) -> Result<Vec<BanEntry>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Getting ban list is unsupported for this instance"),
+        })
+    }
+
+    async fn ban_player(
+        &self,
+        _player_name: &str,
+        _reason: Option<String>,
+        _expires: Option<i64>,
+        _caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Banning players is unsupported for this instance"),
+        })
+    }
+
+    async fn pardon_player(&self, _player_name: &str, _caused_by: CausedBy) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Pardoning players is unsupported for this instance"),
+        })
+    }
+
+    async fn get_op_list(&self) -> Result<HashSet<Player>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Getting op list is unsupported for this instance"),
+        })
+    }
+
+    async fn op_player(&self, _player_name: &str, _caused_by: CausedBy) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Opping players is unsupported for this instance"),
+        })
+    }
+
+    async fn deop_player(&self, _player_name: &str, _caused_by: CausedBy) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Deopping players is unsupported for this instance"),
+        })
+    }
+
+    async fn get_whitelist(&self) -> Result<HashSet<Player>, Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Getting whitelist is unsupported for this instance"),
+        })
+    }
+
+    async fn add_to_whitelist(
+        &self,
+        _player_name: &str,
+        _caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Whitelisting players is unsupported for this instance"),
+        })
+    }
+
+    async fn remove_from_whitelist(
+        &self,
+        _player_name: &str,
+        _caused_by: CausedBy,
+    ) -> Result<(), Error> {
+        Err(Error {
+            kind: ErrorKind::UnsupportedOperation,
+            source: eyre!("Whitelisting players is unsupported for this instance"),
+        })
+    }
 }
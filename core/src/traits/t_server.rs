@@ -42,6 +42,8 @@ pub enum StateAction {
     UserStop,
     InstanceStart,
     InstanceStop,
+    /// The instance process exited without being asked to
+    InstanceCrash,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, TS)]
@@ -100,6 +102,7 @@ impl State {
             }
             (_, StateAction::InstanceStart) => Ok(State::Running),
             (_, StateAction::InstanceStop) => Ok(State::Stopped),
+            (_, StateAction::InstanceCrash) => Ok(State::Error),
             (State::Running, StateAction::UserStart) => {
                 Err(eyre!("Cannot start an instance that is already running"))
             }
@@ -114,8 +117,10 @@ impl State {
             (State::Stopped, StateAction::UserStop) => {
                 Err(eyre!("Cannot stop an instance that is already stopped"))
             }
-            (State::Error, StateAction::UserStart) => todo!(),
-            (State::Error, StateAction::UserStop) => todo!(),
+            (State::Error, StateAction::UserStart) => Ok(State::Starting),
+            (State::Error, StateAction::UserStop) => {
+                Err(eyre!("Cannot stop an instance that has crashed"))
+            }
         }?;
         if let Some(on_transit) = on_transit {
             on_transit(state);
@@ -132,6 +137,12 @@ impl State {
         *self = new_state;
         Ok(())
     }
+
+    /// Whether the server process exiting in this state means it crashed,
+    /// stopping it from lodestone or its console moves it to `Stopping` first
+    pub fn crashed_on_exit(&self) -> bool {
+        matches!(self, State::Starting | State::Running)
+    }
 }
 
 use crate::traits::GameInstance;
@@ -147,3 +158,30 @@ pub trait TServer {
     async fn send_command(&self, command: &str, caused_by: CausedBy) -> Result<(), Error>;
     async fn monitor(&self) -> MonitorReport;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crashed_instance_can_be_started() {
+        let mut state = State::Running;
+        state
+            .try_transition(StateAction::InstanceCrash, None)
+            .unwrap();
+        assert_eq!(state, State::Error);
+        assert!(state.try_new_state(StateAction::UserStop, None).is_err());
+        state.try_transition(StateAction::UserStart, None).unwrap();
+        assert_eq!(state, State::Starting);
+    }
+
+    #[test]
+    fn test_console_stop_is_not_a_crash() {
+        assert!(State::Running.crashed_on_exit());
+        // what sending `stop` to the console does
+        let mut state = State::Running;
+        state.try_transition(StateAction::UserStop, None).unwrap();
+        assert_eq!(state, State::Stopping);
+        assert!(!state.crashed_on_exit());
+    }
+}
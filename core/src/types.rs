@@ -1,9 +1,12 @@
 use std::fmt::Display;
 
 use crate::migration::DotLodestoneConfigV043;
+use crate::scheduler::ScheduledJob;
 use crate::traits::t_configurable::GameType;
 use crate::{
-    implementations::minecraft::Flavour, migration::RestoreConfigV042, prelude::SNOWFLAKE_GENERATOR,
+    implementations::minecraft::Flavour,
+    migration::RestoreConfigV042,
+    prelude::{LODESTONE_EPOCH_MIL, SNOWFLAKE_GENERATOR},
 };
 use serde::{Deserialize, Serialize};
 use serde_aux::prelude::*;
@@ -54,6 +57,16 @@ impl Snowflake {
     pub fn new() -> Self {
         Self(get_snowflake())
     }
+
+    /// Milliseconds since the unix epoch at which the snowflake was generated
+    pub fn timestamp_millis(&self) -> i64 {
+        (self.0 >> 22) + LODESTONE_EPOCH_MIL.with(|p| *p)
+    }
+
+    /// The smallest snowflake that can be generated at `millis`, for comparing snowflakes to times
+    pub fn from_timestamp_millis(millis: i64) -> Self {
+        Self((millis - LODESTONE_EPOCH_MIL.with(|p| *p)) << 22)
+    }
 }
 
 impl ToString for Snowflake {
@@ -77,6 +90,15 @@ impl InstanceUuid {
     pub fn no_prefix(&self) -> String {
         self.0.replace("INSTANCE_", "")
     }
+
+    /// Granting a permission on the wildcard grants it on all current and future instances
+    pub fn wildcard() -> Self {
+        Self("*".to_string())
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.0 == "*"
+    }
 }
 
 impl From<String> for InstanceUuid {
@@ -130,6 +152,8 @@ pub struct DotLodestoneConfig {
     game_type: GameType,
     uuid: InstanceUuid,
     creation_time: i64,
+    #[serde(default)]
+    schedule: Vec<ScheduledJob>,
 }
 
 impl From<RestoreConfigV042> for DotLodestoneConfig {
@@ -145,6 +169,7 @@ impl From<RestoreConfigV042> for DotLodestoneConfig {
             game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            schedule: Vec::new(),
         }
     }
 }
@@ -155,6 +180,7 @@ impl From<DotLodestoneConfigV043> for DotLodestoneConfig {
             game_type: config.game_type,
             uuid: config.uuid,
             creation_time: config.creation_time,
+            schedule: Vec::new(),
         }
     }
 }
@@ -165,6 +191,7 @@ impl DotLodestoneConfig {
             game_type,
             uuid,
             creation_time: chrono::Utc::now().timestamp(),
+            schedule: Vec::new(),
         }
     }
 
@@ -178,6 +205,14 @@ impl DotLodestoneConfig {
     pub fn game_type(&self) -> &GameType {
         &self.game_type
     }
+
+    pub fn schedule(&self) -> &[ScheduledJob] {
+        &self.schedule
+    }
+
+    pub fn set_schedule(&mut self, schedule: Vec<ScheduledJob>) {
+        self.schedule = schedule;
+    }
 }
 
 #[test]
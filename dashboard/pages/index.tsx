@@ -14,12 +14,21 @@ import React, {
   useEffect,
   useLayoutEffect,
   useMemo,
+  useRef,
   useState,
 } from 'react';
 import { Routes, Route, Navigate } from 'react-router-dom';
 import { useLocalStorage } from 'usehooks-ts';
 import { useLocalStorageQueryParam } from 'utils/hooks';
-import { DEFAULT_LOCAL_CORE, errorToString, LODESTONE_PORT } from 'utils/util';
+import {
+  DEFAULT_LOCAL_CORE,
+  errorToString,
+  isLocalCore,
+  LODESTONE_PORT,
+} from 'utils/util';
+import { refreshSession, retryWithRenewedToken } from 'utils/apis';
+import { tauri } from 'utils/tauriUtil';
+import { JwtToken } from 'bindings/JwtToken';
 import Dashboard from 'pages/dashboard';
 import Home from 'pages/home';
 import axios from 'axios';
@@ -188,11 +197,55 @@ export default function App() {
     'tokens',
     {}
   ); //TODO: clear all outdated tokens
+  const [refreshTokens, setRefreshTokens] = useLocalStorage<
+    Record<string, string>
+  >('refreshTokens', {});
   const [uid, setUid] = useState('');
   const token = tokens[socket] ?? '';
-  const setToken = (token: string, coreSocket: string) => {
-    setTokens({ ...tokens, [coreSocket]: token });
+  // read by token renewals started before the latest render
+  const refreshToken = useRef('');
+  refreshToken.current = refreshTokens[socket] ?? '';
+  const setToken = (
+    token: string,
+    coreSocket: string,
+    refreshToken?: string
+  ) => {
+    setTokens((tokens) => ({ ...tokens, [coreSocket]: token }));
+    if (refreshToken !== undefined || !token)
+      setRefreshTokens((refreshTokens) => ({
+        ...refreshTokens,
+        [coreSocket]: refreshToken ?? '',
+      }));
+  };
+  // a new access token for the current session, undefined once the session is over
+  const renewToken = async (): Promise<string | undefined> => {
+    try {
+      if (refreshToken.current) {
+        const reply = await refreshSession(refreshToken.current);
+        refreshToken.current = reply.refresh_token;
+        axios.defaults.headers.common['Authorization'] = `Bearer ${reply.token}`;
+        setToken(reply.token, socket, reply.refresh_token);
+        return reply.token;
+      }
+      // the desktop app signs the owner in to its own core without a password
+      if (tauri && isLocalCore(core)) {
+        const token = await tauri.invoke<JwtToken | null>('get_owner_jwt');
+        if (!token) return undefined;
+        axios.defaults.headers.common['Authorization'] = `Bearer ${token}`;
+        setToken(token, socket);
+        return token;
+      }
+    } catch (e) {
+      console.log('Failed to renew token', e);
+    }
+    return undefined;
   };
+  useLayoutEffect(
+    () => retryWithRenewedToken(renewToken),
+    // renewToken only depends on the core
+    // eslint-disable-next-line react-hooks/exhaustive-deps
+    [socket]
+  );
   useLayoutEffect(() => {
     if (!token) {
       delete axios.defaults.headers.common['Authorization'];
@@ -212,7 +265,17 @@ export default function App() {
 
         if (typeof exp === 'undefined') throw new Error('Invalid exp in token');
         if (typeof uid !== 'string') throw new Error('Invalid uid in token');
-        if (Date.now() >= exp * 1000) throw new Error('Token expired');
+        if (Date.now() >= exp * 1000) {
+          // the session usually outlives its access token
+          delete axios.defaults.headers.common['Authorization'];
+          renewToken().then((token) => {
+            if (token) return;
+            toast.error('Session expired');
+            setToken('', socket);
+            setUid('');
+          });
+          return;
+        }
         setUid(uid);
         axios.defaults.headers.common['Authorization'] = `Bearer ${token}`;
       } catch (e) {
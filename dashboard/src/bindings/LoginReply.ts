@@ -4,5 +4,6 @@ import type { PublicUser } from './PublicUser';
 
 export interface LoginReply {
   token: JwtToken;
+  refresh_token: JwtToken;
   user: PublicUser;
 }
@@ -13,8 +13,11 @@ interface LodestoneContext {
   /** The JWT token string, where no token is an empty string */
   token: string;
   uid: string;
-  /** Sets the JWT token in state and localStorage, where no token is an empty string */
-  setToken: (token: string, coreSocket: string) => void;
+  /**
+   * Sets the JWT token in state and localStorage, where no token is an empty string.
+   * The refresh token is kept to renew the token once it expires, it is dropped along with the token
+   */
+  setToken: (token: string, coreSocket: string, refreshToken?: string) => void;
   /** All the tokens, a record from CoreSocket to token */
   tokens: Record<string, string>;
 }
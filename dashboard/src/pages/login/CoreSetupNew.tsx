@@ -91,7 +91,7 @@ const CoreSetupNew = () => {
         return res.data;
       })
       .then((res) => {
-        setToken(res.token, socket);
+        setToken(res.token, socket, res.refresh_token);
         setPathname('/login/core/first_config');
         gaEventTracker('Setup Owner Account');
         queryClient.invalidateQueries();
@@ -1,5 +1,5 @@
 import Button from 'components/Atoms/Button';
-import { useContext } from 'react';
+import { useContext, useState } from 'react';
 import { LodestoneContext } from 'data/LodestoneContext';
 import InputField from 'components/Atoms/Form/InputField';
 import { Form, Formik, FormikHelpers } from 'formik';
@@ -8,21 +8,30 @@ import { useCoreInfo } from 'data/SystemInfo';
 import { faArrowLeft, faArrowRight } from '@fortawesome/free-solid-svg-icons';
 import { BrowserLocationContext } from 'data/BrowserLocationContext';
 import { DISABLE_AUTOFILL, isLocalCore } from 'utils/util';
-import { loginToCore } from 'utils/apis';
+import { loginToCore, loginTwoFactor } from 'utils/apis';
 import { tauri } from 'utils/tauriUtil';
 import { useDocumentTitle } from 'usehooks-ts';
 import WarningAlert from 'components/Atoms/WarningAlert';
 import useAnalyticsEventTracker from 'utils/hooks';
+import { LoginReply } from 'bindings/LoginReply';
 export type LoginValues = {
   username: string;
   password: string;
 };
 
+type TwoFactorValues = {
+  code: string;
+};
+
 const validationSchema = yup.object({
   username: yup.string().required('Username is required'),
   password: yup.string().required('Password is required'),
 });
 
+const twoFactorValidationSchema = yup.object({
+  code: yup.string().required('Code is required'),
+});
+
 const UserLogin = () => {
   useDocumentTitle('Sign in - Lodestone');
   const { setPathname, navigateBack } = useContext(BrowserLocationContext);
@@ -32,12 +41,20 @@ const UserLogin = () => {
   const { data: coreInfo } = useCoreInfo();
   const { core_name } = coreInfo ?? {};
   const gaEventTracker = useAnalyticsEventTracker('User Login');
+  // set once the password is accepted but a second factor is required
+  const [challenge, setChallenge] = useState<string | null>(null);
 
   const initialValues: LoginValues = {
     username: '',
     password: '',
   };
 
+  const finishLogin = (reply: LoginReply) => {
+    setToken(reply.token, socket, reply.refresh_token);
+    setPathname('/');
+    gaEventTracker('Logged in', 'User');
+  };
+
   const onSubmit = (
     values: LoginValues,
     actions: FormikHelpers<LoginValues>
@@ -51,9 +68,32 @@ const UserLogin = () => {
           actions.setSubmitting(false);
           return;
         }
-        setToken(response.token, socket);
-        setPathname('/');
-        gaEventTracker('Logged in', 'User');
+        if (response.type === 'TwoFactorRequired') {
+          setChallenge(response.challenge);
+        } else {
+          finishLogin(response);
+        }
+        actions.setSubmitting(false);
+      })
+      .catch((error: string) => {
+        actions.setStatus({ error: error });
+        actions.setSubmitting(false);
+      });
+  };
+
+  const onSubmitTwoFactor = (
+    values: TwoFactorValues,
+    actions: FormikHelpers<TwoFactorValues>
+  ) => {
+    if (!challenge) return;
+    loginTwoFactor(challenge, values.code.trim())
+      .then((response) => {
+        if (!response) {
+          actions.setStatus({ error: 'Wrong code' });
+          actions.setSubmitting(false);
+          return;
+        }
+        finishLogin(response);
         actions.setSubmitting(false);
       })
       .catch((error: string) => {
@@ -73,57 +113,103 @@ const UserLogin = () => {
           {core_name} ({socket})
         </h2>
       </div>
-      <Formik
-        initialValues={initialValues}
-        validationSchema={validationSchema}
-        onSubmit={onSubmit}
-        validateOnBlur={false}
-        validateOnChange={false}
-      >
-        {({ isSubmitting, status }) => (
-          <Form
-            id="loginForm"
-            className="flex flex-col gap-12"
-            autoComplete={DISABLE_AUTOFILL}
-          >
-            {status && (
-              <WarningAlert>
-                <p>{status.error}</p>
-              </WarningAlert>
-            )}
-            <div className="grid grid-cols-1 gap-y-14 gap-x-8 ">
-              <InputField type="text" name="username" label="Username" />
-              <InputField type="password" name="password" label="Password" />
-            </div>
-            <div className="flex w-full flex-row justify-between gap-4">
-              <div className="flex flex-row justify-between gap-4">
-                {tauri && isLocalCore(core) ? (
-                  <Button
-                    type="button"
-                    icon={faArrowLeft}
-                    label="Switch Account"
-                    onClick={navigateBack}
-                  />
-                ) : (
-                  <Button
-                    type="button"
-                    icon={faArrowLeft}
-                    label="Change Core"
-                    onClick={() => setPathname('/login/core/select')}
-                  />
-                )}
+      {challenge ? (
+        <Formik
+          initialValues={{ code: '' }}
+          validationSchema={twoFactorValidationSchema}
+          onSubmit={onSubmitTwoFactor}
+          validateOnBlur={false}
+          validateOnChange={false}
+        >
+          {({ isSubmitting, status }) => (
+            <Form
+              id="twoFactorForm"
+              className="flex flex-col gap-12"
+              autoComplete={DISABLE_AUTOFILL}
+            >
+              {status && (
+                <WarningAlert>
+                  <p>{status.error}</p>
+                </WarningAlert>
+              )}
+              <div className="grid grid-cols-1 gap-y-14 gap-x-8 ">
+                <InputField
+                  type="text"
+                  name="code"
+                  label="Code from your authenticator app, or a recovery code"
+                />
+              </div>
+              <div className="flex w-full flex-row justify-between gap-4">
+                <Button
+                  type="button"
+                  icon={faArrowLeft}
+                  label="Back"
+                  onClick={() => setChallenge(null)}
+                />
+                <Button
+                  type="submit"
+                  intention="primary"
+                  iconRight={faArrowRight}
+                  label="Verify"
+                  loading={isSubmitting}
+                />
+              </div>
+            </Form>
+          )}
+        </Formik>
+      ) : (
+        <Formik
+          initialValues={initialValues}
+          validationSchema={validationSchema}
+          onSubmit={onSubmit}
+          validateOnBlur={false}
+          validateOnChange={false}
+        >
+          {({ isSubmitting, status }) => (
+            <Form
+              id="loginForm"
+              className="flex flex-col gap-12"
+              autoComplete={DISABLE_AUTOFILL}
+            >
+              {status && (
+                <WarningAlert>
+                  <p>{status.error}</p>
+                </WarningAlert>
+              )}
+              <div className="grid grid-cols-1 gap-y-14 gap-x-8 ">
+                <InputField type="text" name="username" label="Username" />
+                <InputField type="password" name="password" label="Password" />
+              </div>
+              <div className="flex w-full flex-row justify-between gap-4">
+                <div className="flex flex-row justify-between gap-4">
+                  {tauri && isLocalCore(core) ? (
+                    <Button
+                      type="button"
+                      icon={faArrowLeft}
+                      label="Switch Account"
+                      onClick={navigateBack}
+                    />
+                  ) : (
+                    <Button
+                      type="button"
+                      icon={faArrowLeft}
+                      label="Change Core"
+                      onClick={() => setPathname('/login/core/select')}
+                    />
+                  )}
+                </div>
+                <Button
+                  type="submit"
+                  intention="primary"
+                  iconRight={faArrowRight}
+                  label="Sign in"
+                  loading={isSubmitting}
+                />
               </div>
-              <Button
-                type="submit"
-                intention="primary"
-                iconRight={faArrowRight}
-                label="Sign in"
-                loading={isSubmitting}
-              />
-            </div>
-          </Form>
-        )}
-      </Formik>
+            </Form>
+          )}
+        </Formik>
+      )}
     </div>
   );
 };
@@ -1,9 +1,10 @@
 import { QueryClient } from '@tanstack/react-query';
-import axios from 'axios';
+import axios, { AxiosRequestConfig } from 'axios';
 import { ClientError } from 'bindings/ClientError';
 import { ClientFile } from 'bindings/ClientFile';
 import { MacroEntry } from 'bindings/MacroEntry';
 import { LoginReply } from 'bindings/LoginReply';
+import { LoginResult } from 'bindings/LoginResult';
 import { UserPermission } from 'bindings/UserPermission';
 import { Base64 } from 'js-base64';
 import { LoginValues } from 'pages/login/UserLogin';
@@ -322,16 +323,33 @@ export const unzipInstanceFile = async (
  * Start User API
  ***********************/
 
+// we manually handle error here because we want to show different error messages
+function throwLoginError(error: unknown) {
+  if (isAxiosError<ClientError>(error) && error.response) {
+    if (
+      error.response.status === 401 ||
+      error.response.status === 403 ||
+      error.response.status === 429 ||
+      error.response.status === 500
+    ) {
+      throw `Error: ${error.response.data.kind}: ${error.response.data.causes}`;
+    }
+  } else {
+    throw `Login failed: ${errorToString(error)}`;
+  }
+}
+
 /**
+ * Users with two-factor authentication get a challenge to finish logging in
+ * with `loginTwoFactor` instead of a token
  * @throws string
  */
 export async function loginToCore(
   loginValue: LoginValues
-): Promise<LoginReply | undefined> {
-  // we manually handle error here because we want to show different error messages
+): Promise<LoginResult | undefined> {
   try {
     return await axios
-      .post<LoginReply>(
+      .post<LoginResult>(
         '/user/login',
         {},
         {
@@ -345,20 +363,72 @@ export async function loginToCore(
         return response.data;
       });
   } catch (error) {
-    if (isAxiosError<ClientError>(error) && error.response) {
-      if (
-        error.response.status === 401 ||
-        error.response.status === 403 ||
-        error.response.status === 500
-      ) {
-        throw `Error: ${error.response.data.kind}: ${error.response.data.causes}`;
-      }
-    } else {
-      throw `Login failed: ${errorToString(error)}`;
-    }
+    throwLoginError(error);
   }
 }
 
+/**
+ * Finish logging in with the challenge from `loginToCore`
+ * and a code from an authenticator app or a recovery code
+ * @throws string
+ */
+export async function loginTwoFactor(
+  challenge: string,
+  code: string
+): Promise<LoginReply | undefined> {
+  try {
+    return await axios
+      .post<LoginReply>('/user/login/two_factor', { challenge, code })
+      .then((response) => {
+        return response.data;
+      });
+  } catch (error) {
+    throwLoginError(error);
+  }
+}
+
+/**
+ * Trade a refresh token for a new access token and refresh token,
+ * the old refresh token can't be used again
+ * @throws if the session expired or was revoked
+ */
+export const refreshSession = async (refreshToken: string) => {
+  return await axios
+    .post<LoginReply>('/user/refresh', { refresh_token: refreshToken })
+    .then((response) => response.data);
+};
+
+/**
+ * Retry requests rejected with a 401 once, with a new access token from `renewToken`.
+ * Requests failing at the same time share one renewal, as a refresh token can only be used once.
+ * @returns a function removing the retry
+ */
+export const retryWithRenewedToken = (
+  renewToken: () => Promise<string | undefined>
+) => {
+  let renewal: Promise<string | undefined> | null = null;
+  // retries go around the interceptor, so a request is retried only once
+  const retry = axios.create();
+  const interceptor = axios.interceptors.response.use(
+    (response) => response,
+    async (error) => {
+      if (!isAxiosError(error) || error.response?.status !== 401) throw error;
+      const config: AxiosRequestConfig = error.config;
+      if (config.url === '/user/login' || config.url === '/user/refresh')
+        throw error;
+      if (!renewal)
+        renewal = renewToken().finally(() => {
+          renewal = null;
+        });
+      const token = await renewal;
+      if (!token) throw error;
+      config.headers = { ...config.headers, Authorization: `Bearer ${token}` };
+      return retry.request(config);
+    }
+  );
+  return () => axios.interceptors.response.eject(interceptor);
+};
+
 /**
  * @throws string if error
  * @returns LoginReply if success